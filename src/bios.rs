@@ -130,30 +130,42 @@ static VIDEO_MODES: &'static [VideoMode] = &[
     VideoMode { index: 0x013, category: VideoCategory::VGA, sw: 320, sh: 200, tw: 40, th: 25, cw: 8, ch: 8 , pt: 1, htot: 100, vtot: 449, hde: 80, vde: 400 }
 ];
 
+pub const DISK_SERVICES_ENTRY_POINT: u16 = 0xEC59;
+pub const FPU_ERROR_ENTRY_POINT: u16 = 0xE2C3; // IRQ13, see CPU::long_jump
+const DISKETTE_PARAMETER_TABLE: u16 = 0xEFC7;
+const DEFAULT_DISKETTE_PARAMETERS: [u8; 11] = [0xDF, 0x02, 0x25, 0x02, 0x12, 0x1B, 0xFF, 0x6C, 0xF6, 0x0F, 0x08];
+
 #[repr(C, packed)]
 pub struct BIOS {
     pad0: [u8; 16],
     inital_video_mode: u8,
-    pad1: [u8; 6],
+    pad1: [u8; 2],
+    memory_size: u16, // KiB
+    pad2: [u8; 2],
     keyboard_flags1: u8,
     keyboard_flags2: u8,
     keyboard_token: u8,
     keyboard_buffer_head: u16,
     keyboard_buffer_tail: u16,
     keyboard_buffer: [u16; 0x10],
-    pad2: [u8; 11],
+    pad3: [u8; 3],
+    diskette_status: u8,
+    pad4: [u8; 7],
     video_mode: u8,
     video_colums: u16,
     video_memory_length: u16,
     video_memory_address: u16,
     cursor_pos: u16,
-    pad3: [u8; 14],
+    pad5: [u8; 14],
     cursor_type: u16,
     video_current_page: u8,
     crtc_address: u16,
     current_msr: u8,
     current_pal: u8,
-    pad4: [u8; 25],
+    pad6: [u8; 13],
+    hard_disk_status: u8,
+    pub hard_disk_count: u8,
+    pad7: [u8; 10],
     keyboard_buffer_start: u16,
     keyboard_buffer_end: u16,
     video_rows_minus_one: u8,
@@ -161,7 +173,7 @@ pub struct BIOS {
     video_control: u8,
     video_switches: u8,
     video_modeset_control: u8,
-    pad5: [u8; 12],
+    pad8: [u8; 12],
     keyboard_flags3: u8,
     keyboard_leds: u8
 }
@@ -171,7 +183,8 @@ impl BIOS {
         unsafe { &mut *std::mem::transmute::<*mut u8, *mut Self>(&mut ram[0x400] as *mut u8) }
     }
 
-    pub fn setup(&mut self) {
+    pub fn setup(&mut self, memory_size: u16) {
+        self.memory_size = memory_size;
         self.keyboard_buffer_start = 0x1E;
         self.keyboard_buffer_end = self.keyboard_buffer_start+0x20;
         self.keyboard_buffer_head = self.keyboard_buffer_start;
@@ -182,6 +195,34 @@ impl BIOS {
         self.keyboard_flags3 = 0;
         self.keyboard_leds = 0;
         self.crtc_address = 0x3D4;
+        self.video_mode = 0x03;
+        self.video_colums = 80;
+        self.video_rows_minus_one = 24;
+        self.char_height = 16;
+    }
+
+    /// Points the BIOS interrupts to their handlers, unused vectors up to 0x1F to a plain return if requested
+    pub fn setup_interrupt_vector(ram: &mut [u8], default_handlers: bool) {
+        let interrupt_vector = unsafe { crate::bit_utils::transmute_slice_mut::<u8, u32>(&mut ram[0..]) };
+        if default_handlers {
+            for vector in interrupt_vector[0x00..0x20].iter_mut() {
+                *vector = 0xF000FF53;
+            }
+        }
+        interrupt_vector[0x02] = 0xF000FF53;
+        interrupt_vector[0x08] = 0xF000FEA5;
+        interrupt_vector[0x09] = 0xF000E987;
+        interrupt_vector[0x10] = 0xF000F065;
+        interrupt_vector[0x11] = 0xF000F84D;
+        interrupt_vector[0x12] = 0xF000F841;
+        interrupt_vector[0x13] = 0xF0000000|(DISK_SERVICES_ENTRY_POINT as u32);
+        interrupt_vector[0x15] = 0xF000F859;
+        interrupt_vector[0x16] = 0xF000E82E;
+        interrupt_vector[0x1A] = 0xF000FE6E;
+        interrupt_vector[0x1E] = 0xF0000000|(DISKETTE_PARAMETER_TABLE as u32);
+        interrupt_vector[0x75] = 0xF0000000|(FPU_ERROR_ENTRY_POINT as u32);
+        let diskette_parameter_table = crate::bus::BUS::physical_address(0xF000, DISKETTE_PARAMETER_TABLE);
+        ram[diskette_parameter_table..diskette_parameter_table+11].copy_from_slice(&DEFAULT_DISKETTE_PARAMETERS);
     }
 
     pub fn keyboard_buffer_push(&mut self, cycle_counter: u64, keycode: u16) -> bool {
@@ -196,11 +237,133 @@ impl BIOS {
             println!("BIOS ({}): Keyboard Buffer Overflow, dropped={:04X}", cycle_counter, keycode);
             return false;
         }
-        self.keyboard_buffer[((self.keyboard_buffer_head-self.keyboard_buffer_start)/2) as usize] = keycode;
+        self.keyboard_buffer[((self.keyboard_buffer_tail-self.keyboard_buffer_start)/2) as usize] = keycode;
         self.keyboard_buffer_tail = new_tail;
         return true;
     }
 
+    pub fn set_video_mode(&mut self, cycle_counter: u64, vga: &mut crate::vga::VideoGraphicsArray, mode: u8) {
+        match VIDEO_MODES.iter().position(|video_mode| video_mode.index == mode as u16) {
+            Some(index) => {
+                let video_mode = &VIDEO_MODES[index];
+                vga.width = video_mode.sw;
+                vga.height = video_mode.sh;
+                let (read_write_mode, miscellaneous) = match video_mode.category {
+                    VideoCategory::CGA2 => (0x00, 0x0D),
+                    VideoCategory::CGA4 => (0x20, 0x0F),
+                    VideoCategory::EGA => (0x00, 0x05),
+                    VideoCategory::VGA => (0x40, 0x05),
+                    VideoCategory::TEXT if mode == 0x07 => (0x10, 0x0A),
+                    VideoCategory::TEXT => (0x10, 0x0E)
+                };
+                vga.write_to_port(cycle_counter, 0x3CE, 0x05);
+                vga.write_to_port(cycle_counter, 0x3CF, read_write_mode);
+                vga.write_to_port(cycle_counter, 0x3CE, 0x06);
+                vga.write_to_port(cycle_counter, 0x3CF, miscellaneous);
+                vga.video_mode_dirty = true;
+                self.video_mode = mode;
+                self.video_memory_address = (vga.vram_mapping.0>>4) as u16;
+                self.video_memory_length = (vga.vram_mapping.1>>4) as u16;
+                self.video_colums = video_mode.tw as u16;
+                self.video_rows_minus_one = video_mode.th-1;
+                self.char_height = video_mode.ch as u16;
+                self.cursor_pos = 0;
+                self.scroll_window(vga, true, 0, 0x07, (0, 0), (self.video_colums-1, self.video_rows_minus_one as u16));
+                println!("BIOS ({}): Set video mode={:02X} width={} height={} vram_begin={:05X} vram_len={:05X}", cycle_counter, mode, vga.width, vga.height, vga.vram_mapping.0, vga.vram_mapping.1);
+            },
+            None => {
+                println!("BIOS ({}): Set unsupported video mode={:02X}", cycle_counter, mode);
+            }
+        }
+    }
+
+    fn is_text_mode(&self) -> bool {
+        VIDEO_MODES.iter().any(|video_mode| video_mode.index == self.video_mode as u16 && matches!(video_mode.category, VideoCategory::TEXT))
+    }
+
+    /// Offset of a text cell in the VRAM mapping, the character is followed by its attribute
+    fn text_cell_offset(&self, vga: &crate::vga::VideoGraphicsArray, column: u16, row: u16) -> Option<usize> {
+        if !self.is_text_mode() {
+            return None;
+        }
+        let base = if self.video_mode == 0x07 { 0xB0000 } else { 0xB8000 };
+        let offset = (base+(row as usize*self.video_colums as usize+column as usize)*2).wrapping_sub(vga.vram_mapping.0);
+        // Nothing is drawn until the graphics controller maps the text memory
+        if vga.vram_mapping.0 >= 0xB0000 && offset+1 < vga.vram_mapping.1 { Some(offset) } else { None }
+    }
+
+    fn read_text_cell(&self, vga: &crate::vga::VideoGraphicsArray, column: u16, row: u16) -> (u8, u8) {
+        match self.text_cell_offset(vga, column, row) {
+            Some(offset) => (vga.read_text_byte(offset), vga.read_text_byte(offset+1)),
+            None => (b' ', 0x07)
+        }
+    }
+
+    /// Writes a character and optionally its attribute
+    fn write_text_cell(&self, vga: &mut crate::vga::VideoGraphicsArray, column: u16, row: u16, character: u8, attribute: Option<u8>) {
+        if let Some(offset) = self.text_cell_offset(vga, column, row) {
+            vga.write_text_byte(offset, character);
+            if let Some(attribute) = attribute {
+                vga.write_text_byte(offset+1, attribute);
+            }
+        }
+    }
+
+    /// Scrolls a window up or down by a number of lines (0 clears it), the freed lines are blanks of the attribute
+    fn scroll_window(&self, vga: &mut crate::vga::VideoGraphicsArray, up: bool, lines: u16, attribute: u8, (left, top): (u16, u16), (right, bottom): (u16, u16)) {
+        let (right, bottom) = (right.min(self.video_colums.max(1)-1), bottom.min(self.video_rows_minus_one as u16));
+        if left > right || top > bottom {
+            return;
+        }
+        let height = bottom-top+1;
+        let lines = if lines == 0 || lines > height { height } else { lines };
+        for index in 0..height {
+            let row = if up { top+index } else { bottom-index };
+            for column in left..=right {
+                let (character, attribute) = if index+lines < height {
+                    self.read_text_cell(vga, column, if up { row+lines } else { row-lines })
+                } else {
+                    (b' ', attribute)
+                };
+                self.write_text_cell(vga, column, row, character, Some(attribute));
+            }
+        }
+    }
+
+    /// Teletype output of a character at the cursor, scrolls the screen up at the bottom row
+    pub fn teletype_output(&mut self, vga: &mut crate::vga::VideoGraphicsArray, character: u8) {
+        let (columns, rows) = (self.video_colums.max(1), self.video_rows_minus_one as u16+1);
+        let (mut column, mut row) = (self.cursor_pos&0xFF, self.cursor_pos>>8);
+        match character {
+            0x07 => {}, // Bell
+            0x08 => { column = column.saturating_sub(1); },
+            0x0A => { row += 1; },
+            0x0D => { column = 0; },
+            _ => {
+                self.write_text_cell(vga, column, row, character, None);
+                column += 1;
+                if column >= columns {
+                    column = 0;
+                    row += 1;
+                }
+            }
+        }
+        if row >= rows {
+            // The new line keeps the attribute at the cursor
+            let (_character, attribute) = self.read_text_cell(vga, column, rows-1);
+            self.scroll_window(vga, true, 1, attribute, (0, 0), (columns-1, rows-1));
+            row = rows-1;
+        }
+        self.cursor_pos = (row<<8)|column;
+    }
+
+    pub fn keyboard_buffer_peek(&self) -> Option<u16> {
+        if self.keyboard_buffer_head == self.keyboard_buffer_tail {
+            return None;
+        }
+        Some(self.keyboard_buffer[((self.keyboard_buffer_head-self.keyboard_buffer_start)/2) as usize])
+    }
+
     pub fn keyboard_buffer_pop(&mut self) -> Option<u16> {
         if self.keyboard_buffer_head == self.keyboard_buffer_tail {
             return None;
@@ -213,7 +376,123 @@ impl BIOS {
         Some(keycode)
     }
 
-    pub fn handle_call(&mut self, cpu: &mut crate::cpu::CPU, pic: &mut crate::pic::ProgrammableInterruptController, ps2_controller: &mut crate::ps2_controller::PS2Controller, vga: &mut crate::vga::VideoGraphicsArray, address: u16) {
+    /// INT 13h on the attached disk images, addressed by their BIOS drive number
+    pub fn handle_disk_call(cpu: &mut crate::cpu::CPU, bus: &mut crate::bus::BUS) {
+        let command = cpu.get_register(crate::machinecode::Operand::AH) as u8;
+        let drive = cpu.get_register(crate::machinecode::Operand::DL) as u8;
+        let hard_disk = drive&0x80 != 0;
+        let disk = bus.disks.iter().find(|(number, _)| *number == drive).map(|(_, disk)| disk.clone());
+        let not_present = if hard_disk { 0x01 } else { 0x80 }; // Invalid parameter / timeout
+        let status = match (command, disk) {
+            (0x00, Some(_)) => 0x00, // Reset Disk System
+            (0x01, _) => { // Get Status of Last Operation
+                let bios = Self::from_ram(&mut bus.ram);
+                if hard_disk { bios.hard_disk_status } else { bios.diskette_status }
+            },
+            (0x02, Some(disk)) | (0x03, Some(disk)) | (0x04, Some(disk)) => { // Read / Write / Verify Sectors
+                let count = cpu.get_register(crate::machinecode::Operand::AL) as usize;
+                let sector_and_cylinder = cpu.get_register(crate::machinecode::Operand::CX);
+                let cylinder = (sector_and_cylinder>>8)|((sector_and_cylinder&0xC0)<<2);
+                let head = cpu.get_register(crate::machinecode::Operand::DH) as u8;
+                let buffer_address = crate::bus::BUS::physical_address(cpu.get_register(crate::machinecode::Operand::ES), cpu.get_register(crate::machinecode::Operand::BX));
+                let length = count*crate::disk_image::SECTOR_SIZE;
+                let mut image = disk.lock().unwrap();
+                println!("BIOS ({}): Disk command={:02X} drive={:02X} C={} H={} S={} count={}", cpu.cycle_counter, command, drive, cylinder, head, sector_and_cylinder&0x3F, count);
+                let offset = match image.lba(cylinder, head, (sector_and_cylinder&0x3F) as u8) {
+                    Some(sector) if count > 0 && sector+count <= image.sector_count() => sector*crate::disk_image::SECTOR_SIZE,
+                    _ => {
+                        cpu.set_register(crate::machinecode::Operand::AL, 0);
+                        return Self::set_disk_status(cpu, &mut bus.ram, drive, 0x04); // Sector not found
+                    }
+                };
+                if command != 0x04 && cpu.get_register(crate::machinecode::Operand::BX) as usize+length > 0x10000 { // DMA crosses a 64 KiB boundary
+                    cpu.set_register(crate::machinecode::Operand::AL, 0);
+                    return Self::set_disk_status(cpu, &mut bus.ram, drive, 0x09); // Data boundary error
+                }
+                match command {
+                    0x02 => {
+                        for index in 0..length {
+                            let dst = bus.get_memory(cpu, buffer_address+index);
+                            bus.write_to_memory(cpu, dst, 8, image.data[offset+index] as u32);
+                        }
+                    },
+                    0x03 if image.read_only => {
+                        cpu.set_register(crate::machinecode::Operand::AL, 0);
+                        return Self::set_disk_status(cpu, &mut bus.ram, drive, 0x03); // Write protected
+                    },
+                    0x03 => {
+                        let buffer: Vec<u8> = (0..length).map(|index| {
+                            let src = bus.get_memory(cpu, buffer_address+index);
+                            bus.read_from_memory(cpu, src, 8) as u8
+                        }).collect();
+                        image.write(offset, &buffer);
+                        if image.flush().is_err() {
+                            cpu.set_register(crate::machinecode::Operand::AL, 0);
+                            return Self::set_disk_status(cpu, &mut bus.ram, drive, 0xBB); // Undefined error
+                        }
+                    },
+                    _ => {}
+                }
+                0x00
+            },
+            (0x08, Some(disk)) => { // Get Drive Parameters
+                let image = disk.lock().unwrap();
+                let max_cylinder = image.cylinders-1;
+                cpu.set_register(crate::machinecode::Operand::AL, 0);
+                cpu.set_register(crate::machinecode::Operand::CH, max_cylinder&0xFF);
+                cpu.set_register(crate::machinecode::Operand::CL, ((max_cylinder>>2)&0xC0)|image.sectors_per_track as u16);
+                cpu.set_register(crate::machinecode::Operand::DH, image.heads as u16-1);
+                cpu.set_register(crate::machinecode::Operand::DL, bus.disks.iter().filter(|(number, _)| number&0x80 == drive&0x80).count() as u16);
+                if !hard_disk {
+                    cpu.set_register(crate::machinecode::Operand::BL, image.floppy_type as u16);
+                    cpu.set_register(crate::machinecode::Operand::ES, 0xF000);
+                    cpu.set_register(crate::machinecode::Operand::DI, DISKETTE_PARAMETER_TABLE);
+                    bus.ram[crate::bus::BUS::physical_address(0xF000, DISKETTE_PARAMETER_TABLE)+4] = image.sectors_per_track;
+                }
+                0x00
+            },
+            (0x15, disk) => { // Get Disk Type
+                cpu.reset_flag(crate::cpu::Flag::Carry);
+                match disk {
+                    Some(disk) if hard_disk => {
+                        let sectors = disk.lock().unwrap().sector_count() as u32;
+                        cpu.set_register(crate::machinecode::Operand::CX, (sectors>>16) as u16);
+                        cpu.set_register(crate::machinecode::Operand::DX, sectors as u16);
+                        cpu.set_register(crate::machinecode::Operand::AH, 0x03);
+                    },
+                    Some(_) => cpu.set_register(crate::machinecode::Operand::AH, 0x02), // Floppy with change line
+                    None => cpu.set_register(crate::machinecode::Operand::AH, 0x00)
+                }
+                return;
+            },
+            (0x16, Some(_)) if !hard_disk => 0x00, // Detect Media Change: Not changed
+            (0x00, None) | (0x02..=0x04, None) | (0x08, None) | (0x16, None) => not_present,
+            _ => {
+                println!("BIOS ({}): Unsupported disk command={:02X} drive={:02X}", cpu.cycle_counter, command, drive);
+                0x01 // Invalid function
+            }
+        };
+        Self::set_disk_status(cpu, &mut bus.ram, drive, status);
+    }
+
+    /// Reports the status of a disk operation in AH and the carry flag and keeps it in the BIOS data area
+    fn set_disk_status(cpu: &mut crate::cpu::CPU, ram: &mut [u8], drive: u8, status: u8) {
+        let bios = Self::from_ram(ram);
+        if drive&0x80 != 0 {
+            bios.hard_disk_status = status;
+        } else {
+            bios.diskette_status = status;
+        }
+        cpu.set_register(crate::machinecode::Operand::AH, status as u16);
+        if status == 0 {
+            cpu.reset_flag(crate::cpu::Flag::Carry);
+        } else {
+            cpu.set_flag(crate::cpu::Flag::Carry, 1);
+        }
+    }
+
+    /// Returns true if the call has to be repeated after the next interrupt, like reading a keystroke from an empty buffer
+    pub fn handle_call(&mut self, cpu: &mut crate::cpu::CPU, config: &crate::config::Config, disks: &[(u8, crate::disk_image::SharedDiskImage)], pic: &mut crate::pic::ProgrammableInterruptController, ps2_controller: &mut crate::ps2_controller::PS2Controller, xms: &crate::xms::ExtendedMemoryManager, vga: &mut crate::vga::VideoGraphicsArray, address: u16) -> bool {
         match address {
             0xFEA5 => {}, // 0x08 (IRQ0)
             0xE987 => { // 0x09 (IRQ1)
@@ -266,35 +545,34 @@ impl BIOS {
                 let argument = cpu.get_register(crate::machinecode::Operand::AL) as u8;
                 match command {
                     0x00 => {
-                        match VIDEO_MODES.iter().position(|video_mode| video_mode.index == argument as u16) {
-                            Some(index) => {
-                                let video_mode = &VIDEO_MODES[index];
-                                vga.width = video_mode.sw;
-                                vga.height = video_mode.sh;
-                                let (read_write_mode, miscellaneous) = match video_mode.category {
-                                    VideoCategory::CGA2 => (0x00, 0x0D),
-                                    VideoCategory::CGA4 => (0x20, 0x0F),
-                                    VideoCategory::EGA => (0x00, 0x05),
-                                    VideoCategory::VGA => (0x40, 0x05),
-                                    VideoCategory::TEXT => (0x10, 0x0A)
-                                };
-                                vga.write_to_port(cpu.cycle_counter, 0x3CE, 0x05);
-                                vga.write_to_port(cpu.cycle_counter, 0x3CF, read_write_mode);
-                                vga.write_to_port(cpu.cycle_counter, 0x3CE, 0x06);
-                                vga.write_to_port(cpu.cycle_counter, 0x3CF, miscellaneous);
-                                vga.video_mode_dirty = true;
-                                self.video_mode = argument;
-                                self.video_memory_address = (vga.vram_mapping.0>>4) as u16;
-                                self.video_memory_length = (vga.vram_mapping.1>>4) as u16;
-                                self.video_colums = video_mode.tw as u16;
-                                self.video_rows_minus_one = video_mode.th-1;
-                                self.char_height = video_mode.ch as u16;
-                                println!("BIOS ({}): Set video mode={:02X} width={} height={} vram_begin={:05X} vram_len={:05X}", cpu.cycle_counter, argument, vga.width, vga.height, vga.vram_mapping.0, vga.vram_mapping.1);
-                            },
-                            None => {
-                                println!("BIOS ({}): Set unsupported video mode={:02X}", cpu.cycle_counter, argument);
-                            }
+                        self.set_video_mode(cpu.cycle_counter, vga, argument);
+                    },
+                    0x02 => {
+                        self.cursor_pos = cpu.get_register(crate::machinecode::Operand::DX);
+                    },
+                    0x03 => {
+                        cpu.set_register(crate::machinecode::Operand::CX, self.cursor_type);
+                        cpu.set_register(crate::machinecode::Operand::DX, self.cursor_pos);
+                    },
+                    0x06 | 0x07 => {
+                        let window = (cpu.get_register(crate::machinecode::Operand::CX), cpu.get_register(crate::machinecode::Operand::DX));
+                        let attribute = cpu.get_register(crate::machinecode::Operand::BH) as u8;
+                        self.scroll_window(vga, command == 0x06, argument as u16, attribute, (window.0&0xFF, window.0>>8), (window.1&0xFF, window.1>>8));
+                        println!("BIOS ({}): Scroll window {} lines={} attribute={:02X} from={:04X} to={:04X}", cpu.cycle_counter, if command == 0x06 { "up" } else { "down" }, argument, attribute, window.0, window.1);
+                    },
+                    0x09 | 0x0A => {
+                        let attribute = if command == 0x09 { Some(cpu.get_register(crate::machinecode::Operand::BL) as u8) } else { None };
+                        let columns = self.video_colums.max(1);
+                        let cursor = (self.cursor_pos>>8)*columns+(self.cursor_pos&0xFF);
+                        for index in 0..cpu.get_register(crate::machinecode::Operand::CX) {
+                            let position = cursor.wrapping_add(index);
+                            self.write_text_cell(vga, position%columns, position/columns, argument, attribute);
                         }
+                        println!("BIOS ({}): Write character={:?} attribute={:?} count={}", cpu.cycle_counter, argument as char, attribute, cpu.get_register(crate::machinecode::Operand::CX));
+                    },
+                    0x0E => {
+                        self.teletype_output(vga, argument);
+                        println!("BIOS ({}): Teletype output character={:?}", cpu.cycle_counter, argument as char);
                     },
                     0x0F => {
                         cpu.set_register(crate::machinecode::Operand::BH, self.video_current_page as u16);
@@ -320,8 +598,11 @@ impl BIOS {
                 }
             },
             0xF84D => { // 0x11
-                cpu.set_register(crate::machinecode::Operand::AX, 0xD426); // 1101 0100 0010 0110
-                //   80x87 coprocessor installed
+                let floppy_count = disks.iter().filter(|(number, _)| number&0x80 == 0).count() as u16;
+                let floppies = if floppy_count > 0 { 0x0001|((floppy_count.min(4)-1)<<6) } else { 0x0000 };
+                cpu.set_register(crate::machinecode::Operand::AX, 0xD424|((config.cpu.fpu as u16)<<1)|floppies); // 1101 0100 0010 0110
+                //   floppy drives installed (if attached)
+                //   80x87 coprocessor installed (if enabled)
                 //   pointing device installed
                 // > initial video mode: 80x25 color
                 //   number of serial ports installed: 2
@@ -329,6 +610,73 @@ impl BIOS {
                 //   number of parallel ports installed: 3
                 println!("BIOS ({}): Get Equipment List", cpu.cycle_counter);
             },
+            0xF841 => { // 0x12
+                cpu.set_register(crate::machinecode::Operand::AX, self.memory_size);
+                println!("BIOS ({}): Get Memory Size", cpu.cycle_counter);
+            },
+            0xE82E => { // 0x16
+                let command = cpu.get_register(crate::machinecode::Operand::AH) as u8;
+                match command {
+                    0x00 | 0x10 => { // Read Keystroke
+                        match self.keyboard_buffer_pop() {
+                            Some(keycode) => cpu.set_register(crate::machinecode::Operand::AX, keycode),
+                            None => { return true; }
+                        }
+                    },
+                    0x01 | 0x11 => { // Check For Keystroke
+                        match self.keyboard_buffer_peek() {
+                            Some(keycode) => {
+                                cpu.set_register(crate::machinecode::Operand::AX, keycode);
+                                cpu.reset_flag(crate::cpu::Flag::Zero);
+                            },
+                            None => cpu.set_flag(crate::cpu::Flag::Zero, 1)
+                        }
+                    },
+                    0x02 => { // Get Shift Flags
+                        cpu.set_register(crate::machinecode::Operand::AL, self.keyboard_flags1 as u16);
+                    },
+                    0x05 => { // Store Keystroke
+                        let stored = self.keyboard_buffer_push(cpu.cycle_counter, cpu.get_register(crate::machinecode::Operand::CX));
+                        cpu.set_register(crate::machinecode::Operand::AL, !stored as u16);
+                    },
+                    0x12 => { // Get Extended Shift Flags
+                        cpu.set_register(crate::machinecode::Operand::AL, self.keyboard_flags1 as u16);
+                        cpu.set_register(crate::machinecode::Operand::AH, self.keyboard_flags2 as u16);
+                    },
+                    _ => {
+                        println!("BIOS ({}): Unsupported keyboard command={:02X}", cpu.cycle_counter, command);
+                    }
+                }
+            },
+            0xF859 => { // 0x15
+                let command = cpu.get_register(crate::machinecode::Operand::AH) as u8;
+                let argument = cpu.get_register(crate::machinecode::Operand::AL) as u8;
+                cpu.reset_flag(crate::cpu::Flag::Carry);
+                match (command, argument) {
+                    (0x24, 0x00) | (0x24, 0x01) => {
+                        ps2_controller.set_a20_enabled(cpu.cycle_counter, argument == 0x01);
+                        cpu.set_register(crate::machinecode::Operand::AH, 0x00);
+                    },
+                    (0x24, 0x02) => {
+                        cpu.set_register(crate::machinecode::Operand::AX, ps2_controller.is_a20_enabled() as u16);
+                    },
+                    (0x24, 0x03) => {
+                        cpu.set_register(crate::machinecode::Operand::AH, 0x00);
+                        cpu.set_register(crate::machinecode::Operand::BX, 0x0003); // Keyboard controller and port 0x92
+                    },
+                    (0x88, _) => {
+                        // While the XMS driver is installed it owns the extended memory
+                        let extended_kib = if cpu.model < crate::machinecode::CpuModel::I80286 || xms.is_installed() { 0 } else { config.memory.extended_kib.min(15360) };
+                        cpu.set_register(crate::machinecode::Operand::AX, extended_kib);
+                        println!("BIOS ({}): Get Extended Memory Size", cpu.cycle_counter);
+                    },
+                    _ => {
+                        println!("BIOS ({}): Unsupported system services command={:02X} argument={:02X}", cpu.cycle_counter, command, argument);
+                        cpu.set_register(crate::machinecode::Operand::AH, 0x86);
+                        cpu.set_flag(crate::cpu::Flag::Carry, 1);
+                    }
+                }
+            },
             0xFE6E => { // 0x1A
                 let command = cpu.get_register(crate::machinecode::Operand::AH);
                 match command {
@@ -339,6 +687,22 @@ impl BIOS {
                         cpu.set_register(crate::machinecode::Operand::BX, time as u16);
                         println!("BIOS ({}): Get System Time", cpu.cycle_counter);
                     },
+                    0x02 => {
+                        let now = chrono::Local::now();
+                        let bcd = |value: u32| (((value/10)<<4)|(value%10)) as u16;
+                        cpu.set_register(crate::machinecode::Operand::CX, (bcd(now.hour())<<8)|bcd(now.minute()));
+                        cpu.set_register(crate::machinecode::Operand::DX, bcd(now.second())<<8);
+                        cpu.reset_flag(crate::cpu::Flag::Carry);
+                        println!("BIOS ({}): Get Real Time Clock Time", cpu.cycle_counter);
+                    },
+                    0x04 => {
+                        let now = chrono::Local::now();
+                        let bcd = |value: u32| (((value/10)<<4)|(value%10)) as u16;
+                        cpu.set_register(crate::machinecode::Operand::CX, (bcd(now.year() as u32/100)<<8)|bcd(now.year() as u32%100));
+                        cpu.set_register(crate::machinecode::Operand::DX, (bcd(now.month())<<8)|bcd(now.day()));
+                        cpu.reset_flag(crate::cpu::Flag::Carry);
+                        println!("BIOS ({}): Get Real Time Clock Date", cpu.cycle_counter);
+                    },
                     _ => {
                         println!("BIOS ({}): Unsupported System Time command={}", cpu.cycle_counter, command);
                     }
@@ -348,5 +712,6 @@ impl BIOS {
                 println!("BIOS ({}): Unsupported call to address=0xF{:04X}", cpu.cycle_counter, address);
             }
         }
+        false
     }
 }
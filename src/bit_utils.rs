@@ -55,10 +55,6 @@ pub fn lsb_mask(length: u32) -> u32 {
     u32::max_value()>>(32-length)
 }
 
-pub fn msb_mask(length: u32) -> u32 {
-    u32::max_value()<<(32-length)
-}
-
 pub fn read_bytes<R>(stream: &mut R, length: u8, buffer: &mut[u8], offset: &mut u8, value: &mut u32) -> io::Result<()> where R: Read {
     stream.read_exact(&mut buffer[*offset as usize..*offset as usize+length as usize])?;
     *value = match length {
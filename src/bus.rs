@@ -77,14 +77,18 @@ impl HandlerSchedule {
 }
 
 pub struct BUS {
-    rom: [u8; 4],
+    rom: [u8; 16],
     pub ram: Vec<u8>,
     pub pit: crate::pit::ProgrammableIntervalTimer,
     pub pic: crate::pic::ProgrammableInterruptController,
+    pub fpu: crate::fpu::FloatingPointUnit,
     pub ps2_controller: crate::ps2_controller::PS2Controller,
     pub sound_blaster: crate::sound_blaster::SoundBlaster,
     pub vga: crate::vga::VideoGraphicsArray,
     pub dos: crate::dos::DiskOperatingSystem,
+    pub ems: crate::ems::ExpandedMemoryManager,
+    pub xms: crate::xms::ExtendedMemoryManager,
+    pub disks: Vec<(u8, crate::disk_image::SharedDiskImage)>, // By BIOS drive number
 
     // Emulator Internal
     pub handler_schedule: HandlerSchedule,
@@ -93,7 +97,12 @@ pub struct BUS {
     pub input_event_dst: std::sync::mpsc::Sender<crate::gui::InputEvent>,
     pub input_event_src: std::sync::mpsc::Receiver<crate::gui::InputEvent>,
     pub config: crate::config::Config,
-    pub terminate: bool
+    pub terminate: bool,
+    pub conventional_memory_end: usize,
+    pub upper_memory_blocks: Vec<(usize, usize)>,
+    pub extended_memory_end: usize,
+    pub flat_memory: bool, // Plain RAM and ports without devices or high level interrupt handlers, for the single step tests
+    pub boot_mode: bool // Booted from a disk image, only the BIOS is emulated and DOS interrupts go through the interrupt vector table
 }
 
 impl BUS {
@@ -101,25 +110,38 @@ impl BUS {
         let (audio_event_dst, audio_event_src) = std::sync::mpsc::channel();
         let (input_event_dst, input_event_src) = std::sync::mpsc::channel();
         let mut bus = Self {
-            rom: [0, 0, 0, 0],
-            ram: Vec::with_capacity(0xA0000),
+            rom: [0; 16],
+            ram: Vec::with_capacity(0x1000000),
             pit: crate::pit::ProgrammableIntervalTimer::new(),
             pic: crate::pic::ProgrammableInterruptController::new(),
+            fpu: crate::fpu::FloatingPointUnit::new(),
             ps2_controller: crate::ps2_controller::PS2Controller::new(),
             sound_blaster: crate::sound_blaster::SoundBlaster::new(),
             vga: crate::vga::VideoGraphicsArray::new(),
             dos: crate::dos::DiskOperatingSystem::new(),
+            ems: crate::ems::ExpandedMemoryManager::new(),
+            xms: crate::xms::ExtendedMemoryManager::new(),
+            disks: Vec::new(),
             handler_schedule: HandlerSchedule::new(),
             audio_event_dst: audio_event_dst,
             audio_event_src: audio_event_src,
             input_event_dst: input_event_dst,
             input_event_src: input_event_src,
             config: crate::config::Config {
+                cpu: crate::config::Cpu::default(),
                 timing: unsafe { std::mem::zeroed() },
+                memory: crate::config::Memory::default(),
                 audio: unsafe { std::mem::zeroed() },
-                keymap: toml::value::Table::new()
+                keymap: toml::value::Table::new(),
+                drives: Vec::new(),
+                disks: Vec::new()
             },
-            terminate: false
+            terminate: false,
+            conventional_memory_end: 0xA0000,
+            upper_memory_blocks: Vec::new(),
+            extended_memory_end: 0x1000000,
+            flat_memory: false,
+            boot_mode: false
         };
         bus.ram.resize(bus.ram.capacity(), 0);
         bus
@@ -129,11 +151,126 @@ impl BUS {
         ((segment as usize)<<4)+(offset as usize)
     }
 
-    pub fn get_memory(&mut self, _cpu: &mut crate::cpu::CPU, address: usize) -> *mut u8 {
-        if address < self.ram.capacity() {
+    /// Wraps around at 1 MiB on the 8086 and while the A20 gate is disabled
+    pub fn mask_address(&self, cpu: &crate::cpu::CPU, address: usize) -> usize {
+        if cpu.model < crate::machinecode::CpuModel::I80286 {
+            address&0xFFFFF
+        } else if self.ps2_controller.is_a20_enabled() {
+            address
+        } else {
+            address&!0x100000
+        }
+    }
+
+    /// Applies the memory sizes of the config, the RAM itself always covers the whole 16 MiB address space
+    pub fn configure_memory(&mut self) {
+        let memory = &self.config.memory;
+        self.conventional_memory_end = (memory.conventional_kib.max(64).min(640) as usize)<<10;
+        self.upper_memory_blocks.clear();
+        for (first_segment, last_segment) in memory.upper_memory_blocks.iter() {
+            if *first_segment < 0xC000 || *last_segment > 0xEFFF || first_segment > last_segment {
+                println!("BUS: Ignoring upper memory block {:04X}-{:04X} outside of C000-EFFF", first_segment, last_segment);
+                continue;
+            }
+            self.upper_memory_blocks.push(((*first_segment as usize)<<4, ((*last_segment as usize)+1)<<4));
+        }
+        self.extended_memory_end = 0x100000+((memory.extended_kib as usize)<<10).min(self.ram.capacity()-0x100000);
+        self.dos.memory_end = ((self.conventional_memory_end>>4)-1) as u16;
+        if self.config.cpu.model >= crate::machinecode::CpuModel::I80286 && self.extended_memory_end > 0x100000 {
+            self.xms.install(self.extended_memory_end, &self.upper_memory_blocks);
+        }
+        if memory.expanded_kib > 0 {
+            let page_frame = ((memory.ems_page_frame as usize)<<4, ((memory.ems_page_frame as usize)<<4)+crate::ems::PHYSICAL_PAGE_COUNT*crate::ems::PAGE_SIZE);
+            if page_frame.0 < 0xC0000 || page_frame.1 > 0xF0000 || memory.ems_page_frame&0x3FF != 0 {
+                println!("BUS: Ignoring EMS page frame {:04X} outside of C000-EFFF or not 16 KiB aligned", memory.ems_page_frame);
+            } else if self.upper_memory_blocks.iter().any(|(begin, end)| *begin < page_frame.1 && page_frame.0 < *end) {
+                println!("BUS: Ignoring EMS page frame {:04X} overlapping an upper memory block", memory.ems_page_frame);
+            } else {
+                self.ems.install(memory.ems_page_frame, (memory.expanded_kib as usize)/(crate::ems::PAGE_SIZE>>10));
+                self.ems.setup(&mut self.ram);
+                self.dos.device_names.push("EMMXXXX0");
+            }
+        }
+    }
+
+    /// Attaches a disk image to INT 13h under the given BIOS drive number
+    pub fn attach_disk(&mut self, number: u8, path: &std::path::Path, read_only: bool, copy_on_write: bool) -> std::io::Result<crate::disk_image::SharedDiskImage> {
+        if self.disks.iter().any(|(other, _)| *other == number) {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("BIOS drive {:02X} is already attached", number)));
+        }
+        let disk = std::sync::Arc::new(std::sync::Mutex::new(crate::disk_image::DiskImage::open(path, read_only, copy_on_write)?));
+        self.disks.push((number, disk.clone()));
+        crate::bios::BIOS::from_ram(&mut self.ram).hard_disk_count = self.disks.iter().filter(|(number, _)| number&0x80 != 0).count() as u8;
+        Ok(disk)
+    }
+
+    /// Loads the boot sector of an attached disk to 0000:7C00 and starts it with the BIOS drive number in DL
+    pub fn boot(&mut self, cpu: &mut crate::cpu::CPU, number: u8) -> std::io::Result<()> {
+        let disk = match self.disks.iter().find(|(other, _)| *other == number) {
+            Some((_, disk)) => disk.clone(),
+            None => { return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No disk attached as BIOS drive {:02X}", number))); }
+        };
+        let image = disk.lock().unwrap();
+        if image.data.len() < crate::disk_image::SECTOR_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Disk image has no boot sector"));
+        }
+        if image.data[510] != 0x55 || image.data[511] != 0xAA {
+            println!("BUS: Boot sector of BIOS drive {:02X} has no signature", number);
+        }
+        self.boot_mode = true;
+        let bios = crate::bios::BIOS::from_ram(&mut self.ram);
+        bios.setup((self.conventional_memory_end>>10) as u16);
+        bios.set_video_mode(cpu.cycle_counter, &mut self.vga, 0x03);
+        crate::bios::BIOS::setup_interrupt_vector(&mut self.ram, true);
+        self.ram[0x7C00..0x7C00+crate::disk_image::SECTOR_SIZE].copy_from_slice(&image.data[0..crate::disk_image::SECTOR_SIZE]);
+        cpu.instruction_cache.invalidate(0x7C00, crate::disk_image::SECTOR_SIZE);
+        for segment in [crate::machinecode::Operand::CS, crate::machinecode::Operand::DS, crate::machinecode::Operand::ES, crate::machinecode::Operand::SS].iter() {
+            cpu.set_register(*segment, 0x0000);
+        }
+        cpu.set_register(crate::machinecode::Operand::SP, 0x7C00);
+        cpu.set_register(crate::machinecode::Operand::DX, number as u16);
+        cpu.instruction.position = 0x7C00;
+        Ok(())
+    }
+
+    /// Mounts a host directory, an ISO 9660 image or a FAT disk image.
+    /// FAT images share the attached disk of the same path, or are attached as the next floppy or hard disk.
+    pub fn mount_drive(&mut self, drive: crate::config::Drive) -> std::io::Result<()> {
+        let file_system = if drive.path.is_dir() {
+            crate::file_system::FileSystem::Host(crate::file_system::HostFileSystem::new(&drive.path))
+        } else if crate::iso9660::Iso9660FileSystem::is_iso9660(&drive.path) {
+            crate::file_system::FileSystem::Iso9660(crate::iso9660::Iso9660FileSystem::open(&drive.path)?)
+        } else {
+            let path = drive.path.canonicalize()?;
+            let disk = match self.disks.iter().find(|(_, disk)| disk.lock().unwrap().path == path) {
+                Some((_, disk)) => disk.clone(),
+                None => {
+                    let first_number = if drive.kind == crate::dos::DriveKind::Floppy { 0x00 } else { 0x80 };
+                    let number = (first_number..first_number+0x7F).find(|number| !self.disks.iter().any(|(other, _)| other == number)).unwrap();
+                    self.attach_disk(number, &path, drive.read_only, drive.copy_on_write)?
+                }
+            };
+            crate::file_system::FileSystem::Fat(crate::fat::FatFileSystem::open(disk)?)
+        };
+        self.dos.mount(drive.letter, crate::dos::Drive { kind: drive.kind, host_path: drive.path, read_only: drive.read_only, label: drive.label, file_system: file_system });
+        Ok(())
+    }
+
+    pub fn is_ram_address(&self, address: usize) -> bool {
+        address < self.conventional_memory_end ||
+        (address >= 0x100000 && address < self.extended_memory_end) ||
+        (address >= 0xC0000 && address < 0xF0000 && self.upper_memory_blocks.iter().any(|(begin, end)| address >= *begin && address < *end)) ||
+        (self.flat_memory && address < self.ram.len())
+    }
+
+    pub fn get_memory(&mut self, cpu: &mut crate::cpu::CPU, address: usize) -> *mut u8 {
+        let address = self.mask_address(cpu, address);
+        if self.is_ram_address(address) {
             &mut self.ram[address]
-        } else if address-self.vga.vram_mapping.0 < self.vga.vram_mapping.1 {
+        } else if address.wrapping_sub(self.vga.vram_mapping.0) < self.vga.vram_mapping.1 {
             &mut self.vga.vram[address-self.vga.vram_mapping.0]
+        } else if let Some(pointer) = self.ems.get_memory(address) {
+            pointer
         } else {
             &mut self.rom[0]
         }
@@ -142,16 +279,16 @@ impl BUS {
     pub fn read_from_memory(&mut self, cpu: &mut crate::cpu::CPU, src: *const u8, data_width: u8) -> u32 {
         let vram_offset = src as isize-&self.vga.vram[0] as *const u8 as isize;
         if vram_offset >= 0 && vram_offset < self.vga.vram.capacity() as isize {
-            let mut value = self.vga.read_from_memory(cpu.cycle_counter, vram_offset as usize) as u32;
-            if data_width == 16 {
-                value |= (self.vga.read_from_memory(cpu.cycle_counter, (vram_offset as usize)+1) as u32)<<8;
+            let mut value: u32 = 0;
+            for index in 0..data_width as usize/8 {
+                value |= (self.vga.read_from_memory(cpu.cycle_counter, (vram_offset as usize)+index) as u32)<<(index*8);
             }
             value
         } else {
             match data_width {
                 8 => unsafe { *src as u32 },
-                16 => unsafe { *(src as *const u16) as u32 },
-                32 => unsafe { *(src as *const u32) },
+                16 => unsafe { std::ptr::read_unaligned(src as *const u16) as u32 },
+                32 => unsafe { std::ptr::read_unaligned(src as *const u32) },
                 _ => 0
             }
         }
@@ -160,25 +297,31 @@ impl BUS {
     pub fn write_to_memory(&mut self, cpu: &mut crate::cpu::CPU, dst: *mut u8, data_width: u8, value: u32) {
         let vram_offset = dst as isize-&self.vga.vram[0] as *const u8 as isize;
         if vram_offset >= 0 && vram_offset < self.vga.vram.capacity() as isize {
-            self.vga.write_to_memory(cpu.cycle_counter, vram_offset as usize, value as u8);
-            if data_width == 16 {
-                self.vga.write_to_memory(cpu.cycle_counter, (vram_offset as usize)+1, (value>>8) as u8);
+            for index in 0..data_width as usize/8 {
+                self.vga.write_to_memory(cpu.cycle_counter, (vram_offset as usize)+index, (value>>(index*8)) as u8);
             }
         } else {
+            let address = (dst as usize).wrapping_sub(self.ram.as_ptr() as usize);
+            if address < self.ram.len() {
+                cpu.instruction_cache.invalidate(address, data_width as usize/8);
+            }
             match data_width {
                 8 => unsafe { *dst = value as u8; },
-                16 => unsafe { *(dst as *mut u16) = value as u16; },
-                32 => unsafe { *(dst as *mut u32) = value; },
+                16 => unsafe { std::ptr::write_unaligned(dst as *mut u16, value as u16); },
+                32 => unsafe { std::ptr::write_unaligned(dst as *mut u32, value); },
                 _ => { }
             }
         }
     }
 
     pub fn read_from_port(&mut self, cpu: &mut crate::cpu::CPU, address: u16) -> u8 {
+        if self.flat_memory {
+            return 0xFF;
+        }
         match address {
             0x0040..=0x0047 | 0x0061 => self.pit.read_from_port(cpu.cycle_counter, address),
             0x0020..=0x0021 | 0x00A0..=0x00A1 => self.pic.read_from_port(cpu.cycle_counter, address),
-            0x0060 | 0x0064 => self.ps2_controller.read_from_port(cpu.cycle_counter, address),
+            0x0060 | 0x0064 | 0x0092 => self.ps2_controller.read_from_port(cpu.cycle_counter, address),
             0x0220..=0x0223 | 0x0388 | 0x0389 => self.sound_blaster.read_from_port(cpu.cycle_counter, address),
             0x03B0..=0x03DF => self.vga.read_from_port(cpu.cycle_counter, address),
             _ => {
@@ -189,10 +332,13 @@ impl BUS {
     }
 
     pub fn write_to_port(&mut self, cpu: &mut crate::cpu::CPU, address: u16, value: u8) {
+        if self.flat_memory {
+            return;
+        }
         match address {
             0x0040..=0x0047 | 0x0061 => self.pit.write_to_port(cpu.cycle_counter, &mut self.handler_schedule, &mut self.config, &mut self.audio_event_dst, address, value),
             0x0020..=0x0021 | 0x00A0..=0x00A1 => self.pic.write_to_port(cpu.cycle_counter, address, value),
-            0x0060 | 0x0064 => self.ps2_controller.write_to_port(cpu.cycle_counter, address, value),
+            0x0060 | 0x0064 | 0x0092 => self.ps2_controller.write_to_port(cpu.cycle_counter, address, value),
             0x0220..=0x0223 | 0x0388 | 0x0389 => self.sound_blaster.write_to_port(cpu.cycle_counter, &mut self.handler_schedule, &mut self.config, &mut self.audio_event_dst, address, value),
             0x03B0..=0x03DF => self.vga.write_to_port(cpu.cycle_counter, address, value),
             _ => {
@@ -202,6 +348,9 @@ impl BUS {
     }
 
     pub fn handle_interrupt(&mut self, cpu: &mut crate::cpu::CPU, interrupt: u8) -> bool {
+        if self.flat_memory || self.boot_mode {
+            return false;
+        }
         match interrupt {
             0x20 => {
                 println!("DOS ({}): Exit", cpu.cycle_counter);
@@ -210,9 +359,35 @@ impl BUS {
                 true
             },
             0x21 => {
-                self.dos.handle_interrupt(cpu, &mut self.ram, &mut self.terminate);
+                self.dos.handle_interrupt(cpu, &mut self.ram, &mut self.vga, &mut self.terminate);
+                true
+            },
+            0x67 if self.ems.is_installed() => {
+                self.ems.handle_interrupt(cpu, &mut self.ram);
                 true
             },
+            0x2F => {
+                match cpu.get_register(crate::machinecode::Operand::AX) {
+                    0x4300 if self.xms.is_installed() => {
+                        cpu.set_register(crate::machinecode::Operand::AL, 0x80); // XMS driver installed
+                        true
+                    },
+                    0x4310 if self.xms.is_installed() => {
+                        cpu.set_register(crate::machinecode::Operand::ES, 0xF000);
+                        cpu.set_register(crate::machinecode::Operand::BX, crate::xms::ENTRY_POINT);
+                        true
+                    },
+                    0x1500..=0x15FF if !self.dos.cd_rom_drives().is_empty() => {
+                        crate::mscdex::handle_interrupt(cpu, self);
+                        true
+                    },
+                    function => {
+                        // Leave it to the handlers hooked into the interrupt vector table
+                        println!("BUS ({}): Unsupported multiplex function={:04X}", cpu.cycle_counter, function);
+                        false
+                    }
+                }
+            },
             0x33 => {
                 println!("Mouse ({}): Unsupported command={}", cpu.cycle_counter, cpu.get_register(crate::machinecode::Operand::AX));
                 cpu.set_register(crate::machinecode::Operand::AX, 0); // Mouse driver not installed
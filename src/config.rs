@@ -3,18 +3,119 @@ use serde::Serialize;
 
 #[derive(Deserialize, Serialize)]
 pub struct Config {
+    #[serde(default)]
+    pub cpu: Cpu,
     pub timing: Timing,
+    #[serde(default)]
+    pub memory: Memory,
     pub audio: Audio,
-    pub keymap: toml::value::Table
+    pub keymap: toml::value::Table,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drives: Vec<Drive>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disks: Vec<Disk>
+}
+
+/// Defaults to an 8086 without FPU, as before the CPU was configurable
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Cpu {
+    pub model: crate::machinecode::CpuModel,
+    pub fpu: bool,
+    pub jit: crate::jit::JitMode
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct Timing {
+    #[serde(default)]
+    pub profile: crate::timing::TimingProfile,
+    #[serde(default)]
+    pub memory_wait_states: u64,
     pub clock_frequency: f64,
     pub compensation_frequency: f64,
     pub window_update_frequency: f64
 }
 
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Memory {
+    pub conventional_kib: u16,
+    pub upper_memory_blocks: Vec<(u16, u16)>, // First and last segment, inside C000-EFFF
+    pub extended_kib: u16, // 0 disables XMS
+    pub expanded_kib: u16, // EMS pool, 0 disables INT 67h
+    pub ems_page_frame: u16 // Segment of the 64 KiB page frame
+}
+
+/// Defaults to 640 KiB of conventional memory without EMS or XMS
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            conventional_kib: 640,
+            upper_memory_blocks: Vec::new(),
+            extended_kib: 0,
+            expanded_kib: 0,
+            ems_page_frame: 0xE000
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Drive {
+    pub letter: char,
+    pub path: std::path::PathBuf,
+    pub kind: crate::dos::DriveKind,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub copy_on_write: bool, // Writes to disk images stay in memory
+    #[serde(default)]
+    pub label: String
+}
+
+/// Raw disk image attached to INT 13h
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Disk {
+    pub number: u8, // BIOS drive number, 0x00 and up for floppies, 0x80 and up for hard disks
+    pub path: std::path::PathBuf,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub copy_on_write: bool
+}
+
+impl Drive {
+    /// Parses "D=path[,floppy|hard_disk|cdrom][,ro][,cow][,label=NAME]" as given on the command line
+    pub fn from_argument(argument: &str) -> Result<Self, String> {
+        let mut options = argument.split(',');
+        let mut mount = options.next().unwrap().splitn(2, '=');
+        let letter = mount.next().unwrap().trim_end_matches(':').to_uppercase();
+        let path = mount.next().ok_or(format!("Missing path in mount {}", argument))?;
+        if letter.len() != 1 || !letter.chars().all(|letter| letter.is_ascii_alphabetic()) {
+            return Err(format!("Invalid drive letter in mount {}", argument));
+        }
+        let mut drive = Self {
+            letter: letter.chars().next().unwrap(),
+            path: std::path::PathBuf::from(path),
+            kind: crate::dos::DriveKind::HardDisk,
+            read_only: false,
+            copy_on_write: false,
+            label: String::new()
+        };
+        for option in options {
+            match option {
+                "floppy" => { drive.kind = crate::dos::DriveKind::Floppy; },
+                "hard_disk" => { drive.kind = crate::dos::DriveKind::HardDisk; },
+                "cdrom" => { drive.kind = crate::dos::DriveKind::CdRom; },
+                "ro" => { drive.read_only = true; },
+                "cow" => { drive.copy_on_write = true; },
+                _ if option.starts_with("label=") => { drive.label = option[6..].to_string(); },
+                _ => { return Err(format!("Unknown option {} in mount {}", option, argument)); }
+            }
+        }
+        Ok(drive)
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Audio {
     pub beeper_enabled: bool,
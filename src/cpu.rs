@@ -1,42 +1,51 @@
 use crate::bit_utils::lsb_mask;
-use crate::bit_utils::msb_mask;
+use crate::machinecode::CpuModel;
+use crate::machinecode::FpuOpcode;
 use crate::machinecode::Opcode;
 use crate::machinecode::Operand;
 
 macro_rules! binary_arithmetic_operation {
-    ($cpu:ident, $bus:ident, $update_dst:expr, $carry_adjust:expr, $($operation:tt)*) => {
+    ($cpu:ident, $bus:ident, $update_dst:expr, $carry_adjust:expr, $flags_operation:expr, $($operation:tt)*) => {
         let (dst, first_value) = $cpu.get_operand($bus, $cpu.instruction.first_operand, $cpu.instruction.data_width);
-        $cpu.first_value = first_value;
-        $cpu.second_value = $cpu.get_operand($bus, $cpu.instruction.second_operand, $cpu.instruction.data_width).1.wrapping_add($carry_adjust);
+        $cpu.first_value = first_value as u64;
+        $cpu.second_value = $cpu.get_operand($bus, $cpu.instruction.second_operand, $cpu.instruction.data_width).1 as u64;
         $cpu.result_value = ($cpu.first_value) $($operation)* ($cpu.second_value);
+        // The carry is applied to the result, so that the flags see the original operands
+        let carry: u64 = $carry_adjust;
+        if carry != 0 {
+            $cpu.result_value = if $flags_operation == FlagsOperation::Substraction { $cpu.result_value.wrapping_sub(carry) } else { $cpu.result_value.wrapping_add(carry) };
+        }
         if $update_dst {
-            $bus.write_to_memory($cpu, dst, $cpu.instruction.data_width, $cpu.result_value);
+            $bus.write_to_memory($cpu, dst, $cpu.instruction.data_width, $cpu.result_value as u32);
         }
-        $cpu.set_arithmetic_flags();
+        $cpu.set_lazy_flags($flags_operation);
     };
 }
 
 macro_rules! unary_arithmetic_operation {
-    ($cpu:ident, $bus:ident, $($operation:tt)*) => {
+    ($cpu:ident, $bus:ident, $flags_operation:expr, $($operation:tt)*) => {
         let (dst, second_value) = $cpu.get_operand($bus, $cpu.instruction.first_operand, $cpu.instruction.data_width);
-        $cpu.second_value = second_value;
+        $cpu.second_value = second_value as u64;
         $cpu.result_value = ($cpu.first_value) $($operation)* ($cpu.second_value);
-        $bus.write_to_memory($cpu, dst, $cpu.instruction.data_width, $cpu.result_value);
-        $cpu.set_arithmetic_flags();
+        $bus.write_to_memory($cpu, dst, $cpu.instruction.data_width, $cpu.result_value as u32);
+        $cpu.set_lazy_flags($flags_operation);
     };
 }
 
 macro_rules! bit_shift_operation {
-    ($cpu:ident, $bus:ident, $T8:ty, $T16:ty, $operation:tt) => {
+    ($cpu:ident, $bus:ident, $T8:ty, $T16:ty, $T32:ty, $operation:tt) => {
         let (dst, first_value) = $cpu.get_operand($bus, $cpu.instruction.first_operand, $cpu.instruction.data_width);
-        $cpu.first_value = first_value;
-        $cpu.second_value = $cpu.get_operand($bus, $cpu.instruction.second_operand, 8).1;
-        if $cpu.instruction.data_width == 16 {
-            $cpu.result_value = ($cpu.first_value as $T16).$operation($cpu.second_value) as u32;
-        } else {
-            $cpu.result_value = ($cpu.first_value as $T8).$operation($cpu.second_value) as u32;
+        $cpu.first_value = first_value as u64;
+        $cpu.second_value = $cpu.get_operand($bus, $cpu.instruction.second_operand, 8).1 as u64;
+        if $cpu.model >= CpuModel::I80186 {
+            $cpu.second_value &= 0x1F;
         }
-        $bus.write_to_memory($cpu, dst, $cpu.instruction.data_width, $cpu.result_value);
+        $cpu.result_value = match $cpu.instruction.data_width {
+            32 => ($cpu.first_value as $T32).$operation($cpu.second_value as u32) as u32 as u64,
+            16 => ($cpu.first_value as $T16).$operation($cpu.second_value as u32) as u32 as u64,
+            _ => ($cpu.first_value as $T8).$operation($cpu.second_value as u32) as u32 as u64
+        };
+        $bus.write_to_memory($cpu, dst, $cpu.instruction.data_width, $cpu.result_value as u32);
         $cpu.set_arithmetic_flags();
     };
 }
@@ -44,11 +53,15 @@ macro_rules! bit_shift_operation {
 macro_rules! rotate_with_carry_operation {
     ($cpu:ident, $bus:ident, $carry:ident, $($operation:tt)*) => {
         let (dst, first_value) = $cpu.get_operand($bus, $cpu.instruction.first_operand, $cpu.instruction.data_width);
-        $cpu.first_value = first_value;
-        $cpu.second_value = $cpu.get_operand($bus, $cpu.instruction.second_operand, 8).1;
-        $cpu.result_value = (($cpu.get_flag(Flag::Carry) as u32)<<$cpu.instruction.data_width)|$cpu.first_value;
-        $cpu.result_value = $($operation)*;
-        $bus.write_to_memory($cpu, dst, $cpu.instruction.data_width, $cpu.result_value);
+        $cpu.first_value = first_value as u64;
+        $cpu.second_value = $cpu.get_operand($bus, $cpu.instruction.second_operand, 8).1 as u64;
+        if $cpu.model >= CpuModel::I80186 {
+            $cpu.second_value &= 0x1F;
+        }
+        $cpu.second_value %= $cpu.instruction.data_width as u64+1;
+        $cpu.result_value = (($cpu.get_flag(Flag::Carry) as u64)<<$cpu.instruction.data_width)|$cpu.first_value;
+        $cpu.result_value = ($($operation)*)&((1<<($cpu.instruction.data_width+1))-1);
+        $bus.write_to_memory($cpu, dst, $cpu.instruction.data_width, $cpu.result_value as u32);
         let $carry = (($cpu.result_value>>$cpu.instruction.data_width)&1) as u16;
         $cpu.reset_flag(Flag::Carry);
         $cpu.reset_flag(Flag::Overflow);
@@ -57,24 +70,32 @@ macro_rules! rotate_with_carry_operation {
 }
 
 macro_rules! multiplication_operation {
-    ($cpu:ident, $bus:ident, $T8:ty, $T16:ty, $T32:ty) => {
-        let value: $T32;
+    ($cpu:ident, $bus:ident, $T8:ty, $T16:ty, $T32:ty, $T64:ty) => {
         let flag: bool;
         let second_value = $cpu.get_operand($bus, $cpu.instruction.first_operand, $cpu.instruction.data_width).1;
-        if $cpu.instruction.data_width == 16 {
-            value = ($cpu.get_register(Operand::AX) as $T16 as $T32)*(second_value as $T16 as $T32);
+        if $cpu.instruction.data_width == 32 {
+            let value: $T64 = ($cpu.get_register_32(Operand::EAX) as $T32 as $T64)*(second_value as $T32 as $T64);
+            $cpu.set_register_32(Operand::EDX, (value>>32) as u32);
+            $cpu.set_register_32(Operand::EAX, value as u32);
+            flag = if std::any::TypeId::of::<$T64>() == std::any::TypeId::of::<u64>() {
+                (value>>32) != 0
+            } else {
+                (value as i32 as i64) != (value as i64)
+            };
+        } else if $cpu.instruction.data_width == 16 {
+            let value: $T32 = ($cpu.get_register(Operand::AX) as $T16 as $T32)*(second_value as $T16 as $T32);
             $cpu.set_register(Operand::DX, ((value>>16)&0xFFFF) as u16);
             $cpu.set_register(Operand::AX, (value&0xFFFF) as u16);
             flag = if std::any::TypeId::of::<$T32>() == std::any::TypeId::of::<u32>() {
-                (value>>$cpu.instruction.data_width)&1 == 1
+                (value>>$cpu.instruction.data_width) != 0
             } else {
                 (value as i16 as i32) != (value as i32)
             };
         } else {
-            value = ($cpu.get_register(Operand::AL) as $T8 as $T32)*(second_value as $T8 as $T32);
+            let value: $T32 = ($cpu.get_register(Operand::AL) as $T8 as $T32)*(second_value as $T8 as $T32);
             $cpu.set_register(Operand::AX, value as u16);
             flag = if std::any::TypeId::of::<$T32>() == std::any::TypeId::of::<u32>() {
-                (value>>$cpu.instruction.data_width)&1 == 1
+                (value>>$cpu.instruction.data_width) != 0
             } else {
                 (value as i8 as i32) != (value as i32)
             };
@@ -87,24 +108,45 @@ macro_rules! multiplication_operation {
 }
 
 macro_rules! division_operation {
-    ($cpu:ident, $bus:ident, $T8:ty, $T16:ty, $T32:ty) => {
+    ($cpu:ident, $bus:ident, $T8:ty, $T16:ty, $T32:ty, $T64:ty) => {
         let divisor = $cpu.get_operand($bus, $cpu.instruction.first_operand, $cpu.instruction.data_width).1;
         if divisor == 0 {
-            return $cpu.software_interrupt($bus, 0);
+            return $cpu.raise_exception(Exception::DivideError, 0);
         }
-        if $cpu.instruction.data_width == 16 {
+        if $cpu.instruction.data_width == 32 {
+            let dividend = ((($cpu.get_register_32(Operand::EDX) as u64)<<32)|($cpu.get_register_32(Operand::EAX) as u64)) as $T64;
+            let quotient = match dividend.checked_div(divisor as $T32 as $T64) {
+                Some(quotient) => quotient,
+                None => { return $cpu.raise_exception(Exception::DivideError, 0); } // The most negative dividend divided by -1
+            };
+            if std::any::TypeId::of::<$T64>() == std::any::TypeId::of::<u64>() {
+                if quotient > 0xFFFFFFFF {
+                    return $cpu.raise_exception(Exception::DivideError, 0);
+                }
+            } else {
+                if quotient > 0x7FFFFFFF || (quotient as i64) < -0x80000000 {
+                    return $cpu.raise_exception(Exception::DivideError, 0);
+                }
+            }
+            let remainder = dividend.wrapping_rem(divisor as $T32 as $T64);
+            $cpu.set_register_32(Operand::EDX, remainder as u32);
+            $cpu.set_register_32(Operand::EAX, quotient as u32);
+        } else if $cpu.instruction.data_width == 16 {
             let dividend = (($cpu.get_register(Operand::DX) as $T32)<<16)|($cpu.get_register(Operand::AX) as $T32);
-            let quotient = dividend/(divisor as $T16 as $T32);
+            let quotient = match dividend.checked_div(divisor as $T16 as $T32) {
+                Some(quotient) => quotient,
+                None => { return $cpu.raise_exception(Exception::DivideError, 0); } // The most negative dividend divided by -1
+            };
             if std::any::TypeId::of::<$T32>() == std::any::TypeId::of::<u32>() {
                 if quotient > 0xFFFF {
-                    return $cpu.software_interrupt($bus, 0);
+                    return $cpu.raise_exception(Exception::DivideError, 0);
                 }
             } else {
-                if quotient > 0x7FFF || -(quotient as i32) > 0x8000 {
-                    return $cpu.software_interrupt($bus, 0);
+                if quotient > 0x7FFF || (quotient as i32) < -0x8000 {
+                    return $cpu.raise_exception(Exception::DivideError, 0);
                 }
             }
-            let remainder = dividend%(divisor as $T16 as $T32);
+            let remainder = dividend.wrapping_rem(divisor as $T16 as $T32);
             $cpu.set_register(Operand::DX, remainder as u16);
             $cpu.set_register(Operand::AX, quotient as u16);
         } else {
@@ -112,11 +154,11 @@ macro_rules! division_operation {
             let quotient = dividend/(divisor as $T8 as $T32);
             if std::any::TypeId::of::<$T32>() == std::any::TypeId::of::<u32>() {
                 if quotient > 0xFF {
-                    return $cpu.software_interrupt($bus, 0);
+                    return $cpu.raise_exception(Exception::DivideError, 0);
                 }
             } else {
-                if quotient > 0x7F || -(quotient as i32) > 0x80 {
-                    return $cpu.software_interrupt($bus, 0);
+                if quotient > 0x7F || (quotient as i32) < -0x80 {
+                    return $cpu.raise_exception(Exception::DivideError, 0);
                 }
             }
             let remainder = dividend%(divisor as $T8 as $T32);
@@ -156,19 +198,19 @@ macro_rules! ascii_adjust_operation {
 
 macro_rules! string_operation {
     ($cpu:ident, $bus:ident, $dst:ident, $src:ident, $T:ty, $update_dst:expr, $update_src:expr, $($operation:tt)*) => {
-        while $cpu.instruction.prefix == Opcode::BAD || $cpu.get_register(Operand::CX) > 0 {
-            let $dst = $cpu.memory_operand($bus, false, Operand::ES, $cpu.get_register(Operand::DI));
-            let $src = $cpu.memory_operand($bus, true, Operand::DS, $cpu.get_register(Operand::SI));
+        while $cpu.instruction.prefix == Opcode::BAD || $cpu.get_address_register(Operand::CX) > 0 {
+            let $dst = $cpu.memory_operand($bus, false, Operand::ES, $cpu.get_address_register(Operand::DI));
+            let $src = $cpu.memory_operand($bus, true, Operand::DS, $cpu.get_address_register(Operand::SI));
             $($operation)*;
             if !$cpu.get_flag(Flag::Direction) {
-                $cpu.set_register(Operand::DI, $cpu.get_register(Operand::DI).wrapping_add($update_dst));
-                $cpu.set_register(Operand::SI, $cpu.get_register(Operand::SI).wrapping_add($update_src));
+                $cpu.set_address_register(Operand::DI, $cpu.get_address_register(Operand::DI).wrapping_add($update_dst));
+                $cpu.set_address_register(Operand::SI, $cpu.get_address_register(Operand::SI).wrapping_add($update_src));
             } else {
-                $cpu.set_register(Operand::DI, $cpu.get_register(Operand::DI).wrapping_sub($update_dst));
-                $cpu.set_register(Operand::SI, $cpu.get_register(Operand::SI).wrapping_sub($update_src));
+                $cpu.set_address_register(Operand::DI, $cpu.get_address_register(Operand::DI).wrapping_sub($update_dst));
+                $cpu.set_address_register(Operand::SI, $cpu.get_address_register(Operand::SI).wrapping_sub($update_src));
             }
             if $cpu.instruction.prefix != Opcode::BAD {
-                $cpu.set_register(Operand::CX, $cpu.get_register(Operand::CX).wrapping_sub(1));
+                $cpu.set_address_register(Operand::CX, $cpu.get_address_register(Operand::CX).wrapping_sub(1));
             }
             if match $cpu.instruction.prefix {
                 Opcode::REP => false,
@@ -179,7 +221,7 @@ macro_rules! string_operation {
             } {
                 break;
             }
-            $cpu.cycle_counter += 1;
+            $cpu.cycle_counter += $cpu.timing_model.string_iteration_cycles(&$cpu.instruction);
         }
     };
 }
@@ -197,24 +239,203 @@ pub enum Flag {
     Overflow = 11 // For signed arithmetics
 }
 
+const ARITHMETIC_FLAGS_MASK: u16 = 0x08D5; // OF SF ZF AF PF CF
+
+/// Kind of ALU operation, selects how the arithmetic flags are computed from the operands and result
+#[derive(Copy, Clone, PartialEq)]
+enum FlagsOperation {
+    Logic,
+    Addition,
+    Substraction,
+    Increment, // Like an addition of 1, but keeps the carry flag
+    Decrement // Like a substraction of 1, but keeps the carry flag
+}
+
+/// Operands and result of the last ALU operation, from which the arithmetic flags are only computed when read
+#[derive(Copy, Clone)]
+struct LazyFlags {
+    operation: FlagsOperation,
+    data_width: u8,
+    first_value: u64,
+    second_value: u64,
+    result_value: u64
+}
+
+impl LazyFlags {
+    /// Mask of the flags which the operation changes
+    fn changed_flags(&self) -> u16 {
+        match self.operation {
+            FlagsOperation::Increment | FlagsOperation::Decrement => ARITHMETIC_FLAGS_MASK&!(1<<(Flag::Carry as u16)),
+            _ => ARITHMETIC_FLAGS_MASK
+        }
+    }
+
+    fn get_flag(&self, flag: Flag) -> bool {
+        let (data_width, sign_bit) = (self.data_width, self.data_width-1);
+        let (first, second, result) = (self.first_value, self.second_value, self.result_value);
+        match flag {
+            Flag::Carry => self.operation != FlagsOperation::Logic && (result>>data_width)&1 != 0,
+            Flag::Parity => (result as u8).count_ones()&1 == 0,
+            // The auxiliary carry is the carry or borrow into bit 4, including the carry of ADC and SBB
+            Flag::AuxiliaryCarry => self.operation != FlagsOperation::Logic && ((first^second^result)>>4)&1 != 0,
+            Flag::Zero => result&(lsb_mask(data_width as u32) as u64) == 0,
+            Flag::Sign => (result>>sign_bit)&1 != 0,
+            Flag::Overflow => match self.operation {
+                FlagsOperation::Logic => false,
+                FlagsOperation::Addition | FlagsOperation::Increment => (((first^result)&(second^result))>>sign_bit)&1 != 0,
+                FlagsOperation::Substraction | FlagsOperation::Decrement => (((first^second)&(first^result))>>sign_bit)&1 != 0
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn get_flags(&self) -> u16 {
+        let mut flags = 0;
+        for flag in [Flag::Carry, Flag::Parity, Flag::AuxiliaryCarry, Flag::Zero, Flag::Sign, Flag::Overflow] {
+            flags |= (self.get_flag(flag) as u16)<<(flag as u16);
+        }
+        flags&self.changed_flags()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Exception {
+    DivideError = 0,
+    BoundRangeExceeded = 5,
+    InvalidOpcode = 6,
+    DoubleFault = 8,
+    InvalidTaskStateSegment = 10,
+    SegmentNotPresent = 11,
+    StackSegmentFault = 12,
+    GeneralProtection = 13,
+    MathFault = 16
+}
+
+impl Exception {
+    fn has_error_code(&self) -> bool {
+        match self {
+            Exception::DoubleFault | Exception::InvalidTaskStateSegment | Exception::SegmentNotPresent | Exception::StackSegmentFault | Exception::GeneralProtection => true,
+            _ => false
+        }
+    }
+}
+
+/// Hidden part of a segment register, loaded from a descriptor table in protected mode
+#[derive(Copy, Clone)]
+pub struct SegmentDescriptor {
+    pub base: u32,
+    pub limit: u32,
+    pub access: u8, // Present, DPL, Segment, Type
+    pub flags: u8 // Granularity, Default/Big
+}
+
+impl SegmentDescriptor {
+    fn new(access: u8) -> Self {
+        Self {
+            base: 0,
+            limit: 0xFFFF,
+            access: access,
+            flags: 0
+        }
+    }
+
+    fn from_u64(model: CpuModel, value: u64) -> Self {
+        let mut descriptor = Self {
+            base: (((value>>16)&0xFFFFFF)|((value>>32)&0xFF000000)) as u32,
+            limit: ((value&0xFFFF)|((value>>32)&0xF0000)) as u32,
+            access: (value>>40) as u8,
+            flags: ((value>>48)&0xF0) as u8
+        };
+        if model < CpuModel::I80386 {
+            descriptor.base &= 0xFFFFFF;
+            descriptor.limit &= 0xFFFF;
+            descriptor.flags = 0;
+        } else if descriptor.flags&0x80 != 0 {
+            descriptor.limit = (descriptor.limit<<12)|0xFFF;
+        }
+        descriptor
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.access&0x80 != 0
+    }
+
+    pub fn privilege_level(&self) -> u8 {
+        (self.access>>5)&3
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.access&0x10 == 0
+    }
+
+    pub fn is_code(&self) -> bool {
+        self.access&0x18 == 0x18
+    }
+
+    fn is_conforming(&self) -> bool {
+        self.is_code() && self.access&0x04 != 0
+    }
+
+    fn is_readable(&self) -> bool {
+        !self.is_system() && (!self.is_code() || self.access&0x02 != 0)
+    }
+
+    fn is_writable(&self) -> bool {
+        !self.is_system() && !self.is_code() && self.access&0x02 != 0
+    }
+
+    fn is_expand_down(&self) -> bool {
+        !self.is_system() && !self.is_code() && self.access&0x04 != 0
+    }
+
+    pub fn is_big(&self) -> bool {
+        self.flags&0x40 != 0
+    }
+}
+
+/// Snapshot to roll an instruction back when it faults
+#[derive(Copy, Clone)]
+struct ArchitecturalState {
+    registers: [u32; 14],
+    flags: u16,
+    lazy_flags: Option<LazyFlags>,
+    segment_descriptors: [SegmentDescriptor; 6],
+    privilege_level: u8
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum ExecutionState {
     Running,
     Paused,
     DebuggerHandlesInterrupt(u8),
+    DebuggerHandlesDiagnostic,
     WaitForInterrupt
 }
 
 pub struct CPU {
-    registers: [u16; 12],
+    registers: [u32; 14],
     flags: u16,
+    lazy_flags: Option<LazyFlags>,
+    segment_descriptors: [SegmentDescriptor; 6],
+    pub control_registers: [u32; 4],
+    pub global_descriptor_table: (u32, u16),
+    pub interrupt_descriptor_table: (u32, u16),
+    pub local_descriptor_table: (u16, SegmentDescriptor),
+    pub task_register: (u16, SegmentDescriptor),
+    pub privilege_level: u8,
+    pending_exception: Option<(Exception, u16)>,
+    pub model: CpuModel,
     pub instruction: crate::machinecode::Instruction,
     pub interrupt_breakpoints: [bool; 0x100],
     pub execution_state: ExecutionState,
+    pub diagnostic: Option<String>,
+    pub timing_model: crate::timing::TimingModel,
+    pub instruction_cache: crate::instruction_cache::InstructionCache,
+    pub jit: Option<crate::jit::JustInTimeCompiler>,
     pub cycle_counter: u64,
-    pub first_value: u32,
-    pub second_value: u32,
-    pub result_value: u32,
+    pub first_value: u64,
+    pub second_value: u64,
+    pub result_value: u64,
     pub instruction_profile: std::collections::HashMap::<crate::machinecode::Instruction, usize>,
     pub instruction_profile_recording: bool
 }
@@ -224,9 +445,26 @@ impl CPU {
         Self {
             registers: unsafe { std::mem::zeroed() },
             flags: (1<<(Flag::Interrupt as usize)),
+            lazy_flags: None,
+            segment_descriptors: [
+                SegmentDescriptor::new(0x93), SegmentDescriptor::new(0x9B), SegmentDescriptor::new(0x93),
+                SegmentDescriptor::new(0x93), SegmentDescriptor::new(0x93), SegmentDescriptor::new(0x93)
+            ],
+            control_registers: [0; 4],
+            global_descriptor_table: (0, 0xFFFF),
+            interrupt_descriptor_table: (0, 0x3FF),
+            local_descriptor_table: (0, SegmentDescriptor::new(0)),
+            task_register: (0, SegmentDescriptor::new(0)),
+            privilege_level: 0,
+            pending_exception: None,
+            model: CpuModel::I8086,
             instruction: unsafe { std::mem::zeroed() },
             interrupt_breakpoints: unsafe { std::mem::zeroed() },
             execution_state: ExecutionState::Running,
+            diagnostic: None,
+            timing_model: crate::timing::TimingModel::new(),
+            instruction_cache: crate::instruction_cache::InstructionCache::new(),
+            jit: None,
             cycle_counter: 0,
             first_value: 0,
             second_value: 0,
@@ -236,59 +474,95 @@ impl CPU {
         }
     }
 
+    pub fn pause_with_diagnostic(&mut self, diagnostic: String) {
+        println!("{}", diagnostic);
+        self.diagnostic = Some(diagnostic);
+        self.execution_state = ExecutionState::DebuggerHandlesDiagnostic;
+    }
+
     pub fn get_flag(&self, flag: Flag) -> bool {
-        self.flags&(1<<(flag as u32)) != 0
+        match self.lazy_flags {
+            Some(lazy_flags) if lazy_flags.changed_flags()&(1<<(flag as u16)) != 0 => lazy_flags.get_flag(flag),
+            _ => self.flags&(1<<(flag as u32)) != 0
+        }
+    }
+
+    pub fn get_flags(&self) -> u16 {
+        match self.lazy_flags {
+            Some(lazy_flags) => (self.flags&!lazy_flags.changed_flags())|lazy_flags.get_flags(),
+            None => self.flags
+        }
+    }
+
+    pub fn set_flags(&mut self, value: u16) {
+        self.lazy_flags = None;
+        self.flags = value;
+    }
+
+    /// Computes the pending arithmetic flags, so that self.flags can be accessed directly
+    fn materialize_flags(&mut self) {
+        if let Some(lazy_flags) = self.lazy_flags.take() {
+            self.flags = (self.flags&!lazy_flags.changed_flags())|lazy_flags.get_flags();
+        }
     }
 
     pub fn reset_flag(&mut self, flag: Flag) {
+        if ARITHMETIC_FLAGS_MASK&(1<<(flag as u16)) != 0 {
+            self.materialize_flags();
+        }
         self.flags &= !(1<<(flag as u32));
     }
 
     pub fn set_flag(&mut self, flag: Flag, value: u16) {
+        if ARITHMETIC_FLAGS_MASK&(1<<(flag as u16)) != 0 {
+            self.materialize_flags();
+        }
         self.flags |= (value as u16)<<(flag as u32);
     }
 
     fn set_arithmetic_flags(&mut self) {
+        // All arithmetic flags are overwritten, so the pending ones can be discarded
+        self.lazy_flags = None;
         self.reset_flag(Flag::Carry);
         self.reset_flag(Flag::AuxiliaryCarry);
         self.reset_flag(Flag::Overflow);
         self.reset_flag(Flag::Parity);
         self.reset_flag(Flag::Zero);
         self.reset_flag(Flag::Sign);
-        let truncated_result = self.result_value&lsb_mask(self.instruction.data_width as u32);
-        self.set_flag(Flag::Parity, (truncated_result.count_ones()&1 == 0) as u16);
+        let truncated_result = self.result_value&(lsb_mask(self.instruction.data_width as u32) as u64);
+        self.set_flag(Flag::Parity, ((truncated_result&0xFF).count_ones()&1 == 0) as u16);
         self.set_flag(Flag::Zero, (truncated_result == 0) as u16);
         self.set_flag(Flag::Sign, ((self.result_value>>(self.instruction.data_width-1))&1) as u16);
     }
 
-    fn set_addition_flags(&mut self) {
-        self.set_flag(Flag::AuxiliaryCarry, ((self.first_value&0xF)+(self.second_value&0xF) > 0xF) as u16);
-        self.set_flag(Flag::Carry, ((self.result_value>>self.instruction.data_width)&1) as u16);
-        self.set_flag(Flag::Overflow, (
-            ((self.first_value^self.second_value)>>(self.instruction.data_width-1))&1 == 0 &&
-            ((self.first_value^self.result_value)>>(self.instruction.data_width-1))&1 == 1
-        ) as u16);
-    }
-
-    fn set_substraction_flags(&mut self) {
-        self.set_flag(Flag::AuxiliaryCarry, (((self.first_value&0xF) as i32-(self.second_value&0xF) as i32) < 0x0) as u16);
-        self.set_flag(Flag::Carry, ((self.result_value>>self.instruction.data_width)&1) as u16);
-        self.set_flag(Flag::Overflow, (
-            ((self.first_value^self.second_value)>>(self.instruction.data_width-1))&1 == 1 &&
-            ((self.first_value^self.result_value)>>(self.instruction.data_width-1))&1 == 1
-        ) as u16);
+    /// Records the operands and result of an ALU operation, from which its arithmetic flags are computed on demand
+    fn set_lazy_flags(&mut self, operation: FlagsOperation) {
+        if matches!(operation, FlagsOperation::Increment | FlagsOperation::Decrement) {
+            // The carry flag is kept, so it has to be computed from the previous operation
+            self.materialize_flags();
+        }
+        self.lazy_flags = Some(LazyFlags {
+            operation,
+            data_width: self.instruction.data_width,
+            first_value: self.first_value,
+            second_value: self.second_value,
+            result_value: self.result_value
+        });
     }
 
     pub fn get_register(&self, operand: Operand) -> u16 {
         match operand as usize {
-            0..=11 => {
-                self.registers[operand as usize]
+            0..=13 => {
+                self.registers[operand as usize] as u16
+            },
+            14..=17 => {
+                (self.registers[operand as usize-Operand::AL as usize]&0xFF) as u16
             },
-            12..=15 => {
-                self.registers[operand as usize-Operand::AL as usize]&0xFF
+            18..=21 => {
+                ((self.registers[operand as usize-Operand::AH as usize]>>8)&0xFF) as u16
             },
-            16..=19 => {
-                self.registers[operand as usize-Operand::AH as usize]>>8
+            22..=29 => {
+                self.registers[operand as usize-Operand::EAX as usize] as u16
             },
             _ => unreachable!()
         }
@@ -296,20 +570,59 @@ impl CPU {
 
     pub fn set_register(&mut self, operand: Operand, value: u16) {
         match operand as usize {
-            0..=11 => {
-                self.registers[operand as usize] = value;
+            0..=7 => {
+                self.registers[operand as usize] = (self.registers[operand as usize]&0xFFFF0000)|(value as u32);
+            },
+            8..=13 => {
+                self.registers[operand as usize] = value as u32;
+                if !self.protected_mode() {
+                    self.segment_descriptors[operand as usize-Operand::ES as usize].base = (value as u32)<<4;
+                }
+            },
+            14..=17 => {
+                self.registers[operand as usize-Operand::AL as usize] = (self.registers[operand as usize-Operand::AL as usize]&0xFFFFFF00)|(value as u32&0xFF);
             },
-            12..=15 => {
-                crate::bit_utils::write_low_byte_of_u16(&mut self.registers[operand as usize-Operand::AL as usize], value as u8);
+            18..=21 => {
+                self.registers[operand as usize-Operand::AH as usize] = (self.registers[operand as usize-Operand::AH as usize]&0xFFFF00FF)|((value as u32&0xFF)<<8);
             },
-            16..=19 => {
-                crate::bit_utils::write_high_byte_of_u16(&mut self.registers[operand as usize-Operand::AH as usize], value as u8);
+            22..=29 => {
+                self.registers[operand as usize-Operand::EAX as usize] = value as u32;
             },
             _ => unreachable!()
         }
     }
 
-    fn calculate_displacement(&self, operand: Operand) -> (Operand, u16) {
+    pub fn get_register_32(&self, operand: Operand) -> u32 {
+        match operand as usize {
+            22..=29 => self.registers[operand as usize-Operand::EAX as usize],
+            _ => self.get_register(operand) as u32
+        }
+    }
+
+    pub fn set_register_32(&mut self, operand: Operand, value: u32) {
+        match operand as usize {
+            22..=29 => { self.registers[operand as usize-Operand::EAX as usize] = value; },
+            _ => { self.set_register(operand, value as u16); }
+        }
+    }
+
+    fn get_address_register(&self, operand: Operand) -> u32 {
+        if self.instruction.address_size == 32 {
+            self.registers[operand as usize]
+        } else {
+            self.get_register(operand) as u32
+        }
+    }
+
+    fn set_address_register(&mut self, operand: Operand, value: u32) {
+        if self.instruction.address_size == 32 {
+            self.registers[operand as usize] = value;
+        } else {
+            self.set_register(operand, value as u16);
+        }
+    }
+
+    fn calculate_displacement(&self, operand: Operand) -> (Operand, u32) {
         let (segment_register, offset) = match operand {
             Operand::DisplacementBXSI => (Operand::DS, self.get_register(Operand::BX).wrapping_add(self.get_register(Operand::SI))),
             Operand::DisplacementBXDI => (Operand::DS, self.get_register(Operand::BX).wrapping_add(self.get_register(Operand::DI))),
@@ -320,28 +633,311 @@ impl CPU {
             Operand::DisplacementBP => (Operand::SS, self.get_register(Operand::BP)),
             Operand::DisplacementBX => (Operand::DS, self.get_register(Operand::BX)),
             Operand::Displacement => (Operand::DS, 0),
+            Operand::DisplacementExtended => {
+                let mut offset = self.instruction.displacement as u32;
+                if self.instruction.address_base != Operand::None {
+                    offset = offset.wrapping_add(self.get_register_32(self.instruction.address_base));
+                }
+                if self.instruction.address_index != Operand::None {
+                    offset = offset.wrapping_add(self.get_register_32(self.instruction.address_index).wrapping_mul(self.instruction.address_scale as u32));
+                }
+                let segment_register = match self.instruction.address_base {
+                    Operand::ESP | Operand::EBP => Operand::SS,
+                    _ => Operand::DS
+                };
+                return (segment_register, offset);
+            },
             _ => unreachable!()
         };
-        (segment_register, (self.instruction.displacement as u16).wrapping_add(offset))
+        (segment_register, (self.instruction.displacement as u16).wrapping_add(offset) as u32)
+    }
+
+    pub fn protected_mode(&self) -> bool {
+        self.control_registers[0]&1 != 0
+    }
+
+    pub fn code_size(&self) -> u8 {
+        if self.protected_mode() && self.segment_descriptors[Operand::CS as usize-Operand::ES as usize].is_big() { 32 } else { 16 }
+    }
+
+    fn stack_size(&self) -> u8 {
+        if self.protected_mode() && self.segment_descriptors[Operand::SS as usize-Operand::ES as usize].is_big() { 32 } else { 16 }
+    }
+
+    pub fn linear_address(&self, segment_register: Operand, offset: u32) -> usize {
+        self.segment_descriptors[segment_register as usize-Operand::ES as usize].base.wrapping_add(offset) as usize
+    }
+
+    fn check_segment_limit(&mut self, segment_register: Operand, offset: u32, length: u32) -> bool {
+        let descriptor = self.segment_descriptors[segment_register as usize-Operand::ES as usize];
+        let last = offset.wrapping_add(length.max(1)-1);
+        let is_valid = last >= offset && descriptor.is_present() && if descriptor.is_expand_down() {
+            offset > descriptor.limit && last <= if descriptor.is_big() { 0xFFFFFFFF } else { 0xFFFF }
+        } else {
+            last <= descriptor.limit
+        };
+        if !is_valid {
+            self.raise_exception(if segment_register == Operand::SS { Exception::StackSegmentFault } else { Exception::GeneralProtection }, 0);
+        }
+        is_valid
     }
 
-    pub fn memory_operand(&mut self, bus: &mut crate::bus::BUS, enable_segment_override: bool, mut segment_register: Operand, offset: u16) -> *mut u8 {
+    pub fn memory_operand(&mut self, bus: &mut crate::bus::BUS, enable_segment_override: bool, mut segment_register: Operand, offset: u32) -> *mut u8 {
         if enable_segment_override && self.instruction.segment_override != Operand::None {
             segment_register = self.instruction.segment_override;
         }
-        bus.get_memory(self, crate::bus::BUS::physical_address(self.registers[segment_register as usize], offset))
+        if self.protected_mode() && !self.check_segment_limit(segment_register, offset, (self.instruction.data_width as u32+7)/8) {
+            // The access goes nowhere, the instruction is rolled back afterwards
+            return bus.get_memory(self, usize::max_value());
+        }
+        bus.get_memory(self, self.linear_address(segment_register, offset))
+    }
+
+    fn read_from_linear_address(&mut self, bus: &mut crate::bus::BUS, address: usize, data_width: u8) -> u32 {
+        let src = bus.get_memory(self, address);
+        bus.read_from_memory(self, src, data_width)
+    }
+
+    fn write_to_linear_address(&mut self, bus: &mut crate::bus::BUS, address: usize, data_width: u8, value: u32) {
+        let dst = bus.get_memory(self, address);
+        bus.write_to_memory(self, dst, data_width, value);
+    }
+
+    pub fn raise_exception(&mut self, exception: Exception, error_code: u16) {
+        if self.pending_exception.is_none() {
+            self.pending_exception = Some((exception, error_code));
+        }
+    }
+
+    /// In protected mode the 80286 and later take the math fault before the next waiting FPU instruction after an unmasked exception,
+    /// in real mode the error is delivered through IRQ13 like on the AT, see Opcode::ESC
+    fn report_fpu_error(&mut self, bus: &mut crate::bus::BUS) -> bool {
+        if self.model >= CpuModel::I80286 && self.protected_mode() && bus.config.cpu.fpu && bus.fpu.is_error_pending() {
+            self.raise_exception(Exception::MathFault, 0);
+            return true;
+        }
+        false
+    }
+
+    fn check_supervisor_privilege(&mut self) -> bool {
+        if self.protected_mode() && self.privilege_level != 0 {
+            self.raise_exception(Exception::GeneralProtection, 0);
+            return false;
+        }
+        true
+    }
+
+    fn check_io_privilege(&mut self) -> bool {
+        if self.protected_mode() && self.privilege_level > ((self.flags>>12)&3) as u8 {
+            self.raise_exception(Exception::GeneralProtection, 0);
+            return false;
+        }
+        true
+    }
+
+    fn lookup_descriptor(&mut self, bus: &mut crate::bus::BUS, selector: u16) -> Option<(usize, SegmentDescriptor)> {
+        let (base, limit) = if selector&4 == 0 {
+            self.global_descriptor_table
+        } else if self.local_descriptor_table.1.is_present() {
+            (self.local_descriptor_table.1.base, self.local_descriptor_table.1.limit as u16)
+        } else {
+            return None;
+        };
+        if (selector|7) > limit {
+            return None;
+        }
+        let address = base.wrapping_add((selector&0xFFF8) as u32) as usize;
+        let value = (self.read_from_linear_address(bus, address, 32) as u64)|((self.read_from_linear_address(bus, address+4, 32) as u64)<<32);
+        Some((address, SegmentDescriptor::from_u64(self.model, value)))
+    }
+
+    fn read_descriptor(&mut self, bus: &mut crate::bus::BUS, selector: u16) -> Option<(usize, SegmentDescriptor)> {
+        let result = self.lookup_descriptor(bus, selector);
+        if result.is_none() {
+            self.raise_exception(Exception::GeneralProtection, selector&0xFFFC);
+        }
+        result
+    }
+
+    fn set_accessed(&mut self, bus: &mut crate::bus::BUS, address: usize, descriptor: &mut SegmentDescriptor) {
+        if !descriptor.is_system() && descriptor.access&1 == 0 {
+            descriptor.access |= 1;
+            self.write_to_linear_address(bus, address+5, 8, descriptor.access as u32);
+        }
+    }
+
+    fn load_segment_register(&mut self, bus: &mut crate::bus::BUS, segment_register: Operand, selector: u16) -> bool {
+        if !self.protected_mode() {
+            self.set_register(segment_register, selector);
+            return true;
+        }
+        let index = segment_register as usize-Operand::ES as usize;
+        if selector&0xFFFC == 0 {
+            if segment_register == Operand::SS {
+                self.raise_exception(Exception::GeneralProtection, 0);
+                return false;
+            }
+            self.registers[segment_register as usize] = selector as u32;
+            self.segment_descriptors[index].access = 0;
+            return true;
+        }
+        let (address, mut descriptor) = match self.read_descriptor(bus, selector) {
+            Some(result) => result,
+            None => { return false; }
+        };
+        let requested_privilege_level = (selector&3) as u8;
+        let is_valid = if segment_register == Operand::SS {
+            descriptor.is_writable() && requested_privilege_level == self.privilege_level && descriptor.privilege_level() == self.privilege_level
+        } else {
+            descriptor.is_readable() && (descriptor.is_conforming() || descriptor.privilege_level() >= self.privilege_level.max(requested_privilege_level))
+        };
+        if !is_valid {
+            self.raise_exception(Exception::GeneralProtection, selector&0xFFFC);
+            return false;
+        }
+        if !descriptor.is_present() {
+            self.raise_exception(if segment_register == Operand::SS { Exception::StackSegmentFault } else { Exception::SegmentNotPresent }, selector&0xFFFC);
+            return false;
+        }
+        self.set_accessed(bus, address, &mut descriptor);
+        self.registers[segment_register as usize] = selector as u32;
+        self.segment_descriptors[index] = descriptor;
+        true
+    }
+
+    fn read_code_segment(&mut self, bus: &mut crate::bus::BUS, selector: u16) -> Option<SegmentDescriptor> {
+        if selector&0xFFFC == 0 {
+            self.raise_exception(Exception::GeneralProtection, 0);
+            return None;
+        }
+        let (address, mut descriptor) = self.read_descriptor(bus, selector)?;
+        if !descriptor.is_code() {
+            if descriptor.is_system() {
+                self.pause_with_diagnostic(format!("CPU ({}): Unsupported control transfer through system descriptor selector={:04X} access={:02X}", self.cycle_counter, selector, descriptor.access));
+            }
+            self.raise_exception(Exception::GeneralProtection, selector&0xFFFC);
+            return None;
+        }
+        if !descriptor.is_present() {
+            self.raise_exception(Exception::SegmentNotPresent, selector&0xFFFC);
+            return None;
+        }
+        self.set_accessed(bus, address, &mut descriptor);
+        Some(descriptor)
+    }
+
+    fn set_code_segment(&mut self, selector: u16, descriptor: SegmentDescriptor, privilege_level: u8, offset: u32) -> bool {
+        if offset > descriptor.limit {
+            self.raise_exception(Exception::GeneralProtection, 0);
+            return false;
+        }
+        self.registers[Operand::CS as usize] = ((selector&0xFFFC)|privilege_level as u16) as u32;
+        self.segment_descriptors[Operand::CS as usize-Operand::ES as usize] = descriptor;
+        self.privilege_level = privilege_level;
+        self.instruction.position = offset;
+        true
+    }
+
+    fn invalidate_data_segments(&mut self) {
+        for segment_register in [Operand::ES, Operand::DS, Operand::FS, Operand::GS].iter() {
+            let descriptor = self.segment_descriptors[*segment_register as usize-Operand::ES as usize];
+            if (!descriptor.is_code() || !descriptor.is_conforming()) && descriptor.privilege_level() < self.privilege_level {
+                self.registers[*segment_register as usize] = 0;
+                self.segment_descriptors[*segment_register as usize-Operand::ES as usize].access = 0;
+            }
+        }
+    }
+
+    fn write_flags(&mut self, value: u16) {
+        self.lazy_flags = None;
+        let mut preserved: u16 = 0;
+        if self.protected_mode() {
+            if self.privilege_level > 0 {
+                preserved |= 0x3000; // IOPL
+            }
+            if self.privilege_level > ((self.flags>>12)&3) as u8 {
+                preserved |= 1<<(Flag::Interrupt as u16);
+            }
+        }
+        self.flags = (value&!preserved)|(self.flags&preserved);
+    }
+
+    fn protected_mode_far_transfer(&mut self, bus: &mut crate::bus::BUS, selector: u16, offset: u32, is_call: bool) {
+        let descriptor = match self.read_code_segment(bus, selector) {
+            Some(descriptor) => descriptor,
+            None => { return; }
+        };
+        if if descriptor.is_conforming() {
+            descriptor.privilege_level() > self.privilege_level
+        } else {
+            (selector&3) as u8 > self.privilege_level || descriptor.privilege_level() != self.privilege_level
+        } {
+            self.raise_exception(Exception::GeneralProtection, selector&0xFFFC);
+            return;
+        }
+        if is_call {
+            self.push_onto_stack_sized(bus, self.instruction.operand_size, self.get_register(Operand::CS) as u32);
+            self.push_onto_stack_sized(bus, self.instruction.operand_size, self.instruction.position);
+        }
+        self.set_code_segment(selector, descriptor, self.privilege_level, offset);
+    }
+
+    fn protected_mode_far_return(&mut self, bus: &mut crate::bus::BUS, is_interrupt_return: bool) {
+        let data_width = self.instruction.operand_size;
+        let offset = self.pop_from_stack_sized(bus, data_width);
+        let selector = self.pop_from_stack_sized(bus, data_width) as u16;
+        if is_interrupt_return {
+            let flags = self.pop_from_stack_sized(bus, data_width) as u16;
+            self.write_flags(flags);
+        }
+        let requested_privilege_level = (selector&3) as u8;
+        let descriptor = match self.read_code_segment(bus, selector) {
+            Some(descriptor) => descriptor,
+            None => { return; }
+        };
+        if requested_privilege_level < self.privilege_level || if descriptor.is_conforming() {
+            descriptor.privilege_level() > requested_privilege_level
+        } else {
+            descriptor.privilege_level() != requested_privilege_level
+        } {
+            self.raise_exception(Exception::GeneralProtection, selector&0xFFFC);
+            return;
+        }
+        if !is_interrupt_return && self.instruction.immediate != 0xFFFFFFFF {
+            self.set_stack_pointer(self.get_stack_pointer().wrapping_add(self.instruction.immediate));
+        }
+        if requested_privilege_level > self.privilege_level {
+            let stack_pointer = self.pop_from_stack_sized(bus, data_width);
+            let stack_selector = self.pop_from_stack_sized(bus, data_width) as u16;
+            if !self.set_code_segment(selector, descriptor, requested_privilege_level, offset) || !self.load_segment_register(bus, Operand::SS, stack_selector) {
+                return;
+            }
+            self.set_stack_pointer(stack_pointer);
+            if !is_interrupt_return && self.instruction.immediate != 0xFFFFFFFF {
+                self.set_stack_pointer(self.get_stack_pointer().wrapping_add(self.instruction.immediate));
+            }
+            self.invalidate_data_segments();
+        } else {
+            self.set_code_segment(selector, descriptor, requested_privilege_level, offset);
+        }
     }
 
     fn register_or_memory_operand(&mut self, bus: &mut crate::bus::BUS, operand: Operand) -> *mut u8 {
         match operand as usize {
-            0..=11 => {
-                &mut self.registers[operand as usize] as *mut u16 as *mut u8
+            0..=13 => {
+                &mut self.registers[operand as usize] as *mut u32 as *mut u8
             },
-            12..=15 => {
-                (&mut self.registers[operand as usize-Operand::AL as usize] as *mut u16 as *mut u8)
+            38..=41 => {
+                &mut self.control_registers[operand as usize-Operand::CR0 as usize] as *mut u32 as *mut u8
             },
-            16..=19 => {
-                unsafe { (&mut self.registers[operand as usize-Operand::AH as usize] as *mut u16 as *mut u8).offset(1) }
+            14..=17 => {
+                &mut self.registers[operand as usize-Operand::AL as usize] as *mut u32 as *mut u8
+            },
+            18..=21 => {
+                unsafe { (&mut self.registers[operand as usize-Operand::AH as usize] as *mut u32 as *mut u8).offset(1) }
+            },
+            22..=29 => {
+                &mut self.registers[operand as usize-Operand::EAX as usize] as *mut u32 as *mut u8
             },
             _ => {
                 let (segment_register, offset) = self.calculate_displacement(operand);
@@ -359,61 +955,347 @@ impl CPU {
         }
     }
 
+    fn get_stack_pointer(&self) -> u32 {
+        self.registers[Operand::SP as usize]&lsb_mask(self.stack_size() as u32)
+    }
+
+    fn set_stack_pointer(&mut self, value: u32) {
+        if self.stack_size() == 32 {
+            self.registers[Operand::SP as usize] = value;
+        } else {
+            self.set_register(Operand::SP, value as u16);
+        }
+    }
+
+    fn push_onto_stack_sized(&mut self, bus: &mut crate::bus::BUS, data_width: u8, value: u32) {
+        self.set_stack_pointer(self.get_stack_pointer().wrapping_sub(data_width as u32/8));
+        let dst = self.memory_operand(bus, false, Operand::SS, self.get_stack_pointer());
+        bus.write_to_memory(self, dst, data_width, value);
+    }
+
+    fn pop_from_stack_sized(&mut self, bus: &mut crate::bus::BUS, data_width: u8) -> u32 {
+        let src = self.memory_operand(bus, false, Operand::SS, self.get_stack_pointer());
+        self.set_stack_pointer(self.get_stack_pointer().wrapping_add(data_width as u32/8));
+        bus.read_from_memory(self, src, data_width)
+    }
+
     fn push_onto_stack(&mut self, bus: &mut crate::bus::BUS, value: u16) {
-        self.set_register(Operand::SP, self.get_register(Operand::SP).wrapping_sub(2));
-        let dst = self.memory_operand(bus, false, Operand::SS, self.get_register(Operand::SP));
-        bus.write_to_memory(self, dst, 16, value as u32);
+        self.push_onto_stack_sized(bus, 16, value as u32);
     }
 
     fn pop_from_stack(&mut self, bus: &mut crate::bus::BUS) -> u16 {
-        let src = self.memory_operand(bus, false, Operand::SS, self.get_register(Operand::SP));
-        self.set_register(Operand::SP, self.get_register(Operand::SP).wrapping_add(2));
-        bus.read_from_memory(self, src, 16) as u16
+        self.pop_from_stack_sized(bus, 16) as u16
+    }
+
+    fn evaluate_condition(&self, condition: u8) -> bool {
+        match condition {
+            0x0 => self.get_flag(Flag::Overflow),
+            0x1 => !self.get_flag(Flag::Overflow),
+            0x2 => self.get_flag(Flag::Carry),
+            0x3 => !self.get_flag(Flag::Carry),
+            0x4 => self.get_flag(Flag::Zero),
+            0x5 => !self.get_flag(Flag::Zero),
+            0x6 => self.get_flag(Flag::Carry) || self.get_flag(Flag::Zero),
+            0x7 => !(self.get_flag(Flag::Carry) || self.get_flag(Flag::Zero)),
+            0x8 => self.get_flag(Flag::Sign),
+            0x9 => !self.get_flag(Flag::Sign),
+            0xA => self.get_flag(Flag::Parity),
+            0xB => !self.get_flag(Flag::Parity),
+            0xC => self.get_flag(Flag::Sign) != self.get_flag(Flag::Overflow),
+            0xD => self.get_flag(Flag::Sign) == self.get_flag(Flag::Overflow),
+            0xE => self.get_flag(Flag::Sign) != self.get_flag(Flag::Overflow) || self.get_flag(Flag::Zero),
+            0xF => self.get_flag(Flag::Sign) == self.get_flag(Flag::Overflow) && !self.get_flag(Flag::Zero),
+            _ => unreachable!()
+        }
     }
 
     fn long_jump(&mut self, bus: &mut crate::bus::BUS, address: u32) {
-        if crate::bus::BUS::physical_address((address>>16) as u16, 0) < 0xF0000 {
+        if bus.flat_memory || crate::bus::BUS::physical_address((address>>16) as u16, 0) < 0xF0000 {
             self.push_onto_stack(bus, self.get_register(Operand::CS));
             self.push_onto_stack(bus, self.instruction.position as u16);
             self.set_register(Operand::CS, (address>>16) as u16);
-            self.instruction.position = address as u16;
+            self.instruction.position = address&0xFFFF;
+        } else if address == 0xF0000000|(crate::xms::ENTRY_POINT as u32) {
+            let mut xms = std::mem::replace(&mut bus.xms, crate::xms::ExtendedMemoryManager::new());
+            xms.handle_call(self, bus);
+            bus.xms = xms;
         } else {
-            self.flags = self.pop_from_stack(bus);
-            crate::bios::BIOS::from_ram(&mut bus.ram).handle_call(self, &mut bus.pic, &mut bus.ps2_controller, &mut bus.vga, address as u16);
+            let flags = self.pop_from_stack(bus);
+            self.set_flags(flags);
+            if address == 0xF0000000|(crate::bios::DISK_SERVICES_ENTRY_POINT as u32) {
+                crate::bios::BIOS::handle_disk_call(self, bus);
+            } else if address == 0xF0000000|(crate::bios::FPU_ERROR_ENTRY_POINT as u32) {
+                // The AT BIOS acknowledges IRQ13 and passes the FPU error on to the NMI handler
+                bus.pic.write_to_port(self.cycle_counter, 0xA0, 0x20);
+                self.invoke_interrupt_handler(bus, 2);
+            } else if crate::bios::BIOS::from_ram(&mut bus.ram).handle_call(self, &bus.config, &bus.disks, &mut bus.pic, &mut bus.ps2_controller, &bus.xms, &mut bus.vga, address as u16) {
+                // Repeat the INT, or the CALL FAR after a PUSHF, once an interrupt arrives
+                if self.instruction.opcode != Opcode::INT {
+                    self.push_onto_stack(bus, flags);
+                }
+                self.instruction.position = self.instruction.position.wrapping_sub(self.instruction.length as u32);
+                self.execution_state = ExecutionState::WaitForInterrupt;
+            }
+        }
+    }
+
+    fn read_task_stack(&mut self, bus: &mut crate::bus::BUS, privilege_level: u8) -> Option<(u16, u32)> {
+        let (selector, task_state_segment) = self.task_register;
+        let (offset, data_width) = if task_state_segment.access&0x08 != 0 { (4+privilege_level as u32*8, 32) } else { (2+privilege_level as u32*4, 16) };
+        if !task_state_segment.is_present() || offset+data_width as u32/8+1 > task_state_segment.limit {
+            self.raise_exception(Exception::InvalidTaskStateSegment, selector&0xFFFC);
+            return None;
+        }
+        let address = task_state_segment.base.wrapping_add(offset) as usize;
+        let stack_pointer = self.read_from_linear_address(bus, address, data_width);
+        let stack_selector = self.read_from_linear_address(bus, address+data_width as usize/8, 16) as u16;
+        Some((stack_selector, stack_pointer))
+    }
+
+    fn invoke_protected_mode_interrupt_handler(&mut self, bus: &mut crate::bus::BUS, interrupt: u8, is_software: bool, error_code: Option<u16>) {
+        let gate_error_code = (interrupt as u16)*8+2;
+        let (table_base, table_limit) = self.interrupt_descriptor_table;
+        if interrupt as u32*8+7 > table_limit as u32 {
+            return self.raise_exception(Exception::GeneralProtection, gate_error_code);
+        }
+        let address = table_base.wrapping_add(interrupt as u32*8) as usize;
+        let low = self.read_from_linear_address(bus, address, 32);
+        let high = self.read_from_linear_address(bus, address+4, 32);
+        let gate_type = (high>>8)&0x1F;
+        match gate_type {
+            0x06 | 0x07 | 0x0E | 0x0F => {}, // Interrupt Gate, Trap Gate
+            _ => {
+                if gate_type == 0x05 {
+                    self.pause_with_diagnostic(format!("CPU ({}): Unsupported task gate for interrupt={:02X}", self.cycle_counter, interrupt));
+                }
+                return self.raise_exception(Exception::GeneralProtection, gate_error_code);
+            }
+        }
+        if is_software && (((high>>13)&3) as u8) < self.privilege_level {
+            return self.raise_exception(Exception::GeneralProtection, gate_error_code);
+        }
+        if high&0x8000 == 0 {
+            return self.raise_exception(Exception::SegmentNotPresent, gate_error_code);
+        }
+        let selector = (low>>16) as u16;
+        let (offset, data_width) = if gate_type&0x08 != 0 { ((high&0xFFFF0000)|(low&0xFFFF), 32) } else { (low&0xFFFF, 16) };
+        let descriptor = match self.read_code_segment(bus, selector) {
+            Some(descriptor) => descriptor,
+            None => { return; }
+        };
+        let privilege_level = if descriptor.is_conforming() { self.privilege_level } else { descriptor.privilege_level() };
+        if privilege_level > self.privilege_level {
+            return self.raise_exception(Exception::GeneralProtection, selector&0xFFFC);
+        }
+        let flags = self.get_flags();
+        let code_segment = self.get_register(Operand::CS);
+        let position = self.instruction.position;
+        if privilege_level < self.privilege_level {
+            let (stack_selector, stack_pointer) = match self.read_task_stack(bus, privilege_level) {
+                Some(stack) => stack,
+                None => { return; }
+            };
+            let previous_stack_selector = self.get_register(Operand::SS);
+            let previous_stack_pointer = self.registers[Operand::SP as usize];
+            self.privilege_level = privilege_level;
+            if !self.load_segment_register(bus, Operand::SS, stack_selector) {
+                return;
+            }
+            self.set_stack_pointer(stack_pointer);
+            self.push_onto_stack_sized(bus, data_width, previous_stack_selector as u32);
+            self.push_onto_stack_sized(bus, data_width, previous_stack_pointer);
+        }
+        self.push_onto_stack_sized(bus, data_width, flags as u32);
+        self.push_onto_stack_sized(bus, data_width, code_segment as u32);
+        self.push_onto_stack_sized(bus, data_width, position);
+        if let Some(error_code) = error_code {
+            self.push_onto_stack_sized(bus, data_width, error_code as u32);
+        }
+        if !self.set_code_segment(selector, descriptor, privilege_level, offset) {
+            return;
+        }
+        if gate_type&1 == 0 {
+            self.reset_flag(Flag::Interrupt);
         }
+        self.reset_flag(Flag::Trap);
+        self.flags &= !0x4000; // NT
     }
 
     fn invoke_interrupt_handler(&mut self, bus: &mut crate::bus::BUS, interrupt: u8) {
-        self.push_onto_stack(bus, self.flags);
+        if self.protected_mode() {
+            return self.invoke_protected_mode_interrupt_handler(bus, interrupt, false, None);
+        }
+        self.push_onto_stack(bus, self.get_flags());
         self.reset_flag(Flag::Interrupt);
         self.reset_flag(Flag::Trap);
-        let src = bus.get_memory(self, interrupt as usize*4);
-        let address = bus.read_from_memory(self, src, 32);
+        let address = self.read_from_linear_address(bus, self.interrupt_descriptor_table.0 as usize+interrupt as usize*4, 32);
         self.long_jump(bus, address);
     }
 
     fn software_interrupt(&mut self, bus: &mut crate::bus::BUS, interrupt: u8) {
         if self.interrupt_breakpoints[interrupt as usize] {
             self.execution_state = ExecutionState::DebuggerHandlesInterrupt(interrupt);
+        } else if self.protected_mode() {
+            self.invoke_protected_mode_interrupt_handler(bus, interrupt, true, None);
         } else if !bus.handle_interrupt(self, interrupt) {
             self.invoke_interrupt_handler(bus, interrupt);
         }
     }
 
+    fn save_state(&self) -> ArchitecturalState {
+        ArchitecturalState {
+            registers: self.registers,
+            flags: self.flags,
+            lazy_flags: self.lazy_flags,
+            segment_descriptors: self.segment_descriptors,
+            privilege_level: self.privilege_level
+        }
+    }
+
+    /// Rolls back the faulting instruction and invokes the exception handler,
+    /// escalating to a double fault and finally a shutdown if that faults again.
+    fn handle_exception(&mut self, bus: &mut crate::bus::BUS, state: &ArchitecturalState, position: u32) {
+        let mut previous_exception: Option<Exception> = None;
+        while let Some((mut exception, mut error_code)) = self.pending_exception.take() {
+            self.registers = state.registers;
+            self.flags = state.flags;
+            self.lazy_flags = state.lazy_flags;
+            self.segment_descriptors = state.segment_descriptors;
+            self.privilege_level = state.privilege_level;
+            self.instruction.position = position;
+            match previous_exception {
+                Some(Exception::DoubleFault) => {
+                    self.pause_with_diagnostic(format!("CPU ({}): Triple fault at={:04X}:{:08X}", self.cycle_counter, self.get_register(Operand::CS), position));
+                    return;
+                },
+                Some(_) => {
+                    exception = Exception::DoubleFault;
+                    error_code = 0;
+                },
+                None => {}
+            }
+            previous_exception = Some(exception);
+            if self.protected_mode() {
+                self.invoke_protected_mode_interrupt_handler(bus, exception as u8, false, if exception.has_error_code() { Some(error_code) } else { None });
+            } else {
+                self.software_interrupt(bus, exception as u8);
+            }
+        }
+    }
+
+    /// Counts an execution in the instruction profile and returns how often the entry was seen so far
+    fn count_in_instruction_profile(&mut self, entry: crate::machinecode::Instruction) -> usize {
+        let counter = self.instruction_profile.entry(entry).or_insert(0);
+        *counter += 1;
+        *counter
+    }
+
+    fn execute_translated_block(&mut self, bus: &mut crate::bus::BUS, jit: &mut crate::jit::JustInTimeCompiler) -> bool {
+        if self.get_flag(Flag::Trap) || self.instruction_profile_recording {
+            return false;
+        }
+        let position = self.instruction.position;
+        let address = bus.mask_address(self, self.linear_address(Operand::CS, position));
+        if !bus.is_ram_address(address) {
+            return false;
+        }
+        let lockstep = jit.mode == crate::jit::JitMode::Lockstep;
+        let block = match jit.lookup(address, position, self.code_size(), &bus.ram[address..]) {
+            crate::jit::Lookup::Hit(block) => block,
+            crate::jit::Lookup::Untranslatable => { return false; },
+            crate::jit::Lookup::Miss => {
+                // Block heads are profiled with their position, so they don't mix with the position independent debugger entries
+                let mut head = self.instruction;
+                if !self.instruction_cache.fetch(address, self.code_size(), &mut head) || self.count_in_instruction_profile(head) < crate::jit::HOT_BLOCK_THRESHOLD {
+                    return false;
+                }
+                match jit.translate(address, position, self.code_size(), &bus.ram[address..], self.model, &self.timing_model) {
+                    Some(block) => block,
+                    None => { return false; }
+                }
+            }
+        };
+        if self.cycle_counter+block.cycles >= bus.handler_schedule.next_trigger_cycle ||
+           (self.protected_mode() && block.end_position-1 > self.segment_descriptors[Operand::CS as usize-Operand::ES as usize].limit) {
+            return false;
+        }
+        if lockstep {
+            let mut registers = self.registers;
+            let mut flags = self.get_flags();
+            (block.function)(registers.as_mut_ptr(), &mut flags);
+            let (end_position, cycle_counter) = (block.end_position, self.cycle_counter);
+            for _ in 0..block.instruction_count {
+                let state = self.save_state();
+                self.interpret_instruction(bus, &state, self.instruction.position);
+            }
+            self.cycle_counter = cycle_counter+block.cycles;
+            if registers != self.registers || flags != self.get_flags() || end_position != self.instruction.position {
+                self.pause_with_diagnostic(format!("JIT ({}): Lockstep mismatch in block at={:04X}:{:04X} interpreted={:08X?} flags={:04X} translated={:08X?} flags={:04X}",
+                    self.cycle_counter, self.get_register(Operand::CS), position, self.registers, self.get_flags(), registers, flags));
+            }
+            return true;
+        }
+        self.materialize_flags();
+        (block.function)(self.registers.as_mut_ptr(), &mut self.flags);
+        self.instruction.position = block.end_position;
+        self.cycle_counter += block.cycles;
+        true
+    }
+
     pub fn execute_instruction(&mut self, bus: &mut crate::bus::BUS) {
         bus.tick(self);
+        let mut state = self.save_state();
+        let mut position = self.instruction.position;
+        let mut dispatched_interrupt = false;
         if self.get_flag(Flag::Interrupt) {
             let pending_interrupt = bus.pic.get_interrupt_to_handle();
             if pending_interrupt > 0 {
+                self.cycle_counter += self.timing_model.interrupt_acknowledge_cycles();
                 self.invoke_interrupt_handler(bus, pending_interrupt);
+                dispatched_interrupt = true;
             }
         }
         if self.get_flag(Flag::Trap) {
             self.software_interrupt(bus, 1);
+            dispatched_interrupt = true;
+        }
+        if self.protected_mode() && self.instruction.position > self.segment_descriptors[Operand::CS as usize-Operand::ES as usize].limit {
+            self.raise_exception(Exception::GeneralProtection, 0);
+        }
+        if self.pending_exception.is_some() {
+            return self.handle_exception(bus, &state, position);
+        }
+        if let Some(mut jit) = self.jit.take() {
+            let executed = self.execute_translated_block(bus, &mut jit);
+            self.jit = Some(jit);
+            if executed {
+                return;
+            }
         }
-        let mut read_buffer = unsafe { std::slice::from_raw_parts(bus.get_memory(self, crate::bus::BUS::physical_address(self.get_register(Operand::CS), self.instruction.position)), 8) };
-        if !crate::machinecode::decode_instruction(&mut read_buffer, &mut self.instruction).is_ok() {
-            panic!();
+        if dispatched_interrupt {
+            state = self.save_state();
+            position = self.instruction.position;
+        }
+        self.interpret_instruction(bus, &state, position);
+    }
+
+    /// Decodes and executes the instruction at the current position, without ticking the bus or dispatching interrupts
+    fn interpret_instruction(&mut self, bus: &mut crate::bus::BUS, state: &ArchitecturalState, position: u32) {
+        let code_size = self.code_size();
+        let address = bus.mask_address(self, self.linear_address(Operand::CS, self.instruction.position));
+        if !self.instruction_cache.fetch(address, code_size, &mut self.instruction) {
+            let mut read_buffer = unsafe { std::slice::from_raw_parts(bus.get_memory(self, address), 16) };
+            if !crate::machinecode::decode_instruction(&mut read_buffer, &mut self.instruction, self.model, code_size).is_ok() {
+                self.instruction.position = position;
+                if self.model >= CpuModel::I80286 { // Instruction length limit exceeded
+                    self.raise_exception(Exception::GeneralProtection, 0);
+                    return self.handle_exception(bus, state, position);
+                }
+                return self.pause_with_diagnostic(format!("CPU ({}): Could not decode instruction at={:04X}:{:04X}", self.cycle_counter, self.get_register(Operand::CS), position));
+            }
+            if bus.is_ram_address(address) {
+                self.instruction_cache.insert(address, code_size, &self.instruction);
+            }
         }
         if self.instruction_profile_recording {
             let mut instruction_profile_entry = self.instruction;
@@ -421,82 +1303,145 @@ impl CPU {
             instruction_profile_entry.buffer = unsafe { std::mem::zeroed() };
             instruction_profile_entry.immediate = 0xFFFFFFFF;
             instruction_profile_entry.displacement = 0;
-            match self.instruction_profile.get_mut(&instruction_profile_entry) {
-                Some(counter) => { *counter += 1; },
-                None => { self.instruction_profile.insert(instruction_profile_entry, 1); }
-            }
+            self.count_in_instruction_profile(instruction_profile_entry);
+        }
+        self.instruction.position = self.instruction.position.wrapping_add(self.instruction.length as u32)&lsb_mask(self.code_size() as u32);
+        let count = match self.instruction.opcode {
+            Opcode::ROL | Opcode::ROR | Opcode::RCL | Opcode::RCR | Opcode::SHL | Opcode::SHR | Opcode::SAL | Opcode::SAR => {
+                if self.instruction.second_operand == Operand::CL { self.get_register(Operand::CL) as u64 } else { self.instruction.immediate as u64 }
+            },
+            Opcode::ENTER => (self.instruction.displacement&0x1F) as u64,
+            _ => 0
+        };
+        self.cycle_counter += self.timing_model.instruction_cycles(&self.instruction, count);
+        let fall_through_position = self.instruction.position;
+        self.execute_opcode(bus);
+        if self.instruction.position != fall_through_position {
+            self.cycle_counter += self.timing_model.branch_taken_cycles(self.instruction.opcode);
         }
-        self.instruction.position = self.instruction.position+self.instruction.length as u16;
-        self.cycle_counter += 1;
+        if let Some((exception, _error_code)) = self.pending_exception {
+            let resume_position = if self.model == CpuModel::I8086 || (self.model < CpuModel::I80286 && exception == Exception::DivideError) { self.instruction.position } else { position };
+            self.handle_exception(bus, state, resume_position);
+        }
+    }
+
+    fn execute_opcode(&mut self, bus: &mut crate::bus::BUS) {
         match self.instruction.opcode {
             Opcode::ADD => {
-                binary_arithmetic_operation!(self, bus, true, 0, .wrapping_add);
-                self.set_addition_flags();
+                binary_arithmetic_operation!(self, bus, true, 0, FlagsOperation::Addition, .wrapping_add);
             },
             Opcode::OR => {
-                binary_arithmetic_operation!(self, bus, true, 0, |);
+                binary_arithmetic_operation!(self, bus, true, 0, FlagsOperation::Logic, |);
             },
             Opcode::ADC => {
-                binary_arithmetic_operation!(self, bus, true, self.get_flag(Flag::Carry) as u32, .wrapping_add);
-                self.set_addition_flags();
+                binary_arithmetic_operation!(self, bus, true, self.get_flag(Flag::Carry) as u64, FlagsOperation::Addition, .wrapping_add);
             },
             Opcode::SBB => {
-                binary_arithmetic_operation!(self, bus, true, self.get_flag(Flag::Carry) as u32, .wrapping_sub);
-                self.set_substraction_flags();
+                binary_arithmetic_operation!(self, bus, true, self.get_flag(Flag::Carry) as u64, FlagsOperation::Substraction, .wrapping_sub);
             },
             Opcode::AND => {
-                binary_arithmetic_operation!(self, bus, true, 0, &);
+                binary_arithmetic_operation!(self, bus, true, 0, FlagsOperation::Logic, &);
             },
             Opcode::SUB => {
-                binary_arithmetic_operation!(self, bus, true, 0, .wrapping_sub);
-                self.set_substraction_flags();
+                binary_arithmetic_operation!(self, bus, true, 0, FlagsOperation::Substraction, .wrapping_sub);
             },
             Opcode::XOR => {
-                binary_arithmetic_operation!(self, bus, true, 0, ^);
+                binary_arithmetic_operation!(self, bus, true, 0, FlagsOperation::Logic, ^);
             },
             Opcode::CMP => {
-                binary_arithmetic_operation!(self, bus, false, 0, .wrapping_sub);
-                self.set_substraction_flags();
+                binary_arithmetic_operation!(self, bus, false, 0, FlagsOperation::Substraction, .wrapping_sub);
             },
             Opcode::TEST => {
-                binary_arithmetic_operation!(self, bus, false, 0, &);
+                binary_arithmetic_operation!(self, bus, false, 0, FlagsOperation::Logic, &);
             },
             Opcode::ROL => {
-                bit_shift_operation!(self, bus, u8, u16, rotate_left);
-                let carry = (self.first_value.wrapping_shr(self.instruction.data_width as u32-self.second_value)&1) as u16;
+                bit_shift_operation!(self, bus, u8, u16, u32, rotate_left);
+                let carry = (self.first_value.wrapping_shr((self.instruction.data_width as u32).wrapping_sub(self.second_value as u32))&1) as u16;
                 self.set_flag(Flag::Carry, carry);
-                self.set_flag(Flag::Overflow, ((self.result_value>>(self.instruction.data_width-1)) as u16)^carry);
+                self.set_flag(Flag::Overflow, (((self.result_value>>(self.instruction.data_width-1))&1) as u16)^carry);
             },
             Opcode::ROR => {
-                bit_shift_operation!(self, bus, u8, u16, rotate_right);
-                self.set_flag(Flag::Carry, (self.first_value.wrapping_shr(self.second_value-1)&1) as u16);
-                self.set_flag(Flag::Overflow, ((self.result_value>>(self.instruction.data_width-1))^(self.result_value>>(self.instruction.data_width-2))) as u16);
+                bit_shift_operation!(self, bus, u8, u16, u32, rotate_right);
+                self.set_flag(Flag::Carry, (self.first_value.wrapping_shr((self.second_value as u32).wrapping_sub(1))&1) as u16);
+                self.set_flag(Flag::Overflow, (((self.result_value>>(self.instruction.data_width-1))^(self.result_value>>(self.instruction.data_width-2)))&1) as u16);
             },
             Opcode::RCL => {
-                rotate_with_carry_operation!(self, bus, carry, ((self.result_value&lsb_mask(1+self.instruction.data_width as u32-self.second_value))<<self.second_value)|((self.result_value&msb_mask(31-self.instruction.data_width as u32+self.second_value))>>(1+self.instruction.data_width as u32-self.second_value)));
-                self.set_flag(Flag::Overflow, ((self.result_value>>(self.instruction.data_width-1)) as u16)^carry);
+                rotate_with_carry_operation!(self, bus, carry, (self.result_value<<self.second_value)|(self.result_value>>(self.instruction.data_width as u64+1-self.second_value)));
+                self.set_flag(Flag::Overflow, (((self.result_value>>(self.instruction.data_width-1))&1) as u16)^carry);
             },
             Opcode::RCR => {
-                rotate_with_carry_operation!(self, bus, carry, ((self.result_value&lsb_mask(self.second_value))<<(1+self.instruction.data_width as u32-self.second_value))|((self.result_value&msb_mask(32-self.second_value))>>self.second_value));
-                self.set_flag(Flag::Overflow, ((self.result_value>>(self.instruction.data_width-1))^(self.result_value>>(self.instruction.data_width-2))) as u16);
+                rotate_with_carry_operation!(self, bus, carry, (self.result_value>>self.second_value)|(self.result_value<<(self.instruction.data_width as u64+1-self.second_value)));
+                self.set_flag(Flag::Overflow, (((self.result_value>>(self.instruction.data_width-1))^(self.result_value>>(self.instruction.data_width-2)))&1) as u16);
             },
             Opcode::SHL | Opcode::SAL => {
-                bit_shift_operation!(self, bus, u8, u16, wrapping_shl);
-                let carry = (self.first_value.wrapping_shr(self.instruction.data_width as u32-self.second_value)&1) as u16;
+                bit_shift_operation!(self, bus, u8, u16, u32, wrapping_shl);
+                let carry = (self.first_value.wrapping_shr((self.instruction.data_width as u32).wrapping_sub(self.second_value as u32))&1) as u16;
                 self.set_flag(Flag::Carry, carry);
-                self.set_flag(Flag::Overflow, ((self.result_value>>(self.instruction.data_width-1)) as u16)^carry);
+                self.set_flag(Flag::Overflow, (((self.result_value>>(self.instruction.data_width-1))&1) as u16)^carry);
             },
             Opcode::SHR => {
-                bit_shift_operation!(self, bus, u8, u16, wrapping_shr);
+                bit_shift_operation!(self, bus, u8, u16, u32, wrapping_shr);
                 if self.second_value > 0 {
-                    self.set_flag(Flag::Carry, (self.first_value.wrapping_shr(self.second_value-1)&1) as u16);
+                    self.set_flag(Flag::Carry, (self.first_value.wrapping_shr(self.second_value as u32-1)&1) as u16);
                 }
-                self.set_flag(Flag::Overflow, (self.first_value>>(self.instruction.data_width-1)) as u16);
+                self.set_flag(Flag::Overflow, ((self.first_value>>(self.instruction.data_width-1))&1) as u16);
             },
             Opcode::SAR => {
-                bit_shift_operation!(self, bus, i8, i16, wrapping_shr);
+                bit_shift_operation!(self, bus, i8, i16, i32, wrapping_shr);
                 if self.second_value > 0 {
-                    self.set_flag(Flag::Carry, (self.first_value.wrapping_shr(self.second_value-1)&1) as u16);
+                    self.set_flag(Flag::Carry, (self.first_value.wrapping_shr(self.second_value as u32-1)&1) as u16);
+                }
+            },
+            Opcode::SHLD | Opcode::SHRD => {
+                let (dst, first_value) = self.get_operand(bus, self.instruction.first_operand, self.instruction.data_width);
+                let fill_value = self.get_operand(bus, self.instruction.second_operand, self.instruction.data_width).1 as u64;
+                let count = (self.get_operand(bus, self.instruction.third_operand, 8).1&0x1F) as u64;
+                if count > 0 {
+                    let data_width = self.instruction.data_width as u64;
+                    self.first_value = first_value as u64;
+                    self.second_value = count;
+                    // Shifts through destination:fill:destination, so 16 bit counts above 16 (undefined on hardware) continue with the destination like the 386
+                    let concatenation = ((self.first_value as u128)<<(2*data_width))|((fill_value as u128)<<data_width)|(self.first_value as u128);
+                    let (result_value, carry) = if self.instruction.opcode == Opcode::SHLD {
+                        ((concatenation<<count)>>(2*data_width), concatenation>>(3*data_width-count))
+                    } else {
+                        (concatenation>>count, concatenation>>(count-1))
+                    };
+                    let (result_value, carry) = (result_value as u64, carry as u64);
+                    self.result_value = result_value&(lsb_mask(self.instruction.data_width as u32) as u64);
+                    bus.write_to_memory(self, dst, self.instruction.data_width, self.result_value as u32);
+                    self.set_arithmetic_flags();
+                    self.set_flag(Flag::Carry, (carry&1) as u16);
+                    self.set_flag(Flag::Overflow, (((self.first_value^self.result_value)>>(data_width-1))&1) as u16);
+                }
+            },
+            Opcode::BT | Opcode::BTS | Opcode::BTR | Opcode::BTC => {
+                let data_width = self.instruction.data_width as u32;
+                let bit_offset = self.get_operand(bus, self.instruction.second_operand, self.instruction.data_width).1;
+                if self.instruction.second_operand != Operand::None && self.instruction.first_operand as u8 >= Operand::DisplacementBXSI as u8 {
+                    let word_offset = if data_width == 32 { bit_offset as i32>>5 } else { bit_offset as i16 as i32>>4 };
+                    self.instruction.displacement = self.instruction.displacement.wrapping_add(word_offset*(data_width as i32/8));
+                }
+                let mask = 1u32<<(bit_offset&(data_width-1));
+                let (dst, value) = self.get_operand(bus, self.instruction.first_operand, self.instruction.data_width);
+                self.reset_flag(Flag::Carry);
+                self.set_flag(Flag::Carry, (value&mask != 0) as u16);
+                match self.instruction.opcode {
+                    Opcode::BTS => { bus.write_to_memory(self, dst, self.instruction.data_width, value|mask); },
+                    Opcode::BTR => { bus.write_to_memory(self, dst, self.instruction.data_width, value&!mask); },
+                    Opcode::BTC => { bus.write_to_memory(self, dst, self.instruction.data_width, value^mask); },
+                    _ => {}
+                }
+            },
+            Opcode::BSF | Opcode::BSR => {
+                let value = self.get_operand(bus, self.instruction.second_operand, self.instruction.data_width).1;
+                self.reset_flag(Flag::Zero);
+                if value == 0 {
+                    self.set_flag(Flag::Zero, 1);
+                } else {
+                    let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                    let index = if self.instruction.opcode == Opcode::BSF { value.trailing_zeros() } else { 31-value.leading_zeros() };
+                    bus.write_to_memory(self, dst, self.instruction.data_width, index);
                 }
             },
             Opcode::NOT => {
@@ -505,33 +1450,52 @@ impl CPU {
             },
             Opcode::NEG => {
                 self.first_value = 0;
-                unary_arithmetic_operation!(self, bus, .wrapping_sub);
-                self.set_substraction_flags();
-                let zero = self.get_flag(Flag::Zero);
-                self.reset_flag(Flag::Carry);
-                self.set_flag(Flag::Carry, !zero as u16);
-            },
-            Opcode::INC => {
-                self.first_value = 1;
-                unary_arithmetic_operation!(self, bus, .wrapping_add);
-                self.set_addition_flags();
-            },
-            Opcode::DEC => {
-                self.first_value = u32::max_value();
-                unary_arithmetic_operation!(self, bus, .wrapping_add);
-                self.set_addition_flags();
+                // The borrow of 0-x already sets the carry flag unless x is zero
+                unary_arithmetic_operation!(self, bus, FlagsOperation::Substraction, .wrapping_sub);
+            },
+            Opcode::INC | Opcode::DEC => {
+                let (dst, first_value) = self.get_operand(bus, self.instruction.first_operand, self.instruction.data_width);
+                self.first_value = first_value as u64;
+                self.second_value = 1;
+                let operation = if self.instruction.opcode == Opcode::INC {
+                    self.result_value = self.first_value.wrapping_add(1);
+                    FlagsOperation::Increment
+                } else {
+                    self.result_value = self.first_value.wrapping_sub(1);
+                    FlagsOperation::Decrement
+                };
+                bus.write_to_memory(self, dst, self.instruction.data_width, self.result_value as u32);
+                self.set_lazy_flags(operation);
             },
             Opcode::MUL => {
-                multiplication_operation!(self, bus, u8, u16, u32);
+                multiplication_operation!(self, bus, u8, u16, u32, u64);
             },
             Opcode::IMUL => {
-                multiplication_operation!(self, bus, i8, i16, i32);
+                if self.instruction.second_operand == Operand::None {
+                    multiplication_operation!(self, bus, i8, i16, i32, i64);
+                } else {
+                    let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                    let first_factor = self.get_operand(bus, self.instruction.second_operand, self.instruction.data_width).1;
+                    let second_factor = self.get_operand(bus, self.instruction.third_operand, self.instruction.data_width).1;
+                    let (value, flag) = if self.instruction.data_width == 32 {
+                        let value = (first_factor as i32 as i64)*(second_factor as i32 as i64);
+                        (value as u32, (value as i32 as i64) != value)
+                    } else {
+                        let value = (first_factor as i16 as i32)*(second_factor as i16 as i32);
+                        (value as u32&0xFFFF, (value as i16 as i32) != value)
+                    };
+                    bus.write_to_memory(self, dst, self.instruction.data_width, value);
+                    self.reset_flag(Flag::Carry);
+                    self.reset_flag(Flag::Overflow);
+                    self.set_flag(Flag::Carry, flag as u16);
+                    self.set_flag(Flag::Overflow, flag as u16);
+                }
             },
             Opcode::DIV => {
-                division_operation!(self, bus, u8, u16, u32);
+                division_operation!(self, bus, u8, u16, u32, u64);
             },
             Opcode::IDIV => {
-                division_operation!(self, bus, i8, i16, i32);
+                division_operation!(self, bus, i8, i16, i32, i64);
             },
             Opcode::DAA => {
                 decimal_adjust_operation!(self, wrapping_add);
@@ -547,17 +1511,31 @@ impl CPU {
             },
             Opcode::AAM => {
                 let divisor = self.instruction.immediate as u16;
+                if divisor == 0 {
+                    return self.raise_exception(Exception::DivideError, 0);
+                }
                 self.set_register(Operand::AX, (((self.get_register(Operand::AX)&0xFF)/divisor)<<8)|((self.get_register(Operand::AX)&0xFF)%divisor));
+                let result = self.get_register(Operand::AL);
+                self.reset_flag(Flag::Parity);
+                self.reset_flag(Flag::Zero);
+                self.reset_flag(Flag::Sign);
+                self.set_flag(Flag::Parity, (result.count_ones()&1 == 0) as u16);
+                self.set_flag(Flag::Zero, (result == 0) as u16);
+                self.set_flag(Flag::Sign, result>>7);
             },
             Opcode::AAD => {
                 let factor = self.instruction.immediate as u16;
                 self.set_register(Operand::AX, ((self.get_register(Operand::AX)&0xFF)+(self.get_register(Operand::AX)>>8)*factor)&0xFF);
             },
             Opcode::NOP => {},
-            // Opcode::WAIT => {},
+            Opcode::WAIT => {
+                self.report_fpu_error(bus);
+            },
             // Opcode::LOCK => {},
             Opcode::HLT => {
-                self.execution_state = ExecutionState::WaitForInterrupt;
+                if self.check_supervisor_privilege() {
+                    self.execution_state = ExecutionState::WaitForInterrupt;
+                }
             },
             Opcode::INT => {
                 self.software_interrupt(bus, self.instruction.immediate as u8);
@@ -568,102 +1546,319 @@ impl CPU {
                 }
             },
             Opcode::POP => {
-                let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
-                let value = self.pop_from_stack(bus);
-                bus.write_to_memory(self, dst, 16, value as u32);
+                let value = self.pop_from_stack_sized(bus, self.instruction.data_width);
+                match self.instruction.first_operand {
+                    Operand::ES | Operand::CS | Operand::SS | Operand::DS | Operand::FS | Operand::GS => {
+                        self.load_segment_register(bus, self.instruction.first_operand, value as u16);
+                    },
+                    _ => {
+                        let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                        bus.write_to_memory(self, dst, self.instruction.data_width, value);
+                    }
+                }
             },
             Opcode::POPF => {
-                self.flags = self.pop_from_stack(bus);
+                let value = self.pop_from_stack_sized(bus, self.instruction.data_width) as u16;
+                self.write_flags(value);
             },
             Opcode::PUSH => {
-                let value = self.get_operand(bus, self.instruction.first_operand, 16).1;
-                self.push_onto_stack(bus, value as u16);
+                let value = self.get_operand(bus, self.instruction.first_operand, self.instruction.data_width).1;
+                self.push_onto_stack_sized(bus, self.instruction.data_width, value);
             },
             Opcode::PUSHF => {
-                self.push_onto_stack(bus, self.flags);
+                self.push_onto_stack_sized(bus, self.instruction.data_width, self.get_flags() as u32);
+            },
+            Opcode::PUSHA => {
+                let offset = if self.instruction.data_width == 32 { Operand::EAX as u8 } else { 0 };
+                let sp = self.get_register_32(Operand::from(Operand::SP as u8+offset));
+                for register in [Operand::AX, Operand::CX, Operand::DX, Operand::BX, Operand::SP, Operand::BP, Operand::SI, Operand::DI].iter() {
+                    let value = if *register == Operand::SP { sp } else { self.get_register_32(Operand::from(*register as u8+offset)) };
+                    self.push_onto_stack_sized(bus, self.instruction.data_width, value);
+                }
+            },
+            Opcode::POPA => {
+                let offset = if self.instruction.data_width == 32 { Operand::EAX as u8 } else { 0 };
+                for register in [Operand::DI, Operand::SI, Operand::BP, Operand::SP, Operand::BX, Operand::DX, Operand::CX, Operand::AX].iter() {
+                    let value = self.pop_from_stack_sized(bus, self.instruction.data_width);
+                    if *register != Operand::SP {
+                        self.set_register_32(Operand::from(*register as u8+offset), value);
+                    }
+                }
+            },
+            Opcode::ENTER => { // Pushes with the operand size, addresses the stack frames with the stack size
+                let nesting_level = self.instruction.displacement&0x1F;
+                let data_width = self.instruction.data_width;
+                let stack_mask = lsb_mask(self.stack_size() as u32);
+                self.push_onto_stack_sized(bus, data_width, self.get_register_32(Operand::EBP));
+                let frame_pointer = self.get_stack_pointer();
+                if nesting_level > 0 {
+                    for _ in 1..nesting_level {
+                        let base_pointer = self.get_register_32(Operand::EBP);
+                        let base_pointer = (base_pointer&!stack_mask)|(base_pointer.wrapping_sub(data_width as u32/8)&stack_mask);
+                        self.set_register_32(Operand::EBP, base_pointer);
+                        let src = self.memory_operand(bus, false, Operand::SS, base_pointer&stack_mask);
+                        let value = bus.read_from_memory(self, src, data_width);
+                        self.push_onto_stack_sized(bus, data_width, value);
+                    }
+                    self.push_onto_stack_sized(bus, data_width, frame_pointer);
+                }
+                if data_width == 32 {
+                    self.set_register_32(Operand::EBP, frame_pointer);
+                } else {
+                    self.set_register(Operand::BP, frame_pointer as u16);
+                }
+                self.set_stack_pointer(self.get_stack_pointer().wrapping_sub(self.instruction.immediate));
+            },
+            Opcode::LEAVE => {
+                self.set_stack_pointer(self.registers[Operand::BP as usize]);
+                let value = self.pop_from_stack_sized(bus, self.instruction.data_width);
+                let dst = self.register_or_memory_operand(bus, Operand::BP);
+                bus.write_to_memory(self, dst, self.instruction.data_width, value);
+            },
+            Opcode::BOUND => {
+                let index = self.get_operand(bus, self.instruction.first_operand, self.instruction.data_width).1;
+                let lower_bound_ptr = self.register_or_memory_operand(bus, self.instruction.second_operand);
+                let lower_bound = bus.read_from_memory(self, lower_bound_ptr, self.instruction.data_width);
+                let upper_bound = bus.read_from_memory(self, unsafe { lower_bound_ptr.offset(self.instruction.data_width as isize/8) }, self.instruction.data_width);
+                let (index, lower_bound, upper_bound) = if self.instruction.data_width == 32 {
+                    (index as i32, lower_bound as i32, upper_bound as i32)
+                } else {
+                    (index as i16 as i32, lower_bound as i16 as i32, upper_bound as i16 as i32)
+                };
+                if index < lower_bound || index > upper_bound {
+                    self.raise_exception(Exception::BoundRangeExceeded, 0);
+                }
             },
             Opcode::RET => {
-                self.instruction.position = self.pop_from_stack(bus);
+                self.instruction.position = self.pop_from_stack_sized(bus, self.instruction.operand_size);
                 if self.instruction.immediate != 0xFFFFFFFF {
-                    self.set_register(Operand::SP, self.get_register(Operand::SP).wrapping_add(self.instruction.immediate as u16));
+                    self.set_stack_pointer(self.get_stack_pointer().wrapping_add(self.instruction.immediate));
                 }
             },
             Opcode::RETF => {
-                self.instruction.position = self.pop_from_stack(bus);
-                let cs = self.pop_from_stack(bus);
+                if self.protected_mode() {
+                    return self.protected_mode_far_return(bus, false);
+                }
+                self.instruction.position = self.pop_from_stack_sized(bus, self.instruction.operand_size);
+                let cs = self.pop_from_stack_sized(bus, self.instruction.operand_size) as u16;
                 self.set_register(Operand::CS, cs);
                 if self.instruction.immediate != 0xFFFFFFFF {
-                    self.set_register(Operand::SP, self.get_register(Operand::SP).wrapping_add(self.instruction.immediate as u16));
+                    self.set_stack_pointer(self.get_stack_pointer().wrapping_add(self.instruction.immediate));
                 }
             },
             Opcode::IRET => {
-                self.instruction.position = self.pop_from_stack(bus);
-                let cs = self.pop_from_stack(bus);
+                if self.protected_mode() {
+                    return self.protected_mode_far_return(bus, true);
+                }
+                self.instruction.position = self.pop_from_stack_sized(bus, self.instruction.operand_size);
+                let cs = self.pop_from_stack_sized(bus, self.instruction.operand_size) as u16;
                 self.set_register(Operand::CS, cs);
-                self.flags = self.pop_from_stack(bus);
-            },
-            Opcode::LCALL => {
-                let address = self.get_operand(bus, self.instruction.first_operand, 32).1;
-                self.long_jump(bus, address);
+                let flags = self.pop_from_stack_sized(bus, self.instruction.operand_size) as u16;
+                self.set_flags(flags);
+            },
+            Opcode::LCALL | Opcode::LJMP => {
+                let (selector, offset) = if self.instruction.first_operand == Operand::None {
+                    if self.instruction.data_width == 48 {
+                        (self.instruction.displacement as u16, self.instruction.immediate)
+                    } else {
+                        ((self.instruction.immediate>>16) as u16, self.instruction.immediate&0xFFFF)
+                    }
+                } else {
+                    let src = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                    let offset = bus.read_from_memory(self, src, self.instruction.operand_size);
+                    (bus.read_from_memory(self, unsafe { src.offset(self.instruction.operand_size as isize/8) }, 16) as u16, offset)
+                };
+                if self.protected_mode() {
+                    self.protected_mode_far_transfer(bus, selector, offset, self.instruction.opcode == Opcode::LCALL);
+                } else if self.instruction.opcode == Opcode::LCALL {
+                    self.long_jump(bus, ((selector as u32)<<16)|(offset&0xFFFF));
+                } else {
+                    self.set_register(Operand::CS, selector);
+                    self.instruction.position = offset;
+                }
             },
             Opcode::CALL => {
-                self.push_onto_stack(bus, self.instruction.position as u16);
-                self.instruction.position = self.get_operand(bus, self.instruction.first_operand, 16).1 as u16;
-            },
-            Opcode::LJMP => {
-                let address = self.get_operand(bus, self.instruction.first_operand, 32).1;
-                self.set_register(Operand::CS, (address>>16) as u16);
-                self.instruction.position = address as u16;
+                self.push_onto_stack_sized(bus, self.instruction.operand_size, self.instruction.position);
+                self.instruction.position = self.get_operand(bus, self.instruction.first_operand, self.instruction.operand_size).1;
             },
             Opcode::JMP => {
-                self.instruction.position = self.get_operand(bus, self.instruction.first_operand, 16).1 as u16;
+                self.instruction.position = self.get_operand(bus, self.instruction.first_operand, self.instruction.operand_size).1;
             },
             Opcode::JO | Opcode::JNO | Opcode::JB | Opcode::JNB | Opcode::JE | Opcode::JNE | Opcode::JBE | Opcode::JNBE | Opcode::JS | Opcode::JNS | Opcode::JP | Opcode::JNP | Opcode::JL | Opcode::JNL | Opcode::JLE | Opcode::JNLE | Opcode::JCXZ => {
                 let condition = match self.instruction.opcode {
-                    Opcode::JO => self.get_flag(Flag::Overflow),
-                    Opcode::JNO => !self.get_flag(Flag::Overflow),
-                    Opcode::JB => self.get_flag(Flag::Carry),
-                    Opcode::JNB => !self.get_flag(Flag::Carry),
-                    Opcode::JE => self.get_flag(Flag::Zero),
-                    Opcode::JNE => !self.get_flag(Flag::Zero),
-                    Opcode::JBE => self.get_flag(Flag::Carry) || self.get_flag(Flag::Zero),
-                    Opcode::JNBE => !(self.get_flag(Flag::Carry) || self.get_flag(Flag::Zero)),
-                    Opcode::JS => self.get_flag(Flag::Sign),
-                    Opcode::JNS => !self.get_flag(Flag::Sign),
-                    Opcode::JP => self.get_flag(Flag::Parity),
-                    Opcode::JNP => !self.get_flag(Flag::Parity),
-                    Opcode::JL => self.get_flag(Flag::Sign) != self.get_flag(Flag::Overflow),
-                    Opcode::JNL => self.get_flag(Flag::Sign) == self.get_flag(Flag::Overflow),
-                    Opcode::JLE => self.get_flag(Flag::Sign) != self.get_flag(Flag::Overflow) || self.get_flag(Flag::Zero),
-                    Opcode::JNLE => self.get_flag(Flag::Sign) == self.get_flag(Flag::Overflow) && !self.get_flag(Flag::Zero),
-                    Opcode::JCXZ => self.get_register(Operand::CX) == 0,
-                    _ => unreachable!()
+                    Opcode::JCXZ => self.get_address_register(Operand::CX) == 0,
+                    _ => self.evaluate_condition(self.instruction.opcode as u8-Opcode::JO as u8)
                 };
                 if condition {
-                    self.instruction.position = self.instruction.immediate as u16;
+                    self.instruction.position = self.instruction.immediate&lsb_mask(self.instruction.operand_size as u32);
                 }
             },
+            Opcode::SETO | Opcode::SETNO | Opcode::SETB | Opcode::SETNB | Opcode::SETE | Opcode::SETNE | Opcode::SETBE | Opcode::SETNBE | Opcode::SETS | Opcode::SETNS | Opcode::SETP | Opcode::SETNP | Opcode::SETL | Opcode::SETNL | Opcode::SETLE | Opcode::SETNLE => {
+                let condition = crate::machinecode::SETCC_OPCODES.iter().position(|opcode| *opcode == self.instruction.opcode).unwrap();
+                let value = self.evaluate_condition(condition as u8) as u32;
+                let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                bus.write_to_memory(self, dst, 8, value);
+            },
             Opcode::LOOPNZ | Opcode::LOOPZ | Opcode::LOOP => {
-                self.set_register(Operand::CX, self.get_register(Operand::CX).wrapping_sub(1));
+                self.set_address_register(Operand::CX, self.get_address_register(Operand::CX).wrapping_sub(1));
                 if match self.instruction.opcode {
                     Opcode::LOOPNZ => !self.get_flag(Flag::Zero),
                     Opcode::LOOPZ => self.get_flag(Flag::Zero),
                     Opcode::LOOP => true,
                     _ => unreachable!()
-                } && self.get_register(Operand::CX) > 0 {
-                    self.instruction.position = self.instruction.immediate as u16;
+                } && self.get_address_register(Operand::CX) > 0 {
+                    self.instruction.position = self.instruction.immediate&lsb_mask(self.instruction.operand_size as u32);
                 }
             },
-            Opcode::LES | Opcode::LDS => {
+            Opcode::LES | Opcode::LDS | Opcode::LSS | Opcode::LFS | Opcode::LGS => {
                 let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
-                let value = self.get_operand(bus, self.instruction.second_operand, 32).1;
-                self.set_register(if self.instruction.opcode == Opcode::LES { Operand::ES } else { Operand::DS }, (value>>16) as u16);
-                bus.write_to_memory(self, dst, 16, value&0xFFFF);
+                let src = self.register_or_memory_operand(bus, self.instruction.second_operand);
+                let offset = bus.read_from_memory(self, src, self.instruction.data_width);
+                let segment = bus.read_from_memory(self, unsafe { src.offset(self.instruction.data_width as isize/8) }, 16);
+                if self.load_segment_register(bus, match self.instruction.opcode {
+                    Opcode::LES => Operand::ES,
+                    Opcode::LDS => Operand::DS,
+                    Opcode::LSS => Operand::SS,
+                    Opcode::LFS => Operand::FS,
+                    Opcode::LGS => Operand::GS,
+                    _ => unreachable!()
+                }, segment as u16) {
+                    bus.write_to_memory(self, dst, self.instruction.data_width, offset);
+                }
             },
             Opcode::LEA => {
                 let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
                 let (_segment_register, offset) = self.calculate_displacement(self.instruction.second_operand);
-                bus.write_to_memory(self, dst, 16, offset as u32);
+                bus.write_to_memory(self, dst, self.instruction.data_width, offset);
+            },
+            Opcode::MOVZX | Opcode::MOVSX | Opcode::MOVZXW | Opcode::MOVSXW => {
+                let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                let value = match self.instruction.opcode {
+                    Opcode::MOVZX => self.get_operand(bus, self.instruction.second_operand, 8).1,
+                    Opcode::MOVSX => self.get_operand(bus, self.instruction.second_operand, 8).1 as i8 as u32,
+                    Opcode::MOVZXW => self.get_operand(bus, self.instruction.second_operand, 16).1,
+                    Opcode::MOVSXW => self.get_operand(bus, self.instruction.second_operand, 16).1 as i16 as u32,
+                    _ => unreachable!()
+                };
+                bus.write_to_memory(self, dst, self.instruction.data_width, value);
+            },
+            Opcode::SGDT | Opcode::SIDT => {
+                let (base, limit) = if self.instruction.opcode == Opcode::SGDT { self.global_descriptor_table } else { self.interrupt_descriptor_table };
+                let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                bus.write_to_memory(self, dst, 16, limit as u32);
+                bus.write_to_memory(self, unsafe { dst.offset(2) }, 32, if self.model < CpuModel::I80386 {
+                    base|0xFF000000
+                } else if self.instruction.operand_size == 16 {
+                    base&0xFFFFFF
+                } else {
+                    base
+                });
+            },
+            Opcode::LGDT | Opcode::LIDT => {
+                if !self.check_supervisor_privilege() {
+                    return;
+                }
+                let src = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                let limit = bus.read_from_memory(self, src, 16) as u16;
+                let mut base = bus.read_from_memory(self, unsafe { src.offset(2) }, 32);
+                if self.instruction.operand_size == 16 {
+                    base &= 0xFFFFFF;
+                }
+                if self.instruction.opcode == Opcode::LGDT {
+                    self.global_descriptor_table = (base, limit);
+                } else {
+                    self.interrupt_descriptor_table = (base, limit);
+                }
+            },
+            Opcode::SMSW => {
+                let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                bus.write_to_memory(self, dst, 16, self.control_registers[0]);
+            },
+            Opcode::LMSW => {
+                let value = self.get_operand(bus, self.instruction.first_operand, 16).1;
+                if self.check_supervisor_privilege() {
+                    self.control_registers[0] = (self.control_registers[0]&!0xE)|(value&0xF); // PE can not be cleared
+                }
+            },
+            Opcode::CLTS => {
+                if self.check_supervisor_privilege() {
+                    self.control_registers[0] &= !0x8;
+                }
+            },
+            Opcode::SLDT | Opcode::STR | Opcode::LLDT | Opcode::LTR | Opcode::VERR | Opcode::VERW | Opcode::LAR | Opcode::LSL | Opcode::ARPL if !self.protected_mode() => {
+                self.raise_exception(Exception::InvalidOpcode, 0);
+            },
+            Opcode::SLDT | Opcode::STR => {
+                let value = if self.instruction.opcode == Opcode::SLDT { self.local_descriptor_table.0 } else { self.task_register.0 };
+                let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                bus.write_to_memory(self, dst, 16, value as u32);
+            },
+            Opcode::LLDT | Opcode::LTR => {
+                let selector = self.get_operand(bus, self.instruction.first_operand, 16).1 as u16;
+                if !self.check_supervisor_privilege() {
+                    return;
+                }
+                if self.instruction.opcode == Opcode::LLDT && selector&0xFFFC == 0 {
+                    self.local_descriptor_table = (selector, SegmentDescriptor::new(0));
+                    return;
+                }
+                if selector&4 != 0 {
+                    return self.raise_exception(Exception::GeneralProtection, selector&0xFFFC);
+                }
+                let (address, mut descriptor) = match self.read_descriptor(bus, selector) {
+                    Some(result) => result,
+                    None => { return; }
+                };
+                let is_valid = descriptor.is_system() && match self.instruction.opcode {
+                    Opcode::LLDT => descriptor.access&0x0F == 0x02, // LDT
+                    _ => descriptor.access&0x0F == 0x01 || descriptor.access&0x0F == 0x09 // Available TSS
+                };
+                if !is_valid {
+                    return self.raise_exception(Exception::GeneralProtection, selector&0xFFFC);
+                }
+                if !descriptor.is_present() {
+                    return self.raise_exception(Exception::SegmentNotPresent, selector&0xFFFC);
+                }
+                if self.instruction.opcode == Opcode::LLDT {
+                    self.local_descriptor_table = (selector, descriptor);
+                } else {
+                    descriptor.access |= 0x02; // Busy
+                    self.write_to_linear_address(bus, address+5, 8, descriptor.access as u32);
+                    self.task_register = (selector, descriptor);
+                }
+            },
+            Opcode::VERR | Opcode::VERW | Opcode::LAR | Opcode::LSL => {
+                let selector = self.get_operand(bus, if self.instruction.second_operand == Operand::None { self.instruction.first_operand } else { self.instruction.second_operand }, 16).1 as u16;
+                let descriptor = if selector&0xFFFC == 0 { None } else { self.lookup_descriptor(bus, selector) };
+                let is_valid = match descriptor {
+                    Some((_address, descriptor)) => {
+                        let is_accessible = descriptor.is_conforming() || descriptor.privilege_level() >= self.privilege_level.max((selector&3) as u8);
+                        is_accessible && match self.instruction.opcode {
+                            Opcode::VERR => descriptor.is_readable(),
+                            Opcode::VERW => descriptor.is_writable(),
+                            Opcode::LAR => !descriptor.is_system() || [0x1, 0x2, 0x3, 0x4, 0x5, 0x9, 0xB, 0xC].contains(&(descriptor.access&0x0F)),
+                            _ => !descriptor.is_system() || [0x1, 0x2, 0x3, 0x9, 0xB].contains(&(descriptor.access&0x0F))
+                        }
+                    },
+                    None => false
+                };
+                self.reset_flag(Flag::Zero);
+                self.set_flag(Flag::Zero, is_valid as u16);
+                if is_valid && (self.instruction.opcode == Opcode::LAR || self.instruction.opcode == Opcode::LSL) {
+                    let descriptor = descriptor.unwrap().1;
+                    let value = if self.instruction.opcode == Opcode::LAR { ((descriptor.flags as u32)<<16)|((descriptor.access as u32)<<8) } else { descriptor.limit };
+                    let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                    bus.write_to_memory(self, dst, self.instruction.data_width, value);
+                }
+            },
+            Opcode::ARPL => {
+                let (dst, value) = self.get_operand(bus, self.instruction.first_operand, 16);
+                let requested_privilege_level = self.get_operand(bus, self.instruction.second_operand, 16).1&3;
+                self.reset_flag(Flag::Zero);
+                if value&3 < requested_privilege_level {
+                    bus.write_to_memory(self, dst, 16, (value&!3)|requested_privilege_level);
+                    self.set_flag(Flag::Zero, 1);
+                }
             },
             Opcode::CLC => {
                 self.reset_flag(Flag::Carry);
@@ -672,10 +1867,14 @@ impl CPU {
                 self.set_flag(Flag::Carry, 1);
             },
             Opcode::CLI => {
-                self.reset_flag(Flag::Interrupt);
+                if self.check_io_privilege() {
+                    self.reset_flag(Flag::Interrupt);
+                }
             },
             Opcode::STI => {
-                self.set_flag(Flag::Interrupt, 1);
+                if self.check_io_privilege() {
+                    self.set_flag(Flag::Interrupt, 1);
+                }
             },
             Opcode::CLD => {
                 self.reset_flag(Flag::Direction);
@@ -689,24 +1888,53 @@ impl CPU {
                 self.set_flag(Flag::Carry, carry as u16);
             },
             Opcode::CBW => {
-                let value = self.get_register(Operand::AL);
-                self.set_register(Operand::AX, value as i8 as i16 as u16);
+                if self.instruction.operand_size == 32 {
+                    let value = self.get_register(Operand::AX);
+                    self.set_register_32(Operand::EAX, value as i16 as i32 as u32);
+                } else {
+                    let value = self.get_register(Operand::AL);
+                    self.set_register(Operand::AX, value as i8 as i16 as u16);
+                }
             },
             Opcode::CWD => {
-                let value = self.get_register(Operand::AX);
-                self.set_register(Operand::DX, ((value as i16 as i32)>>16) as u16);
+                if self.instruction.operand_size == 32 {
+                    let value = self.get_register_32(Operand::EAX);
+                    self.set_register_32(Operand::EDX, ((value as i32 as i64)>>32) as u32);
+                } else {
+                    let value = self.get_register(Operand::AX);
+                    self.set_register(Operand::DX, ((value as i16 as i32)>>16) as u16);
+                }
             },
             Opcode::SAHF => {
                 let value = self.get_register(Operand::AH) as u8;
+                self.materialize_flags();
                 crate::bit_utils::write_low_byte_of_u16(&mut self.flags, value);
             },
             Opcode::LAHF => {
-                self.set_register(Operand::AH, self.flags&0xFF);
+                self.set_register(Operand::AH, self.get_flags()&0xFF);
             },
             Opcode::MOV => {
-                let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
                 let value = self.get_operand(bus, self.instruction.second_operand, self.instruction.data_width).1;
-                bus.write_to_memory(self, dst, self.instruction.data_width, value);
+                match self.instruction.first_operand {
+                    Operand::ES | Operand::CS | Operand::SS | Operand::DS | Operand::FS | Operand::GS => {
+                        self.load_segment_register(bus, self.instruction.first_operand, value as u16);
+                    },
+                    Operand::CR0 | Operand::CR2 | Operand::CR3 => {
+                        if self.check_supervisor_privilege() {
+                            if self.instruction.first_operand == Operand::CR0 && value&0x80000000 != 0 {
+                                self.pause_with_diagnostic(format!("CPU ({}): Paging is not supported", self.cycle_counter));
+                            }
+                            self.control_registers[self.instruction.first_operand as usize-Operand::CR0 as usize] = value;
+                        }
+                    },
+                    _ => {
+                        if self.instruction.second_operand as u8 >= Operand::CR0 as u8 && self.instruction.second_operand as u8 <= Operand::CR3 as u8 && !self.check_supervisor_privilege() {
+                            return;
+                        }
+                        let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
+                        bus.write_to_memory(self, dst, self.instruction.data_width, value);
+                    }
+                }
             },
             Opcode::XCHG => {
                 let (ptr_a, value_a) = self.get_operand(bus, self.instruction.first_operand, self.instruction.data_width);
@@ -714,105 +1942,156 @@ impl CPU {
                 bus.write_to_memory(self, ptr_a, self.instruction.data_width, value_b);
                 bus.write_to_memory(self, ptr_b, self.instruction.data_width, value_a);
             },
+            Opcode::SALC => {
+                self.set_register(Operand::AL, if self.get_flag(Flag::Carry) { 0xFF } else { 0x00 });
+            },
             Opcode::XLAT => {
-                let src = self.memory_operand(bus, true, Operand::DS, self.get_register(Operand::AL));
+                let src = self.memory_operand(bus, true, Operand::DS, self.get_address_register(Operand::BX).wrapping_add(self.get_register(Operand::AL) as u32));
                 let value = bus.read_from_memory(self, src, 8);
                 self.set_register(Operand::AL, value as u16);
             },
             Opcode::IN => {
+                if !self.check_io_privilege() {
+                    return;
+                }
                 let src_offset = self.get_operand(bus, self.instruction.second_operand, 16).1 as u16;
                 let dst = self.register_or_memory_operand(bus, self.instruction.first_operand);
-                let value = if self.instruction.data_width == 16 {
-                    ((bus.read_from_port(self, src_offset+1) as u32)<<8)|(bus.read_from_port(self, src_offset) as u32)
-                } else {
-                    bus.read_from_port(self, src_offset) as u32
-                };
+                let mut value: u32 = 0;
+                for index in 0..self.instruction.data_width as u16/8 {
+                    value |= (bus.read_from_port(self, src_offset.wrapping_add(index)) as u32)<<(index*8);
+                }
                 bus.write_to_memory(self, dst, self.instruction.data_width, value);
             },
             Opcode::OUT => {
+                if !self.check_io_privilege() {
+                    return;
+                }
                 let dst_offset = self.get_operand(bus, self.instruction.first_operand, 16).1 as u16;
                 let value = self.get_operand(bus, self.instruction.second_operand, self.instruction.data_width).1;
-                if self.instruction.data_width == 16 {
-                    bus.write_to_port(self, dst_offset, value as u8);
-                    bus.write_to_port(self, dst_offset+1, (value>>8) as u8);
-                } else {
-                    bus.write_to_port(self, dst_offset, value as u8);
+                for index in 0..self.instruction.data_width as u16/8 {
+                    bus.write_to_port(self, dst_offset.wrapping_add(index), (value>>(index*8)) as u8);
                 }
             },
-            Opcode::MOVSB => {
-                string_operation!(self, bus, dst, src, u8, 1, 1, {
-                    self.second_value = bus.read_from_memory(self, src, 8);
-                    bus.write_to_memory(self, dst, self.instruction.data_width, self.second_value);
+            Opcode::MOVSB | Opcode::MOVSW => {
+                let step = self.instruction.data_width as u32/8;
+                string_operation!(self, bus, dst, src, u8, step, step, {
+                    self.second_value = bus.read_from_memory(self, src, self.instruction.data_width) as u64;
+                    bus.write_to_memory(self, dst, self.instruction.data_width, self.second_value as u32);
                 });
             },
-            Opcode::MOVSW => {
-                string_operation!(self, bus, dst, src, u16, 2, 2, {
-                    self.second_value = bus.read_from_memory(self, src, 16);
-                    bus.write_to_memory(self, dst, self.instruction.data_width, self.second_value);
-                });
-            },
-            Opcode::CMPSB => {
-                string_operation!(self, bus, dst, src, u8, 1, 1, {
-                    self.first_value = bus.read_from_memory(self, dst, 8);
-                    self.second_value = bus.read_from_memory(self, src, 8);
+            Opcode::CMPSB | Opcode::CMPSW => {
+                let step = self.instruction.data_width as u32/8;
+                string_operation!(self, bus, dst, src, u8, step, step, {
+                    self.first_value = bus.read_from_memory(self, dst, self.instruction.data_width) as u64;
+                    self.second_value = bus.read_from_memory(self, src, self.instruction.data_width) as u64;
                     self.result_value = self.first_value.wrapping_sub(self.second_value);
-                    self.set_arithmetic_flags();
-                    self.set_substraction_flags();
-                });
-            },
-            Opcode::CMPSW => {
-                string_operation!(self, bus, dst, src, u16, 2, 2, {
-                    self.first_value = bus.read_from_memory(self, dst, 16);
-                    self.second_value = bus.read_from_memory(self, src, 16);
-                    self.result_value = self.first_value.wrapping_sub(self.second_value);
-                    self.set_arithmetic_flags();
-                    self.set_substraction_flags();
+                    self.set_lazy_flags(FlagsOperation::Substraction);
                 });
             },
-            Opcode::STOSB => {
-                string_operation!(self, bus, dst, _src, u8, 1, 0,
-                    bus.write_to_memory(self, dst, self.instruction.data_width, self.get_register(self.instruction.second_operand) as u32)
+            Opcode::STOSB | Opcode::STOSW => {
+                let step = self.instruction.data_width as u32/8;
+                string_operation!(self, bus, dst, _src, u8, step, 0,
+                    bus.write_to_memory(self, dst, self.instruction.data_width, self.get_register_32(self.instruction.second_operand))
                 );
             },
-            Opcode::STOSW => {
-                string_operation!(self, bus, dst, _src, u16, 2, 0,
-                    bus.write_to_memory(self, dst, self.instruction.data_width, self.get_register(self.instruction.second_operand) as u32)
-                );
-            },
-            Opcode::LODSB => {
-                string_operation!(self, bus, _dst, src, u8, 0, 1, {
-                    self.second_value = bus.read_from_memory(self, src, self.instruction.data_width);
-                    self.set_register(self.instruction.first_operand, self.second_value as u16);
+            Opcode::LODSB | Opcode::LODSW => {
+                let step = self.instruction.data_width as u32/8;
+                string_operation!(self, bus, _dst, src, u8, 0, step, {
+                    self.second_value = bus.read_from_memory(self, src, self.instruction.data_width) as u64;
+                    self.set_register_32(self.instruction.first_operand, self.second_value as u32);
                 });
             },
-            Opcode::LODSW => {
-                string_operation!(self, bus, _dst, src, u16, 0, 2, {
-                    self.second_value = bus.read_from_memory(self, src, self.instruction.data_width);
-                    self.set_register(self.instruction.first_operand, self.second_value as u16);
+            Opcode::INSB | Opcode::INSW => {
+                if !self.check_io_privilege() {
+                    return;
+                }
+                let step = self.instruction.data_width as u32/8;
+                string_operation!(self, bus, dst, _src, u8, step, 0, {
+                    let port = self.get_register(Operand::DX);
+                    let mut value: u32 = 0;
+                    for index in 0..step as u16 {
+                        value |= (bus.read_from_port(self, port.wrapping_add(index)) as u32)<<(index*8);
+                    }
+                    bus.write_to_memory(self, dst, self.instruction.data_width, value);
                 });
             },
-            Opcode::SCASB => {
-                string_operation!(self, bus, dst, _src, u8, 1, 0, {
-                    self.first_value = self.get_register(self.instruction.second_operand) as u32;
-                    self.second_value = bus.read_from_memory(self, dst, self.instruction.data_width);
-                    self.result_value = self.first_value.wrapping_sub(self.second_value);
-                    self.set_arithmetic_flags();
-                    self.set_substraction_flags();
+            Opcode::OUTSB | Opcode::OUTSW => {
+                if !self.check_io_privilege() {
+                    return;
+                }
+                let step = self.instruction.data_width as u32/8;
+                string_operation!(self, bus, _dst, src, u8, 0, step, {
+                    let port = self.get_register(Operand::DX);
+                    let value = bus.read_from_memory(self, src, self.instruction.data_width);
+                    for index in 0..step as u16 {
+                        bus.write_to_port(self, port.wrapping_add(index), (value>>(index*8)) as u8);
+                    }
                 });
             },
-            Opcode::SCASW => {
-                string_operation!(self, bus, dst, _src, u16, 2, 0, {
-                    self.first_value = self.get_register(self.instruction.second_operand) as u32;
-                    self.second_value = bus.read_from_memory(self, dst, self.instruction.data_width);
+            Opcode::SCASB | Opcode::SCASW => {
+                let step = self.instruction.data_width as u32/8;
+                string_operation!(self, bus, dst, _src, u8, step, 0, {
+                    self.first_value = self.get_register_32(self.instruction.second_operand) as u64;
+                    self.second_value = bus.read_from_memory(self, dst, self.instruction.data_width) as u64;
                     self.result_value = self.first_value.wrapping_sub(self.second_value);
-                    self.set_arithmetic_flags();
-                    self.set_substraction_flags();
+                    self.set_lazy_flags(FlagsOperation::Substraction);
                 });
             },
+            Opcode::ESC => {
+                if !bus.config.cpu.fpu {
+                    return;
+                }
+                if crate::fpu::FloatingPointUnit::is_waiting(self.instruction.fpu_opcode) && self.report_fpu_error(bus) {
+                    return;
+                }
+                let length = match self.instruction.fpu_opcode {
+                    FpuOpcode::FLDENV | FpuOpcode::FNSTENV => self.instruction.operand_size as usize/8*7,
+                    FpuOpcode::FRSTOR | FpuOpcode::FNSAVE => self.instruction.operand_size as usize/8*7+80,
+                    _ if self.instruction.first_operand as u8 >= Operand::DisplacementBXSI as u8 => self.instruction.data_width as usize/8,
+                    _ => 0
+                };
+                let (operand, operand_address) = if length > 0 {
+                    let (mut segment_register, offset) = self.calculate_displacement(self.instruction.first_operand);
+                    if self.instruction.segment_override != Operand::None {
+                        segment_register = self.instruction.segment_override;
+                    }
+                    (self.register_or_memory_operand(bus, self.instruction.first_operand), self.linear_address(segment_register, offset) as u32)
+                } else {
+                    (std::ptr::null_mut(), 0)
+                };
+                if self.pending_exception.is_some() {
+                    return;
+                }
+                let mut buffer: [u8; 108] = [0; 108];
+                for index in 0..length {
+                    buffer[index] = bus.read_from_memory(self, unsafe { operand.offset(index as isize) }, 8) as u8;
+                }
+                let escape_index = self.instruction.buffer.iter().position(|byte| byte&0xF8 == 0xD8).unwrap();
+                let opcode = (((self.instruction.buffer[escape_index]&7) as u16)<<8)|(self.instruction.buffer[escape_index+1] as u16);
+                let was_error_pending = bus.fpu.is_error_pending();
+                if bus.fpu.execute_instruction(self, opcode, operand_address, &mut buffer[0..length]) {
+                    for index in 0..length {
+                        bus.write_to_memory(self, unsafe { operand.offset(index as isize) }, 8, buffer[index] as u32);
+                    }
+                }
+                if !was_error_pending && bus.fpu.is_error_pending() {
+                    if self.model < CpuModel::I80286 { // The 8087 INT output drives the NMI on the PC/XT
+                        self.invoke_interrupt_handler(bus, 2);
+                    } else if !self.protected_mode() { // The 80287 ERROR output is wired to IRQ13 on the AT
+                        bus.pic.request_interrupt(self, 13);
+                    }
+                }
+            },
             Opcode::BAD => {
-                let read_buffer = unsafe { std::slice::from_raw_parts(bus.get_memory(self, crate::bus::BUS::physical_address(self.get_register(Operand::CS), self.instruction.position-self.instruction.length as u16)), 8) };
-                println!("CPU ({}): Encountered BAD Instruction at={:04X}:{:04X} machinecode={:?}", self.cycle_counter, self.get_register(Operand::CS), self.instruction.position-self.instruction.length as u16, read_buffer);
-                self.software_interrupt(bus, 6);
+                let position = self.instruction.position.wrapping_sub(self.instruction.length as u32);
+                let read_buffer = unsafe { std::slice::from_raw_parts(bus.get_memory(self, self.linear_address(Operand::CS, position)), self.instruction.length as usize) };
+                let diagnostic = format!("CPU ({}): Encountered BAD Instruction at={:04X}:{:04X} machinecode={:?}", self.cycle_counter, self.get_register(Operand::CS), position, read_buffer);
+                if self.model >= CpuModel::I80186 {
+                    println!("{}", diagnostic);
+                    self.raise_exception(Exception::InvalidOpcode, 0);
+                } else { // The 8086 has no invalid opcode exception
+                    self.pause_with_diagnostic(diagnostic);
+                }
             },
             _ => unreachable!()
         }
@@ -49,11 +49,12 @@ impl Debugger {
             crate::cpu::ExecutionState::Running => { cpu.execution_state = crate::cpu::ExecutionState::Paused; },
             crate::cpu::ExecutionState::DebuggerHandlesInterrupt(interrupt) => {
                 if interrupt == 3 {
-                    cpu.instruction.position = cpu.instruction.position.wrapping_sub(cpu.instruction.length as u16);
-                    self.remove_break_point_at(cpu, bus, cpu.get_register(Operand::CS), cpu.instruction.position);
+                    cpu.instruction.position = cpu.instruction.position.wrapping_sub(cpu.instruction.length as u32);
+                    self.remove_break_point_at(cpu, bus, cpu.get_register(Operand::CS), cpu.instruction.position as u16);
                 }
                 cpu.execution_state = crate::cpu::ExecutionState::Paused;
             },
+            crate::cpu::ExecutionState::DebuggerHandlesDiagnostic => { cpu.execution_state = crate::cpu::ExecutionState::Paused; },
             _ => {}
         }
         print!("{}{}{}", termion::cursor::Goto(1, 1), termion::clear::All, termion::style::Invert);
@@ -70,22 +71,25 @@ impl Debugger {
             return;
         }
         cpu.execution_state = crate::cpu::ExecutionState::Running;
+        cpu.diagnostic = None;
         print!("{}{}", termion::cursor::Goto(1, 1), termion::clear::All);
         std::io::stdout().flush().unwrap();
     }
 
     pub fn render(&mut self, cpu: &mut crate::cpu::CPU, bus: &mut crate::bus::BUS) {
-        println!("{}EAX={:08X}  ESI={:08X}  DS={:04X}   ES={:04X}                       SS={:04X}",
+        println!("{}EAX={:08X}  ESI={:08X}  DS={:04X}   ES={:04X}   FS={:04X}   GS={:04X}   SS={:04X}",
             termion::cursor::Goto(1, 2),
-            cpu.get_register(Operand::AX),
-            cpu.get_register(Operand::SI),
+            cpu.get_register_32(Operand::EAX),
+            cpu.get_register_32(Operand::ESI),
             cpu.get_register(Operand::DS),
             cpu.get_register(Operand::ES),
+            cpu.get_register(Operand::FS),
+            cpu.get_register(Operand::GS),
             cpu.get_register(Operand::SS)
         );
         println!("EBX={:08X}  EDI={:08X}  CS={:04X}   EIP={:08X}  C{} Z{} S{} O{} A{} P{} D{} I{} T{}",
-            cpu.get_register(Operand::BX),
-            cpu.get_register(Operand::DI),
+            cpu.get_register_32(Operand::EBX),
+            cpu.get_register_32(Operand::EDI),
             cpu.get_register(Operand::CS),
             cpu.instruction.position,
             cpu.get_flag(Flag::Carry) as u8,
@@ -99,15 +103,15 @@ impl Debugger {
             cpu.get_flag(Flag::Trap) as u8
         );
         println!("ECX={:08X}  EBP={:08X}  res={:04X}  v1={:04X} v2={:04X}",
-            cpu.get_register(Operand::CX),
-            cpu.get_register(Operand::BP),
+            cpu.get_register_32(Operand::ECX),
+            cpu.get_register_32(Operand::EBP),
             cpu.result_value,
             cpu.first_value,
             cpu.second_value
         );
         print!("EDX={:08X}  ESP={:08X}                                  {}",
-            cpu.get_register(Operand::DX),
-            cpu.get_register(Operand::SP),
+            cpu.get_register_32(Operand::EDX),
+            cpu.get_register_32(Operand::ESP),
             cpu.cycle_counter
         );
         {
@@ -135,14 +139,14 @@ impl Debugger {
         {
             print!("{}", termion::cursor::Goto(1, 18));
             self.instruction.position = cpu.instruction.position;
-            let position = crate::bus::BUS::physical_address(cpu.get_register(Operand::CS), cpu.instruction.position) as usize;
+            let position = cpu.linear_address(Operand::CS, cpu.instruction.position);
             let mut buffer = &bus.ram[position..];
             for _line in 0..10 {
-                if !crate::machinecode::decode_instruction(&mut buffer, &mut self.instruction).is_ok() {
+                if !crate::machinecode::decode_instruction(&mut buffer, &mut self.instruction, cpu.model, cpu.code_size()).is_ok() {
                     break;
                 }
                 println!("{}{:04X}:{:04X}  {:16}    {}", termion::clear::CurrentLine, cpu.get_register(Operand::CS), self.instruction.position, crate::disassembler::BytesAsHexDec::from_instruction(&self.instruction), self.instruction);
-                self.instruction.position += self.instruction.length as u16;
+                self.instruction.position += self.instruction.length as u32;
             }
         }
         {
@@ -159,6 +163,9 @@ impl Debugger {
             }
         }
         print!("{}{}", termion::cursor::Goto(1, 35), termion::clear::AfterCursor);
+        if let Some(diagnostic) = &cpu.diagnostic {
+            print!("{}{}{}", termion::style::Invert, diagnostic, termion::style::Reset);
+        }
         std::io::stdout().flush().unwrap();
     }
 
@@ -196,11 +203,11 @@ impl Debugger {
             },
             termion::event::Key::F(10) => {
                 self.instruction.position = cpu.instruction.position;
-                let position = crate::bus::BUS::physical_address(cpu.get_register(Operand::CS), cpu.instruction.position) as usize;
+                let position = cpu.linear_address(Operand::CS, cpu.instruction.position);
                 let mut buffer = &bus.ram[position..];
-                crate::machinecode::decode_instruction(&mut buffer, &mut self.instruction).unwrap();
-                self.instruction.position = self.instruction.position.wrapping_add(self.instruction.length as u16);
-                self.set_break_point_at(cpu, bus, cpu.get_register(Operand::CS), self.instruction.position);
+                crate::machinecode::decode_instruction(&mut buffer, &mut self.instruction, cpu.model, cpu.code_size()).unwrap();
+                self.instruction.position = self.instruction.position.wrapping_add(self.instruction.length as u32);
+                self.set_break_point_at(cpu, bus, cpu.get_register(Operand::CS), self.instruction.position as u16);
                 self.unpause(cpu, bus);
                 return;
             },
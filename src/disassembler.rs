@@ -1,26 +1,34 @@
 use std::fmt;
 
-use crate::machinecode::{Instruction, Opcode};
+use crate::machinecode::{CpuModel, Instruction, Opcode};
 
 static OPERAND_NAMES: &'static [&str] = &[
     "AX", "CX", "DX", "BX",
     "SP", "BP", "SI", "DI",
     "ES", "CS", "SS", "DS",
+    "FS", "GS",
     "AL", "CL", "DL", "BL",
     "AH", "CH", "DH", "BH",
+    "EAX", "ECX", "EDX", "EBX",
+    "ESP", "EBP", "ESI", "EDI",
+    "ST(0)", "ST(1)", "ST(2)", "ST(3)",
+    "ST(4)", "ST(5)", "ST(6)", "ST(7)",
+    "CR0", "CR1", "CR2", "CR3",
     "BX+SI", "BX+DI", "BP+SI", "BP+DI",
     "SI", "DI", "BP", "BX",
-    "", ""
+    "", "", ""
 ];
 
 impl Instruction {
-    fn fmt_operand(&self, formatter: &mut fmt::Formatter, is_string_operation: bool, operand: crate::machinecode::Operand) -> fmt::Result {
+    fn fmt_operand(&self, formatter: &mut fmt::Formatter, is_string_operation: bool, data_width: u8, operand: crate::machinecode::Operand) -> fmt::Result {
         if operand as u8 >= crate::machinecode::Operand::DisplacementBXSI as u8 {
-            formatter.write_str(match self.data_width {
+            formatter.write_str(match data_width {
                 8 => "byte ptr ",
                 16 => "word ptr ",
                 32 => "dword ptr ",
-                _ => unreachable!()
+                64 => "qword ptr ",
+                80 => "tbyte ptr ",
+                _ => ""
             })?;
             let segment_override = if is_string_operation && operand == crate::machinecode::Operand::DisplacementDI { crate::machinecode::Operand::ES } else { self.segment_override };
             if segment_override != crate::machinecode::Operand::None {
@@ -28,16 +36,33 @@ impl Instruction {
                 formatter.write_str(":")?;
             }
             formatter.write_str("[")?;
-            formatter.write_str(OPERAND_NAMES[operand as usize])?;
+            let has_base = if operand == crate::machinecode::Operand::DisplacementExtended {
+                if self.address_base != crate::machinecode::Operand::None {
+                    formatter.write_str(OPERAND_NAMES[self.address_base as usize])?;
+                }
+                if self.address_index != crate::machinecode::Operand::None {
+                    if self.address_base != crate::machinecode::Operand::None {
+                        formatter.write_str("+")?;
+                    }
+                    formatter.write_str(OPERAND_NAMES[self.address_index as usize])?;
+                    if self.address_scale > 1 {
+                        formatter.write_fmt(format_args!("*{}", self.address_scale))?;
+                    }
+                }
+                self.address_base != crate::machinecode::Operand::None || self.address_index != crate::machinecode::Operand::None
+            } else {
+                formatter.write_str(OPERAND_NAMES[operand as usize])?;
+                operand != crate::machinecode::Operand::Displacement
+            };
             if self.displacement != 0 {
-                if self.displacement > 0 {
-                    if operand != crate::machinecode::Operand::Displacement {
+                if self.displacement > 0 || !has_base {
+                    if has_base {
                         formatter.write_str("+")?;
                     }
                 } else {
                     formatter.write_str("-")?;
                 }
-                formatter.write_fmt(format_args!("{:#06X}", self.displacement.abs()))?;
+                formatter.write_fmt(format_args!("{:#06X}", if has_base { self.displacement.abs() as u32 } else if self.address_size == 32 { self.displacement as u32 } else { self.displacement as u16 as u32 }))?;
             }
             formatter.write_str("]")?;
         } else {
@@ -53,22 +78,40 @@ impl fmt::Display for Instruction {
             formatter.write_fmt(format_args!("{}", self.prefix.to_string()))?;
             formatter.write_str(" ")?;
         }
-        formatter.write_fmt(format_args!("{}", self.opcode.to_string()))?;
-        let is_string_operation = self.opcode as u8 >= 0xA4 && self.opcode as u8<= 0xAF;
+        if self.opcode == Opcode::ESC {
+            formatter.write_fmt(format_args!("{}", self.fpu_opcode.to_string()))?;
+        } else {
+            formatter.write_fmt(format_args!("{}", self.opcode.to_string()))?;
+        }
+        let is_string_operation = (self.opcode as u8 >= 0xA4 && self.opcode as u8<= 0xAF) || (self.opcode as u8 >= 0x6C && self.opcode as u8 <= 0x6F);
         if self.first_operand != crate::machinecode::Operand::None {
             formatter.write_str(" ")?;
-            self.fmt_operand(formatter, is_string_operation, self.first_operand)?;
+            self.fmt_operand(formatter, is_string_operation, self.data_width, self.first_operand)?;
         }
         if self.second_operand != crate::machinecode::Operand::None {
             formatter.write_str(", ")?;
-            self.fmt_operand(formatter, is_string_operation, self.second_operand)?;
+            let data_width = match self.opcode {
+                Opcode::MOVZX | Opcode::MOVSX => 8,
+                Opcode::MOVZXW | Opcode::MOVSXW => 16,
+                _ => self.data_width
+            };
+            self.fmt_operand(formatter, is_string_operation, data_width, self.second_operand)?;
         }
-        if self.immediate != 0xFFFFFFFF {
+        if self.third_operand != crate::machinecode::Operand::None {
+            formatter.write_str(", ")?;
+            self.fmt_operand(formatter, is_string_operation, self.data_width, self.third_operand)?;
+        }
+        if self.data_width == 48 && (self.opcode == Opcode::LCALL || self.opcode == Opcode::LJMP) {
+            formatter.write_fmt(format_args!(" {:#X}:{:#X}", self.displacement, self.immediate))?;
+        } else if self.immediate != 0xFFFFFFFF {
             if self.first_operand != crate::machinecode::Operand::None {
                 formatter.write_str(",")?;
             }
             formatter.write_fmt(format_args!(" {:#X}", self.immediate))?;
         }
+        if self.opcode == Opcode::ENTER {
+            formatter.write_fmt(format_args!(", {:#X}", self.displacement))?;
+        }
         Ok(())
     }
 }
@@ -114,6 +157,8 @@ pub fn get_reference(instruction: &Instruction, position: u32) -> (u32, LabelTyp
     }
     (if instruction.data_width == 32 {
         crate::bus::BUS::physical_address((instruction.immediate>>16) as u16, instruction.immediate as u16) as u32
+    } else if instruction.data_width == 48 {
+        instruction.immediate
     } else {
         (position as i32+instruction.immediate as i32) as u32
     }, match instruction.opcode {
@@ -139,12 +184,12 @@ impl LabelBlock {
         }
     }
 
-    pub fn decode(&mut self, ram: &[u8], label_pool: &mut std::collections::BTreeMap<u32, LabelType>, mut position: u32) {
+    pub fn decode(&mut self, ram: &[u8], model: CpuModel, label_pool: &mut std::collections::BTreeMap<u32, LabelType>, mut position: u32) {
         let mut instruction: Instruction = unsafe { std::mem::zeroed() };
         let mut continue_decoding = true;
         while continue_decoding {
-            let mut read_buffer = &ram[position as usize..position as usize+8];
-            if !crate::machinecode::decode_instruction(&mut read_buffer, &mut instruction).is_ok() {
+            let mut read_buffer = &ram[position as usize..position as usize+16];
+            if !crate::machinecode::decode_instruction(&mut read_buffer, &mut instruction, model, 16).is_ok() {
                 panic!();
             }
             self.positions.push(position);
@@ -161,7 +206,7 @@ impl LabelBlock {
         self.end_position = position;
     }
 
-    pub fn print(&self, ram: &[u8]) {
+    pub fn print(&self, ram: &[u8], model: CpuModel) {
         print!("label_{:05X}:", self.positions[0]);
         if self.label_type == LabelType::Other {
             println!("");
@@ -170,8 +215,8 @@ impl LabelBlock {
         }
         let mut instruction: Instruction = unsafe { std::mem::zeroed() };
         for position in &self.positions {
-            let mut read_buffer = &ram[*position as usize..*position as usize+8];
-            if !crate::machinecode::decode_instruction(&mut read_buffer, &mut instruction).is_ok() {
+            let mut read_buffer = &ram[*position as usize..*position as usize+16];
+            if !crate::machinecode::decode_instruction(&mut read_buffer, &mut instruction, model, 16).is_ok() {
                 panic!();
             }
             let (reference, label_type) = get_reference(&instruction, *position);
@@ -184,7 +229,7 @@ impl LabelBlock {
     }
 }
 
-pub fn disassemble(ram: &[u8], entry_position: u32) {
+pub fn disassemble(ram: &[u8], model: CpuModel, entry_position: u32) {
     let mut blocks: std::collections::BTreeMap<u32, LabelBlock> = std::collections::BTreeMap::new();
     let mut label_pool: std::collections::BTreeMap<u32, LabelType> = std::collections::BTreeMap::new();
     label_pool.insert(entry_position, LabelType::Entry);
@@ -212,10 +257,10 @@ pub fn disassemble(ram: &[u8], entry_position: u32) {
             }
         }
         let mut block = LabelBlock::new(label.1);
-        block.decode(ram, &mut label_pool, label.0);
+        block.decode(ram, model, &mut label_pool, label.0);
         blocks.insert(label.0, block);
     }
     for block in blocks.values() {
-        block.print(ram);
+        block.print(ram, model);
     }
 }
@@ -0,0 +1,112 @@
+use std::io;
+use std::os::unix::fs::FileExt;
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// Standard floppy formats by their size: Cylinders, heads, sectors per track, drive type of INT 13h AH=08h
+const FLOPPY_GEOMETRIES: [(usize, u16, u8, u8, u8); 9] = [
+    (160*1024, 40, 1, 8, 1),
+    (180*1024, 40, 1, 9, 1),
+    (320*1024, 40, 2, 8, 1),
+    (360*1024, 40, 2, 9, 1),
+    (720*1024, 80, 2, 9, 3),
+    (1200*1024, 80, 2, 15, 2),
+    (1440*1024, 80, 2, 18, 4),
+    (1680*1024, 80, 2, 21, 4), // DMF
+    (2880*1024, 80, 2, 36, 6)
+];
+
+/// Raw floppy or hard disk image, kept in memory and shared by INT 13h and the FAT drives on it.
+/// Modified sectors are written back on flush, unless the image is copy-on-write.
+pub struct DiskImage {
+    pub path: std::path::PathBuf,
+    pub data: Vec<u8>,
+    file: Option<std::fs::File>,
+    dirty_sectors: std::collections::BTreeSet<usize>,
+    pub read_only: bool,
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors_per_track: u8,
+    pub floppy_type: u8 // 0 if not a standard floppy format
+}
+
+pub type SharedDiskImage = std::sync::Arc<std::sync::Mutex<DiskImage>>;
+
+impl DiskImage {
+    pub fn open(path: &std::path::Path, read_only: bool, copy_on_write: bool) -> io::Result<Self> {
+        let path = path.canonicalize()?;
+        let data = std::fs::read(&path)?;
+        let file = if read_only || copy_on_write {
+            None
+        } else {
+            Some(std::fs::OpenOptions::new().write(true).open(&path)?)
+        };
+        let (cylinders, heads, sectors_per_track, floppy_type) = Self::detect_geometry(&data);
+        println!("DISK: {:?} C={} H={} S={}", path, cylinders, heads, sectors_per_track);
+        Ok(Self {
+            path: path,
+            data: data,
+            file: file,
+            dirty_sectors: std::collections::BTreeSet::new(),
+            read_only: read_only,
+            cylinders: cylinders,
+            heads: heads,
+            sectors_per_track: sectors_per_track,
+            floppy_type: floppy_type
+        })
+    }
+
+    /// CHS geometry from the image size for floppies, else from the partition table or BIOS parameter block
+    fn detect_geometry(data: &[u8]) -> (u16, u8, u8, u8) {
+        if let Some(geometry) = FLOPPY_GEOMETRIES.iter().find(|geometry| geometry.0 == data.len()) {
+            return (geometry.1, geometry.2, geometry.3, geometry.4);
+        }
+        let (mut heads, mut sectors_per_track) = (16, 63);
+        if data.len() >= SECTOR_SIZE && data[510] == 0x55 && data[511] == 0xAA {
+            let bpb_heads = crate::bit_utils::read_from_buffer_u16(&data[0x1A..]);
+            let bpb_sectors_per_track = crate::bit_utils::read_from_buffer_u16(&data[0x18..]);
+            let partition = &data[0x1BE..0x1CE];
+            if partition[4] != 0 && partition[6]&0x3F != 0 { // Ending CHS of the first partition
+                heads = partition[5] as usize+1;
+                sectors_per_track = (partition[6]&0x3F) as usize;
+            } else if (1..=255).contains(&bpb_heads) && (1..=63).contains(&bpb_sectors_per_track) {
+                heads = bpb_heads as usize;
+                sectors_per_track = bpb_sectors_per_track as usize;
+            }
+        }
+        let cylinders = (data.len()/SECTOR_SIZE/(heads*sectors_per_track)).max(1).min(1024);
+        (cylinders as u16, heads as u8, sectors_per_track as u8, 0)
+    }
+
+    pub fn sector_count(&self) -> usize {
+        self.data.len()/SECTOR_SIZE
+    }
+
+    /// Linear sector of a CHS address, None if it is outside of the geometry
+    pub fn lba(&self, cylinder: u16, head: u8, sector: u8) -> Option<usize> {
+        if cylinder >= self.cylinders || head >= self.heads || sector == 0 || sector > self.sectors_per_track {
+            return None;
+        }
+        Some((cylinder as usize*self.heads as usize+head as usize)*self.sectors_per_track as usize+sector as usize-1)
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[u8]) {
+        self.data[offset..offset+data.len()].copy_from_slice(data);
+        if self.file.is_some() && !data.is_empty() {
+            self.dirty_sectors.extend(offset/SECTOR_SIZE..=(offset+data.len()-1)/SECTOR_SIZE);
+        }
+    }
+
+    /// Writes the modified sectors back to the image file
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            for sector in self.dirty_sectors.iter() {
+                let begin = sector*SECTOR_SIZE;
+                let end = (begin+SECTOR_SIZE).min(self.data.len());
+                file.write_all_at(&self.data[begin..end], begin as u64)?;
+            }
+        }
+        self.dirty_sectors.clear();
+        Ok(())
+    }
+}
@@ -1,10 +1,12 @@
 use std::mem;
 use std::slice;
 use std::io::prelude::*;
-use chrono::prelude::*;
 
 use crate::machinecode::Operand;
 use crate::cpu::Flag;
+use chrono::Timelike;
+use serde::Deserialize;
+use serde::Serialize;
 
 #[repr(C, packed)]
 struct MZDOS {
@@ -68,25 +70,189 @@ struct FindFirstDataBlock {
     filename: [u8; 13]
 }
 
-fn path_from_ptr(mount_point_c: &std::path::Path, ptr: *const u8) -> std::result::Result<std::path::PathBuf, &'static str> {
-    let path = match unsafe { std::ffi::CStr::from_ptr(ptr as *const i8) }.to_str() {
-        Ok(path) => path,
-        Err(_) => { return Err("Could not read file path"); }
-    };
-    if &path[0..3] != "C:\\" {
-        return Err("File path does not start with C:\\");
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct FileControlBlock {
+    drive: u8, // 0 for the default drive, 1 for A:
+    name: [u8; 11],
+    current_block: u16, // 128 records each
+    record_size: u16,
+    file_size: u32,
+    date: u16,
+    time: u16,
+    system_file_table_index: u16, // Reserved area, set by Open / Create
+    search_index: u16, // Reserved area, set by Find First
+    search_entry_index: u16,
+    reserved: [u8; 2],
+    current_record: u8,
+    random_record: u32 // Only 3 bytes for records of 64 bytes or more
+}
+
+impl FileControlBlock {
+    fn record_size(&self) -> u32 {
+        if self.record_size == 0 { 128 } else { self.record_size as u32 }
+    }
+
+    fn current_record_number(&self) -> u32 {
+        self.current_block as u32*128+self.current_record as u32
+    }
+
+    fn set_current_record_number(&mut self, record: u32) {
+        self.current_block = (record/128) as u16;
+        self.current_record = (record%128) as u8;
+    }
+
+    fn random_record_number(&self) -> u32 {
+        if self.record_size() >= 64 { self.random_record&0x00FF_FFFF } else { self.random_record }
+    }
+
+    fn set_random_record_number(&mut self, record: u32) {
+        self.random_record = if self.record_size() >= 64 { (self.random_record&0xFF00_0000)|(record&0x00FF_FFFF) } else { record };
+    }
+}
+
+#[repr(C, packed)]
+struct MemoryControlBlock {
+    signature: u8, // 'M', or 'Z' for the last block of the chain
+    owner: u16, // PSP segment, 0 if free
+    size: u16, // paragraphs
+    reserved: [u8; 3],
+    name: [u8; 8]
+}
+
+const FIRST_MCB_SEGMENT: u16 = 0x0117;
+const LIST_OF_LISTS: (u16, u16) = (0x0070, 0x0026);
+
+#[derive(Copy, Clone, PartialEq)]
+enum AllocationStrategy {
+    FirstFit = 0,
+    BestFit = 1,
+    LastFit = 2
+}
+
+const DRIVE_COUNT: u8 = 26;
+const DRIVE_C: u8 = 2;
+
+#[derive(Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum DriveKind {
+    #[serde(rename = "floppy")]
+    Floppy,
+    #[serde(rename = "hard_disk")]
+    HardDisk,
+    #[serde(rename = "cdrom")]
+    CdRom
+}
+
+pub struct Drive {
+    pub kind: DriveKind,
+    pub host_path: std::path::PathBuf, // Directory or disk image
+    pub read_only: bool,
+    pub label: String,
+    pub file_system: crate::file_system::FileSystem
+}
+
+/// Entry of the system file table, shared by all handles duplicated from the handle that opened it
+struct OpenFileEntry {
+    drive: u8,
+    components: Vec<String>,
+    mode: u8, // Access mode in bits 0-2 and sharing mode in bits 4-6
+    file: crate::file_system::OpenFile
+}
+
+impl OpenFileEntry {
+    fn is_readable(&self) -> bool {
+        self.mode&0x07 != 1
+    }
+
+    fn is_writable(&self) -> bool {
+        self.mode&0x07 != 0
+    }
+
+    /// Whether a file opened with the mode may be opened again with the other mode.
+    /// The compatibility mode shares like "deny none", as there is only a single program running.
+    fn allows_sharing(&self, mode: u8) -> bool {
+        let denies = |mode: u8| match (mode>>4)&0x07 {
+            1 => (true, true), // Deny read and write
+            2 => (false, true), // Deny write
+            3 => (true, false), // Deny read
+            _ => (false, false)
+        };
+        let (deny_read, deny_write) = denies(self.mode);
+        let (other_deny_read, other_deny_write) = denies(mode);
+        !(deny_read && mode&0x07 != 1) && !(deny_write && mode&0x07 != 0) &&
+            !(other_deny_read && self.is_readable()) && !(other_deny_write && self.is_writable())
+    }
+}
+
+/// Character device which handles can reference instead of a file
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Device {
+    Console,
+    Null,
+    Auxiliary,
+    Printer,
+    Clock,
+    Driver(&'static str) // Device of a driver, which accepts any input and output
+}
+
+/// Names of the built-in devices, which are recognized in any directory
+const DEVICE_NAMES: [(&str, Device); 7] = [
+    ("CON", Device::Console),
+    ("NUL", Device::Null),
+    ("AUX", Device::Auxiliary),
+    ("COM1", Device::Auxiliary),
+    ("PRN", Device::Printer),
+    ("LPT1", Device::Printer),
+    ("CLOCK$", Device::Clock)
+];
+
+impl Device {
+    fn name(&self) -> &'static str {
+        match self {
+            Device::Driver(name) => name,
+            device => DEVICE_NAMES.iter().find(|(_, other)| other == device).unwrap().0
+        }
+    }
+
+    /// Device information word of IOCTL AH=44h AL=00h, without the raw mode bit
+    fn information(&self) -> u16 {
+        match self {
+            Device::Console => 0x80D3, // Character device, console input and output
+            Device::Null => 0x8084, // Character device, null device
+            Device::Auxiliary => 0x80C0,
+            Device::Printer => 0xA8C0, // Character device, output until busy
+            Device::Clock => 0x80C8, // Character device, clock device
+            Device::Driver(_) => 0xC080 // Character device supporting IOCTL
+        }
+    }
+}
+
+fn string_from_ptr<'a>(ptr: *const u8) -> std::result::Result<&'a str, &'static str> {
+    match unsafe { std::ffi::CStr::from_ptr(ptr as *const i8) }.to_str() {
+        Ok(string) => Ok(string),
+        Err(_) => Err("Could not read file path")
     }
-    Ok(mount_point_c.join(&path[3..].replace("\\", "/")))
 }
 
 macro_rules! get_path {
     ($dos:ident, $cpu:ident, $ram:ident) => {
+        get_path!($dos, $cpu, $ram, false)
+    };
+    ($dos:ident, $cpu:ident, $ram:ident, writable) => {
+        get_path!($dos, $cpu, $ram, true)
+    };
+    ($dos:ident, $cpu:ident, $ram:ident, $writable:expr) => {
         {
             let address = crate::bus::BUS::physical_address($cpu.get_register(Operand::DS), $cpu.get_register(Operand::DX));
-            match path_from_ptr(&$dos.mount_point_c, &mut $ram[address as usize]) {
-                Ok(path) => path,
-                Err(_) => {
-                    println!("FS ({}): err=(Path not found)", $cpu.cycle_counter);
+            match string_from_ptr(&$ram[address as usize]).and_then(|path| $dos.resolve_path(path)) {
+                Ok((drive, _components)) if $writable && !$dos.is_drive_writable(drive) => {
+                    println!("FS ({}): err=(Access denied: Read only drive)", $cpu.cycle_counter);
+                    $cpu.set_register(Operand::AX, 5); // Access denied
+                    return;
+                },
+                Ok(resolved) => resolved,
+                Err(error) => {
+                    println!("FS ({}): err=(Path not found: {})", $cpu.cycle_counter, error);
                     $cpu.set_register(Operand::AX, 3); // Path not found
                     return;
                 }
@@ -96,8 +262,8 @@ macro_rules! get_path {
 }
 
 macro_rules! access_path {
-    ($path:expr, $cpu:ident,  $value:ident, $operation_name:expr, $operation:expr, $($on_success:tt)*) => {
-        match $operation(&$path) {
+    ($path:expr, $cpu:ident,  $value:ident, $operation_name:expr, $result:expr, $($on_success:tt)*) => {
+        match $result {
             Ok($value) => {
                 println!("FS ({}): {} path={:?}", $cpu.cycle_counter, $operation_name, $path);
                 $cpu.reset_flag(Flag::Carry);
@@ -108,12 +274,16 @@ macro_rules! access_path {
                 $cpu.set_register(Operand::AX, 2); // File not found
                 return;
             },
-            Err(ref e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            Err(ref e) if e.kind() == std::io::ErrorKind::PermissionDenied || e.kind() == std::io::ErrorKind::AlreadyExists => {
                 println!("FS ({}): {} path={:?} err=(Access denied)", $cpu.cycle_counter, $operation_name, $path);
                 $cpu.set_register(Operand::AX, 5); // Access denied
                 return;
             },
-            Err(_) => { panic!(); }
+            Err(ref e) => {
+                $cpu.pause_with_diagnostic(format!("FS ({}): {} path={:?} err=({})", $cpu.cycle_counter, $operation_name, $path, e));
+                $cpu.set_register(Operand::AX, 0x1F); // General failure
+                return;
+            }
         };
     };
 }
@@ -122,9 +292,18 @@ pub struct DiskOperatingSystem {
     pub load_segment: u16,
     pub psp_segment: u16,
     pub dta_address: u32,
-    pub open_handles: std::collections::HashMap<u16, std::fs::File>,
-    pub mount_point_c: std::path::PathBuf,
-    read_directory: Option<std::fs::ReadDir>,
+    pub open_handles: std::collections::HashMap<u16, usize>, // Index into the system file table
+    system_file_table: Vec<Option<OpenFileEntry>>,
+    pub device_names: Vec<&'static str>, // Devices of drivers which can be opened like files
+    device_handles: std::collections::HashMap<u16, Device>,
+    raw_devices: std::collections::HashSet<Device>, // Devices switched to raw mode by IOCTL
+    console_input: Vec<u8>, // Typed characters not yet read from the console
+    drives: Vec<Option<Drive>>,
+    current_drive: u8,
+    current_directories: Vec<Vec<String>>, // Components per drive
+    pub memory_end: u16, // Last paragraph of conventional memory
+    allocation_strategy: AllocationStrategy,
+    search_directories: Vec<(u8, Vec<String>, Vec<crate::file_system::DirectoryEntry>)>, // Listings referenced by the DTA of FindFirst / FindNext
     keyboard_spill: u8
 }
 
@@ -135,8 +314,18 @@ impl DiskOperatingSystem {
             psp_segment: 0,
             dta_address: 0,
             open_handles: std::collections::HashMap::new(),
-            mount_point_c: std::path::PathBuf::new(),
-            read_directory: None,
+            system_file_table: Vec::new(),
+            device_names: Vec::new(),
+            // Standard input, output, error, auxiliary and printer
+            device_handles: vec![(0, Device::Console), (1, Device::Console), (2, Device::Console), (3, Device::Auxiliary), (4, Device::Printer)].into_iter().collect(),
+            raw_devices: std::collections::HashSet::new(),
+            console_input: Vec::new(),
+            drives: (0..DRIVE_COUNT).map(|_| None).collect(),
+            current_drive: DRIVE_C,
+            current_directories: vec![Vec::new(); DRIVE_COUNT as usize],
+            memory_end: 0x9FFF,
+            allocation_strategy: AllocationStrategy::FirstFit,
+            search_directories: Vec::new(),
             keyboard_spill: 0
         }
     }
@@ -145,6 +334,643 @@ impl DiskOperatingSystem {
         unsafe { &mut *std::mem::transmute::<*mut u8, *mut ProgramSegmentPrefix>(&mut ram[psp_segment << 4] as *mut u8) }
     }
 
+    pub fn mount(&mut self, letter: char, mut drive: Drive) {
+        let index = (letter.to_ascii_uppercase() as u8).wrapping_sub(b'A');
+        if index >= DRIVE_COUNT {
+            println!("FS: Invalid drive letter {}", letter);
+            return;
+        }
+        if let crate::file_system::FileSystem::Iso9660(_) = drive.file_system {
+            drive.kind = DriveKind::CdRom;
+        }
+        println!("FS: Mounted {}: at {:?}", (b'A'+index) as char, drive.host_path);
+        self.drives[index as usize] = Some(drive);
+    }
+
+    fn is_drive_mounted(&self, drive: u8) -> bool {
+        self.drives.get(drive as usize).map_or(false, |drive| drive.is_some())
+    }
+
+    fn is_drive_writable(&self, drive: u8) -> bool {
+        match &self.drives[drive as usize] {
+            Some(drive) => !drive.read_only && drive.kind != DriveKind::CdRom,
+            None => false
+        }
+    }
+
+    /// Splits a DOS path into its drive and components, relative paths start at the current directory of the drive
+    fn resolve_path(&self, path: &str) -> std::result::Result<(u8, Vec<String>), &'static str> {
+        let (drive, path) = match path.as_bytes() {
+            [letter, b':', ..] if letter.is_ascii_alphabetic() => (letter.to_ascii_uppercase()-b'A', &path[2..]),
+            _ => (self.current_drive, path)
+        };
+        if !self.is_drive_mounted(drive) {
+            return Err("Invalid drive");
+        }
+        let mut components = if path.starts_with('\\') || path.starts_with('/') { Vec::new() } else { self.current_directories[drive as usize].clone() };
+        let parts: Vec<&str> = path.split(|character| character == '\\' || character == '/').collect();
+        for (index, component) in parts.iter().enumerate() {
+            match *component {
+                "" if index == 0 || index == parts.len()-1 => {}, // Leading or trailing separator
+                "." => {},
+                ".." => { components.pop(); },
+                _ if !crate::name_mapping::is_valid_component(component) => { return Err("Invalid path component"); },
+                _ => { components.push(component.to_string()); }
+            }
+        }
+        Ok((drive, components))
+    }
+
+    pub fn cd_rom_drives(&self) -> Vec<u8> {
+        (0..DRIVE_COUNT).filter(|drive| self.drives[*drive as usize].as_ref().map_or(false, |drive| drive.kind == DriveKind::CdRom)).collect()
+    }
+
+    pub fn cd_rom_image(&mut self, drive: u8) -> Option<&mut crate::iso9660::Iso9660FileSystem> {
+        match self.drives.get_mut(drive as usize)?.as_mut()?.file_system {
+            crate::file_system::FileSystem::Iso9660(ref mut image) => Some(image),
+            _ => None
+        }
+    }
+
+    fn file_system(&mut self, drive: u8) -> &mut crate::file_system::FileSystem {
+        &mut self.drives[drive as usize].as_mut().unwrap().file_system
+    }
+
+    fn display_path(drive: u8, components: &[String]) -> String {
+        format!("{}:\\{}", (b'A'+drive) as char, components.join("\\"))
+    }
+
+    /// Sectors per cluster, bytes per sector, free and total clusters
+    fn free_disk_space(&self, drive: u8) -> Option<(u16, u16, u16, u16)> {
+        let writable = self.is_drive_mounted(drive) && self.is_drive_writable(drive);
+        let drive = self.drives.get(drive as usize)?.as_ref()?;
+        let (sectors_per_cluster, bytes_per_sector, free_clusters, total_clusters) = drive.file_system.free_space(drive.kind);
+        Some((sectors_per_cluster, bytes_per_sector, if writable { free_clusters } else { 0 }, total_clusters))
+    }
+
+    /// DOS path of a host path inside one of the mounted host directories
+    fn dos_path(&self, host_path: &std::path::Path) -> Option<(u8, Vec<String>)> {
+        self.drives.iter().enumerate().find_map(|(index, drive)| {
+            let root = match &drive.as_ref()?.file_system {
+                crate::file_system::FileSystem::Host(host) => &host.root,
+                _ => { return None; }
+            };
+            let relative_path = host_path.strip_prefix(root).ok()?;
+            Some((index as u8, relative_path.iter().map(|component| component.to_string_lossy().to_string()).collect()))
+        })
+    }
+
+    fn device_from_ptr(&self, name: &[u8]) -> Option<Device> {
+        let length = name.iter().position(|byte| *byte == 0).unwrap_or(0);
+        let name = std::str::from_utf8(&name[0..length]).ok()?.to_uppercase();
+        let name = name.rsplit(|character| character == '\\' || character == '/' || character == ':').next().unwrap();
+        let name = name.split('.').next().unwrap().trim_end(); // Devices ignore extensions
+        DEVICE_NAMES.iter().find(|(device_name, _)| *device_name == name).map(|(_, device)| *device)
+            .or_else(|| self.device_names.iter().find(|device_name| **device_name == name).map(|device_name| Device::Driver(device_name)))
+    }
+
+    fn free_handle(&self) -> Option<u16> {
+        (0..20).find(|handle| !self.open_handles.contains_key(handle) && !self.device_handles.contains_key(handle))
+    }
+
+    /// Adds an opened file to the system file table and returns its index
+    fn insert_open_file(&mut self, drive: u8, components: Vec<String>, mode: u8, file: crate::file_system::OpenFile) -> usize {
+        let entry = Some(OpenFileEntry { drive: drive, components: components, mode: mode, file: file });
+        let index = match self.system_file_table.iter().position(|entry| entry.is_none()) {
+            Some(index) => index,
+            None => {
+                self.system_file_table.push(None);
+                self.system_file_table.len()-1
+            }
+        };
+        self.system_file_table[index] = entry;
+        index
+    }
+
+    /// Adds an opened file to the system file table and returns a new handle referencing it
+    fn insert_handle(&mut self, drive: u8, components: Vec<String>, mode: u8, file: crate::file_system::OpenFile) -> u16 {
+        let index = self.insert_open_file(drive, components, mode, file);
+        let handle = self.free_handle().unwrap();
+        self.open_handles.insert(handle, index);
+        handle
+    }
+
+    /// Makes the new handle reference the same file or device as the handle
+    fn duplicate_handle(&mut self, handle: u16, new_handle: u16) {
+        if let Some(index) = self.open_handles.get(&handle).cloned() {
+            self.open_handles.insert(new_handle, index);
+        } else if let Some(device) = self.device_handles.get(&handle).cloned() {
+            self.device_handles.insert(new_handle, device);
+        }
+    }
+
+    /// Closes the handle and the file once no other handle references it, false if the handle is not open
+    fn close_handle(&mut self, handle: u16) -> bool {
+        if self.device_handles.remove(&handle).is_some() {
+            return true;
+        }
+        match self.open_handles.remove(&handle) {
+            Some(index) => {
+                if !self.open_handles.values().any(|other_index| *other_index == index) {
+                    self.system_file_table[index] = None;
+                }
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Whether the file may be opened with the mode, considering the sharing modes of all open files
+    fn allows_sharing(&self, drive: u8, components: &[String], mode: u8) -> bool {
+        let uppercase = |components: &[String]| components.iter().map(|component| component.to_uppercase()).collect::<Vec<String>>();
+        self.system_file_table.iter().flatten()
+            .filter(|entry| entry.drive == drive && uppercase(&entry.components) == uppercase(components))
+            .all(|entry| entry.allows_sharing(mode))
+    }
+
+    /// Opens the device named by the path at DS:DX and returns its handle in AX, false if the path names no device
+    fn open_device_handle(&mut self, cpu: &mut crate::cpu::CPU, ram: &[u8], operation_name: &str) -> bool {
+        let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
+        let device = match self.device_from_ptr(&ram[address..]) {
+            Some(device) => device,
+            None => { return false; }
+        };
+        let handle = self.free_handle().unwrap();
+        println!("FS ({}): {} device={} fd={}", cpu.cycle_counter, operation_name, device.name(), handle);
+        self.device_handles.insert(handle, device);
+        cpu.set_register(Operand::AX, handle);
+        cpu.reset_flag(Flag::Carry);
+        true
+    }
+
+    /// Reads from a device into the buffer, None if the console has to wait for more input.
+    /// The console in cooked mode echoes the typed characters and returns a line once it is entered.
+    fn read_device(&mut self, ram: &mut [u8], vga: &mut crate::vga::VideoGraphicsArray, device: Device, buffer: &mut [u8]) -> Option<usize> {
+        match device {
+            Device::Console => {
+                let raw = self.raw_devices.contains(&device);
+                let bios = crate::bios::BIOS::from_ram(ram);
+                while !self.console_input.contains(&b'\n') || raw {
+                    let keycode = match bios.keyboard_buffer_pop() {
+                        Some(keycode) => keycode,
+                        None => { break; }
+                    };
+                    let character = keycode as u8;
+                    if raw {
+                        self.console_input.push(character);
+                        if character == 0 { // Extended keys are followed by their scan code
+                            self.console_input.push((keycode>>8) as u8);
+                        }
+                        continue;
+                    }
+                    let echo: &[u8] = match character {
+                        0x00 => b"",
+                        0x08 => {
+                            if self.console_input.pop().is_none() {
+                                continue;
+                            }
+                            b"\x08 \x08"
+                        },
+                        b'\r' => {
+                            self.console_input.extend_from_slice(b"\r\n");
+                            b"\r\n"
+                        },
+                        _ => {
+                            self.console_input.push(character);
+                            if character == 0x1A { b"^Z" } else { std::slice::from_ref(&character) }
+                        }
+                    };
+                    echo.iter().for_each(|character| bios.teletype_output(vga, *character));
+                }
+                let available = if raw {
+                    self.console_input.len()
+                } else {
+                    self.console_input.iter().position(|character| *character == b'\n')? + 1
+                };
+                if available == 0 {
+                    return None;
+                }
+                let length = available.min(buffer.len());
+                buffer[0..length].copy_from_slice(&self.console_input[0..length]);
+                self.console_input.drain(0..length);
+                Some(length)
+            },
+            Device::Clock => { // Days since 1980, minutes, hours, hundredths and seconds
+                let now = chrono::Local::now().naive_local();
+                let days = (now.date()-chrono::NaiveDate::from_ymd_opt(1980, 1, 1).unwrap()).num_days() as u16;
+                let time = now.time();
+                let record = [days as u8, (days>>8) as u8, time.minute() as u8, time.hour() as u8, (time.nanosecond()/10_000_000).min(99) as u8, time.second() as u8];
+                let length = record.len().min(buffer.len());
+                buffer[0..length].copy_from_slice(&record[0..length]);
+                Some(length)
+            },
+            _ => Some(0) // End of file
+        }
+    }
+
+    /// Writes to a device and returns the number of bytes written, which the devices always accept completely
+    fn write_device(&mut self, cpu: &crate::cpu::CPU, ram: &mut [u8], vga: &mut crate::vga::VideoGraphicsArray, device: Device, address: usize, length: usize) -> usize {
+        let data = ram[address..address+length].to_vec();
+        match device {
+            Device::Console => {
+                let bios = crate::bios::BIOS::from_ram(ram);
+                data.iter().for_each(|character| bios.teletype_output(vga, *character));
+                println!("DOS ({}): Console output {:?}", cpu.cycle_counter, String::from_utf8_lossy(&data));
+            },
+            Device::Auxiliary | Device::Printer => {
+                println!("DOS ({}): {} output {:?}", cpu.cycle_counter, device.name(), String::from_utf8_lossy(&data));
+            },
+            _ => {}
+        }
+        length
+    }
+
+    /// Creates the file, truncating it unless it must be new, and returns a read / write handle in AX
+    fn create_file_handle(&mut self, cpu: &mut crate::cpu::CPU, operation_name: &str, drive: u8, components: Vec<String>, attribute: u8, must_be_new: bool) {
+        let path = Self::display_path(drive, &components);
+        if must_be_new && self.file_system(drive).attributes(&components).is_ok() {
+            println!("FS ({}): {} path={} err=(File exists)", cpu.cycle_counter, operation_name, path);
+            cpu.set_register(Operand::AX, 0x50); // File exists
+            return;
+        }
+        if !self.allows_sharing(drive, &components, 0x02) {
+            println!("FS ({}): {} path={} err=(Sharing violation)", cpu.cycle_counter, operation_name, path);
+            cpu.set_register(Operand::AX, 0x20); // Sharing violation
+            return;
+        }
+        access_path!(path, cpu, file, operation_name, self.file_system(drive).create_file(&components), {
+            if attribute&0x07 != 0 {
+                // The new handle stays writable even for read only files
+                if let Err(error) = self.file_system(drive).set_attributes(&components, attribute) {
+                    println!("FS ({}): {} path={} attribute={:02X} err=({})", cpu.cycle_counter, operation_name, path, attribute, error);
+                }
+            }
+            let handle = self.insert_handle(drive, components, 0x02, file);
+            cpu.set_register(Operand::AX, handle);
+        });
+    }
+
+    /// Label of the drive as configured, else as stored on its volume
+    fn volume_label(&self, drive: u8) -> String {
+        let drive = self.drives[drive as usize].as_ref().unwrap();
+        if drive.label.is_empty() { drive.file_system.volume_label().unwrap_or_default() } else { drive.label.to_uppercase() }
+    }
+
+    /// Keeps a listing for FindFirst / FindNext, reusing the slot of an earlier search in the same directory
+    fn add_search_directory(&mut self, drive: u8, components: Vec<String>, entries: Vec<crate::file_system::DirectoryEntry>) -> usize {
+        match self.search_directories.iter().position(|search| search.0 == drive && search.1 == components) {
+            Some(search_index) => {
+                self.search_directories[search_index].2 = entries;
+                search_index
+            },
+            None => {
+                self.search_directories.push((drive, components, entries));
+                self.search_directories.len()-1
+            }
+        }
+    }
+
+    /// Address of the FCB at DS:DX and the search attribute, which only extended FCBs have
+    fn fcb_address(cpu: &crate::cpu::CPU, ram: &[u8]) -> (usize, Option<u8>) {
+        let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
+        if ram[address] == 0xFF { (address+7, Some(ram[address+6])) } else { (address, None) }
+    }
+
+    fn read_fcb(ram: &[u8], address: usize) -> FileControlBlock {
+        let data = &ram[address..address+std::mem::size_of::<FileControlBlock>()];
+        unsafe { std::ptr::read_unaligned(data.as_ptr() as *const FileControlBlock) }
+    }
+
+    fn write_fcb(ram: &mut [u8], address: usize, fcb: &FileControlBlock) {
+        let data = &mut ram[address..address+std::mem::size_of::<FileControlBlock>()];
+        unsafe { std::ptr::write_unaligned(data.as_mut_ptr() as *mut FileControlBlock, *fcb) }
+    }
+
+    /// Upper case name of an FCB, which may contain "?" wildcards
+    fn fcb_pattern(fcb: &FileControlBlock) -> [u8; 11] {
+        let mut pattern = fcb.name;
+        pattern.iter_mut().for_each(|character| *character = character.to_ascii_uppercase());
+        pattern
+    }
+
+    /// Drive and path of the file an FCB names, which is in the current directory of its drive, None for an invalid drive or name
+    fn fcb_path(&self, fcb: &FileControlBlock) -> Option<(u8, Vec<String>)> {
+        let drive = match fcb.drive {
+            0 => self.current_drive,
+            drive => drive-1
+        };
+        if !self.is_drive_mounted(drive) {
+            return None;
+        }
+        let name = crate::name_mapping::from_fcb_form(&fcb.name);
+        if !crate::name_mapping::is_valid_component(&name) {
+            return None;
+        }
+        let mut components = self.current_directories[drive as usize].clone();
+        components.push(name);
+        Some((drive, components))
+    }
+
+    /// System file table index of the file opened by the FCB, None if it is not an open FCB
+    fn fcb_file(&self, fcb: &FileControlBlock) -> Option<usize> {
+        let index = fcb.system_file_table_index as usize;
+        let entry = self.system_file_table.get(index)?.as_ref()?;
+        let name = crate::name_mapping::from_fcb_form(&fcb.name);
+        if self.open_handles.values().any(|other_index| *other_index == index) ||
+           entry.components.last().map(|component| crate::name_mapping::normalize_short_name(component)) != Some(name) {
+            return None;
+        }
+        Some(index)
+    }
+
+    /// Adds the file opened or created by an FCB to the system file table and fills in the FCB
+    fn open_fcb(&mut self, fcb: &mut FileControlBlock, drive: u8, components: Vec<String>, mode: u8, mut file: crate::file_system::OpenFile) -> std::io::Result<()> {
+        let file_system = self.file_system(drive);
+        let size = file_system.seek(&mut file, std::io::SeekFrom::End(0))?;
+        file_system.seek(&mut file, std::io::SeekFrom::Start(0))?;
+        let (time, date) = file_system.file_time(&file)?;
+        fcb.drive = drive+1;
+        fcb.current_block = 0;
+        fcb.record_size = 128;
+        fcb.file_size = size as u32;
+        fcb.date = date;
+        fcb.time = time;
+        fcb.system_file_table_index = self.insert_open_file(drive, components, mode, file) as u16;
+        Ok(())
+    }
+
+    /// Reads or writes records between the DTA and the file of an open FCB, writing no records sets the file size.
+    /// Returns the status for AL and the number of records transferred, where a partial record read counts as one.
+    fn transfer_fcb_records(&mut self, cpu: &mut crate::cpu::CPU, ram: &mut [u8], fcb: &mut FileControlBlock, record: u32, count: u16, write: bool) -> (u8, u16) {
+        let operation_name = if write { "FCB Write" } else { "FCB Read" };
+        let index = match self.fcb_file(fcb) {
+            Some(index) => index,
+            None => {
+                println!("FS ({}): {} err=(File not open)", cpu.cycle_counter, operation_name);
+                return (1, 0);
+            }
+        };
+        let record_size = fcb.record_size() as usize;
+        let length = record_size*count as usize;
+        if (self.dta_address&0xFFFF) as usize+length > 0x10000 {
+            return (2, 0); // The DTA would wrap around its segment
+        }
+        let address = crate::bus::BUS::physical_address((self.dta_address>>16) as u16, self.dta_address as u16);
+        let entry = self.system_file_table[index].as_mut().unwrap();
+        let position = record as u64*record_size as u64;
+        println!("FS ({}): {} path={} record={} count={}", cpu.cycle_counter, operation_name, Self::display_path(entry.drive, &entry.components), record, count);
+        if write && !entry.is_writable() {
+            return (1, 0);
+        }
+        let file_system = &mut self.drives[entry.drive as usize].as_mut().unwrap().file_system;
+        if let Err(error) = file_system.seek(&mut entry.file, std::io::SeekFrom::Start(position)) {
+            println!("FS ({}): {} err=({})", cpu.cycle_counter, operation_name, error);
+            return (1, 0);
+        }
+        if write {
+            let result = if count == 0 {
+                file_system.set_length(&mut entry.file).map(|_| 0)
+            } else {
+                file_system.write(&mut entry.file, &ram[address..address+length])
+            };
+            let written = result.unwrap_or_else(|error| {
+                println!("FS ({}): {} err=({})", cpu.cycle_counter, operation_name, error);
+                0
+            });
+            fcb.file_size = if count == 0 { position as u32 } else { fcb.file_size.max((position as usize+written) as u32) };
+            (if written < length { 1 } else { 0 }, (written/record_size) as u16) // Disk full
+        } else {
+            let read = file_system.read(&mut entry.file, &mut ram[address..address+length]).unwrap_or_else(|error| {
+                println!("FS ({}): {} err=({})", cpu.cycle_counter, operation_name, error);
+                0
+            });
+            let records = (read+record_size-1)/record_size;
+            ram[address+read..address+records*record_size].iter_mut().for_each(|byte| *byte = 0);
+            cpu.instruction_cache.invalidate(address, length);
+            (if read == length { 0 } else if read%record_size != 0 { 3 } else { 1 }, records as u16) // Partial record / End of file
+        }
+    }
+
+    /// Writes a directory entry found by FCB FindFirst / FindNext to the DTA as an unopened FCB, extended like the search FCB
+    fn write_found_fcb(ram: &mut [u8], mut address: usize, extended_attribute: Option<u8>, drive: u8, entry: &crate::file_system::DirectoryEntry) {
+        if let Some(attribute) = extended_attribute {
+            ram[address..address+7].copy_from_slice(&[0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, attribute]);
+            address += 7;
+        }
+        ram[address] = drive+1;
+        ram[address+1..address+12].copy_from_slice(&crate::name_mapping::to_fcb_form(&entry.short_name));
+        ram[address+12] = entry.attribute;
+        ram[address+13..address+23].iter_mut().for_each(|byte| *byte = 0);
+        crate::bit_utils::write_to_buffer_u16(&mut ram[address+23..], entry.time);
+        crate::bit_utils::write_to_buffer_u16(&mut ram[address+25..], entry.date);
+        crate::bit_utils::write_to_buffer_u16(&mut ram[address+27..], 0);
+        crate::bit_utils::write_to_buffer_u32(&mut ram[address+29..], entry.size);
+    }
+
+    /// Parses a file name like "A:NAME.EXT" into the drive and name fields of an FCB like AH=29h.
+    /// Returns the parsed length and 0, 1 if the name has wildcards or 0xFF if the drive is invalid.
+    fn parse_fcb_name(&self, text: &[u8], flags: u8, fcb: &mut [u8]) -> (usize, u8) {
+        let character = |position: usize| text.get(position).cloned().unwrap_or(0);
+        let is_terminator = |character: u8| character <= b' ' || b".\"/\\[]:|<>+=;,".contains(&character);
+        let is_blank = |character: u8| character == b' ' || character == b'\t';
+        let mut position = 0;
+        while is_blank(character(position)) {
+            position += 1;
+        }
+        if flags&0x01 != 0 && b":.;,=+".contains(&character(position)) { // Skip a leading separator
+            position += 1;
+            while is_blank(character(position)) {
+                position += 1;
+            }
+        }
+        let mut result = 0;
+        if character(position).is_ascii_alphabetic() && character(position+1) == b':' {
+            let drive = character(position).to_ascii_uppercase()-b'A';
+            if !self.is_drive_mounted(drive) {
+                result = 0xFF;
+            }
+            fcb[0] = drive+1;
+            position += 2;
+        } else if flags&0x02 == 0 {
+            fcb[0] = 0;
+        }
+        for &(begin, length, keep_flag) in [(1, 8, 0x04), (9, 3, 0x08)].iter() {
+            let present = if begin == 1 {
+                !is_terminator(character(position))
+            } else if character(position) == b'.' {
+                position += 1;
+                true
+            } else {
+                false
+            };
+            if !present {
+                if flags&keep_flag == 0 {
+                    fcb[begin..begin+length].iter_mut().for_each(|byte| *byte = b' ');
+                }
+                continue;
+            }
+            let mut part = vec![b' '; length];
+            let mut index = 0;
+            while !is_terminator(character(position)) {
+                if character(position) == b'*' && index < length {
+                    part[index..].iter_mut().for_each(|byte| *byte = b'?');
+                    index = length;
+                } else if index < length {
+                    part[index] = character(position).to_ascii_uppercase();
+                    index += 1;
+                }
+                position += 1;
+            }
+            fcb[begin..begin+length].copy_from_slice(&part);
+        }
+        if result == 0 && fcb[1..12].contains(&b'?') {
+            result = 1;
+        }
+        (position, result)
+    }
+
+    fn get_mcb(segment: u16, ram: &mut [u8]) -> &mut MemoryControlBlock {
+        unsafe { &mut *std::mem::transmute::<*mut u8, *mut MemoryControlBlock>(&mut ram[(segment as usize) << 4] as *mut u8) }
+    }
+
+    fn write_mcb(ram: &mut [u8], segment: u16, signature: u8, owner: u16, size: u16, name: &[u8]) {
+        let mcb = Self::get_mcb(segment, ram);
+        mcb.signature = signature;
+        mcb.owner = owner;
+        mcb.size = size;
+        mcb.reserved = [0; 3];
+        mcb.name = [0; 8];
+        mcb.name[0..name.len().min(8)].copy_from_slice(&name[0..name.len().min(8)]);
+    }
+
+    /// Segments of all MCBs in the chain, or error 7 (Memory control blocks destroyed)
+    fn mcb_chain(&self, ram: &mut [u8]) -> Result<Vec<u16>, u16> {
+        let mut chain = Vec::new();
+        let mut segment = FIRST_MCB_SEGMENT;
+        loop {
+            let mcb = Self::get_mcb(segment, ram);
+            let (signature, size) = (mcb.signature, mcb.size);
+            if signature != b'M' && signature != b'Z' {
+                return Err(7);
+            }
+            chain.push(segment);
+            if signature == b'Z' {
+                return Ok(chain);
+            }
+            segment = match size.checked_add(1).and_then(|paragraphs| segment.checked_add(paragraphs)) {
+                Some(next_segment) if next_segment > segment && next_segment <= self.memory_end => next_segment,
+                _ => { return Err(7); }
+            };
+        }
+    }
+
+    /// Merges every free block with the free blocks directly following it
+    fn coalesce_free_blocks(&self, ram: &mut [u8]) -> Result<(), u16> {
+        let chain = self.mcb_chain(ram)?;
+        let mut index = 0;
+        while index < chain.len() {
+            let segment = chain[index];
+            index += 1;
+            if Self::get_mcb(segment, ram).owner != 0 {
+                continue;
+            }
+            while index < chain.len() && Self::get_mcb(chain[index], ram).owner == 0 {
+                let (signature, size) = { let next = Self::get_mcb(chain[index], ram); (next.signature, next.size) };
+                let mcb = Self::get_mcb(segment, ram);
+                mcb.signature = signature;
+                mcb.size += size+1;
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits the block so that it keeps the given size and the rest becomes a free block
+    fn split_block(ram: &mut [u8], segment: u16, paragraphs: u16) {
+        let (signature, size) = { let mcb = Self::get_mcb(segment, ram); (mcb.signature, mcb.size) };
+        if size <= paragraphs {
+            return;
+        }
+        Self::write_mcb(ram, segment+paragraphs+1, signature, 0, size-paragraphs-1, &[]);
+        let mcb = Self::get_mcb(segment, ram);
+        mcb.signature = b'M';
+        mcb.size = paragraphs;
+    }
+
+    /// Returns the segment of the new block, or the error code and the size of the largest free block
+    fn allocate_memory(&self, ram: &mut [u8], paragraphs: u16) -> Result<u16, (u16, u16)> {
+        self.coalesce_free_blocks(ram).map_err(|error| (error, 0))?;
+        let mut largest = 0;
+        let mut candidate: Option<u16> = None;
+        for segment in self.mcb_chain(ram).map_err(|error| (error, 0))? {
+            let mcb = Self::get_mcb(segment, ram);
+            let size = mcb.size;
+            if mcb.owner != 0 {
+                continue;
+            }
+            largest = largest.max(size);
+            if size < paragraphs {
+                continue;
+            }
+            candidate = match (candidate, self.allocation_strategy) {
+                (Some(_), AllocationStrategy::FirstFit) => candidate,
+                (Some(best), AllocationStrategy::BestFit) if Self::get_mcb(best, ram).size <= size => candidate,
+                _ => Some(segment)
+            };
+        }
+        let mut segment = match candidate {
+            Some(segment) => segment,
+            None => { return Err((8, largest)); } // Insufficient memory
+        };
+        let size = Self::get_mcb(segment, ram).size;
+        if self.allocation_strategy == AllocationStrategy::LastFit && size > paragraphs {
+            Self::split_block(ram, segment, size-paragraphs-1);
+            segment += size-paragraphs;
+        } else {
+            Self::split_block(ram, segment, paragraphs);
+        }
+        let mcb = Self::get_mcb(segment, ram);
+        mcb.owner = self.psp_segment;
+        mcb.name = [0; 8];
+        Ok(segment+1)
+    }
+
+    fn free_memory(&self, ram: &mut [u8], segment: u16) -> Result<(), u16> {
+        if !self.mcb_chain(ram)?.contains(&segment.wrapping_sub(1)) || Self::get_mcb(segment-1, ram).owner == 0 {
+            return Err(9); // Memory block address invalid
+        }
+        Self::get_mcb(segment-1, ram).owner = 0;
+        self.coalesce_free_blocks(ram)
+    }
+
+    /// Returns the error code and the maximum size the block could grow to
+    fn resize_memory_block(&self, ram: &mut [u8], segment: u16, paragraphs: u16) -> Result<(), (u16, u16)> {
+        let chain = self.mcb_chain(ram).map_err(|error| (error, 0))?;
+        let index = match chain.iter().position(|mcb_segment| *mcb_segment == segment.wrapping_sub(1)) {
+            Some(index) if Self::get_mcb(chain[index], ram).owner != 0 => index,
+            _ => { return Err((9, 0)); } // Memory block address invalid
+        };
+        self.coalesce_free_blocks(ram).map_err(|error| (error, 0))?;
+        let mcb_segment = chain[index];
+        let (signature, size) = { let mcb = Self::get_mcb(mcb_segment, ram); (mcb.signature, mcb.size) };
+        let mut available = size;
+        if signature == b'M' && Self::get_mcb(mcb_segment+size+1, ram).owner == 0 {
+            let next = Self::get_mcb(mcb_segment+size+1, ram);
+            let (next_signature, next_size) = (next.signature, next.size);
+            available += next_size+1;
+            if paragraphs > size && paragraphs <= available {
+                let mcb = Self::get_mcb(mcb_segment, ram);
+                mcb.signature = next_signature;
+                mcb.size = available;
+            }
+        }
+        if paragraphs > available {
+            return Err((8, available)); // Insufficient memory
+        }
+        Self::split_block(ram, mcb_segment, paragraphs);
+        self.coalesce_free_blocks(ram).map_err(|error| (error, 0))
+    }
+
     pub fn load_executable(&mut self, cpu: &mut crate::cpu::CPU, ram: &mut [u8], executable_path: &std::path::Path) -> std::io::Result<()> {
         let mut file = std::fs::File::open(executable_path)?;
         let mut mz_dos: MZDOS = unsafe { mem::zeroed() };
@@ -165,7 +991,7 @@ impl DiskOperatingSystem {
             cpu.set_register(Operand::CS, mz_dos.initial_cs+(self.load_segment as u16));
             cpu.set_register(Operand::SS, mz_dos.initial_ss+self.load_segment);
             cpu.set_register(Operand::DS, self.psp_segment);
-            cpu.instruction.position = mz_dos.initial_ip;
+            cpu.instruction.position = mz_dos.initial_ip as u32;
         }
         // Load Code from File
         {
@@ -200,7 +1026,7 @@ impl DiskOperatingSystem {
         {
             let psp = Self::get_psp(self.psp_segment as usize, ram);
             psp.int20 = [0xCD, 0x20];
-            psp.allocation_end = 0x9FFF; // mz_dos.minimum_allocation;
+            psp.allocation_end = self.memory_end; // mz_dos.minimum_allocation;
             psp.reserved0 = [0x00];
             psp.int21 = [0xEA, 0xFF, 0xFF, 0xAD, 0xDE];
             psp.parent_psp_segment = 0x0118;
@@ -222,10 +1048,23 @@ impl DiskOperatingSystem {
             psp.fcb1_extension = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
             psp.fcb1 = [0x00, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x00, 0x00, 0x00, 0x00];
             psp.fcb2 = [0x00, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+            // Like COMMAND.COM, the first two arguments are parsed into the FCBs
+            let mut fcb = [0; 12];
+            fcb.copy_from_slice(&psp.fcb1[0..12]);
+            let (length, _) = self.parse_fcb_name(arguments.as_bytes(), 0x01, &mut fcb);
+            psp.fcb1[0..12].copy_from_slice(&fcb);
+            fcb.copy_from_slice(&psp.fcb2[0..12]);
+            self.parse_fcb_name(&arguments.as_bytes()[length..], 0x01, &mut fcb);
+            psp.fcb2[0..12].copy_from_slice(&fcb);
             psp.parameter_length = arguments.len() as u8;
             psp.parameter[0..arguments.len()].copy_from_slice(arguments.as_bytes());
             psp.parameter[psp.parameter_length as usize] = 0x0D;
-            let path = std::path::Path::new("C:").join(executable_path.strip_prefix(&self.mount_point_c).unwrap()).to_str().unwrap().replace("/", "\\");
+            let (drive, mut components) = self.dos_path(executable_path).expect("Executable is not inside a mounted drive");
+            let path = Self::display_path(drive, &components);
+            // Start in the directory of the executable, like a shell would after changing into it
+            components.pop();
+            self.current_drive = drive;
+            self.current_directories[drive as usize] = components;
             let mut environment_segment = std::string::String::new();
             for environment in environments {
                 environment_segment.push_str(environment);
@@ -237,43 +1076,23 @@ impl DiskOperatingSystem {
             let environment_segment_address = crate::bus::BUS::physical_address(psp.environment_segment, 0);
             ram[environment_segment_address..environment_segment_address+environment_segment_data.len()].copy_from_slice(environment_segment_data);
         }
-        // Setup BIOS
-        crate::bios::BIOS::from_ram(ram).setup();
-        // Setup Interrupt Vector
-        let interrupt_vector = unsafe { crate::bit_utils::transmute_slice_mut::<u8, u32>(&mut ram[0..]) };
-        interrupt_vector[0x08] = 0xF000FEA5;
-        interrupt_vector[0x09] = 0xF000E987;
-        interrupt_vector[0x10] = 0xF000F065;
-        interrupt_vector[0x11] = 0xF000F84D;
-        interrupt_vector[0x1A] = 0xF000FE6E;
-    }
-
-    fn find_next_directory_entry<'a>(&mut self, pattern: &'a str) -> Option<std::path::PathBuf> {
-        let read_directory = self.read_directory.as_mut().unwrap();
-        match pattern.find("*") {
-            Some(index) => {
-                let prefix = &pattern[0..index];
-                let suffix = &pattern[index+1..];
-                loop {
-                    match read_directory.next() {
-                        Some(entry_result) => {
-                            let entry = entry_result.unwrap();
-                            let file_name = entry.file_name();
-                            let file_name_str = file_name.to_str().unwrap();
-                            if file_name_str.starts_with(prefix) && file_name_str.ends_with(suffix) {
-                                return Some(entry.path());
-                            }
-                        },
-                        None => { break; }
-                    }
-                }
-            },
-            None => {}
+        // Setup Memory Control Blocks
+        {
+            let program_name = executable_path.file_stem().unwrap().to_str().unwrap().to_uppercase();
+            let psp = Self::get_psp(self.psp_segment as usize, ram);
+            let (parent_psp_segment, environment_segment) = (psp.parent_psp_segment, psp.environment_segment);
+            Self::write_mcb(ram, FIRST_MCB_SEGMENT, b'M', parent_psp_segment, environment_segment-FIRST_MCB_SEGMENT-2, b"COMMAND");
+            Self::write_mcb(ram, environment_segment-1, b'M', self.psp_segment, self.psp_segment-environment_segment-1, program_name.as_bytes());
+            Self::write_mcb(ram, self.psp_segment-1, b'Z', self.psp_segment, self.memory_end-self.psp_segment+1, program_name.as_bytes());
+            let list_of_lists_address = crate::bus::BUS::physical_address(LIST_OF_LISTS.0, LIST_OF_LISTS.1);
+            crate::bit_utils::write_to_buffer_u16(&mut ram[list_of_lists_address-2..], FIRST_MCB_SEGMENT);
         }
-        return None;
+        // Setup BIOS
+        crate::bios::BIOS::from_ram(ram).setup(((self.memory_end as u32+1)>>6) as u16);
+        crate::bios::BIOS::setup_interrupt_vector(ram, false);
     }
 
-    pub fn handle_interrupt(&mut self, cpu: &mut crate::cpu::CPU, ram: &mut [u8], terminate: &mut bool) {
+    pub fn handle_interrupt(&mut self, cpu: &mut crate::cpu::CPU, ram: &mut [u8], vga: &mut crate::vga::VideoGraphicsArray, terminate: &mut bool) {
         let argument = cpu.get_register(Operand::AL);
         match cpu.get_register(Operand::AH) {
             0x00 => { // Exit
@@ -292,14 +1111,272 @@ impl DiskOperatingSystem {
                     self.keyboard_spill = if keycode == 0 || keycode&0xFF != 0 { 0 } else { (keycode>>8) as u8 };
                 }
             },
+            0x0E => { // Select Default Drive
+                let drive = cpu.get_register(Operand::DL) as u8;
+                if self.is_drive_mounted(drive) {
+                    self.current_drive = drive;
+                }
+                cpu.set_register(Operand::AL, DRIVE_COUNT as u16);
+                println!("FS ({}): Select Default Drive drive={}", cpu.cycle_counter, (b'A'+self.current_drive) as char);
+            },
+            0x0F | 0x16 => { // Open File / Create or Truncate File with FCB
+                let create = cpu.get_register(Operand::AH) == 0x16;
+                let operation_name = if create { "FCB Create File" } else { "FCB Open File" };
+                let (address, extended_attribute) = Self::fcb_address(cpu, ram);
+                let mut fcb = Self::read_fcb(ram, address);
+                cpu.set_register(Operand::AL, 0xFF);
+                let (drive, components) = match self.fcb_path(&fcb) {
+                    Some(path) => path,
+                    None => {
+                        println!("FS ({}): {} err=(Invalid drive or file name)", cpu.cycle_counter, operation_name);
+                        return;
+                    }
+                };
+                let path = Self::display_path(drive, &components);
+                let writable = self.is_drive_writable(drive);
+                if (create && !writable) || !self.allows_sharing(drive, &components, if writable { 0x02 } else { 0x00 }) {
+                    println!("FS ({}): {} path={} err=(Access denied)", cpu.cycle_counter, operation_name, path);
+                    return;
+                }
+                // Files are opened for reading and writing, unless they or their drive are read only
+                let result = if create {
+                    self.file_system(drive).create_file(&components).map(|file| (file, 0x02))
+                } else {
+                    match self.file_system(drive).open_file(&components, writable) {
+                        Ok(file) => Ok((file, if writable { 0x02 } else { 0x00 })),
+                        Err(_) if writable => self.file_system(drive).open_file(&components, false).map(|file| (file, 0x00)),
+                        Err(error) => Err(error)
+                    }
+                };
+                match result.and_then(|(file, mode)| self.open_fcb(&mut fcb, drive, components.clone(), mode, file)) {
+                    Ok(_) => {
+                        println!("FS ({}): {} path={}", cpu.cycle_counter, operation_name, path);
+                        if let (true, Some(attribute)) = (create, extended_attribute) {
+                            if attribute&0x07 != 0 {
+                                let _ = self.file_system(drive).set_attributes(&components, attribute);
+                            }
+                        }
+                        Self::write_fcb(ram, address, &fcb);
+                        cpu.set_register(Operand::AL, 0);
+                    },
+                    Err(error) => {
+                        println!("FS ({}): {} path={} err=({})", cpu.cycle_counter, operation_name, path, error);
+                    }
+                }
+            },
+            0x10 => { // Close File with FCB
+                let (address, _) = Self::fcb_address(cpu, ram);
+                let fcb = Self::read_fcb(ram, address);
+                match self.fcb_file(&fcb) {
+                    Some(index) => {
+                        let entry = self.system_file_table[index].take().unwrap();
+                        println!("FS ({}): FCB Close File path={}", cpu.cycle_counter, Self::display_path(entry.drive, &entry.components));
+                        cpu.set_register(Operand::AL, 0);
+                    },
+                    None => {
+                        println!("FS ({}): FCB Close File err=(File not open)", cpu.cycle_counter);
+                        cpu.set_register(Operand::AL, 0xFF);
+                    }
+                }
+            },
+            0x11 | 0x12 => { // Find First / Next Matching File with FCB
+                let find_first = cpu.get_register(Operand::AH) == 0x11;
+                let operation_name = if find_first { "FCB Find First Matching File" } else { "FCB Find Next Matching File" };
+                let (address, extended_attribute) = Self::fcb_address(cpu, ram);
+                let mut fcb = Self::read_fcb(ram, address);
+                let search_attribute = extended_attribute.unwrap_or(0);
+                let dta_address = crate::bus::BUS::physical_address((self.dta_address>>16) as u16, self.dta_address as u16);
+                cpu.set_register(Operand::AL, 0xFF);
+                if find_first {
+                    let (drive, mut components) = match self.fcb_path(&fcb) {
+                        Some(path) => path,
+                        None => {
+                            println!("FS ({}): {} err=(Invalid drive or file name)", cpu.cycle_counter, operation_name);
+                            return;
+                        }
+                    };
+                    components.pop();
+                    if search_attribute == 0x08 { // Volume Label
+                        let label = self.volume_label(drive);
+                        if components.is_empty() && !label.is_empty() && crate::name_mapping::matches_pattern(&label, &Self::fcb_pattern(&fcb)) {
+                            let entry = crate::file_system::DirectoryEntry { short_name: label, attribute: 0x08, time: 0, date: 0, size: 0 };
+                            Self::write_found_fcb(ram, dta_address, extended_attribute, drive, &entry);
+                            cpu.set_register(Operand::AL, 0);
+                        }
+                        return;
+                    }
+                    let entries = match self.file_system(drive).list_directory(&components) {
+                        Ok(entries) => entries,
+                        Err(_) => {
+                            println!("FS ({}): {} path={} err=(Path not found)", cpu.cycle_counter, operation_name, Self::display_path(drive, &components));
+                            return;
+                        }
+                    };
+                    fcb.search_index = self.add_search_directory(drive, components, entries) as u16;
+                    fcb.search_entry_index = 0;
+                }
+                let (drive, components, entries) = match self.search_directories.get(fcb.search_index as usize) {
+                    Some(search) => search,
+                    None => { return; }
+                };
+                let pattern = Self::fcb_pattern(&fcb);
+                let next_entry = entries.iter().enumerate().skip(fcb.search_entry_index as usize)
+                    .find(|(_, entry)| crate::name_mapping::matches_pattern(&entry.short_name, &pattern) && entry.attribute&0x16&!search_attribute == 0);
+                match next_entry {
+                    Some((index, entry)) => {
+                        println!("FS ({}): {} path={} name={}", cpu.cycle_counter, operation_name, Self::display_path(*drive, components), entry.short_name);
+                        Self::write_found_fcb(ram, dta_address, extended_attribute, *drive, entry);
+                        fcb.search_entry_index = index as u16+1;
+                        cpu.set_register(Operand::AL, 0);
+                    },
+                    None => {
+                        fcb.search_entry_index = entries.len() as u16;
+                    }
+                }
+                Self::write_fcb(ram, address, &fcb);
+            },
+            0x13 | 0x17 => { // Delete File / Rename File with FCB
+                let rename = cpu.get_register(Operand::AH) == 0x17;
+                let operation_name = if rename { "FCB Rename File" } else { "FCB Delete File" };
+                let (address, extended_attribute) = Self::fcb_address(cpu, ram);
+                let fcb = Self::read_fcb(ram, address);
+                let search_attribute = extended_attribute.unwrap_or(0);
+                cpu.set_register(Operand::AL, 0xFF);
+                let (drive, mut components) = match self.fcb_path(&fcb) {
+                    Some(path) if self.is_drive_writable(path.0) => path,
+                    _ => {
+                        println!("FS ({}): {} err=(Invalid or read only drive or invalid file name)", cpu.cycle_counter, operation_name);
+                        return;
+                    }
+                };
+                components.pop();
+                let pattern = Self::fcb_pattern(&fcb);
+                let names: Vec<String> = match self.file_system(drive).list_directory(&components) {
+                    Ok(entries) => entries.into_iter()
+                        .filter(|entry| entry.attribute&0x08 == 0 && entry.attribute&0x16&!search_attribute == 0 && !entry.short_name.starts_with('.'))
+                        .filter(|entry| crate::name_mapping::matches_pattern(&entry.short_name, &pattern))
+                        .map(|entry| entry.short_name)
+                        .collect(),
+                    Err(_) => Vec::new()
+                };
+                // The new name of a rename follows the old one at offset 11h, where "?" keeps the character of the old name
+                let mut new_pattern = [0; 11];
+                new_pattern.copy_from_slice(&ram[address+0x11..address+0x1C]);
+                for name in names {
+                    let mut path = components.clone();
+                    path.push(name.clone());
+                    let result = if rename {
+                        let mut new_name = crate::name_mapping::to_fcb_form(&name);
+                        new_name.iter_mut().zip(new_pattern.iter()).filter(|(_, character)| **character != b'?')
+                            .for_each(|(new_character, character)| *new_character = character.to_ascii_uppercase());
+                        let mut new_path = components.clone();
+                        new_path.push(crate::name_mapping::from_fcb_form(&new_name));
+                        if crate::name_mapping::is_valid_component(new_path.last().unwrap()) {
+                            self.file_system(drive).rename(&path, &new_path)
+                        } else {
+                            Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid file name"))
+                        }
+                    } else {
+                        self.file_system(drive).delete_file(&path)
+                    };
+                    match result {
+                        Ok(_) => {
+                            println!("FS ({}): {} path={}", cpu.cycle_counter, operation_name, Self::display_path(drive, &path));
+                            cpu.set_register(Operand::AL, 0);
+                        },
+                        Err(error) => {
+                            println!("FS ({}): {} path={} err=({})", cpu.cycle_counter, operation_name, Self::display_path(drive, &path), error);
+                            cpu.set_register(Operand::AL, 0xFF);
+                            return;
+                        }
+                    }
+                }
+            },
+            0x14 | 0x15 => { // Sequential Read / Write with FCB
+                let write = cpu.get_register(Operand::AH) == 0x15;
+                let (address, _) = Self::fcb_address(cpu, ram);
+                let mut fcb = Self::read_fcb(ram, address);
+                let record = fcb.current_record_number();
+                let (status, records) = self.transfer_fcb_records(cpu, ram, &mut fcb, record, 1, write);
+                fcb.set_current_record_number(record+records as u32);
+                Self::write_fcb(ram, address, &fcb);
+                cpu.set_register(Operand::AL, status as u16);
+            },
+            0x19 => { // Get Default Drive
+                cpu.set_register(Operand::AL, self.current_drive as u16);
+            },
             0x1A => { // Set DTA address
                 self.dta_address = ((cpu.get_register(Operand::DS) as u32)<<16)+(cpu.get_register(Operand::DX) as u32);
             },
+            0x21 | 0x22 => { // Random Read / Write with FCB
+                let write = cpu.get_register(Operand::AH) == 0x22;
+                let (address, _) = Self::fcb_address(cpu, ram);
+                let mut fcb = Self::read_fcb(ram, address);
+                let record = fcb.random_record_number();
+                fcb.set_current_record_number(record);
+                let (status, _) = self.transfer_fcb_records(cpu, ram, &mut fcb, record, 1, write);
+                Self::write_fcb(ram, address, &fcb);
+                cpu.set_register(Operand::AL, status as u16);
+            },
+            0x23 => { // Get File Size with FCB
+                let (address, _) = Self::fcb_address(cpu, ram);
+                let mut fcb = Self::read_fcb(ram, address);
+                cpu.set_register(Operand::AL, 0xFF);
+                let (drive, mut components) = match self.fcb_path(&fcb) {
+                    Some(path) => path,
+                    None => { return; }
+                };
+                let name = components.pop().unwrap();
+                let size = self.file_system(drive).list_directory(&components).ok()
+                    .and_then(|entries| entries.into_iter().find(|entry| entry.short_name == name && entry.attribute&0x18 == 0).map(|entry| entry.size));
+                components.push(name);
+                match size {
+                    Some(size) => {
+                        println!("FS ({}): FCB Get File Size path={} size={}", cpu.cycle_counter, Self::display_path(drive, &components), size);
+                        let record_size = fcb.record_size();
+                        fcb.set_random_record_number((size+record_size-1)/record_size);
+                        Self::write_fcb(ram, address, &fcb);
+                        cpu.set_register(Operand::AL, 0);
+                    },
+                    None => {
+                        println!("FS ({}): FCB Get File Size path={} err=(File not found)", cpu.cycle_counter, Self::display_path(drive, &components));
+                    }
+                }
+            },
+            0x24 => { // Set Random Record Number with FCB
+                let (address, _) = Self::fcb_address(cpu, ram);
+                let mut fcb = Self::read_fcb(ram, address);
+                fcb.set_random_record_number(fcb.current_record_number());
+                Self::write_fcb(ram, address, &fcb);
+            },
             0x25 => { // Set Interrupt Handler
                 crate::bit_utils::write_to_buffer_u16(&mut ram[argument as usize*4..], cpu.get_register(Operand::DX));
                 crate::bit_utils::write_to_buffer_u16(&mut ram[argument as usize*4+2..], cpu.get_register(Operand::DS));
                 println!("DOS ({}): Set interrupt={:#04X} handler={:04X}:{:04X}", cpu.cycle_counter, argument, cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
             },
+            0x27 | 0x28 => { // Random Block Read / Write with FCB
+                let write = cpu.get_register(Operand::AH) == 0x28;
+                let (address, _) = Self::fcb_address(cpu, ram);
+                let mut fcb = Self::read_fcb(ram, address);
+                let record = fcb.random_record_number();
+                let (status, records) = self.transfer_fcb_records(cpu, ram, &mut fcb, record, cpu.get_register(Operand::CX), write);
+                fcb.set_random_record_number(record+records as u32);
+                fcb.set_current_record_number(record+records as u32);
+                Self::write_fcb(ram, address, &fcb);
+                cpu.set_register(Operand::AL, status as u16);
+                cpu.set_register(Operand::CX, records);
+            },
+            0x29 => { // Parse Filename into FCB
+                let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::SI));
+                let fcb_address = crate::bus::BUS::physical_address(cpu.get_register(Operand::ES), cpu.get_register(Operand::DI));
+                let text = ram[address..(address+128).min(ram.len())].to_vec();
+                let mut fcb = [0; 12];
+                fcb.copy_from_slice(&ram[fcb_address..fcb_address+12]);
+                let (length, result) = self.parse_fcb_name(&text, argument as u8, &mut fcb);
+                ram[fcb_address..fcb_address+12].copy_from_slice(&fcb);
+                cpu.set_register(Operand::SI, cpu.get_register(Operand::SI).wrapping_add(length as u16));
+                cpu.set_register(Operand::AL, result as u16);
+            },
             0x2F => { // Get DTA address
                 cpu.set_register(Operand::BX, self.dta_address as u16);
                 cpu.set_register(Operand::ES, (self.dta_address>>16) as u16);
@@ -315,44 +1392,97 @@ impl DiskOperatingSystem {
                 cpu.set_register(Operand::ES, (address>>16) as u16);
                 println!("DOS ({}): Get interrupt={:#04X} handler={:04X}:{:04X}", cpu.cycle_counter, argument, cpu.get_register(Operand::BX), cpu.get_register(Operand::ES));
             },
-            0x3C => { // Create or Truncate File
+            0x36 => { // Get Free Disk Space
+                let drive = match cpu.get_register(Operand::DL) as u8 {
+                    0 => self.current_drive,
+                    drive => drive-1
+                };
+                match self.free_disk_space(drive) {
+                    Some((sectors_per_cluster, bytes_per_sector, free_clusters, total_clusters)) => {
+                        cpu.set_register(Operand::AX, sectors_per_cluster);
+                        cpu.set_register(Operand::BX, free_clusters);
+                        cpu.set_register(Operand::CX, bytes_per_sector);
+                        cpu.set_register(Operand::DX, total_clusters);
+                    },
+                    None => {
+                        cpu.set_register(Operand::AX, 0xFFFF); // Invalid drive
+                    }
+                }
+            },
+            0x39 => { // Create Directory
+                cpu.set_flag(Flag::Carry, 1);
+                let (drive, components) = get_path!(self, cpu, ram, writable);
+                access_path!(Self::display_path(drive, &components), cpu, _result, "Create Directory", self.file_system(drive).create_directory(&components), {});
+            },
+            0x3A => { // Remove Directory
+                cpu.set_flag(Flag::Carry, 1);
+                let (drive, components) = get_path!(self, cpu, ram, writable);
+                if self.current_directories[drive as usize].starts_with(&components) {
+                    println!("FS ({}): Remove Directory err=(Attempt to remove current directory)", cpu.cycle_counter);
+                    cpu.set_register(Operand::AX, 0x10);
+                    return;
+                }
+                access_path!(Self::display_path(drive, &components), cpu, _result, "Remove Directory", self.file_system(drive).remove_directory(&components), {});
+            },
+            0x3B => { // Set Current Directory
+                cpu.set_flag(Flag::Carry, 1);
+                let (drive, components) = get_path!(self, cpu, ram);
+                let path = Self::display_path(drive, &components);
+                if !self.file_system(drive).is_directory(&components) {
+                    println!("FS ({}): Set Current Directory path={} err=(Path not found)", cpu.cycle_counter, path);
+                    cpu.set_register(Operand::AX, 3); // Path not found
+                    return;
+                }
+                println!("FS ({}): Set Current Directory path={}", cpu.cycle_counter, path);
+                self.current_directories[drive as usize] = components;
+                cpu.reset_flag(Flag::Carry);
+            },
+            0x3C | 0x5B => { // Create or Truncate File / Create New File
+                let operation_name = if cpu.get_register(Operand::AH) == 0x3C { "Create or Truncate File" } else { "Create New File" };
                 cpu.set_flag(Flag::Carry, 1);
-                if self.open_handles.len() >= 15 {
-                    println!("FS ({}): Create or Truncate File err=(No handle available)", cpu.cycle_counter);
+                if self.free_handle().is_none() {
+                    println!("FS ({}): {} err=(No handle available)", cpu.cycle_counter, operation_name);
                     cpu.set_register(Operand::AX, 4);
                     return;
                 }
-                access_path!(get_path!(self, cpu, ram), cpu, file, "Create or Truncate File", std::fs::File::create, {
-                    for i in 5..20 {
-                        if !self.open_handles.contains_key(&i) {
-                            cpu.set_register(Operand::AX, i);
-                            break;
-                        }
-                    }
-                    self.open_handles.insert(cpu.get_register(Operand::AX), file);
-                });
+                if self.open_device_handle(cpu, ram, operation_name) {
+                    return;
+                }
+                let (drive, components) = get_path!(self, cpu, ram, writable);
+                let attribute = cpu.get_register(Operand::CL) as u8;
+                self.create_file_handle(cpu, operation_name, drive, components, attribute, operation_name == "Create New File");
             },
             0x3D => { // Open Existing File
                 cpu.set_flag(Flag::Carry, 1);
-                if self.open_handles.len() >= 15 {
+                if argument&0x07 > 2 || (argument>>4)&0x07 > 4 {
+                    println!("FS ({}): Open Existing File mode={:02X} err=(Invalid access code)", cpu.cycle_counter, argument);
+                    cpu.set_register(Operand::AX, 0x0C);
+                    return;
+                }
+                if self.free_handle().is_none() {
                     println!("FS ({}): Open Existing File err=(No handle available)", cpu.cycle_counter);
                     cpu.set_register(Operand::AX, 4);
                     return;
                 }
-                access_path!(get_path!(self, cpu, ram), cpu, file, "Open Existing File", std::fs::File::open, {
-                    for i in 5..20 {
-                        if !self.open_handles.contains_key(&i) {
-                            cpu.set_register(Operand::AX, i);
-                            break;
-                        }
-                    }
-                    self.open_handles.insert(cpu.get_register(Operand::AX), file);
+                if self.open_device_handle(cpu, ram, "Open Existing File") {
+                    return;
+                }
+                let writable = argument&0x07 != 0;
+                let (drive, components) = get_path!(self, cpu, ram, writable);
+                let path = Self::display_path(drive, &components);
+                if !self.allows_sharing(drive, &components, argument as u8) {
+                    println!("FS ({}): Open Existing File path={} mode={:02X} err=(Sharing violation)", cpu.cycle_counter, path, argument);
+                    cpu.set_register(Operand::AX, 0x20);
+                    return;
+                }
+                access_path!(path, cpu, file, "Open Existing File", self.file_system(drive).open_file(&components, writable), {
+                    let handle = self.insert_handle(drive, components, argument as u8&0x77, file);
+                    cpu.set_register(Operand::AX, handle);
                 });
             },
             0x3E => { // Close File
-                if self.open_handles.contains_key(&cpu.get_register(Operand::BX)) {
+                if self.close_handle(cpu.get_register(Operand::BX)) {
                     println!("FS ({}): Close File fd={}", cpu.cycle_counter, cpu.get_register(Operand::BX));
-                    self.open_handles.remove(&cpu.get_register(Operand::BX));
                     cpu.reset_flag(Flag::Carry);
                 } else {
                     println!("FS ({}): Close File fd={} err=(Invalid handle)", cpu.cycle_counter, cpu.get_register(Operand::BX));
@@ -361,53 +1491,118 @@ impl DiskOperatingSystem {
                 }
             },
             0x3F => { // Read From File
-                match self.open_handles.get(&cpu.get_register(Operand::BX)) {
-                    Some(mut file) => {
+                cpu.set_flag(Flag::Carry, 1);
+                if let Some(device) = self.device_handles.get(&cpu.get_register(Operand::BX)).cloned() {
+                    let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
+                    let mut buffer = vec![0; cpu.get_register(Operand::CX) as usize];
+                    match self.read_device(ram, vga, device, &mut buffer) {
+                        Some(length) => {
+                            ram[address..address+length].copy_from_slice(&buffer[0..length]);
+                            cpu.instruction_cache.invalidate(address, length);
+                            cpu.set_register(Operand::AX, length as u16);
+                            cpu.reset_flag(Flag::Carry);
+                        },
+                        None => { // Repeat the INT once a key is pressed
+                            cpu.instruction.position = cpu.instruction.position.wrapping_sub(cpu.instruction.length as u32);
+                            cpu.execution_state = crate::cpu::ExecutionState::WaitForInterrupt;
+                        }
+                    }
+                    return;
+                }
+                match self.open_handles.get(&cpu.get_register(Operand::BX)).cloned() {
+                    Some(index) => {
+                        let entry = self.system_file_table[index].as_mut().unwrap();
+                        if !entry.is_readable() {
+                            println!("FS ({}): Read From File fd={} err=(Opened for writing only)", cpu.cycle_counter, cpu.get_register(Operand::BX));
+                            cpu.set_register(Operand::AX, 5); // Access denied
+                            return;
+                        }
                         let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
                         let length = cpu.get_register(Operand::CX) as usize;
-                        cpu.set_register(Operand::AX, file.read(&mut ram[address..address+length]).unwrap() as u16);
-                        cpu.reset_flag(Flag::Carry);
+                        let file_system = &mut self.drives[entry.drive as usize].as_mut().unwrap().file_system;
+                        match file_system.read(&mut entry.file, &mut ram[address..address+length]) {
+                            Ok(length) => {
+                                cpu.set_register(Operand::AX, length as u16);
+                                cpu.instruction_cache.invalidate(address, length);
+                                cpu.reset_flag(Flag::Carry);
+                            },
+                            Err(error) => {
+                                println!("FS ({}): Read From File fd={} err=({})", cpu.cycle_counter, cpu.get_register(Operand::BX), error);
+                                cpu.set_register(Operand::AX, 5); // Access denied
+                            }
+                        }
                     },
                     None => {
-                        cpu.set_flag(Flag::Carry, 1);
                         cpu.set_register(Operand::AX, 6); // Invalid handle
                     }
                 }
             },
             0x40 => { // Write To File
-                match self.open_handles.get(&cpu.get_register(Operand::BX)) {
-                    Some(mut file) => {
+                cpu.set_flag(Flag::Carry, 1);
+                if let Some(device) = self.device_handles.get(&cpu.get_register(Operand::BX)).cloned() {
+                    let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
+                    let length = self.write_device(cpu, ram, vga, device, address, cpu.get_register(Operand::CX) as usize);
+                    cpu.set_register(Operand::AX, length as u16);
+                    cpu.reset_flag(Flag::Carry);
+                    return;
+                }
+                match self.open_handles.get(&cpu.get_register(Operand::BX)).cloned() {
+                    Some(index) => {
+                        let entry = self.system_file_table[index].as_mut().unwrap();
+                        if !entry.is_writable() {
+                            println!("FS ({}): Write To File fd={} err=(Opened for reading only)", cpu.cycle_counter, cpu.get_register(Operand::BX));
+                            cpu.set_register(Operand::AX, 5); // Access denied
+                            return;
+                        }
                         let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
                         let length = cpu.get_register(Operand::CX) as usize;
-                        if length == 0 {
-                            file.set_len(file.seek(std::io::SeekFrom::Current(0)).unwrap()).unwrap();
-                            cpu.set_register(Operand::AX, 0);
+                        let file_system = &mut self.drives[entry.drive as usize].as_mut().unwrap().file_system;
+                        let result = if length == 0 {
+                            file_system.set_length(&mut entry.file).map(|_| 0)
                         } else {
-                            cpu.set_register(Operand::AX, file.write(&mut ram[address..address+length]).unwrap() as u16);
+                            file_system.write(&mut entry.file, &ram[address..address+length])
+                        };
+                        match result {
+                            Ok(length) => {
+                                cpu.set_register(Operand::AX, length as u16);
+                                cpu.reset_flag(Flag::Carry);
+                            },
+                            Err(error) => {
+                                println!("FS ({}): Write To File fd={} err=({})", cpu.cycle_counter, cpu.get_register(Operand::BX), error);
+                                cpu.set_register(Operand::AX, 5); // Access denied
+                            }
                         }
-                        cpu.reset_flag(Flag::Carry);
                     },
                     None => {
-                        cpu.set_flag(Flag::Carry, 1);
                         cpu.set_register(Operand::AX, 6); // Invalid handle
                     }
                 }
             },
             0x41 => { // Delete File
                 cpu.set_flag(Flag::Carry, 1);
-                if self.open_handles.len() >= 15 {
-                    cpu.set_register(Operand::AX, 4); // Too many open files (no handles available)
+                let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
+                if self.device_from_ptr(&ram[address..]).is_some() {
+                    println!("FS ({}): Delete File err=(Access denied: Device)", cpu.cycle_counter);
+                    cpu.set_register(Operand::AX, 5); // Access denied
                     return;
                 }
-                access_path!(get_path!(self, cpu, ram), cpu, _result, "Delete File", std::fs::remove_file, {});
+                let (drive, components) = get_path!(self, cpu, ram, writable);
+                access_path!(Self::display_path(drive, &components), cpu, _result, "Delete File", self.file_system(drive).delete_file(&components), {});
             },
             0x42 => { // Seek In File
                 cpu.set_flag(Flag::Carry, 1);
-                match self.open_handles.get(&cpu.get_register(Operand::BX)) {
-                    Some(mut file) => {
+                if self.device_handles.contains_key(&cpu.get_register(Operand::BX)) { // Devices stay at position 0
+                    cpu.set_register(Operand::DX, 0);
+                    cpu.set_register(Operand::AX, 0);
+                    cpu.reset_flag(Flag::Carry);
+                    return;
+                }
+                match self.open_handles.get(&cpu.get_register(Operand::BX)).cloned() {
+                    Some(index) => {
+                        let entry = self.system_file_table[index].as_mut().unwrap();
                         let in_position = (((cpu.get_register(Operand::CX) as u32)<<16)+cpu.get_register(Operand::DX) as u32) as i32;
                         let seek = match cpu.get_register(Operand::AX) as u8 {
-                            0 => std::io::SeekFrom::Start(in_position as u64),
+                            0 => std::io::SeekFrom::Start(in_position as u32 as u64),
                             1 => std::io::SeekFrom::Current(in_position as i64),
                             2 => std::io::SeekFrom::End(in_position as i64),
                             _ => {
@@ -415,7 +1610,15 @@ impl DiskOperatingSystem {
                                 return;
                             }
                         };
-                        let out_position = file.seek(seek).unwrap() as u32;
+                        let file_system = &mut self.drives[entry.drive as usize].as_mut().unwrap().file_system;
+                        let out_position = match file_system.seek(&mut entry.file, seek) {
+                            Ok(out_position) => out_position as u32,
+                            Err(error) => {
+                                println!("FS ({}): Seek In File fd={} pos={} err=({})", cpu.cycle_counter, cpu.get_register(Operand::BX), in_position, error);
+                                cpu.set_register(Operand::AX, 0x19); // Seek error
+                                return;
+                            }
+                        };
                         cpu.set_register(Operand::DX, (out_position>>16) as u16);
                         cpu.set_register(Operand::AX, out_position as u16);
                         cpu.reset_flag(Flag::Carry);
@@ -426,60 +1629,204 @@ impl DiskOperatingSystem {
                     }
                 }
             },
-            0x44 => { // Get Device Information
+            0x43 => { // Get or Set File Attributes
+                cpu.set_flag(Flag::Carry, 1);
+                match argument {
+                    0x00 => {
+                        let (drive, components) = get_path!(self, cpu, ram);
+                        access_path!(Self::display_path(drive, &components), cpu, attribute, "Get File Attributes", self.file_system(drive).attributes(&components), {
+                            cpu.set_register(Operand::CX, attribute as u16);
+                        });
+                    },
+                    0x01 => {
+                        let (drive, components) = get_path!(self, cpu, ram, writable);
+                        let attribute = cpu.get_register(Operand::CX);
+                        if attribute&!0x27 != 0 { // Only read only, hidden, system and archive can be set
+                            println!("FS ({}): Set File Attributes attribute={:04X} err=(Access denied)", cpu.cycle_counter, attribute);
+                            cpu.set_register(Operand::AX, 5);
+                            return;
+                        }
+                        access_path!(Self::display_path(drive, &components), cpu, _result, "Set File Attributes", self.file_system(drive).set_attributes(&components, attribute as u8), {});
+                    },
+                    _ => {
+                        cpu.set_register(Operand::AX, 1); // Invalid function
+                    }
+                }
+            },
+            0x44 if argument == 0x08 || argument == 0x09 => { // IOCTL of a block device
+                let drive = match cpu.get_register(Operand::BL) as u8 {
+                    0 => self.current_drive,
+                    drive => drive-1
+                };
+                let kind = match self.drives.get(drive as usize) {
+                    Some(Some(drive)) => drive.kind,
+                    _ => {
+                        cpu.set_flag(Flag::Carry, 1);
+                        cpu.set_register(Operand::AX, 0x0F); // Invalid drive
+                        return;
+                    }
+                };
                 cpu.reset_flag(Flag::Carry);
-                cpu.set_register(Operand::DX, 0x80D3);
-                cpu.set_register(Operand::AX, cpu.get_register(Operand::DX));
-                println!("FS ({}): Get Device Information fd={}", cpu.cycle_counter, cpu.get_register(Operand::BX));
-                if cpu.get_register(Operand::BX) >= 2 {
-                    panic!("FS ({}): Unsupported fd={}", cpu.cycle_counter, cpu.get_register(Operand::BX));
+                if argument == 0x08 { // Removable Media
+                    cpu.set_register(Operand::AX, (kind != DriveKind::Floppy) as u16);
+                } else { // Remote Device, MSCDEX drives are network redirector drives
+                    cpu.set_register(Operand::DX, if kind == DriveKind::CdRom { 0x1000 } else { 0x0000 });
                 }
             },
-            0x48 => { // Allocate Memory
-                let psp = Self::get_psp(self.psp_segment as usize, ram);
-                let paragraphs = cpu.get_register(Operand::BX);
-                let segment = psp.allocation_end;
-                let allocation_end = paragraphs as u32+self.psp_segment as u32;
-                if allocation_end < 0x9FFF {
-                    cpu.reset_flag(Flag::Carry);
-                    cpu.set_register(Operand::AX, segment);
-                    psp.allocation_end = allocation_end as u16;
-                    println!("DOS ({}): Allocate Memory segment={:04X} paragraphs={:04X}", cpu.cycle_counter, segment, paragraphs);
+            0x44 => { // IOCTL of a handle
+                cpu.set_flag(Flag::Carry, 1);
+                let handle = cpu.get_register(Operand::BX);
+                let device = self.device_handles.get(&handle).cloned();
+                let index = match (device, self.open_handles.get(&handle)) {
+                    (None, None) => {
+                        println!("FS ({}): IOCTL function={:02X} fd={} err=(Invalid handle)", cpu.cycle_counter, argument, handle);
+                        cpu.set_register(Operand::AX, 6); // Invalid handle
+                        return;
+                    },
+                    (_, index) => index.cloned()
+                };
+                match (argument, device) {
+                    (0x00, Some(device)) => { // Get Device Information
+                        let raw = if self.raw_devices.contains(&device) { 0x0020 } else { 0x0000 };
+                        cpu.set_register(Operand::DX, device.information()|raw);
+                    },
+                    (0x00, None) => { // Drive number of the file
+                        let entry = self.system_file_table[index.unwrap()].as_ref().unwrap();
+                        cpu.set_register(Operand::DX, entry.drive as u16);
+                    },
+                    (0x01, Some(device)) if cpu.get_register(Operand::DH) == 0 => { // Set Device Information
+                        if cpu.get_register(Operand::DL)&0x20 != 0 {
+                            self.raw_devices.insert(device);
+                        } else {
+                            self.raw_devices.remove(&device);
+                        }
+                    },
+                    (0x06, Some(device)) => { // Get Input Status
+                        let ready = match device {
+                            Device::Console => !self.console_input.is_empty() || crate::bios::BIOS::from_ram(ram).keyboard_buffer_peek().is_some(),
+                            Device::Clock | Device::Driver(_) => true,
+                            Device::Null | Device::Auxiliary | Device::Printer => false
+                        };
+                        cpu.set_register(Operand::AL, if ready { 0xFF } else { 0x00 });
+                    },
+                    (0x06, None) => { // Get Input Status, ready until the end of the file
+                        let entry = self.system_file_table[index.unwrap()].as_mut().unwrap();
+                        let file_system = &mut self.drives[entry.drive as usize].as_mut().unwrap().file_system;
+                        let position = file_system.seek(&mut entry.file, std::io::SeekFrom::Current(0)).unwrap_or(0);
+                        let end = file_system.seek(&mut entry.file, std::io::SeekFrom::End(0)).unwrap_or(0);
+                        file_system.seek(&mut entry.file, std::io::SeekFrom::Start(position)).ok();
+                        cpu.set_register(Operand::AL, if position < end { 0xFF } else { 0x00 });
+                    },
+                    (0x07, _) => { // Get Output Status
+                        cpu.set_register(Operand::AL, 0xFF);
+                    },
+                    _ => {
+                        println!("FS ({}): Unsupported IOCTL function={:02X} fd={}", cpu.cycle_counter, argument, handle);
+                        cpu.set_register(Operand::AX, 1); // Invalid function
+                        return;
+                    }
+                }
+                if argument == 0x00 {
+                    cpu.set_register(Operand::AX, cpu.get_register(Operand::DX));
+                }
+                println!("FS ({}): IOCTL function={:02X} fd={} device={}", cpu.cycle_counter, argument, handle, device.map_or("", |device| device.name()));
+                cpu.reset_flag(Flag::Carry);
+            },
+            0x45 | 0x46 => { // Duplicate File Handle / Force Duplicate File Handle
+                cpu.set_flag(Flag::Carry, 1);
+                let handle = cpu.get_register(Operand::BX);
+                if !self.open_handles.contains_key(&handle) && !self.device_handles.contains_key(&handle) {
+                    cpu.set_register(Operand::AX, 6); // Invalid handle
+                    return;
+                }
+                let new_handle = if cpu.get_register(Operand::AH) == 0x45 {
+                    match self.free_handle() {
+                        Some(new_handle) => new_handle,
+                        None => {
+                            cpu.set_register(Operand::AX, 4); // Too many open files
+                            return;
+                        }
+                    }
                 } else {
-                    let available = 0x9FFF-segment as u32;
+                    let new_handle = cpu.get_register(Operand::CX);
+                    if new_handle >= 20 {
+                        cpu.set_register(Operand::AX, 6); // Invalid handle
+                        return;
+                    }
+                    if new_handle != handle {
+                        self.close_handle(new_handle);
+                    }
+                    new_handle
+                };
+                self.duplicate_handle(handle, new_handle);
+                println!("FS ({}): Duplicate File Handle fd={} new_fd={}", cpu.cycle_counter, handle, new_handle);
+                if cpu.get_register(Operand::AH) == 0x45 {
+                    cpu.set_register(Operand::AX, new_handle);
+                }
+                cpu.reset_flag(Flag::Carry);
+            },
+            0x47 => { // Get Current Directory
+                let drive = match cpu.get_register(Operand::DL) as u8 {
+                    0 => self.current_drive,
+                    drive => drive-1
+                };
+                if !self.is_drive_mounted(drive) {
                     cpu.set_flag(Flag::Carry, 1);
-                    cpu.set_register(Operand::AX, 8); // Insufficient memory
-                    cpu.set_register(Operand::BX, available as u16); // Size of largest available block
-                    println!("DOS ({}): Get Memory segment={:04X} available={:04X}", cpu.cycle_counter, segment, available);
+                    cpu.set_register(Operand::AX, 0x0F); // Invalid drive
+                    return;
+                }
+                let directory = self.current_directories[drive as usize].join("\\").to_uppercase();
+                let directory = &directory.as_bytes()[0..directory.len().min(63)];
+                let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::SI));
+                ram[address..address+directory.len()].copy_from_slice(directory);
+                ram[address+directory.len()] = 0;
+                cpu.set_register(Operand::AX, 0x0100);
+                cpu.reset_flag(Flag::Carry);
+            },
+            0x48 => { // Allocate Memory
+                let paragraphs = cpu.get_register(Operand::BX);
+                match self.allocate_memory(ram, paragraphs) {
+                    Ok(segment) => {
+                        cpu.reset_flag(Flag::Carry);
+                        cpu.set_register(Operand::AX, segment);
+                        println!("DOS ({}): Allocate Memory segment={:04X} paragraphs={:04X}", cpu.cycle_counter, segment, paragraphs);
+                    },
+                    Err((error, available)) => {
+                        cpu.set_flag(Flag::Carry, 1);
+                        cpu.set_register(Operand::AX, error);
+                        cpu.set_register(Operand::BX, available); // Size of largest available block
+                        println!("DOS ({}): Allocate Memory paragraphs={:04X} available={:04X} err={}", cpu.cycle_counter, paragraphs, available, error);
+                    }
                 }
             },
             0x49 => { // Free Memory
                 let segment = cpu.get_register(Operand::ES);
-                if segment >= self.psp_segment && segment < 0x9FFF {
-                    cpu.reset_flag(Flag::Carry);
-                    Self::get_psp(self.psp_segment as usize, ram).allocation_end = segment as u16;
-                    println!("DOS ({}): Free Memory allocation_end={:04X}", cpu.cycle_counter, segment);
-                } else {
-                    cpu.set_flag(Flag::Carry, 1);
-                    cpu.set_register(Operand::AX, 9); // Memory block address invalid
+                match self.free_memory(ram, segment) {
+                    Ok(()) => {
+                        cpu.reset_flag(Flag::Carry);
+                        println!("DOS ({}): Free Memory segment={:04X}", cpu.cycle_counter, segment);
+                    },
+                    Err(error) => {
+                        cpu.set_flag(Flag::Carry, 1);
+                        cpu.set_register(Operand::AX, error);
+                        println!("DOS ({}): Free Memory segment={:04X} err={}", cpu.cycle_counter, segment, error);
+                    }
                 }
             },
             0x4A => { // Resize Memory Block
                 let segment = cpu.get_register(Operand::ES);
                 let paragraphs = cpu.get_register(Operand::BX);
-                if segment == self.psp_segment {
-                    let allocation_end = paragraphs as u32+segment as u32+1;
-                    if allocation_end < 0x9FFF {
+                match self.resize_memory_block(ram, segment, paragraphs) {
+                    Ok(()) => {
                         cpu.reset_flag(Flag::Carry);
-                        Self::get_psp(self.psp_segment as usize, ram).allocation_end = allocation_end as u16;
                         println!("DOS ({}): Resize Memory Block segment={:04X} paragraphs={:04X}", cpu.cycle_counter, segment, paragraphs);
-                    } else {
+                    },
+                    Err((error, available)) => {
                         cpu.set_flag(Flag::Carry, 1);
-                        cpu.set_register(Operand::AX, 8); // Insufficient memory
+                        cpu.set_register(Operand::AX, error);
+                        cpu.set_register(Operand::BX, available); // Maximum size of the block
+                        println!("DOS ({}): Resize Memory Block segment={:04X} paragraphs={:04X} available={:04X} err={}", cpu.cycle_counter, segment, paragraphs, available, error);
                     }
-                } else {
-                    cpu.set_flag(Flag::Carry, 1);
-                    cpu.set_register(Operand::AX, 9); // Memory block address invalid
                 }
             },
             0x4E | 0x4F => { // Find Matching File
@@ -487,57 +1834,221 @@ impl DiskOperatingSystem {
                 let dta_address = crate::bus::BUS::physical_address((self.dta_address>>16) as u16, self.dta_address as u16);
                 let dta = unsafe { &mut *std::mem::transmute::<*mut u8, *mut FindFirstDataBlock>(&mut ram[dta_address] as *mut u8) };
                 let find_fist = cpu.get_register(Operand::AH) == 0x4E;
-                let path = if find_fist {
-                    let pattern_path = get_path!(self, cpu, ram);
-                    let mut path = pattern_path.clone();
-                    for ancestor in pattern_path.ancestors() {
-                        if ancestor.starts_with(&self.mount_point_c) && ancestor.ends_with(&self.mount_point_c) {
-                            break;
-                        }
-                        let parent = ancestor.parent().unwrap();
-                        let file_name = ancestor.file_name().unwrap();
-                        let pattern = file_name.to_str().unwrap();
-                        let pattern_bytes = pattern.as_bytes();
-                        dta.search_template = unsafe { mem::zeroed() };
-                        dta.search_template[0..pattern_bytes.len()].copy_from_slice(&pattern_bytes[0..pattern_bytes.len()]);
-                        dta.search_attributes = 0;
-                        self.read_directory = Some(std::fs::read_dir(parent).unwrap());
-                        match self.find_next_directory_entry(pattern) {
-                            Some(match_path) => {
-                                path = match_path;
-                                break;
-                            },
-                            None => {}
+                if find_fist {
+                    let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
+                    let (drive, mut components) = match string_from_ptr(&ram[address]).and_then(|path| self.resolve_path(path)) {
+                        Ok(resolved) => resolved,
+                        Err(error) => {
+                            println!("FS ({}): Find First Matching File err=(Path not found: {})", cpu.cycle_counter, error);
+                            cpu.set_register(Operand::AX, 3); // Path not found
+                            return;
                         }
-                    }
-                    path
-                } else {
-                    let pattern = match unsafe { std::ffi::CStr::from_ptr(&dta.search_template[0] as *const u8 as *const i8) }.to_str() {
-                        Ok(path) => path,
-                        Err(_) => { panic!(); }
                     };
-                    match self.find_next_directory_entry(pattern) {
-                        Some(match_path) => match_path,
-                        None => {
-                            cpu.set_register(Operand::AX, 2); // File not found
+                    let pattern = components.pop().unwrap_or_default();
+                    let entries = match self.file_system(drive).list_directory(&components) {
+                        Ok(entries) => entries,
+                        Err(_) => {
+                            println!("FS ({}): Find First Matching File path={} err=(Path not found)", cpu.cycle_counter, Self::display_path(drive, &components));
+                            cpu.set_register(Operand::AX, 3); // Path not found
                             return;
                         }
+                    };
+                    if cpu.get_register(Operand::CL) == 0x08 { // Volume Label
+                        let label = self.volume_label(drive);
+                        if components.is_empty() && !label.is_empty() && crate::name_mapping::matches_pattern(&label, &crate::name_mapping::to_fcb_form(&pattern)) {
+                            let label = label.as_bytes();
+                            dta.attribute = 0x08;
+                            dta.file_size = 0;
+                            dta.filename = [0; 13];
+                            dta.filename[0..label.len().min(12)].copy_from_slice(&label[0..label.len().min(12)]);
+                            dta.parent_directory_cluster_number = 0xFFFF;
+                            cpu.set_register(Operand::AX, 0);
+                            cpu.reset_flag(Flag::Carry);
+                        } else {
+                            cpu.set_register(Operand::AX, 0x12); // No more files
+                        }
+                        return;
+                    }
+                    let search_index = self.add_search_directory(drive, components, entries);
+                    dta.drive_letter = drive+1;
+                    dta.search_template = crate::name_mapping::to_fcb_form(&pattern);
+                    dta.search_attributes = cpu.get_register(Operand::CL) as u8;
+                    dta.entry_index_in_directory = 0;
+                    dta.parent_directory_cluster_number = search_index as u16;
+                }
+                let (drive, components, entries) = match self.search_directories.get(dta.parent_directory_cluster_number as usize) {
+                    Some(search) => search,
+                    None => {
+                        cpu.set_register(Operand::AX, 0x12); // No more files
+                        return;
                     }
                 };
-                access_path!(path, cpu, metadata, if find_fist { "Find First Matching File" } else { "Find Next Matching File" }, std::fs::metadata, {
-                    let filename = path.file_name().unwrap().to_str().unwrap();
-                    let modified = chrono::DateTime::<Local>::from(metadata.modified().unwrap());
-                    dta.attribute = 0;
-                    dta.file_time = (modified.hour() as u16)<<11|(modified.minute() as u16)<<5|(modified.second() as u16/2);
-                    dta.file_date = (modified.year() as u16-1980)<<9|(modified.month() as u16)<<5|(modified.day() as u16);
-                    dta.file_size = metadata.len() as u32;
-                    dta.filename[0..filename.len()].copy_from_slice(unsafe { std::slice::from_raw_parts(filename.as_ptr(), filename.len()) });
-                    dta.filename[filename.len()] = 0x00;
+                let search_template = dta.search_template;
+                for (index, entry) in entries.iter().enumerate().skip(dta.entry_index_in_directory as usize) {
+                    if !crate::name_mapping::matches_pattern(&entry.short_name, &search_template) {
+                        continue;
+                    }
+                    if entry.attribute&0x16&!dta.search_attributes != 0 { // Directories, hidden and system files are only found if asked for
+                        continue;
+                    }
+                    println!("FS ({}): {} path={} name={}", cpu.cycle_counter, if find_fist { "Find First Matching File" } else { "Find Next Matching File" }, Self::display_path(*drive, components), entry.short_name);
+                    dta.entry_index_in_directory = index as u16+1;
+                    dta.attribute = entry.attribute;
+                    dta.file_time = entry.time;
+                    dta.file_date = entry.date;
+                    dta.file_size = entry.size;
+                    dta.filename = [0; 13];
+                    dta.filename[0..entry.short_name.len()].copy_from_slice(entry.short_name.as_bytes());
                     cpu.set_register(Operand::AX, 0);
-                });
+                    cpu.reset_flag(Flag::Carry);
+                    return;
+                }
+                dta.entry_index_in_directory = entries.len() as u16;
+                cpu.set_register(Operand::AX, if find_fist { 2 } else { 0x12 }); // File not found / No more files
+            },
+            0x52 => { // Get List of Lists
+                cpu.set_register(Operand::ES, LIST_OF_LISTS.0);
+                cpu.set_register(Operand::BX, LIST_OF_LISTS.1);
+            },
+            0x56 => { // Rename File
+                cpu.set_flag(Flag::Carry, 1);
+                let (drive, components) = get_path!(self, cpu, ram, writable);
+                let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::ES), cpu.get_register(Operand::DI));
+                let (new_drive, new_components) = match string_from_ptr(&ram[address]).and_then(|path| self.resolve_path(path)) {
+                    Ok(resolved) => resolved,
+                    Err(error) => {
+                        println!("FS ({}): Rename File err=(Path not found: {})", cpu.cycle_counter, error);
+                        cpu.set_register(Operand::AX, 3); // Path not found
+                        return;
+                    }
+                };
+                let path = format!("{} -> {}", Self::display_path(drive, &components), Self::display_path(new_drive, &new_components));
+                if new_drive != drive {
+                    println!("FS ({}): Rename File path={} err=(Not same device)", cpu.cycle_counter, path);
+                    cpu.set_register(Operand::AX, 0x11);
+                    return;
+                }
+                if !self.allows_sharing(drive, &components, 0x12) {
+                    println!("FS ({}): Rename File path={} err=(Sharing violation)", cpu.cycle_counter, path);
+                    cpu.set_register(Operand::AX, 0x20);
+                    return;
+                }
+                access_path!(path, cpu, _result, "Rename File", self.file_system(drive).rename(&components, &new_components), {});
+            },
+            0x57 => { // Get or Set File Date and Time
+                cpu.set_flag(Flag::Carry, 1);
+                let entry = match self.open_handles.get(&cpu.get_register(Operand::BX)).cloned() {
+                    Some(index) => self.system_file_table[index].as_mut().unwrap(),
+                    None => {
+                        cpu.set_register(Operand::AX, 6); // Invalid handle
+                        return;
+                    }
+                };
+                let file_system = &mut self.drives[entry.drive as usize].as_mut().unwrap().file_system;
+                let result = match argument {
+                    0x00 => file_system.file_time(&entry.file).map(|(time, date)| {
+                        cpu.set_register(Operand::CX, time);
+                        cpu.set_register(Operand::DX, date);
+                    }),
+                    0x01 => file_system.set_file_time(&entry.file, cpu.get_register(Operand::CX), cpu.get_register(Operand::DX)),
+                    _ => {
+                        cpu.set_register(Operand::AX, 1); // Invalid function
+                        return;
+                    }
+                };
+                match result {
+                    Ok(_) => {
+                        println!("FS ({}): {} File Date and Time fd={} time={:04X} date={:04X}", cpu.cycle_counter, if argument == 0 { "Get" } else { "Set" },
+                            cpu.get_register(Operand::BX), cpu.get_register(Operand::CX), cpu.get_register(Operand::DX));
+                        cpu.reset_flag(Flag::Carry);
+                    },
+                    Err(error) => {
+                        println!("FS ({}): File Date and Time fd={} err=({})", cpu.cycle_counter, cpu.get_register(Operand::BX), error);
+                        cpu.set_register(Operand::AX, 5); // Access denied
+                    }
+                }
+            },
+            0x58 => { // Get or Set Memory Allocation Strategy and UMB Link State
+                cpu.reset_flag(Flag::Carry);
+                match (argument, cpu.get_register(Operand::BX)) {
+                    (0x00, _) => {
+                        cpu.set_register(Operand::AX, self.allocation_strategy as u16);
+                    },
+                    (0x01, strategy) if strategy&0x3F <= 2 => {
+                        // The upper memory bits are ignored, as there are no UMBs in the DOS arena
+                        self.allocation_strategy = match strategy&0x3F {
+                            0 => AllocationStrategy::FirstFit,
+                            1 => AllocationStrategy::BestFit,
+                            _ => AllocationStrategy::LastFit
+                        };
+                        println!("DOS ({}): Set Memory Allocation Strategy strategy={:04X}", cpu.cycle_counter, strategy);
+                    },
+                    (0x02, _) => {
+                        cpu.set_register(Operand::AL, 0); // UMBs not linked
+                    },
+                    _ => {
+                        cpu.set_flag(Flag::Carry, 1);
+                        cpu.set_register(Operand::AX, 1); // Invalid function
+                    }
+                }
+            },
+            0x5A => { // Create Temporary File
+                cpu.set_flag(Flag::Carry, 1);
+                if self.free_handle().is_none() {
+                    println!("FS ({}): Create Temporary File err=(No handle available)", cpu.cycle_counter);
+                    cpu.set_register(Operand::AX, 4);
+                    return;
+                }
+                let (drive, mut components) = get_path!(self, cpu, ram, writable);
+                // Names are made from the cycle counter like DOS makes them from the time
+                let mut name = format!("{:08X}", cpu.cycle_counter as u32);
+                components.push(name.clone());
+                while self.file_system(drive).attributes(&components).is_ok() {
+                    name = format!("{:08X}", u32::from_str_radix(&name, 16).unwrap().wrapping_add(1));
+                    *components.last_mut().unwrap() = name.clone();
+                }
+                let attribute = cpu.get_register(Operand::CL) as u8;
+                self.create_file_handle(cpu, "Create Temporary File", drive, components, attribute, true);
+                if !cpu.get_flag(Flag::Carry) {
+                    let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::DX));
+                    let mut end = address+ram[address..].iter().position(|byte| *byte == 0).unwrap();
+                    if end > address && ram[end-1] != b'\\' && ram[end-1] != b':' {
+                        ram[end] = b'\\';
+                        end += 1;
+                    }
+                    ram[end..end+name.len()].copy_from_slice(name.as_bytes());
+                    ram[end+name.len()] = 0;
+                }
+            },
+            0x68 => { // Commit File
+                cpu.set_flag(Flag::Carry, 1);
+                let entry = match self.open_handles.get(&cpu.get_register(Operand::BX)).cloned() {
+                    Some(index) => self.system_file_table[index].as_mut().unwrap(),
+                    None => {
+                        if self.device_handles.contains_key(&cpu.get_register(Operand::BX)) {
+                            cpu.reset_flag(Flag::Carry);
+                        } else {
+                            cpu.set_register(Operand::AX, 6); // Invalid handle
+                        }
+                        return;
+                    }
+                };
+                let file_system = &mut self.drives[entry.drive as usize].as_mut().unwrap().file_system;
+                match file_system.commit(&entry.file) {
+                    Ok(_) => {
+                        println!("FS ({}): Commit File fd={}", cpu.cycle_counter, cpu.get_register(Operand::BX));
+                        cpu.reset_flag(Flag::Carry);
+                    },
+                    Err(error) => {
+                        println!("FS ({}): Commit File fd={} err=({})", cpu.cycle_counter, cpu.get_register(Operand::BX), error);
+                        cpu.set_register(Operand::AX, 0x1F); // General failure
+                    }
+                }
             },
             _ => {
-                panic!("DOS ({}): Unsupported syscall {:04X}", cpu.cycle_counter, cpu.get_register(Operand::AX));
+                cpu.pause_with_diagnostic(format!("DOS ({}): Unsupported syscall {:04X}", cpu.cycle_counter, cpu.get_register(Operand::AX)));
+                cpu.set_flag(Flag::Carry, 1);
+                cpu.set_register(Operand::AX, 1); // Invalid function
             }
         }
     }
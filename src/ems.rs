@@ -0,0 +1,297 @@
+use crate::machinecode::Operand;
+
+pub const PAGE_SIZE: usize = 0x4000;
+pub const PHYSICAL_PAGE_COUNT: usize = 4;
+pub const DEVICE_HEADER_SEGMENT: u16 = 0x00F0;
+const MAX_HANDLE_COUNT: usize = 255;
+const PAGE_MAP_SIZE: u16 = (PHYSICAL_PAGE_COUNT*2) as u16;
+const UNMAPPED: u16 = 0xFFFF;
+
+struct Handle {
+    pages: Vec<u16>, // Indices into the pool
+    name: [u8; 8],
+    saved_page_map: Option<[u16; PHYSICAL_PAGE_COUNT]>
+}
+
+pub struct ExpandedMemoryManager {
+    pub page_frame_segment: u16,
+    pool: Vec<u8>,
+    allocated: Vec<bool>,
+    handles: Vec<Option<Handle>>,
+    page_map: [u16; PHYSICAL_PAGE_COUNT] // Pool page mapped to each physical page
+}
+
+impl ExpandedMemoryManager {
+    pub fn new() -> Self {
+        Self {
+            page_frame_segment: 0,
+            pool: Vec::new(),
+            allocated: Vec::new(),
+            handles: Vec::new(),
+            page_map: [UNMAPPED; PHYSICAL_PAGE_COUNT]
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.page_frame_segment != 0
+    }
+
+    pub fn install(&mut self, page_frame_segment: u16, page_count: usize) {
+        self.page_frame_segment = page_frame_segment;
+        self.pool = vec![0; page_count*PAGE_SIZE];
+        self.allocated = vec![false; page_count];
+        self.handles.clear();
+        self.handles.push(Some(Handle { pages: Vec::new(), name: [0; 8], saved_page_map: None })); // Operating system handle
+        self.page_map = [UNMAPPED; PHYSICAL_PAGE_COUNT];
+    }
+
+    /// Writes the device header, which programs look for at the segment of the INT 67h vector
+    pub fn setup(&self, ram: &mut [u8]) {
+        let address = crate::bus::BUS::physical_address(DEVICE_HEADER_SEGMENT, 0);
+        ram[address..address+0x0A].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xC0, 0x12, 0x00, 0x12, 0x00]);
+        ram[address+0x0A..address+0x12].copy_from_slice(b"EMMXXXX0");
+        ram[address+0x12] = 0xCF; // IRET
+        let interrupt_vector = unsafe { crate::bit_utils::transmute_slice_mut::<u8, u32>(&mut ram[0..]) };
+        interrupt_vector[0x67] = ((DEVICE_HEADER_SEGMENT as u32)<<16)|0x0012;
+    }
+
+    pub fn get_memory(&mut self, address: usize) -> Option<*mut u8> {
+        let offset = address.wrapping_sub((self.page_frame_segment as usize)<<4);
+        if !self.is_installed() || offset >= PHYSICAL_PAGE_COUNT*PAGE_SIZE {
+            return None;
+        }
+        match self.page_map[offset/PAGE_SIZE] {
+            UNMAPPED => None,
+            pool_page => Some(&mut self.pool[(pool_page as usize)*PAGE_SIZE+offset%PAGE_SIZE])
+        }
+    }
+
+    fn free_page_count(&self) -> usize {
+        self.allocated.iter().filter(|allocated| !**allocated).count()
+    }
+
+    fn allocate_pages(&mut self, pages: &mut Vec<u16>, page_count: usize) {
+        for (index, allocated) in self.allocated.iter_mut().enumerate() {
+            if pages.len() >= page_count {
+                break;
+            }
+            if !*allocated {
+                *allocated = true;
+                pages.push(index as u16);
+            }
+        }
+    }
+
+    fn release_pages(&mut self, pages: &[u16]) {
+        for pool_page in pages {
+            self.allocated[*pool_page as usize] = false;
+            for mapped in self.page_map.iter_mut() {
+                if *mapped == *pool_page {
+                    *mapped = UNMAPPED;
+                }
+            }
+        }
+    }
+
+    fn map_page(&mut self, handle: u16, physical_page: u16, logical_page: u16) -> Result<(), u8> {
+        if physical_page as usize >= PHYSICAL_PAGE_COUNT {
+            return Err(0x8B); // Physical page out of range
+        }
+        let pages = &self.handles[handle as usize].as_ref().unwrap().pages;
+        self.page_map[physical_page as usize] = if logical_page == UNMAPPED {
+            UNMAPPED
+        } else {
+            *pages.get(logical_page as usize).ok_or(0x8Au8)? // Logical page out of range
+        };
+        Ok(())
+    }
+
+    fn write_page_map(&self, ram: &mut [u8], address: usize) {
+        for (index, pool_page) in self.page_map.iter().enumerate() {
+            crate::bit_utils::write_to_buffer_u16(&mut ram[address+index*2..], *pool_page);
+        }
+    }
+
+    fn read_page_map(&mut self, ram: &[u8], address: usize) -> Result<(), u8> {
+        let mut page_map = [UNMAPPED; PHYSICAL_PAGE_COUNT];
+        for (index, pool_page) in page_map.iter_mut().enumerate() {
+            *pool_page = crate::bit_utils::read_from_buffer_u16(&ram[address+index*2..]);
+            if *pool_page != UNMAPPED && !self.allocated.get(*pool_page as usize).cloned().unwrap_or(false) {
+                return Err(0xA3); // Contents of the source array corrupted
+            }
+        }
+        self.page_map = page_map;
+        Ok(())
+    }
+
+    fn handle_function(&mut self, cpu: &mut crate::cpu::CPU, ram: &mut [u8]) -> Result<(), u8> {
+        let argument = cpu.get_register(Operand::AL);
+        let handle = cpu.get_register(Operand::DX);
+        let is_valid_handle = self.handles.get(handle as usize).map_or(false, |handle| handle.is_some());
+        match cpu.get_register(Operand::AH) {
+            0x40 => {}, // Get Status
+            0x41 => { // Get Page Frame Address
+                cpu.set_register(Operand::BX, self.page_frame_segment);
+            },
+            0x42 => { // Get Unallocated Page Count
+                cpu.set_register(Operand::BX, self.free_page_count() as u16);
+                cpu.set_register(Operand::DX, self.allocated.len() as u16);
+            },
+            0x43 => { // Allocate Pages
+                let page_count = cpu.get_register(Operand::BX) as usize;
+                if page_count == 0 {
+                    return Err(0x89); // Zero pages requested
+                } else if page_count > self.allocated.len() {
+                    return Err(0x87); // More pages requested than exist
+                } else if page_count > self.free_page_count() {
+                    return Err(0x88); // More pages requested than are free
+                }
+                let handle = match self.handles.iter().position(|handle| handle.is_none()) {
+                    Some(handle) => handle,
+                    None if self.handles.len() < MAX_HANDLE_COUNT => { self.handles.push(None); self.handles.len()-1 },
+                    None => { return Err(0x85); } // No more handles available
+                };
+                let mut pages = Vec::new();
+                self.allocate_pages(&mut pages, page_count);
+                self.handles[handle] = Some(Handle { pages: pages, name: [0; 8], saved_page_map: None });
+                cpu.set_register(Operand::DX, handle as u16);
+                println!("EMS ({}): Allocate Pages handle={} pages={}", cpu.cycle_counter, handle, page_count);
+            },
+            0x44 if is_valid_handle => { // Map Page
+                self.map_page(handle, argument, cpu.get_register(Operand::BX))?;
+            },
+            0x45 if is_valid_handle => { // Deallocate Pages
+                if self.handles[handle as usize].as_ref().unwrap().saved_page_map.is_some() {
+                    return Err(0x86); // Page map of the handle is still saved
+                }
+                let pages = std::mem::replace(&mut self.handles[handle as usize].as_mut().unwrap().pages, Vec::new());
+                self.release_pages(&pages);
+                if handle != 0 {
+                    self.handles[handle as usize] = None;
+                }
+                println!("EMS ({}): Deallocate Pages handle={}", cpu.cycle_counter, handle);
+            },
+            0x46 => { // Get Version
+                cpu.set_register(Operand::AL, 0x40);
+            },
+            0x47 if is_valid_handle => { // Save Page Map
+                let handle = self.handles[handle as usize].as_mut().unwrap();
+                if handle.saved_page_map.is_some() {
+                    return Err(0x8D); // Page map already saved for this handle
+                }
+                handle.saved_page_map = Some(self.page_map);
+            },
+            0x48 if is_valid_handle => { // Restore Page Map
+                self.page_map = self.handles[handle as usize].as_mut().unwrap().saved_page_map.take().ok_or(0x8Eu8)?; // No saved page map
+            },
+            0x4B => { // Get Handle Count
+                cpu.set_register(Operand::BX, self.handles.iter().filter(|handle| handle.is_some()).count() as u16);
+            },
+            0x4C if is_valid_handle => { // Get Handle Pages
+                cpu.set_register(Operand::BX, self.handles[handle as usize].as_ref().unwrap().pages.len() as u16);
+            },
+            0x4D => { // Get All Handle Pages
+                let mut address = crate::bus::BUS::physical_address(cpu.get_register(Operand::ES), cpu.get_register(Operand::DI));
+                let mut count = 0;
+                for (index, handle) in self.handles.iter().enumerate() {
+                    if let Some(handle) = handle {
+                        crate::bit_utils::write_to_buffer_u16(&mut ram[address..], index as u16);
+                        crate::bit_utils::write_to_buffer_u16(&mut ram[address+2..], handle.pages.len() as u16);
+                        address += 4;
+                        count += 1;
+                    }
+                }
+                cpu.set_register(Operand::BX, count);
+            },
+            0x4E => { // Get or Set Page Map
+                let source = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::SI));
+                let destination = crate::bus::BUS::physical_address(cpu.get_register(Operand::ES), cpu.get_register(Operand::DI));
+                match argument {
+                    0x00 => self.write_page_map(ram, destination),
+                    0x01 => self.read_page_map(ram, source)?,
+                    0x02 => {
+                        self.write_page_map(ram, destination);
+                        self.read_page_map(ram, source)?;
+                    },
+                    0x03 => cpu.set_register(Operand::AL, PAGE_MAP_SIZE),
+                    _ => { return Err(0x8F); } // Subfunction not supported
+                }
+            },
+            0x50 if is_valid_handle => { // Map Multiple Pages
+                let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::SI));
+                for index in 0..cpu.get_register(Operand::CX) as usize {
+                    let logical_page = crate::bit_utils::read_from_buffer_u16(&ram[address+index*4..]);
+                    let physical_page = crate::bit_utils::read_from_buffer_u16(&ram[address+index*4+2..]);
+                    let physical_page = match argument {
+                        0x00 => physical_page,
+                        0x01 => physical_page.wrapping_sub(self.page_frame_segment)/((PAGE_SIZE>>4) as u16),
+                        _ => { return Err(0x8F); } // Subfunction not supported
+                    };
+                    self.map_page(handle, physical_page, logical_page)?;
+                }
+            },
+            0x51 if is_valid_handle => { // Reallocate Pages
+                let page_count = cpu.get_register(Operand::BX) as usize;
+                let mut pages = std::mem::replace(&mut self.handles[handle as usize].as_mut().unwrap().pages, Vec::new());
+                if page_count > pages.len() && page_count-pages.len() > self.free_page_count() {
+                    self.handles[handle as usize].as_mut().unwrap().pages = pages;
+                    return Err(if page_count > self.allocated.len() { 0x87 } else { 0x88 });
+                }
+                if page_count < pages.len() {
+                    self.release_pages(&pages[page_count..]);
+                    pages.truncate(page_count);
+                } else {
+                    self.allocate_pages(&mut pages, page_count);
+                }
+                self.handles[handle as usize].as_mut().unwrap().pages = pages;
+                println!("EMS ({}): Reallocate Pages handle={} pages={}", cpu.cycle_counter, handle, page_count);
+            },
+            0x53 if is_valid_handle => { // Get or Set Handle Name
+                let handle = self.handles[handle as usize].as_mut().unwrap();
+                match argument {
+                    0x00 => {
+                        let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::ES), cpu.get_register(Operand::DI));
+                        ram[address..address+8].copy_from_slice(&handle.name);
+                    },
+                    0x01 => {
+                        let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::SI));
+                        handle.name.copy_from_slice(&ram[address..address+8]);
+                    },
+                    _ => { return Err(0x8F); } // Subfunction not supported
+                }
+            },
+            0x58 => { // Get Mappable Physical Address Array
+                if argument == 0x00 {
+                    let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::ES), cpu.get_register(Operand::DI));
+                    for physical_page in 0..PHYSICAL_PAGE_COUNT {
+                        let segment = self.page_frame_segment+(physical_page*(PAGE_SIZE>>4)) as u16;
+                        crate::bit_utils::write_to_buffer_u16(&mut ram[address+physical_page*4..], segment);
+                        crate::bit_utils::write_to_buffer_u16(&mut ram[address+physical_page*4+2..], physical_page as u16);
+                    }
+                } else if argument != 0x01 {
+                    return Err(0x8F); // Subfunction not supported
+                }
+                cpu.set_register(Operand::CX, PHYSICAL_PAGE_COUNT as u16);
+            },
+            0x44 | 0x45 | 0x47 | 0x48 | 0x4C | 0x50 | 0x51 | 0x53 => {
+                return Err(0x83); // Invalid handle
+            },
+            _ => {
+                println!("EMS ({}): Unsupported function={:04X}", cpu.cycle_counter, cpu.get_register(Operand::AX));
+                return Err(0x84); // Function not defined
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle_interrupt(&mut self, cpu: &mut crate::cpu::CPU, ram: &mut [u8]) {
+        let function = cpu.get_register(Operand::AH);
+        match self.handle_function(cpu, ram) {
+            Ok(()) => cpu.set_register(Operand::AH, 0x00),
+            Err(status) => {
+                println!("EMS ({}): function={:02X} status={:02X}", cpu.cycle_counter, function, status);
+                cpu.set_register(Operand::AH, status as u16);
+            }
+        }
+    }
+}
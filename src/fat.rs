@@ -0,0 +1,705 @@
+use std::io;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct BiosParameterBlock {
+    jump: [u8; 3],
+    oem_name: [u8; 8],
+    bytes_per_sector: u16,
+    sectors_per_cluster: u8,
+    reserved_sectors: u16,
+    fat_count: u8,
+    root_entry_count: u16,
+    total_sectors_16: u16,
+    media_descriptor: u8,
+    sectors_per_fat: u16,
+    sectors_per_track: u16,
+    head_count: u16,
+    hidden_sectors: u32,
+    total_sectors_32: u32,
+    drive_number: u8,
+    reserved: u8,
+    extended_boot_signature: u8,
+    volume_serial_number: u32,
+    volume_label: [u8; 11],
+    file_system_type: [u8; 8]
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct DirectoryRecord {
+    name: [u8; 11],
+    attribute: u8,
+    reserved: u8,
+    creation_time_tenths: u8,
+    creation_time: u16,
+    creation_date: u16,
+    access_date: u16,
+    first_cluster_high: u16,
+    write_time: u16,
+    write_date: u16,
+    first_cluster: u16,
+    size: u32
+}
+
+const RECORD_SIZE: usize = 32;
+const IMAGE_SECTOR_SIZE: usize = crate::disk_image::SECTOR_SIZE;
+
+/// Floppy formats of DOS 1.x disks without a BIOS parameter block, recognized by their size and media descriptor:
+/// Total sectors, sectors per cluster, root entries, sectors per FAT, media descriptor
+const FLOPPY_FORMATS: [(u16, u8, u16, u16, u8); 8] = [
+    (320, 1, 64, 1, 0xFE), // 160 KiB
+    (360, 1, 64, 2, 0xFC), // 180 KiB
+    (640, 2, 112, 1, 0xFF), // 320 KiB
+    (720, 2, 112, 2, 0xFD), // 360 KiB
+    (1440, 2, 112, 3, 0xF9), // 720 KiB
+    (2400, 1, 224, 7, 0xF9), // 1.2 MiB
+    (2880, 1, 224, 9, 0xF0), // 1.44 MiB
+    (5760, 2, 240, 9, 0xF0) // 2.88 MiB
+];
+
+impl BiosParameterBlock {
+    fn from_slice(slice: &[u8]) -> Option<Self> {
+        if slice.len() < std::mem::size_of::<Self>() {
+            return None;
+        }
+        Some(unsafe { std::ptr::read_unaligned(slice.as_ptr() as *const Self) })
+    }
+
+    fn total_sectors(&self) -> usize {
+        if self.total_sectors_16 != 0 { self.total_sectors_16 as usize } else { self.total_sectors_32 as usize }
+    }
+
+    fn is_valid(&self) -> bool {
+        let (bytes_per_sector, reserved_sectors, root_entry_count, sectors_per_fat) = (self.bytes_per_sector, self.reserved_sectors, self.root_entry_count, self.sectors_per_fat);
+        [512, 1024, 2048, 4096].contains(&bytes_per_sector) && self.sectors_per_cluster.is_power_of_two() &&
+        reserved_sectors >= 1 && (self.fat_count == 1 || self.fat_count == 2) &&
+        root_entry_count > 0 && sectors_per_fat > 0 && self.total_sectors() > 0
+    }
+}
+
+impl DirectoryRecord {
+    fn new(name: [u8; 11], attribute: u8, first_cluster: u16) -> Self {
+        let (time, date) = crate::file_system::dos_timestamp(chrono::Local::now());
+        Self {
+            name: name,
+            attribute: attribute,
+            reserved: 0,
+            creation_time_tenths: 0,
+            creation_time: time,
+            creation_date: date,
+            access_date: date,
+            first_cluster_high: 0,
+            write_time: time,
+            write_date: date,
+            first_cluster: first_cluster,
+            size: 0
+        }
+    }
+
+    fn short_name(&self) -> String {
+        let mut name = self.name;
+        if name[0] == 0x05 { // Escaped 0xE5 lead byte
+            name[0] = 0xE5;
+        }
+        let base: String = name[0..8].iter().map(|byte| *byte as char).collect();
+        let extension: String = name[8..11].iter().map(|byte| *byte as char).collect();
+        let (base, extension) = (base.trim_end(), extension.trim_end());
+        if extension.is_empty() { base.to_string() } else { format!("{}.{}", base, extension) }
+    }
+
+    fn touch(&mut self) {
+        let (time, date) = crate::file_system::dos_timestamp(chrono::Local::now());
+        self.write_time = time;
+        self.write_date = date;
+        self.access_date = date;
+    }
+}
+
+fn error(kind: io::ErrorKind, message: &str) -> io::Error {
+    io::Error::new(kind, message.to_string())
+}
+
+/// Open file on a FAT volume, its size and first cluster are always taken from the directory record
+pub struct FatFile {
+    record: usize,
+    position: u32
+}
+
+/// FAT12 / FAT16 volume of a disk image, which is shared with INT 13h.
+/// Modified sectors are flushed after every operation.
+pub struct FatFileSystem {
+    disk: crate::disk_image::SharedDiskImage,
+    bytes_per_sector: usize,
+    sectors_per_cluster: usize,
+    fat_offset: usize,
+    fat_size: usize,
+    fat_count: usize,
+    root_directory_offset: usize,
+    root_entry_count: usize,
+    data_offset: usize,
+    cluster_count: usize,
+    fat16: bool,
+    next_free_cluster: u16,
+    boot_sector_label: Option<String>
+}
+
+impl FatFileSystem {
+    pub fn open(disk: crate::disk_image::SharedDiskImage) -> io::Result<Self> {
+        let image_size = disk.lock().unwrap().data.len();
+        let (volume_offset, bpb) = match Self::find_volume(&disk.lock().unwrap().data) {
+            Some(volume) => volume,
+            None => { return Err(error(io::ErrorKind::InvalidData, "No FAT12 or FAT16 volume found")); }
+        };
+        let bytes_per_sector = bpb.bytes_per_sector as usize;
+        let reserved_size = bpb.reserved_sectors as usize*bytes_per_sector;
+        let fat_size = bpb.sectors_per_fat as usize*bytes_per_sector;
+        let root_entry_count = bpb.root_entry_count as usize;
+        let root_directory_size = (root_entry_count*RECORD_SIZE+bytes_per_sector-1)/bytes_per_sector*bytes_per_sector;
+        let data_offset = reserved_size+fat_size*bpb.fat_count as usize+root_directory_size;
+        let volume_size = bpb.total_sectors()*bytes_per_sector;
+        if data_offset >= volume_size || volume_offset+volume_size > image_size {
+            return Err(error(io::ErrorKind::InvalidData, "FAT volume exceeds the image"));
+        }
+        let cluster_count = (volume_size-data_offset)/(bytes_per_sector*bpb.sectors_per_cluster as usize);
+        if cluster_count >= 65525 {
+            return Err(error(io::ErrorKind::InvalidData, "FAT32 is not supported"));
+        }
+        let boot_sector_label = if bpb.extended_boot_signature == 0x29 {
+            Some(String::from_utf8_lossy(&bpb.volume_label).trim_end().to_string()).filter(|label| !label.is_empty() && label != "NO NAME")
+        } else {
+            None
+        };
+        Ok(Self {
+            disk: disk,
+            bytes_per_sector: bytes_per_sector,
+            sectors_per_cluster: bpb.sectors_per_cluster as usize,
+            fat_offset: volume_offset+reserved_size,
+            fat_size: fat_size,
+            fat_count: bpb.fat_count as usize,
+            root_directory_offset: volume_offset+reserved_size+fat_size*bpb.fat_count as usize,
+            root_entry_count: root_entry_count,
+            data_offset: volume_offset+data_offset,
+            cluster_count: cluster_count,
+            fat16: cluster_count >= 4085,
+            next_free_cluster: 2,
+            boot_sector_label: boot_sector_label
+        })
+    }
+
+    /// Byte offset and parameters of the volume, which is either the whole image or the first FAT partition
+    fn find_volume(image: &[u8]) -> Option<(usize, BiosParameterBlock)> {
+        let bpb = BiosParameterBlock::from_slice(image)?;
+        if bpb.is_valid() {
+            return Some((0, bpb));
+        }
+        if image.len() >= IMAGE_SECTOR_SIZE && image[510] == 0x55 && image[511] == 0xAA {
+            for partition in image[0x1BE..0x1FE].chunks(16) {
+                if [0x01, 0x04, 0x06, 0x0E].contains(&partition[4]) {
+                    let offset = crate::bit_utils::read_from_buffer_u32(&partition[8..]) as usize*IMAGE_SECTOR_SIZE;
+                    match image.get(offset..).and_then(BiosParameterBlock::from_slice) {
+                        Some(bpb) if bpb.is_valid() => { return Some((offset, bpb)); },
+                        _ => {}
+                    }
+                }
+            }
+        }
+        let format = FLOPPY_FORMATS.iter().find(|format| format.0 as usize*IMAGE_SECTOR_SIZE == image.len() && image[IMAGE_SECTOR_SIZE] == format.4)?;
+        let mut bpb: BiosParameterBlock = unsafe { std::mem::zeroed() };
+        bpb.bytes_per_sector = IMAGE_SECTOR_SIZE as u16;
+        bpb.sectors_per_cluster = format.1;
+        bpb.reserved_sectors = 1;
+        bpb.fat_count = 2;
+        bpb.root_entry_count = format.2;
+        bpb.total_sectors_16 = format.0;
+        bpb.media_descriptor = format.4;
+        bpb.sectors_per_fat = format.3;
+        Some((0, bpb))
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.bytes_per_sector*self.sectors_per_cluster
+    }
+
+    fn cluster_offset(&self, cluster: u16) -> usize {
+        self.data_offset+(cluster as usize-2)*self.cluster_size()
+    }
+
+    fn is_valid_cluster(&self, cluster: u16) -> bool {
+        cluster >= 2 && (cluster as usize) < self.cluster_count+2
+    }
+
+    fn end_of_chain(&self) -> u16 {
+        if self.fat16 { 0xFFFF } else { 0x0FFF }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.disk.lock().unwrap().flush()
+    }
+
+    fn read_image(&self, offset: usize, buffer: &mut [u8]) {
+        buffer.copy_from_slice(&self.disk.lock().unwrap().data[offset..offset+buffer.len()]);
+    }
+
+    fn read_image_u8(&self, offset: usize) -> u8 {
+        self.disk.lock().unwrap().data[offset]
+    }
+
+    fn read_image_u16(&self, offset: usize) -> u16 {
+        crate::bit_utils::read_from_buffer_u16(&self.disk.lock().unwrap().data[offset..])
+    }
+
+    fn write_image(&mut self, offset: usize, data: &[u8]) {
+        self.disk.lock().unwrap().write(offset, data);
+    }
+
+    fn get_fat_entry(&self, cluster: u16) -> u16 {
+        if self.fat16 {
+            self.read_image_u16(self.fat_offset+cluster as usize*2)
+        } else {
+            let value = self.read_image_u16(self.fat_offset+cluster as usize*3/2);
+            if cluster&1 == 0 { value&0x0FFF } else { value>>4 }
+        }
+    }
+
+    /// Updates the entry in every copy of the FAT
+    fn set_fat_entry(&mut self, cluster: u16, value: u16) {
+        for fat_index in 0..self.fat_count {
+            let fat_offset = self.fat_offset+fat_index*self.fat_size;
+            let (offset, value) = if self.fat16 {
+                (fat_offset+cluster as usize*2, value)
+            } else {
+                let offset = fat_offset+cluster as usize*3/2;
+                let previous = self.read_image_u16(offset);
+                (offset, if cluster&1 == 0 { (previous&0xF000)|(value&0x0FFF) } else { (previous&0x000F)|(value<<4) })
+            };
+            self.write_image(offset, &value.to_le_bytes());
+        }
+    }
+
+    fn cluster_chain(&self, first_cluster: u16) -> Vec<u16> {
+        let mut chain = Vec::new();
+        let mut cluster = first_cluster;
+        while self.is_valid_cluster(cluster) && chain.len() < self.cluster_count {
+            chain.push(cluster);
+            cluster = self.get_fat_entry(cluster);
+        }
+        chain
+    }
+
+    /// Allocates a zeroed cluster and appends it to the chain ending in the previous cluster, if any
+    fn allocate_cluster(&mut self, previous_cluster: u16) -> Option<u16> {
+        let start = if self.is_valid_cluster(self.next_free_cluster) { self.next_free_cluster } else { 2 };
+        let cluster = (start as usize..self.cluster_count+2).chain(2..start as usize)
+            .map(|cluster| cluster as u16)
+            .find(|cluster| self.get_fat_entry(*cluster) == 0)?;
+        self.set_fat_entry(cluster, self.end_of_chain());
+        if self.is_valid_cluster(previous_cluster) {
+            self.set_fat_entry(previous_cluster, cluster);
+        }
+        let offset = self.cluster_offset(cluster);
+        let cluster_size = self.cluster_size();
+        self.write_image(offset, &vec![0; cluster_size]);
+        self.next_free_cluster = cluster+1;
+        Some(cluster)
+    }
+
+    fn free_cluster_chain(&mut self, first_cluster: u16) {
+        for cluster in self.cluster_chain(first_cluster) {
+            self.set_fat_entry(cluster, 0);
+        }
+    }
+
+    fn record(&self, offset: usize) -> DirectoryRecord {
+        let mut data = [0; RECORD_SIZE];
+        self.read_image(offset, &mut data);
+        unsafe { std::ptr::read_unaligned(data.as_ptr() as *const DirectoryRecord) }
+    }
+
+    fn write_record(&mut self, offset: usize, record: &DirectoryRecord) {
+        let data = unsafe { std::slice::from_raw_parts(record as *const DirectoryRecord as *const u8, RECORD_SIZE) }.to_vec();
+        self.write_image(offset, &data);
+    }
+
+    /// Offsets of all record slots of a directory, cluster 0 is the root directory
+    fn directory_slots(&self, directory_cluster: u16) -> Vec<usize> {
+        if directory_cluster == 0 {
+            (0..self.root_entry_count).map(|index| self.root_directory_offset+index*RECORD_SIZE).collect()
+        } else {
+            let records_per_cluster = self.cluster_size()/RECORD_SIZE;
+            self.cluster_chain(directory_cluster).iter()
+                .flat_map(|cluster| {
+                    let offset = self.cluster_offset(*cluster);
+                    (0..records_per_cluster).map(move |index| offset+index*RECORD_SIZE)
+                })
+                .collect()
+        }
+    }
+
+    /// Offsets of the records in use, skipping deleted entries and long file name fragments
+    fn directory_records(&self, directory_cluster: u16) -> Vec<usize> {
+        let mut records = Vec::new();
+        for offset in self.directory_slots(directory_cluster) {
+            match self.read_image_u8(offset) {
+                0x00 => { break; }, // End of directory
+                0xE5 => { continue; }, // Deleted
+                _ => {}
+            }
+            if self.read_image_u8(offset+11) == 0x0F { // Long file name
+                continue;
+            }
+            records.push(offset);
+        }
+        records
+    }
+
+    /// Record offset of the file or directory at the given path, None for the root directory
+    fn find(&self, components: &[String]) -> io::Result<Option<usize>> {
+        let mut found: Option<usize> = None;
+        for component in components {
+            let directory_cluster = match found {
+                Some(offset) => {
+                    let record = self.record(offset);
+                    if record.attribute&0x10 == 0 {
+                        return Err(error(io::ErrorKind::NotFound, "Not a directory"));
+                    }
+                    record.first_cluster
+                },
+                None => 0
+            };
+            let short_name = crate::name_mapping::normalize_short_name(component);
+            found = Some(self.directory_records(directory_cluster).into_iter()
+                .find(|offset| {
+                    let record = self.record(*offset);
+                    record.attribute&0x08 == 0 && record.short_name() == short_name
+                })
+                .ok_or_else(|| error(io::ErrorKind::NotFound, "No such file or directory"))?);
+        }
+        Ok(found)
+    }
+
+    fn directory_cluster(&self, components: &[String]) -> io::Result<u16> {
+        match self.find(components)? {
+            Some(offset) if self.record(offset).attribute&0x10 == 0 => Err(error(io::ErrorKind::NotFound, "Not a directory")),
+            Some(offset) => Ok(self.record(offset).first_cluster),
+            None => Ok(0)
+        }
+    }
+
+    /// Cluster of the parent directory and the FCB form of the last component
+    fn parent_and_name(&self, components: &[String]) -> io::Result<(u16, [u8; 11])> {
+        let (name, parent) = match components.split_last() {
+            Some(split) => split,
+            None => { return Err(error(io::ErrorKind::PermissionDenied, "Root directory")); }
+        };
+        let name = crate::name_mapping::to_fcb_form(&crate::name_mapping::normalize_short_name(name));
+        if name[0] == b' ' || name.contains(&b'?') {
+            return Err(error(io::ErrorKind::NotFound, "Invalid file name"));
+        }
+        Ok((self.directory_cluster(parent)?, name))
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.disk.lock().unwrap().read_only { Err(error(io::ErrorKind::PermissionDenied, "Read only image")) } else { Ok(()) }
+    }
+
+    /// Adds a record to the directory, growing it by a cluster if it is full
+    fn create_record(&mut self, directory_cluster: u16, record: &DirectoryRecord) -> io::Result<usize> {
+        let slots = self.directory_slots(directory_cluster);
+        let offset = match slots.iter().find(|offset| [0x00, 0xE5].contains(&self.read_image_u8(**offset))) {
+            Some(offset) => *offset,
+            None if directory_cluster == 0 => { return Err(error(io::ErrorKind::PermissionDenied, "Root directory full")); },
+            None => {
+                let last_cluster = *self.cluster_chain(directory_cluster).last().unwrap();
+                let cluster = self.allocate_cluster(last_cluster).ok_or_else(|| error(io::ErrorKind::PermissionDenied, "Disk full"))?;
+                self.cluster_offset(cluster)
+            }
+        };
+        self.write_record(offset, record);
+        Ok(offset)
+    }
+
+    pub fn is_directory(&self, components: &[String]) -> bool {
+        self.directory_cluster(components).is_ok()
+    }
+
+    pub fn list_directory(&self, components: &[String]) -> io::Result<Vec<crate::file_system::DirectoryEntry>> {
+        let directory_cluster = self.directory_cluster(components)?;
+        Ok(self.directory_records(directory_cluster).into_iter()
+            .map(|offset| self.record(offset))
+            .filter(|record| record.attribute&0x08 == 0)
+            .map(|record| crate::file_system::DirectoryEntry {
+                short_name: record.short_name(),
+                attribute: record.attribute&0x3F,
+                time: record.write_time,
+                date: record.write_date,
+                size: record.size
+            })
+            .collect())
+    }
+
+    pub fn volume_label(&self) -> Option<String> {
+        self.directory_records(0).into_iter()
+            .map(|offset| self.record(offset))
+            .find(|record| record.attribute&0x18 == 0x08)
+            .map(|record| record.name.iter().map(|byte| *byte as char).collect::<String>().trim_end().to_string())
+            .or_else(|| self.boot_sector_label.clone())
+    }
+
+    /// Sectors per cluster, bytes per sector, free and total clusters
+    pub fn free_space(&self) -> (u16, u16, u16, u16) {
+        let free_clusters = (2..self.cluster_count+2).filter(|cluster| self.get_fat_entry(*cluster as u16) == 0).count();
+        (self.sectors_per_cluster as u16, self.bytes_per_sector as u16, free_clusters as u16, self.cluster_count as u16)
+    }
+
+    pub fn create_directory(&mut self, components: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        if self.find(components).is_ok() {
+            return Err(error(io::ErrorKind::AlreadyExists, "Already exists"));
+        }
+        let (parent_cluster, name) = self.parent_and_name(components)?;
+        let cluster = self.allocate_cluster(0).ok_or_else(|| error(io::ErrorKind::PermissionDenied, "Disk full"))?;
+        let offset = self.cluster_offset(cluster);
+        self.write_record(offset, &DirectoryRecord::new(*b".          ", 0x10, cluster));
+        self.write_record(offset+RECORD_SIZE, &DirectoryRecord::new(*b"..         ", 0x10, parent_cluster));
+        if let Err(error) = self.create_record(parent_cluster, &DirectoryRecord::new(name, 0x10, cluster)) {
+            self.free_cluster_chain(cluster);
+            return Err(error);
+        }
+        self.flush()
+    }
+
+    pub fn remove_directory(&mut self, components: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        let offset = match self.find(components)? {
+            Some(offset) => offset,
+            None => { return Err(error(io::ErrorKind::PermissionDenied, "Root directory")); }
+        };
+        let record = self.record(offset);
+        if record.attribute&0x10 == 0 {
+            return Err(error(io::ErrorKind::NotFound, "Not a directory"));
+        }
+        if self.directory_records(record.first_cluster).into_iter().any(|offset| self.read_image_u8(offset) != b'.') {
+            return Err(error(io::ErrorKind::PermissionDenied, "Directory not empty"));
+        }
+        self.free_cluster_chain(record.first_cluster);
+        self.write_image(offset, &[0xE5]);
+        self.flush()
+    }
+
+    /// Opens the file, truncating it if it already exists
+    pub fn create_file(&mut self, components: &[String]) -> io::Result<FatFile> {
+        self.check_writable()?;
+        let offset = match self.find(components) {
+            Ok(Some(offset)) => {
+                let mut record = self.record(offset);
+                if record.attribute&0x19 != 0 { // Read only, volume label or directory
+                    return Err(error(io::ErrorKind::PermissionDenied, "Not a writable file"));
+                }
+                self.free_cluster_chain(record.first_cluster);
+                record.first_cluster = 0;
+                record.size = 0;
+                record.touch();
+                self.write_record(offset, &record);
+                offset
+            },
+            Ok(None) => { return Err(error(io::ErrorKind::PermissionDenied, "Root directory")); },
+            Err(_) => {
+                let (parent_cluster, name) = self.parent_and_name(components)?;
+                self.create_record(parent_cluster, &DirectoryRecord::new(name, 0x20, 0))?
+            }
+        };
+        self.flush()?;
+        Ok(FatFile { record: offset, position: 0 })
+    }
+
+    pub fn open_file(&self, components: &[String], writable: bool) -> io::Result<FatFile> {
+        if writable {
+            self.check_writable()?;
+        }
+        match self.find(components)? {
+            Some(offset) if self.record(offset).attribute&0x18 == 0 => {
+                if writable && self.record(offset).attribute&0x01 != 0 {
+                    return Err(error(io::ErrorKind::PermissionDenied, "Read only file"));
+                }
+                Ok(FatFile { record: offset, position: 0 })
+            },
+            _ => Err(error(io::ErrorKind::PermissionDenied, "Not a file"))
+        }
+    }
+
+    pub fn delete_file(&mut self, components: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        let offset = match self.find(components)? {
+            Some(offset) if self.record(offset).attribute&0x19 == 0 => offset,
+            _ => { return Err(error(io::ErrorKind::PermissionDenied, "Not a writable file")); }
+        };
+        self.free_cluster_chain(self.record(offset).first_cluster);
+        self.write_image(offset, &[0xE5]);
+        self.flush()
+    }
+
+    /// Sets the read only, hidden, system and archive attributes, keeping the directory bit
+    pub fn set_attributes(&mut self, components: &[String], attribute: u8) -> io::Result<()> {
+        self.check_writable()?;
+        let offset = match self.find(components)? {
+            Some(offset) => offset,
+            None => { return Err(error(io::ErrorKind::PermissionDenied, "Root directory")); }
+        };
+        let mut record = self.record(offset);
+        record.attribute = (record.attribute&0x10)|(attribute&0x27);
+        self.write_record(offset, &record);
+        self.flush()
+    }
+
+    /// Renames the record, moving it to another directory if the parent differs
+    pub fn rename(&mut self, from: &[String], to: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        let offset = match self.find(from)? {
+            Some(offset) => offset,
+            None => { return Err(error(io::ErrorKind::PermissionDenied, "Root directory")); }
+        };
+        if self.find(to).is_ok() {
+            return Err(error(io::ErrorKind::AlreadyExists, "Already exists"));
+        }
+        let (from_parent_cluster, _) = self.parent_and_name(from)?;
+        let (parent_cluster, name) = self.parent_and_name(to)?;
+        let mut record = self.record(offset);
+        record.name = name;
+        if parent_cluster == from_parent_cluster {
+            self.write_record(offset, &record);
+        } else {
+            if record.attribute&0x10 != 0 {
+                if to.starts_with(from) {
+                    return Err(error(io::ErrorKind::PermissionDenied, "Move into itself"));
+                }
+                let parent_offset = self.cluster_offset(record.first_cluster)+RECORD_SIZE; // ".." record
+                let mut parent_record = self.record(parent_offset);
+                parent_record.first_cluster = parent_cluster;
+                self.write_record(parent_offset, &parent_record);
+            }
+            self.create_record(parent_cluster, &record)?;
+            self.write_image(offset, &[0xE5]);
+        }
+        self.flush()
+    }
+
+    /// DOS time and date of the last write
+    pub fn file_time(&self, file: &FatFile) -> (u16, u16) {
+        let record = self.record(file.record);
+        (record.write_time, record.write_date)
+    }
+
+    pub fn set_file_time(&mut self, file: &FatFile, time: u16, date: u16) -> io::Result<()> {
+        self.check_writable()?;
+        let mut record = self.record(file.record);
+        record.write_time = time;
+        record.write_date = date;
+        self.write_record(file.record, &record);
+        self.flush()
+    }
+
+    /// Every operation is flushed already, this only retries a failed flush
+    pub fn commit(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    /// Grows the cluster chain of the file to hold the given size, returns the size it can hold
+    fn reserve(&mut self, record_offset: usize, size: usize) -> usize {
+        let mut record = self.record(record_offset);
+        let mut chain = self.cluster_chain(record.first_cluster);
+        let cluster_size = self.cluster_size();
+        while chain.len()*cluster_size < size {
+            let cluster = match self.allocate_cluster(chain.last().cloned().unwrap_or(0)) {
+                Some(cluster) => cluster,
+                None => { break; } // Disk full
+            };
+            if chain.is_empty() {
+                record.first_cluster = cluster;
+                self.write_record(record_offset, &record);
+            }
+            chain.push(cluster);
+        }
+        chain.len()*cluster_size
+    }
+
+    /// Copies between the buffer and the clusters of a file, starting at the given position
+    fn transfer(&mut self, first_cluster: u16, position: usize, buffer: &mut [u8], write: bool) -> usize {
+        let cluster_size = self.cluster_size();
+        let chain = self.cluster_chain(first_cluster);
+        let mut done = 0;
+        while done < buffer.len() {
+            let cluster = match chain.get((position+done)/cluster_size) {
+                Some(cluster) => *cluster,
+                None => { break; }
+            };
+            let offset_in_cluster = (position+done)%cluster_size;
+            let length = (cluster_size-offset_in_cluster).min(buffer.len()-done);
+            let offset = self.cluster_offset(cluster)+offset_in_cluster;
+            if write {
+                self.write_image(offset, &buffer[done..done+length]);
+            } else {
+                self.read_image(offset, &mut buffer[done..done+length]);
+            }
+            done += length;
+        }
+        done
+    }
+
+    pub fn read(&mut self, file: &mut FatFile, buffer: &mut [u8]) -> io::Result<usize> {
+        let record = self.record(file.record);
+        let length = (record.size.saturating_sub(file.position) as usize).min(buffer.len());
+        let length = self.transfer(record.first_cluster, file.position as usize, &mut buffer[0..length], false);
+        file.position += length as u32;
+        Ok(length)
+    }
+
+    pub fn write(&mut self, file: &mut FatFile, buffer: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+        let capacity = self.reserve(file.record, file.position as usize+buffer.len());
+        let length = capacity.saturating_sub(file.position as usize).min(buffer.len());
+        let mut record = self.record(file.record);
+        let mut data = buffer[0..length].to_vec();
+        self.transfer(record.first_cluster, file.position as usize, &mut data, true);
+        file.position += length as u32;
+        record.size = record.size.max(file.position);
+        record.touch();
+        self.write_record(file.record, &record);
+        self.flush()?;
+        Ok(length)
+    }
+
+    /// Truncates or extends the file to its current position
+    pub fn set_length(&mut self, file: &FatFile) -> io::Result<()> {
+        self.check_writable()?;
+        let cluster_size = self.cluster_size();
+        let capacity = self.reserve(file.record, file.position as usize);
+        let mut record = self.record(file.record);
+        let chain = self.cluster_chain(record.first_cluster);
+        let keep = (file.position as usize+cluster_size-1)/cluster_size;
+        if keep == 0 {
+            self.free_cluster_chain(record.first_cluster);
+            record.first_cluster = 0;
+        } else if keep < chain.len() {
+            self.free_cluster_chain(chain[keep]);
+            self.set_fat_entry(chain[keep-1], self.end_of_chain());
+        }
+        record.size = (file.position as usize).min(capacity) as u32;
+        record.touch();
+        self.write_record(file.record, &record);
+        self.flush()
+    }
+
+    pub fn seek(&self, file: &mut FatFile, seek: io::SeekFrom) -> io::Result<u64> {
+        let position = match seek {
+            io::SeekFrom::Start(offset) => offset as i64,
+            io::SeekFrom::Current(offset) => file.position as i64+offset,
+            io::SeekFrom::End(offset) => self.record(file.record).size as i64+offset
+        };
+        if position < 0 || position > std::u32::MAX as i64 {
+            return Err(error(io::ErrorKind::InvalidInput, "Invalid seek position"));
+        }
+        file.position = position as u32;
+        Ok(position as u64)
+    }
+}
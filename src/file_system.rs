@@ -0,0 +1,321 @@
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::PermissionsExt;
+use chrono::prelude::*;
+
+/// Entry of a directory listing as FindFirst / FindNext report it
+pub struct DirectoryEntry {
+    pub short_name: String,
+    pub attribute: u8,
+    pub time: u16,
+    pub date: u16,
+    pub size: u32
+}
+
+/// DOS time and date of a timestamp
+pub fn dos_timestamp(timestamp: chrono::DateTime<Local>) -> (u16, u16) {
+    ((timestamp.hour() as u16)<<11|(timestamp.minute() as u16)<<5|(timestamp.second() as u16/2),
+     ((timestamp.year().max(1980)-1980) as u16)<<9|(timestamp.month() as u16)<<5|(timestamp.day() as u16))
+}
+
+/// Timestamp of a DOS time and date, None if they are invalid
+pub fn from_dos_timestamp(time: u16, date: u16) -> Option<chrono::DateTime<Local>> {
+    let timestamp = chrono::NaiveDate::from_ymd_opt(1980+(date>>9) as i32, ((date>>5)&0x0F) as u32, (date&0x1F) as u32)?
+        .and_hms_opt((time>>11) as u32, ((time>>5)&0x3F) as u32, (time&0x1F) as u32*2)?;
+    Local.from_local_datetime(&timestamp).earliest()
+}
+
+/// Directory of the host mapped to 8.3 names
+pub struct HostFileSystem {
+    pub root: std::path::PathBuf,
+    name_mapping: crate::name_mapping::NameMapping
+}
+
+pub enum FileSystem {
+    Host(HostFileSystem),
+    Fat(crate::fat::FatFileSystem),
+    Iso9660(crate::iso9660::Iso9660FileSystem)
+}
+
+pub enum OpenFile {
+    Host(std::fs::File),
+    Fat(crate::fat::FatFile),
+    Iso9660(crate::iso9660::IsoFile)
+}
+
+fn read_only_error() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "Read only file system")
+}
+
+impl HostFileSystem {
+    pub fn new(root: &std::path::Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            name_mapping: crate::name_mapping::NameMapping::new()
+        }
+    }
+
+    fn path(&mut self, components: &[String]) -> std::path::PathBuf {
+        let path = self.name_mapping.host_path(&self.root, components);
+        assert!(path.strip_prefix(&self.root).map_or(false, |relative| relative.components().all(|component| matches!(component, std::path::Component::Normal(_)))),
+                "Host path {:?} is outside of {:?}", path, self.root);
+        path
+    }
+
+    fn list_directory(&mut self, components: &[String]) -> io::Result<Vec<DirectoryEntry>> {
+        let directory = self.path(components);
+        if !directory.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Not a directory"));
+        }
+        Ok(self.name_mapping.scan(&directory).iter().filter_map(|entry| {
+            let metadata = std::fs::metadata(directory.join(&entry.host_name)).ok()?;
+            let (time, date) = dos_timestamp(chrono::DateTime::<Local>::from(metadata.modified().ok()?));
+            Some(DirectoryEntry {
+                short_name: entry.short_name.clone(),
+                attribute: if metadata.is_dir() { 0x10 } else { 0x00 }|
+                    if entry.host_name.to_string_lossy().starts_with('.') { 0x02 } else { 0x00 }|
+                    (metadata.permissions().readonly() as u8),
+                time: time,
+                date: date,
+                size: if metadata.is_dir() { 0 } else { metadata.len() as u32 }
+            })
+        }).collect())
+    }
+
+    /// Sectors per cluster, bytes per sector, free and total clusters, capped like FAT16 at 2 GiB
+    fn free_space(&self, kind: crate::dos::DriveKind) -> (u16, u16, u16, u16) {
+        let (sectors_per_cluster, max_clusters) = match kind {
+            crate::dos::DriveKind::Floppy => (1, 2847), // 1.44 MB
+            crate::dos::DriveKind::HardDisk | crate::dos::DriveKind::CdRom => (64, 0xFFF6)
+        };
+        let mut statistics: libc::statvfs = unsafe { std::mem::zeroed() };
+        let root = std::ffi::CString::new(self.root.to_string_lossy().as_bytes()).unwrap_or_default();
+        let (free_bytes, total_bytes) = if unsafe { libc::statvfs(root.as_ptr(), &mut statistics) } == 0 {
+            ((statistics.f_bavail as u64)*(statistics.f_frsize as u64), (statistics.f_blocks as u64)*(statistics.f_frsize as u64))
+        } else {
+            (0, 0)
+        };
+        let cluster_size = sectors_per_cluster as u64*512;
+        let total_clusters = (total_bytes/cluster_size).min(max_clusters);
+        let free_clusters = (free_bytes/cluster_size).min(total_clusters);
+        (sectors_per_cluster, 512, free_clusters as u16, total_clusters as u16)
+    }
+}
+
+impl FileSystem {
+    pub fn is_directory(&mut self, components: &[String]) -> bool {
+        match self {
+            FileSystem::Host(host) => host.path(components).is_dir(),
+            FileSystem::Fat(fat) => fat.is_directory(components),
+            FileSystem::Iso9660(iso9660) => iso9660.is_directory(components)
+        }
+    }
+
+    pub fn list_directory(&mut self, components: &[String]) -> io::Result<Vec<DirectoryEntry>> {
+        match self {
+            FileSystem::Host(host) => host.list_directory(components),
+            FileSystem::Fat(fat) => fat.list_directory(components),
+            FileSystem::Iso9660(iso9660) => iso9660.list_directory(components)
+        }
+    }
+
+    pub fn volume_label(&self) -> Option<String> {
+        match self {
+            FileSystem::Host(_) => None,
+            FileSystem::Fat(fat) => fat.volume_label(),
+            FileSystem::Iso9660(iso9660) => iso9660.volume_label()
+        }
+    }
+
+    pub fn free_space(&self, kind: crate::dos::DriveKind) -> (u16, u16, u16, u16) {
+        match self {
+            FileSystem::Host(host) => host.free_space(kind),
+            FileSystem::Fat(fat) => fat.free_space(),
+            FileSystem::Iso9660(iso9660) => iso9660.free_space()
+        }
+    }
+
+    pub fn create_directory(&mut self, components: &[String]) -> io::Result<()> {
+        match self {
+            FileSystem::Host(host) => std::fs::create_dir(host.path(components)),
+            FileSystem::Fat(fat) => fat.create_directory(components),
+            FileSystem::Iso9660(_) => Err(read_only_error())
+        }
+    }
+
+    pub fn remove_directory(&mut self, components: &[String]) -> io::Result<()> {
+        match self {
+            FileSystem::Host(host) => {
+                let path = host.path(components);
+                if std::fs::read_dir(&path).map_or(false, |mut entries| entries.next().is_some()) {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Directory not empty"));
+                }
+                std::fs::remove_dir(path)
+            },
+            FileSystem::Fat(fat) => fat.remove_directory(components),
+            FileSystem::Iso9660(_) => Err(read_only_error())
+        }
+    }
+
+    pub fn create_file(&mut self, components: &[String]) -> io::Result<OpenFile> {
+        match self {
+            FileSystem::Host(host) => Ok(OpenFile::Host(std::fs::File::create(host.path(components))?)),
+            FileSystem::Fat(fat) => Ok(OpenFile::Fat(fat.create_file(components)?)),
+            FileSystem::Iso9660(_) => Err(read_only_error())
+        }
+    }
+
+    pub fn open_file(&mut self, components: &[String], writable: bool) -> io::Result<OpenFile> {
+        match self {
+            FileSystem::Host(host) => {
+                let path = host.path(components);
+                if writable && std::fs::metadata(&path)?.permissions().readonly() { // Even if the host user may write it
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Read only file"));
+                }
+                Ok(OpenFile::Host(std::fs::OpenOptions::new().read(true).write(writable).open(path)?))
+            },
+            FileSystem::Fat(fat) => Ok(OpenFile::Fat(fat.open_file(components, writable)?)),
+            FileSystem::Iso9660(_) if writable => Err(read_only_error()),
+            FileSystem::Iso9660(iso9660) => Ok(OpenFile::Iso9660(iso9660.open_file(components)?))
+        }
+    }
+
+    pub fn delete_file(&mut self, components: &[String]) -> io::Result<()> {
+        match self {
+            FileSystem::Host(host) => std::fs::remove_file(host.path(components)),
+            FileSystem::Fat(fat) => fat.delete_file(components),
+            FileSystem::Iso9660(_) => Err(read_only_error())
+        }
+    }
+
+    /// Attributes of a file or directory as listed in its parent directory
+    pub fn attributes(&mut self, components: &[String]) -> io::Result<u8> {
+        let (name, parent) = match components.split_last() {
+            Some(split) => split,
+            None => { return Err(io::Error::new(io::ErrorKind::NotFound, "Root directory")); }
+        };
+        let short_name = crate::name_mapping::normalize_short_name(name);
+        self.list_directory(parent)?.iter().find(|entry| entry.short_name == short_name).map(|entry| entry.attribute)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such file or directory"))
+    }
+
+    /// Sets the read only, hidden, system and archive attributes, host files only keep the read only one
+    pub fn set_attributes(&mut self, components: &[String], attribute: u8) -> io::Result<()> {
+        match self {
+            FileSystem::Host(host) => {
+                let path = host.path(components);
+                let mut permissions = std::fs::metadata(&path)?.permissions();
+                let mode = permissions.mode();
+                permissions.set_mode(if attribute&0x01 != 0 { mode&!0o222 } else { mode|0o200 });
+                std::fs::set_permissions(path, permissions)
+            },
+            FileSystem::Fat(fat) => fat.set_attributes(components, attribute),
+            FileSystem::Iso9660(_) => Err(read_only_error())
+        }
+    }
+
+    /// Renames or moves a file or directory, the new name must not exist yet
+    pub fn rename(&mut self, from: &[String], to: &[String]) -> io::Result<()> {
+        match self {
+            FileSystem::Host(host) => {
+                let (from, to) = (host.path(from), host.path(to));
+                if std::fs::symlink_metadata(&to).is_ok() {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Already exists"));
+                }
+                std::fs::rename(from, to)
+            },
+            FileSystem::Fat(fat) => fat.rename(from, to),
+            FileSystem::Iso9660(_) => Err(read_only_error())
+        }
+    }
+
+    /// DOS time and date of the last write
+    pub fn file_time(&mut self, file: &OpenFile) -> io::Result<(u16, u16)> {
+        match (self, file) {
+            (FileSystem::Fat(fat), OpenFile::Fat(file)) => Ok(fat.file_time(file)),
+            (_, OpenFile::Iso9660(file)) => Ok((file.time, file.date)),
+            (_, OpenFile::Host(file)) => Ok(dos_timestamp(chrono::DateTime::<Local>::from(file.metadata()?.modified()?))),
+            _ => unreachable!()
+        }
+    }
+
+    pub fn set_file_time(&mut self, file: &OpenFile, time: u16, date: u16) -> io::Result<()> {
+        match (self, file) {
+            (FileSystem::Fat(fat), OpenFile::Fat(file)) => fat.set_file_time(file, time, date),
+            (_, OpenFile::Iso9660(_)) => Err(read_only_error()),
+            (_, OpenFile::Host(file)) => {
+                let timestamp = from_dos_timestamp(time, date).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid time or date"))?;
+                file.set_modified(std::time::SystemTime::from(timestamp))
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Writes the buffered data of the file to its host file or disk image
+    pub fn commit(&mut self, file: &OpenFile) -> io::Result<()> {
+        match (self, file) {
+            (FileSystem::Fat(fat), OpenFile::Fat(_)) => fat.commit(),
+            (_, OpenFile::Iso9660(_)) => Ok(()),
+            (_, OpenFile::Host(file)) => file.sync_all(),
+            _ => unreachable!()
+        }
+    }
+
+    pub fn read(&mut self, file: &mut OpenFile, buffer: &mut [u8]) -> io::Result<usize> {
+        match (self, file) {
+            (FileSystem::Fat(fat), OpenFile::Fat(file)) => fat.read(file, buffer),
+            (FileSystem::Iso9660(iso9660), OpenFile::Iso9660(file)) => iso9660.read(file, buffer),
+            (_, OpenFile::Host(file)) => file.read(buffer),
+            _ => unreachable!()
+        }
+    }
+
+    pub fn write(&mut self, file: &mut OpenFile, buffer: &[u8]) -> io::Result<usize> {
+        match (self, file) {
+            (FileSystem::Fat(fat), OpenFile::Fat(file)) => fat.write(file, buffer),
+            (_, OpenFile::Iso9660(_)) => Err(read_only_error()),
+            (_, OpenFile::Host(file)) => file.write(buffer),
+            _ => unreachable!()
+        }
+    }
+
+    /// Truncates or extends the file to its current position
+    pub fn set_length(&mut self, file: &mut OpenFile) -> io::Result<()> {
+        match (self, file) {
+            (FileSystem::Fat(fat), OpenFile::Fat(file)) => fat.set_length(file),
+            (_, OpenFile::Iso9660(_)) => Err(read_only_error()),
+            (_, OpenFile::Host(file)) => {
+                let position = file.seek(io::SeekFrom::Current(0))?;
+                file.set_len(position)
+            },
+            _ => unreachable!()
+        }
+    }
+
+    pub fn seek(&mut self, file: &mut OpenFile, seek: io::SeekFrom) -> io::Result<u64> {
+        match (self, file) {
+            (FileSystem::Fat(fat), OpenFile::Fat(file)) => fat.seek(file, seek),
+            (FileSystem::Iso9660(iso9660), OpenFile::Iso9660(file)) => iso9660.seek(file, seek),
+            (_, OpenFile::Host(file)) => file.seek(seek),
+            _ => unreachable!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn host_path_stays_under_root() {
+        let root = std::env::temp_dir().join("host_path_stays_under_root"); // Not created, so no host names get mapped
+        let mut file_system = super::HostFileSystem::new(&root);
+        assert_eq!(file_system.path(&["DIR".to_string(), "FILE.TXT".to_string()]), root.join("DIR").join("FILE.TXT"));
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of")]
+    fn host_path_outside_root_panics() {
+        // "..." is shortened to "..", resolve_path rejects it before it gets here
+        let mut file_system = super::HostFileSystem::new(&std::env::temp_dir());
+        file_system.path(&["...".to_string(), "etc".to_string()]);
+    }
+}
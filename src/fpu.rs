@@ -0,0 +1,1348 @@
+use crate::machinecode::FpuOpcode;
+use crate::machinecode::Operand;
+
+const EXPONENT_BIAS: i32 = 16383;
+const MIN_EXPONENT: i32 = -16382;
+const MAX_EXPONENT: i32 = 16383;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Exception {
+    InvalidOperation = 0,
+    DenormalOperand = 1,
+    ZeroDivide = 2,
+    Overflow = 3,
+    Underflow = 4,
+    Precision = 5,
+    StackFault = 6,
+    ErrorSummary = 7
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ConditionCode {
+    C0 = 8,
+    C1 = 9,
+    C2 = 10,
+    C3 = 14
+}
+
+/// 80 bit extended precision value, as held in the register stack
+#[derive(Copy, Clone, PartialEq)]
+pub struct Extended {
+    pub sign_exponent: u16,
+    pub mantissa: u64
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Class {
+    Zero,
+    Finite(i32, u64), // Normalized: value = mantissa/2^63*2^exponent
+    Infinity,
+    NaN
+}
+
+const INDEFINITE: Extended = Extended { sign_exponent: 0xFFFF, mantissa: 0xC000000000000000 };
+const ZERO: Extended = Extended { sign_exponent: 0x0000, mantissa: 0 };
+const ONE: Extended = Extended { sign_exponent: 0x3FFF, mantissa: 0x8000000000000000 };
+
+static CONSTANTS: &'static [Extended] = &[
+    ONE, // FLD1
+    Extended { sign_exponent: 0x4000, mantissa: 0xD49A784BCD1B8AFE }, // FLDL2T
+    Extended { sign_exponent: 0x3FFF, mantissa: 0xB8AA3B295C17F0BC }, // FLDL2E
+    Extended { sign_exponent: 0x4000, mantissa: 0xC90FDAA22168C235 }, // FLDPI
+    Extended { sign_exponent: 0x3FFD, mantissa: 0x9A209A84FBCFF799 }, // FLDLG2
+    Extended { sign_exponent: 0x3FFE, mantissa: 0xB17217F7D1CF79AC }, // FLDLN2
+    ZERO // FLDZ
+];
+
+impl Extended {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut mantissa: u64 = 0;
+        for index in 0..8 {
+            mantissa |= (bytes[index] as u64)<<(index*8);
+        }
+        Self {
+            sign_exponent: (bytes[8] as u16)|((bytes[9] as u16)<<8),
+            mantissa: mantissa
+        }
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8]) {
+        for index in 0..8 {
+            bytes[index] = (self.mantissa>>(index*8)) as u8;
+        }
+        bytes[8] = self.sign_exponent as u8;
+        bytes[9] = (self.sign_exponent>>8) as u8;
+    }
+
+    fn pack(sign: bool, exponent: i32, mantissa: u64) -> Self {
+        let biased_exponent = if mantissa>>63 == 0 { 0 } else { (exponent+EXPONENT_BIAS) as u16 };
+        Self {
+            sign_exponent: ((sign as u16)<<15)|biased_exponent,
+            mantissa: mantissa
+        }
+    }
+
+    fn sign(&self) -> bool {
+        self.sign_exponent>>15 == 1
+    }
+
+    fn with_sign(&self, sign: bool) -> Self {
+        Self {
+            sign_exponent: (self.sign_exponent&0x7FFF)|((sign as u16)<<15),
+            mantissa: self.mantissa
+        }
+    }
+
+    fn infinity(sign: bool) -> Self {
+        Self::pack(sign, MAX_EXPONENT+1, 0x8000000000000000)
+    }
+
+    fn class(&self) -> Class {
+        let biased_exponent = (self.sign_exponent&0x7FFF) as i32;
+        if biased_exponent == 0x7FFF {
+            if self.mantissa<<1 == 0 { Class::Infinity } else { Class::NaN }
+        } else if self.mantissa == 0 {
+            Class::Zero
+        } else {
+            let shift = self.mantissa.leading_zeros();
+            Class::Finite(biased_exponent.max(1)-EXPONENT_BIAS-shift as i32, self.mantissa<<shift)
+        }
+    }
+
+    fn is_signaling_nan(&self) -> bool {
+        self.class() == Class::NaN && (self.mantissa>>62)&1 == 0
+    }
+
+    fn is_denormal(&self) -> bool {
+        self.sign_exponent&0x7FFF == 0 && self.mantissa != 0
+    }
+}
+
+fn shift_right_sticky(value: u128, shift: u32) -> u128 {
+    if shift == 0 {
+        value
+    } else if shift >= 128 {
+        (value != 0) as u128
+    } else {
+        (value>>shift)|((value&((1u128<<shift)-1) != 0) as u128)
+    }
+}
+
+fn integer_square_root(value: u128) -> (u128, u128) {
+    let mut root: u128 = 0;
+    let mut remainder = value;
+    let mut bit: u128 = 1<<126;
+    while bit > remainder {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if remainder >= root+bit {
+            remainder -= root+bit;
+            root = (root>>1)+bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    (root, remainder)
+}
+
+fn multiply_high(a: u128, b: u128) -> u128 {
+    let mask = std::u64::MAX as u128;
+    let (a_high, a_low, b_high, b_low) = (a>>64, a&mask, b>>64, b&mask);
+    let (low, first_middle, second_middle) = (a_low*b_low, a_high*b_low, a_low*b_high);
+    let carry = ((low>>64)+(first_middle&mask)+(second_middle&mask))>>64;
+    a_high*b_high+(first_middle>>64)+(second_middle>>64)+carry
+}
+
+/// Intermediate value of the transcendental instructions: mantissa/2^127*2^exponent.
+/// Twice the precision of the register stack, so the final result rounds correctly to 64 bits.
+#[derive(Copy, Clone)]
+struct Wide {
+    sign: bool,
+    exponent: i32,
+    mantissa: u128
+}
+
+const WIDE_ONE: Wide = Wide { sign: false, exponent: 0, mantissa: 1<<127 };
+const WIDE_PI: Wide = Wide { sign: false, exponent: 1, mantissa: 0xC90FDAA22168C234C4C6628B80DC1CD1 };
+const WIDE_LN2: Wide = Wide { sign: false, exponent: -1, mantissa: 0xB17217F7D1CF79ABC9E3B39803F2F6AF };
+const WIDE_LOG2E: Wide = Wide { sign: false, exponent: 0, mantissa: 0xB8AA3B295C17F0BBBE87FED0691D3E88 };
+const WIDE_SQRT2_MANTISSA: u128 = 0xB504F333F9DE6484597D89B3754ABE9F;
+
+impl Wide {
+    fn normalize(sign: bool, exponent: i32, mantissa: u128) -> Self {
+        let shift = mantissa.leading_zeros().min(127);
+        Self {
+            sign: sign,
+            exponent: exponent-shift as i32,
+            mantissa: mantissa<<shift
+        }
+    }
+
+    fn from_integer(value: i64) -> Self {
+        Self::normalize(value < 0, 127, value.wrapping_abs() as u64 as u128)
+    }
+
+    fn from_extended(value: Extended) -> Self {
+        match value.class() {
+            Class::Finite(exponent, mantissa) => Self { sign: value.sign(), exponent: exponent, mantissa: (mantissa as u128)<<64 },
+            _ => Self { sign: value.sign(), exponent: 0, mantissa: 0 }
+        }
+    }
+
+    fn negate(self) -> Self {
+        Self { sign: !self.sign, ..self }
+    }
+
+    fn abs(self) -> Self {
+        Self { sign: false, ..self }
+    }
+
+    fn scale(self, factor: i32) -> Self {
+        Self { exponent: self.exponent+factor, ..self }
+    }
+
+    fn magnitude(&self) -> (bool, i32, u128) {
+        (self.mantissa != 0, self.exponent, self.mantissa)
+    }
+
+    fn add(self, other: Self) -> Self {
+        if other.mantissa == 0 {
+            return self;
+        } else if self.mantissa == 0 {
+            return other;
+        }
+        let (a, b) = if self.magnitude() >= other.magnitude() { (self, other) } else { (other, self) };
+        let shift = (a.exponent-b.exponent) as u32;
+        let b_mantissa = if shift >= 128 { 0 } else { b.mantissa>>shift };
+        if a.sign != b.sign {
+            return Self::normalize(a.sign, a.exponent, a.mantissa-b_mantissa);
+        }
+        match a.mantissa.overflowing_add(b_mantissa) {
+            (mantissa, true) => Self { sign: a.sign, exponent: a.exponent+1, mantissa: (mantissa>>1)|(1<<127) },
+            (mantissa, false) => Self::normalize(a.sign, a.exponent, mantissa)
+        }
+    }
+
+    fn multiply(self, other: Self) -> Self {
+        Self::normalize(self.sign^other.sign, self.exponent+other.exponent+1, multiply_high(self.mantissa, other.mantissa))
+    }
+
+    fn divide(self, other: Self) -> Self {
+        let mut remainder = self.mantissa;
+        let mut quotient: u128 = 0;
+        let mut carry = false;
+        for _ in 0..128 {
+            quotient <<= 1;
+            if carry || remainder >= other.mantissa {
+                remainder = remainder.wrapping_sub(other.mantissa);
+                quotient |= 1;
+            }
+            carry = remainder>>127 == 1;
+            remainder <<= 1;
+        }
+        Self::normalize(self.sign^other.sign, self.exponent-other.exponent, quotient)
+    }
+
+    /// Sums first+first*x/((n+1)*..*(n+step))+..., starting with n = first_index
+    fn power_series(x: Self, first: Self, first_index: i64, step: i64) -> Self {
+        let (mut term, mut sum, mut index) = (first, first, first_index);
+        loop {
+            let mut divisor = 1;
+            for _ in 0..step {
+                index += 1;
+                divisor *= index;
+            }
+            term = term.multiply(x).divide(Self::from_integer(divisor));
+            if term.mantissa == 0 || term.exponent < sum.exponent-130 {
+                return sum;
+            }
+            sum = sum.add(term);
+        }
+    }
+
+    /// Sums x+x^3/3+x^5/5+..., which is atanh(x), or with alternating signs atan(x), for |x| < 1
+    fn odd_power_series(self, alternating: bool) -> Self {
+        let square = self.multiply(self);
+        let (mut power, mut sum, mut index) = (self, self, 1);
+        loop {
+            power = power.multiply(square);
+            index += 2;
+            let term = power.divide(Self::from_integer(index));
+            if term.mantissa == 0 || term.exponent < sum.exponent-130 {
+                return sum;
+            }
+            sum = sum.add(if alternating && index&2 == 2 { term.negate() } else { term });
+        }
+    }
+
+    /// 2^x-1, the integral part of x only scales the result so the series converges quickly
+    fn exponential_minus_one(self) -> Self {
+        if self.exponent > 16 {
+            return if self.sign { WIDE_ONE.negate() } else { WIDE_ONE.scale(1<<17) };
+        }
+        let integer = if self.exponent < 0 { 0 } else { (self.mantissa>>(127-self.exponent)) as i64 };
+        let integer = if self.sign { -integer } else { integer };
+        let fraction = self.add(Self::from_integer(-integer)).multiply(WIDE_LN2);
+        let result = Self::power_series(fraction, fraction, 1, 1);
+        if integer == 0 { result } else { result.add(WIDE_ONE).scale(integer as i32).add(WIDE_ONE.negate()) }
+    }
+
+    /// log2(x) of a positive x, the mantissa m is reduced to [sqrt(1/2), sqrt(2)) for ln(m) = 2*atanh((m-1)/(m+1))
+    fn logarithm(self) -> Self {
+        let (exponent, mantissa) = if self.mantissa > WIDE_SQRT2_MANTISSA {
+            (self.exponent+1, Self { sign: false, exponent: -1, mantissa: self.mantissa })
+        } else {
+            (self.exponent, Self { sign: false, exponent: 0, mantissa: self.mantissa })
+        };
+        let natural = mantissa.add(WIDE_ONE.negate()).divide(mantissa.add(WIDE_ONE)).odd_power_series(false).scale(1);
+        Self::from_integer(exponent as i64).add(natural.multiply(WIDE_LOG2E))
+    }
+
+    /// log2(x+1), evaluated as 2*atanh(x/(x+2)) near zero to keep the precision of x
+    fn logarithm_plus_one(self) -> Self {
+        if self.exponent >= -2 {
+            return self.add(WIDE_ONE).logarithm();
+        }
+        self.divide(self.add(WIDE_ONE.scale(1))).odd_power_series(false).scale(1).multiply(WIDE_LOG2E)
+    }
+
+    /// atan(x) for 0 <= x, values above 1/2 are reduced with atan(x) = pi/4+atan((x-1)/(x+1))
+    fn arctangent(self) -> Self {
+        if self.exponent < -1 {
+            return self.odd_power_series(true);
+        }
+        self.add(WIDE_ONE.negate()).divide(self.add(WIDE_ONE)).odd_power_series(true).add(WIDE_PI.scale(-2))
+    }
+
+    /// Sine and cosine, the argument is reduced modulo pi/2 to [-pi/4, pi/4] first
+    fn sine_cosine(self) -> (Self, Self) {
+        let half_pi = WIDE_PI.scale(-1);
+        let quotient = self.abs().divide(half_pi);
+        let quadrant = if quotient.exponent < -1 { 0 } else { ((quotient.mantissa>>(126-quotient.exponent))+1)>>1 };
+        let remainder = self.abs().add(Self::from_integer(quadrant as i64).multiply(half_pi).negate());
+        let square = remainder.multiply(remainder).negate();
+        let sine = Self::power_series(square, remainder, 1, 2);
+        let cosine = Self::power_series(square, WIDE_ONE, 0, 2);
+        let (sine, cosine) = match quadrant&3 {
+            0 => (sine, cosine),
+            1 => (cosine, sine.negate()),
+            2 => (sine.negate(), cosine.negate()),
+            _ => (cosine.negate(), sine)
+        };
+        (if self.sign { sine.negate() } else { sine }, cosine)
+    }
+}
+
+pub struct FloatingPointUnit {
+    registers: [Extended; 8],
+    pub control_word: u16,
+    pub status_word: u16,
+    tag_word: u16,
+    instruction_pointer: u32,
+    operand_pointer: u32,
+    last_opcode: u16
+}
+
+impl FloatingPointUnit {
+    pub fn new() -> Self {
+        let mut fpu = Self {
+            registers: [ZERO; 8],
+            control_word: 0,
+            status_word: 0,
+            tag_word: 0,
+            instruction_pointer: 0,
+            operand_pointer: 0,
+            last_opcode: 0
+        };
+        fpu.initialize();
+        fpu
+    }
+
+    fn initialize(&mut self) {
+        self.control_word = 0x037F;
+        self.status_word = 0;
+        self.tag_word = 0xFFFF;
+        self.instruction_pointer = 0;
+        self.operand_pointer = 0;
+        self.last_opcode = 0;
+    }
+
+    fn top(&self) -> usize {
+        ((self.status_word>>11)&7) as usize
+    }
+
+    fn set_top(&mut self, top: usize) {
+        self.status_word = (self.status_word&!0x3800)|(((top&7) as u16)<<11);
+    }
+
+    fn physical_index(&self, index: usize) -> usize {
+        (self.top()+index)&7
+    }
+
+    fn is_empty(&self, index: usize) -> bool {
+        (self.tag_word>>(self.physical_index(index)*2))&3 == 3
+    }
+
+    fn set_empty(&mut self, index: usize, empty: bool) {
+        let shift = self.physical_index(index)*2;
+        self.tag_word = (self.tag_word&!(3<<shift))|(((empty as u16)*3)<<shift);
+    }
+
+    fn full_tag_word(&self) -> u16 {
+        let mut tag_word: u16 = 0;
+        for physical_index in 0..8 {
+            let tag = if (self.tag_word>>(physical_index*2))&3 == 3 {
+                3
+            } else {
+                match self.registers[physical_index].class() {
+                    Class::Zero => 1,
+                    Class::Finite(_, _) if !self.registers[physical_index].is_denormal() && self.registers[physical_index].mantissa>>63 == 1 => 0,
+                    _ => 2
+                }
+            };
+            tag_word |= tag<<(physical_index*2);
+        }
+        tag_word
+    }
+
+    fn raise(&mut self, exception: Exception) {
+        self.status_word |= 1<<(exception as u16);
+        if (self.control_word>>(exception as u16))&1 == 0 && exception != Exception::StackFault {
+            self.status_word |= (1<<(Exception::ErrorSummary as u16))|0x8000;
+        }
+    }
+
+    /// An unmasked exception is reported once its error summary is set and until FNCLEX or FNINIT clears it
+    pub fn is_error_pending(&self) -> bool {
+        (self.status_word>>(Exception::ErrorSummary as u16))&1 == 1
+    }
+
+    /// Whether an instruction reports a pending unmasked exception first, the FN forms don't wait
+    pub fn is_waiting(fpu_opcode: FpuOpcode) -> bool {
+        match fpu_opcode {
+            FpuOpcode::FNINIT | FpuOpcode::FNCLEX | FpuOpcode::FNSTCW | FpuOpcode::FNSTSW | FpuOpcode::FNSTENV | FpuOpcode::FNSAVE |
+            FpuOpcode::FENI | FpuOpcode::FDISI => false,
+            _ => true
+        }
+    }
+
+    fn set_condition_code(&mut self, condition_code: ConditionCode, value: bool) {
+        self.status_word = (self.status_word&!(1<<(condition_code as u16)))|((value as u16)<<(condition_code as u16));
+    }
+
+    fn set_condition_codes(&mut self, c3: bool, c2: bool, c1: bool, c0: bool) {
+        self.set_condition_code(ConditionCode::C3, c3);
+        self.set_condition_code(ConditionCode::C2, c2);
+        self.set_condition_code(ConditionCode::C1, c1);
+        self.set_condition_code(ConditionCode::C0, c0);
+    }
+
+    fn get(&mut self, index: usize) -> Extended {
+        if self.is_empty(index) {
+            self.raise(Exception::StackFault);
+            self.raise(Exception::InvalidOperation);
+            self.set_condition_code(ConditionCode::C1, false);
+            INDEFINITE
+        } else {
+            self.registers[self.physical_index(index)]
+        }
+    }
+
+    fn set(&mut self, index: usize, value: Extended) {
+        let physical_index = self.physical_index(index);
+        self.registers[physical_index] = value;
+        self.set_empty(index, false);
+    }
+
+    fn push(&mut self, value: Extended) {
+        self.set_top(self.top().wrapping_sub(1));
+        if !self.is_empty(0) {
+            self.raise(Exception::StackFault);
+            self.raise(Exception::InvalidOperation);
+            self.set_condition_code(ConditionCode::C1, true);
+            self.set(0, INDEFINITE);
+        } else {
+            self.set(0, value);
+        }
+    }
+
+    fn pop(&mut self) {
+        self.set_empty(0, true);
+        self.set_top(self.top()+1);
+    }
+
+    fn rounding_control(&self) -> u16 {
+        (self.control_word>>10)&3
+    }
+
+    fn precision(&self) -> u32 {
+        match (self.control_word>>8)&3 {
+            0 => 24,
+            2 => 53,
+            _ => 64
+        }
+    }
+
+    /// Rounds a normalized mantissa (most significant bit at 127) to the given precision and exponent range.
+    /// Returns the exponent and mantissa (most significant bit at 63, cleared for denormals).
+    fn round(&mut self, sign: bool, mut exponent: i32, mut mantissa: u128, precision: u32, min_exponent: i32, max_exponent: i32) -> (i32, u64) {
+        let mut tiny = false;
+        if exponent < min_exponent {
+            mantissa = shift_right_sticky(mantissa, (min_exponent-exponent).min(128) as u32);
+            exponent = min_exponent;
+            tiny = true;
+        }
+        let shift = 128-precision;
+        let mut kept = mantissa>>shift;
+        let rest = mantissa&((1u128<<shift)-1);
+        let half = 1u128<<(shift-1);
+        if rest != 0 {
+            let round_up = match self.rounding_control() {
+                0 => rest > half || (rest == half && kept&1 == 1),
+                1 => sign,
+                2 => !sign,
+                _ => false
+            };
+            if round_up {
+                kept += 1;
+                if kept>>precision != 0 {
+                    kept >>= 1;
+                    exponent += 1;
+                }
+            }
+            if tiny {
+                self.raise(Exception::Underflow);
+            }
+            self.raise(Exception::Precision);
+            self.set_condition_code(ConditionCode::C1, round_up);
+        }
+        if exponent > max_exponent {
+            self.raise(Exception::Overflow);
+            self.raise(Exception::Precision);
+            let to_infinity = match self.rounding_control() {
+                0 => true,
+                1 => sign,
+                2 => !sign,
+                _ => false
+            };
+            return if to_infinity {
+                (max_exponent+1, 0x8000000000000000)
+            } else {
+                (max_exponent, (((1u128<<precision)-1) as u64)<<(64-precision))
+            };
+        }
+        (exponent, (kept as u64)<<(64-precision))
+    }
+
+    /// Builds a register value from sign*mantissa*2^(exponent-127), rounding to the given precision
+    fn make_extended_with_precision(&mut self, sign: bool, exponent: i32, mantissa: u128, precision: u32) -> Extended {
+        if mantissa == 0 {
+            return ZERO.with_sign(sign);
+        }
+        let shift = mantissa.leading_zeros();
+        let (exponent, mantissa) = self.round(sign, exponent-shift as i32, mantissa<<shift, precision, MIN_EXPONENT, MAX_EXPONENT);
+        Extended::pack(sign, exponent, mantissa)
+    }
+
+    /// Same as make_extended_with_precision, but rounds to the precision control of arithmetic results
+    fn make_extended(&mut self, sign: bool, exponent: i32, mantissa: u128) -> Extended {
+        let precision = self.precision();
+        self.make_extended_with_precision(sign, exponent, mantissa, precision)
+    }
+
+    fn propagate_nan(&mut self, a: Extended, b: Extended) -> Extended {
+        if a.is_signaling_nan() || b.is_signaling_nan() {
+            self.raise(Exception::InvalidOperation);
+        }
+        let result = match (a.class(), b.class()) {
+            (Class::NaN, Class::NaN) => if a.mantissa<<1 >= b.mantissa<<1 { a } else { b },
+            (Class::NaN, _) => a,
+            _ => b
+        };
+        Extended {
+            sign_exponent: result.sign_exponent,
+            mantissa: result.mantissa|0xC000000000000000
+        }
+    }
+
+    fn invalid(&mut self) -> Extended {
+        self.raise(Exception::InvalidOperation);
+        INDEFINITE
+    }
+
+    fn check_denormal(&mut self, a: Extended, b: Extended) {
+        if a.is_denormal() || b.is_denormal() {
+            self.raise(Exception::DenormalOperand);
+        }
+    }
+
+    fn add(&mut self, a: Extended, b: Extended, subtract: bool) -> Extended {
+        let b = b.with_sign(b.sign()^subtract);
+        self.check_denormal(a, b);
+        match (a.class(), b.class()) {
+            (Class::NaN, _) | (_, Class::NaN) => self.propagate_nan(a, b),
+            (Class::Infinity, Class::Infinity) => if a.sign() == b.sign() { a } else { self.invalid() },
+            (Class::Infinity, _) => a,
+            (_, Class::Infinity) => b,
+            (Class::Zero, Class::Zero) => ZERO.with_sign(if a.sign() == b.sign() { a.sign() } else { self.rounding_control() == 1 }),
+            (Class::Zero, Class::Finite(exponent, mantissa)) => self.make_extended(b.sign(), exponent, (mantissa as u128)<<64),
+            (Class::Finite(exponent, mantissa), Class::Zero) => self.make_extended(a.sign(), exponent, (mantissa as u128)<<64),
+            (Class::Finite(a_exponent, a_mantissa), Class::Finite(b_exponent, b_mantissa)) => {
+                let (mut a_sign, mut a_exponent, mut a_mantissa, b_sign, b_exponent, mut b_mantissa) = if a_exponent >= b_exponent {
+                    (a.sign(), a_exponent, (a_mantissa as u128)<<63, b.sign(), b_exponent, (b_mantissa as u128)<<63)
+                } else {
+                    (b.sign(), b_exponent, (b_mantissa as u128)<<63, a.sign(), a_exponent, (a_mantissa as u128)<<63)
+                };
+                b_mantissa = shift_right_sticky(b_mantissa, (a_exponent-b_exponent).min(128) as u32);
+                if a_sign == b_sign {
+                    a_mantissa += b_mantissa;
+                } else if a_mantissa >= b_mantissa {
+                    a_mantissa -= b_mantissa;
+                } else {
+                    a_mantissa = b_mantissa-a_mantissa;
+                    a_sign = b_sign;
+                }
+                if a_mantissa == 0 {
+                    return ZERO.with_sign(self.rounding_control() == 1);
+                }
+                a_exponent += 1;
+                self.make_extended(a_sign, a_exponent, a_mantissa)
+            }
+        }
+    }
+
+    fn multiply(&mut self, a: Extended, b: Extended) -> Extended {
+        let sign = a.sign()^b.sign();
+        self.check_denormal(a, b);
+        match (a.class(), b.class()) {
+            (Class::NaN, _) | (_, Class::NaN) => self.propagate_nan(a, b),
+            (Class::Infinity, Class::Zero) | (Class::Zero, Class::Infinity) => self.invalid(),
+            (Class::Infinity, _) | (_, Class::Infinity) => Extended::infinity(sign),
+            (Class::Zero, _) | (_, Class::Zero) => ZERO.with_sign(sign),
+            (Class::Finite(a_exponent, a_mantissa), Class::Finite(b_exponent, b_mantissa)) => {
+                self.make_extended(sign, a_exponent+b_exponent+1, (a_mantissa as u128)*(b_mantissa as u128))
+            }
+        }
+    }
+
+    fn divide(&mut self, a: Extended, b: Extended) -> Extended {
+        let sign = a.sign()^b.sign();
+        self.check_denormal(a, b);
+        match (a.class(), b.class()) {
+            (Class::NaN, _) | (_, Class::NaN) => self.propagate_nan(a, b),
+            (Class::Infinity, Class::Infinity) | (Class::Zero, Class::Zero) => self.invalid(),
+            (Class::Infinity, _) => Extended::infinity(sign),
+            (_, Class::Infinity) => ZERO.with_sign(sign),
+            (Class::Zero, _) => ZERO.with_sign(sign),
+            (_, Class::Zero) => {
+                self.raise(Exception::ZeroDivide);
+                Extended::infinity(sign)
+            },
+            (Class::Finite(a_exponent, a_mantissa), Class::Finite(b_exponent, b_mantissa)) => {
+                let divisor = b_mantissa as u128;
+                let mut remainder = a_mantissa as u128;
+                let mut quotient: u128 = 0;
+                for _ in 0..128 {
+                    quotient <<= 1;
+                    if remainder >= divisor {
+                        remainder -= divisor;
+                        quotient |= 1;
+                    }
+                    remainder <<= 1;
+                }
+                quotient |= (remainder != 0) as u128;
+                self.make_extended(sign, a_exponent-b_exponent, quotient)
+            }
+        }
+    }
+
+    fn square_root(&mut self, a: Extended) -> Extended {
+        self.check_denormal(a, a);
+        match a.class() {
+            Class::NaN => self.propagate_nan(a, a),
+            Class::Zero => a,
+            _ if a.sign() => self.invalid(),
+            Class::Infinity => a,
+            Class::Finite(exponent, mantissa) => {
+                let shift = if exponent&1 == 0 { 63 } else { 64 };
+                let (root, remainder) = integer_square_root((mantissa as u128)<<shift);
+                let guard = ((remainder > root) as u128)<<63;
+                let sticky = (remainder != 0) as u128;
+                self.make_extended(false, (exponent-63-shift as i32)/2+63, (root<<64)|guard|sticky)
+            }
+        }
+    }
+
+    fn compare(&mut self, a: Extended, b: Extended, unordered: bool) {
+        self.check_denormal(a, b);
+        let ordering = match (a.class(), b.class()) {
+            (Class::NaN, _) | (_, Class::NaN) => {
+                if !unordered || a.is_signaling_nan() || b.is_signaling_nan() {
+                    self.raise(Exception::InvalidOperation);
+                }
+                None
+            },
+            (Class::Zero, Class::Zero) => Some(std::cmp::Ordering::Equal),
+            _ => {
+                let key = |value: Extended| -> (i32, u64) {
+                    match value.class() {
+                        Class::Zero => (std::i32::MIN, 0),
+                        Class::Infinity => (std::i32::MAX, 0),
+                        Class::Finite(exponent, mantissa) => (exponent, mantissa),
+                        Class::NaN => unreachable!()
+                    }
+                };
+                let (a_key, b_key) = (key(a), key(b));
+                Some(match (a.sign(), b.sign()) {
+                    (false, true) => std::cmp::Ordering::Greater,
+                    (true, false) => std::cmp::Ordering::Less,
+                    (false, _) => a_key.cmp(&b_key),
+                    (true, _) => b_key.cmp(&a_key)
+                })
+            }
+        };
+        match ordering {
+            Some(std::cmp::Ordering::Greater) => self.set_condition_codes(false, false, false, false),
+            Some(std::cmp::Ordering::Less) => self.set_condition_codes(false, false, false, true),
+            Some(std::cmp::Ordering::Equal) => self.set_condition_codes(true, false, false, false),
+            None => self.set_condition_codes(true, true, false, true)
+        }
+    }
+
+    fn examine(&mut self) {
+        let value = self.registers[self.physical_index(0)];
+        let sign = value.sign();
+        if self.is_empty(0) {
+            self.set_condition_codes(true, false, sign, true);
+            return;
+        }
+        match value.class() {
+            Class::NaN => self.set_condition_codes(false, false, sign, true),
+            Class::Infinity => self.set_condition_codes(false, true, sign, true),
+            Class::Zero => self.set_condition_codes(true, false, sign, false),
+            Class::Finite(_, _) if value.is_denormal() => self.set_condition_codes(true, true, sign, false),
+            Class::Finite(_, _) if value.mantissa>>63 == 0 => self.set_condition_codes(false, false, sign, false),
+            Class::Finite(_, _) => self.set_condition_codes(false, true, sign, false)
+        }
+    }
+
+    /// Splits a value into its integral magnitude, rounded with the rounding control
+    fn round_to_integer(&mut self, value: Extended) -> Option<u128> {
+        match value.class() {
+            Class::Zero => Some(0),
+            Class::Finite(exponent, mantissa) => {
+                if exponent >= 63 {
+                    return if exponent > 64 { None } else { Some((mantissa as u128)<<(exponent-63)) };
+                }
+                let shift = (63-exponent) as u32;
+                let mantissa = mantissa as u128;
+                let (mut integer, rest, half) = if shift >= 128 {
+                    (0, 1, std::u128::MAX)
+                } else {
+                    (mantissa>>shift, mantissa&((1u128<<shift)-1), 1u128<<(shift-1))
+                };
+                if rest != 0 {
+                    let round_up = match self.rounding_control() {
+                        0 => rest > half || (rest == half && integer&1 == 1),
+                        1 => value.sign(),
+                        2 => !value.sign(),
+                        _ => false
+                    };
+                    integer += round_up as u128;
+                    self.raise(Exception::Precision);
+                    self.set_condition_code(ConditionCode::C1, round_up);
+                }
+                Some(integer)
+            },
+            _ => None
+        }
+    }
+
+    fn from_integer(&mut self, value: i64) -> Extended {
+        self.make_extended_with_precision(value < 0, 63, (value.wrapping_abs() as u64 as u128)<<64, 64)
+    }
+
+    fn to_integer(&mut self, value: Extended, data_width: u8) -> u64 {
+        let limit = 1u128<<(data_width-1);
+        match self.round_to_integer(value) {
+            Some(integer) if integer < limit || (integer == limit && value.sign()) => {
+                (if value.sign() { (integer as u64).wrapping_neg() } else { integer as u64 })&(std::u64::MAX>>(64-data_width))
+            },
+            _ => {
+                self.raise(Exception::InvalidOperation);
+                limit as u64
+            }
+        }
+    }
+
+    fn from_memory(&mut self, bytes: &[u8], data_width: u8, is_integer: bool) -> Extended {
+        let mut raw: u64 = 0;
+        for index in 0..(data_width as usize/8).min(8) {
+            raw |= (bytes[index] as u64)<<(index*8);
+        }
+        if is_integer {
+            let value = match data_width {
+                16 => raw as u16 as i16 as i64,
+                32 => raw as u32 as i32 as i64,
+                _ => raw as i64
+            };
+            return self.from_integer(value);
+        }
+        let (exponent_bits, fraction_bits) = match data_width {
+            32 => (8, 23),
+            64 => (11, 52),
+            _ => return Extended::from_bytes(bytes)
+        };
+        let sign = raw>>(exponent_bits+fraction_bits) == 1;
+        let bias = (1i32<<(exponent_bits-1))-1;
+        let biased_exponent = ((raw>>fraction_bits)&((1<<exponent_bits)-1)) as i32;
+        let fraction = raw&((1<<fraction_bits)-1);
+        if biased_exponent == (1<<exponent_bits)-1 {
+            if fraction == 0 {
+                Extended::infinity(sign)
+            } else {
+                if (fraction>>(fraction_bits-1))&1 == 0 {
+                    self.raise(Exception::InvalidOperation);
+                }
+                Extended::pack(sign, MAX_EXPONENT+1, 0xC000000000000000|(fraction<<(63-fraction_bits)))
+            }
+        } else if biased_exponent == 0 {
+            if fraction == 0 {
+                ZERO.with_sign(sign)
+            } else {
+                self.raise(Exception::DenormalOperand);
+                let shift = fraction.leading_zeros();
+                Extended::pack(sign, 1-bias-(shift as i32-(63-fraction_bits as i32)), fraction<<shift)
+            }
+        } else {
+            Extended::pack(sign, biased_exponent-bias, (1<<63)|(fraction<<(63-fraction_bits)))
+        }
+    }
+
+    fn to_memory(&mut self, value: Extended, bytes: &mut [u8], data_width: u8) {
+        let (exponent_bits, fraction_bits) = match data_width {
+            32 => (8, 23),
+            64 => (11, 52),
+            _ => {
+                value.to_bytes(bytes);
+                return;
+            }
+        };
+        let bias = (1i32<<(exponent_bits-1))-1;
+        let sign = value.sign() as u64;
+        let raw = match value.class() {
+            Class::Zero => 0,
+            Class::Infinity => ((1<<exponent_bits)-1)<<fraction_bits,
+            Class::NaN => {
+                if value.is_signaling_nan() {
+                    self.raise(Exception::InvalidOperation);
+                }
+                (((1<<exponent_bits)-1)<<fraction_bits)|(1<<(fraction_bits-1))|((value.mantissa<<1)>>(64-fraction_bits))
+            },
+            Class::Finite(exponent, mantissa) => {
+                let (exponent, mantissa) = self.round(value.sign(), exponent, (mantissa as u128)<<64, fraction_bits+1, 1-bias, bias);
+                let biased_exponent = if mantissa>>63 == 0 { 0 } else { (exponent+bias) as u64 };
+                (biased_exponent<<fraction_bits)|((mantissa<<1)>>(64-fraction_bits))
+            }
+        }|(sign<<(exponent_bits+fraction_bits));
+        for index in 0..data_width as usize/8 {
+            bytes[index] = (raw>>(index*8)) as u8;
+        }
+    }
+
+    fn from_bcd(&mut self, bytes: &[u8]) -> Extended {
+        let mut value: i64 = 0;
+        for index in (0..9).rev() {
+            value = value*100+((bytes[index]>>4) as i64)*10+((bytes[index]&0xF) as i64);
+        }
+        if bytes[9]&0x80 != 0 {
+            value = -value;
+        }
+        self.from_integer(value)
+    }
+
+    fn to_bcd(&mut self, value: Extended, bytes: &mut [u8]) {
+        match self.round_to_integer(value) {
+            Some(mut integer) if integer < 1000000000000000000 => {
+                for index in 0..9 {
+                    bytes[index] = ((integer%10) as u8)|((((integer/10)%10) as u8)<<4);
+                    integer /= 100;
+                }
+                bytes[9] = (value.sign() as u8)<<7;
+            },
+            _ => {
+                self.raise(Exception::InvalidOperation);
+                for index in 0..7 {
+                    bytes[index] = 0;
+                }
+                bytes[7] = 0xC0;
+                bytes[8] = 0xFF;
+                bytes[9] = 0xFF;
+            }
+        }
+    }
+
+    fn scale(&mut self, a: Extended, b: Extended) -> Extended {
+        match (a.class(), b.class()) {
+            (Class::NaN, _) | (_, Class::NaN) => self.propagate_nan(a, b),
+            (Class::Zero, Class::Infinity) if !b.sign() => self.invalid(),
+            (Class::Infinity, Class::Infinity) if b.sign() => self.invalid(),
+            (Class::Finite(_, _), Class::Infinity) => if b.sign() { ZERO.with_sign(a.sign()) } else { Extended::infinity(a.sign()) },
+            (Class::Finite(exponent, mantissa), Class::Finite(_, _)) | (Class::Finite(exponent, mantissa), Class::Zero) => {
+                let rounding_control = self.control_word;
+                self.control_word |= 0x0C00;
+                let status_word = self.status_word;
+                let factor = self.round_to_integer(b).unwrap_or(1<<20).min(1<<20) as i32;
+                self.status_word = status_word;
+                self.control_word = rounding_control;
+                let factor = if b.sign() { -factor } else { factor };
+                self.make_extended_with_precision(a.sign(), exponent+factor, (mantissa as u128)<<64, 64)
+            },
+            _ => a
+        }
+    }
+
+    /// Computes the partial remainder ST(0) mod ST(1), truncating (FPREM) or rounding to nearest (FPREM1)
+    fn partial_remainder(&mut self, a: Extended, b: Extended, nearest: bool) -> Extended {
+        match (a.class(), b.class()) {
+            (Class::NaN, _) | (_, Class::NaN) => self.propagate_nan(a, b),
+            (Class::Infinity, _) | (_, Class::Zero) => self.invalid(),
+            (Class::Zero, _) | (_, Class::Infinity) => {
+                self.set_condition_codes(false, false, false, false);
+                a
+            },
+            (Class::Finite(a_exponent, a_mantissa), Class::Finite(b_exponent, b_mantissa)) => {
+                let difference = a_exponent-b_exponent;
+                if difference >= 64 {
+                    let reduction = 32+difference%32;
+                    let remainder = ((a_mantissa as u128)<<reduction)%(b_mantissa as u128);
+                    self.set_condition_code(ConditionCode::C2, true);
+                    return self.make_extended_with_precision(a.sign(), a_exponent-reduction, remainder<<64, 64);
+                }
+                let (dividend, divisor, unit_exponent) = if difference >= 0 {
+                    ((a_mantissa as u128)<<difference, b_mantissa as u128, b_exponent)
+                } else if difference == -1 {
+                    (a_mantissa as u128, (b_mantissa as u128)<<1, a_exponent)
+                } else {
+                    (a_mantissa as u128, std::u128::MAX, a_exponent)
+                };
+                let mut quotient = dividend/divisor;
+                let mut remainder = dividend%divisor;
+                let mut sign = a.sign();
+                if nearest && (remainder<<1 > divisor || (remainder<<1 == divisor && quotient&1 == 1)) {
+                    remainder = divisor-remainder;
+                    quotient += 1;
+                    sign = !sign;
+                }
+                self.set_condition_codes(quotient&1 == 1, false, (quotient>>1)&1 == 1, (quotient>>2)&1 == 1);
+                if remainder == 0 {
+                    return ZERO.with_sign(a.sign());
+                }
+                self.make_extended_with_precision(sign, unit_exponent, remainder<<64, 64)
+            }
+        }
+    }
+
+    /// Rounds an intermediate transcendental result, these are always computed to 64 bits regardless of precision control
+    fn make_extended_from_wide(&mut self, value: Wide) -> Extended {
+        self.make_extended_with_precision(value.sign, value.exponent, value.mantissa, 64)
+    }
+
+    /// Computes F2XM1, 2^x-1
+    fn exponential_minus_one(&mut self, value: Extended) -> Extended {
+        self.check_denormal(value, value);
+        match value.class() {
+            Class::NaN => self.propagate_nan(value, value),
+            Class::Infinity => if value.sign() { ONE.with_sign(true) } else { value },
+            Class::Zero => value,
+            Class::Finite(_, _) => {
+                let result = Wide::from_extended(value).exponential_minus_one();
+                self.make_extended_from_wide(result)
+            }
+        }
+    }
+
+    /// Computes FYL2X, y*log2(x), or FYL2XP1, y*log2(x+1)
+    fn logarithm(&mut self, x: Extended, y: Extended, plus_one: bool) -> Extended {
+        self.check_denormal(x, y);
+        if x.class() == Class::NaN || y.class() == Class::NaN {
+            return self.propagate_nan(x, y);
+        }
+        let is_pole = match x.class() {
+            Class::Zero => !plus_one,
+            Class::Finite(exponent, mantissa) => plus_one && x.sign() && exponent == 0 && mantissa == 0x8000000000000000,
+            _ => false
+        };
+        if is_pole {
+            if let Class::Finite(_, _) = y.class() {
+                self.raise(Exception::ZeroDivide);
+            }
+            return self.multiply(y, Extended::infinity(true));
+        }
+        match x.class() {
+            Class::Zero if plus_one => return self.multiply(y, x),
+            Class::Infinity if !x.sign() => return self.multiply(y, x),
+            Class::Finite(exponent, _) if !x.sign() || (plus_one && exponent < 0) => {},
+            _ => return self.invalid()
+        }
+        let x = Wide::from_extended(x);
+        let logarithm = if plus_one { x.logarithm_plus_one() } else { x.logarithm() };
+        match y.class() {
+            Class::Finite(_, _) => self.make_extended_from_wide(Wide::from_extended(y).multiply(logarithm)),
+            _ => self.multiply(y, if logarithm.mantissa == 0 { ZERO } else { ONE.with_sign(logarithm.sign) })
+        }
+    }
+
+    /// Computes FPATAN, the angle of the point (x, y)
+    fn arctangent(&mut self, x: Extended, y: Extended) -> Extended {
+        self.check_denormal(x, y);
+        let angle = match (x.class(), y.class()) {
+            (Class::NaN, _) | (_, Class::NaN) => return self.propagate_nan(x, y),
+            (_, Class::Zero) if !x.sign() => return y,
+            (Class::Infinity, Class::Finite(_, _)) if !x.sign() => return ZERO.with_sign(y.sign()),
+            (_, Class::Zero) | (Class::Infinity, Class::Finite(_, _)) => WIDE_PI,
+            (Class::Infinity, Class::Infinity) => if x.sign() { WIDE_PI.add(WIDE_PI.scale(-2).negate()) } else { WIDE_PI.scale(-2) },
+            (Class::Zero, _) | (_, Class::Infinity) => WIDE_PI.scale(-1),
+            (Class::Finite(_, _), Class::Finite(_, _)) => {
+                let (x_wide, y_wide) = (Wide::from_extended(x).abs(), Wide::from_extended(y).abs());
+                let angle = if y_wide.magnitude() > x_wide.magnitude() {
+                    WIDE_PI.scale(-1).add(x_wide.divide(y_wide).arctangent().negate())
+                } else {
+                    y_wide.divide(x_wide).arctangent()
+                };
+                if x.sign() { WIDE_PI.add(angle.negate()) } else { angle }
+            }
+        };
+        self.make_extended_from_wide(Wide { sign: y.sign(), ..angle })
+    }
+
+    /// Computes FSIN, FCOS, FPTAN (tangent and 1) or FSINCOS (sine and cosine) of a value inside the reduction range
+    fn trigonometric(&mut self, fpu_opcode: FpuOpcode, value: Extended) -> (Extended, Extended) {
+        self.check_denormal(value, value);
+        match value.class() {
+            Class::NaN => {
+                let result = self.propagate_nan(value, value);
+                (result, result)
+            },
+            Class::Infinity => {
+                let result = self.invalid();
+                (result, result)
+            },
+            Class::Zero if fpu_opcode == FpuOpcode::FCOS => (ONE, ONE),
+            Class::Zero => (value, ONE),
+            Class::Finite(_, _) => {
+                let (sine, cosine) = Wide::from_extended(value).sine_cosine();
+                match fpu_opcode {
+                    FpuOpcode::FSIN => (self.make_extended_from_wide(sine), ONE),
+                    FpuOpcode::FCOS => (self.make_extended_from_wide(cosine), ONE),
+                    FpuOpcode::FPTAN => (self.make_extended_from_wide(sine.divide(cosine)), ONE),
+                    _ => (self.make_extended_from_wide(sine), self.make_extended_from_wide(cosine))
+                }
+            }
+        }
+    }
+
+    fn binary_operation(&mut self, fpu_opcode: FpuOpcode, a: Extended, b: Extended) -> Extended {
+        match fpu_opcode {
+            FpuOpcode::FADD | FpuOpcode::FADDP | FpuOpcode::FIADD => self.add(a, b, false),
+            FpuOpcode::FSUB | FpuOpcode::FSUBP | FpuOpcode::FISUB => self.add(a, b, true),
+            FpuOpcode::FSUBR | FpuOpcode::FSUBRP | FpuOpcode::FISUBR => self.add(b, a, true),
+            FpuOpcode::FMUL | FpuOpcode::FMULP | FpuOpcode::FIMUL => self.multiply(a, b),
+            FpuOpcode::FDIV | FpuOpcode::FDIVP | FpuOpcode::FIDIV => self.divide(a, b),
+            FpuOpcode::FDIVR | FpuOpcode::FDIVRP | FpuOpcode::FIDIVR => self.divide(b, a),
+            _ => unreachable!()
+        }
+    }
+
+    fn store_environment(&self, bytes: &mut [u8], operand_size: u8) {
+        let fields = [
+            self.control_word as u32,
+            self.status_word as u32,
+            self.full_tag_word() as u32,
+            self.instruction_pointer&0xFFFF,
+            ((self.instruction_pointer>>16)<<12)|(self.last_opcode as u32&0x7FF),
+            self.operand_pointer&0xFFFF,
+            (self.operand_pointer>>16)<<12
+        ];
+        let field_size = operand_size as usize/8;
+        for (index, field) in fields.iter().enumerate() {
+            for byte in 0..field_size {
+                bytes[index*field_size+byte] = (field>>(byte*8)) as u8;
+            }
+        }
+    }
+
+    fn load_environment(&mut self, bytes: &[u8], operand_size: u8) {
+        let field_size = operand_size as usize/8;
+        let mut fields: [u32; 7] = [0; 7];
+        for index in 0..7 {
+            for byte in 0..field_size {
+                fields[index] |= (bytes[index*field_size+byte] as u32)<<(byte*8);
+            }
+        }
+        self.control_word = fields[0] as u16;
+        self.status_word = fields[1] as u16;
+        self.tag_word = 0;
+        for physical_index in 0..8 {
+            if (fields[2]>>(physical_index*2))&3 == 3 {
+                self.tag_word |= 3<<(physical_index*2);
+            }
+        }
+        self.instruction_pointer = (fields[3]&0xFFFF)|((fields[4]>>12)<<16);
+        self.last_opcode = (fields[4]&0x7FF) as u16;
+        self.operand_pointer = (fields[5]&0xFFFF)|((fields[6]>>12)<<16);
+    }
+
+    /// Executes an ESC instruction. The memory operand (if any) is passed in as bytes,
+    /// returns true if they were modified and need to be written back.
+    pub fn execute_instruction(&mut self, cpu: &mut crate::cpu::CPU, opcode: u16, operand_address: u32, bytes: &mut [u8]) -> bool {
+        let instruction = cpu.instruction;
+        let is_memory = instruction.first_operand as u8 >= Operand::DisplacementBXSI as u8;
+        let register_index = |operand: Operand| -> usize { operand as usize-Operand::ST0 as usize };
+        match instruction.fpu_opcode {
+            FpuOpcode::FNINIT | FpuOpcode::FNCLEX | FpuOpcode::FLDCW | FpuOpcode::FNSTCW | FpuOpcode::FNSTSW |
+            FpuOpcode::FLDENV | FpuOpcode::FNSTENV | FpuOpcode::FRSTOR | FpuOpcode::FNSAVE |
+            FpuOpcode::FENI | FpuOpcode::FDISI | FpuOpcode::FSETPM => {},
+            _ => {
+                self.instruction_pointer = cpu.linear_address(Operand::CS, instruction.position.wrapping_sub(instruction.length as u32)) as u32;
+                self.operand_pointer = operand_address;
+                self.last_opcode = opcode;
+            }
+        }
+        match instruction.fpu_opcode {
+            FpuOpcode::FADD | FpuOpcode::FMUL | FpuOpcode::FSUB | FpuOpcode::FSUBR | FpuOpcode::FDIV | FpuOpcode::FDIVR |
+            FpuOpcode::FADDP | FpuOpcode::FMULP | FpuOpcode::FSUBP | FpuOpcode::FSUBRP | FpuOpcode::FDIVP | FpuOpcode::FDIVRP |
+            FpuOpcode::FIADD | FpuOpcode::FIMUL | FpuOpcode::FISUB | FpuOpcode::FISUBR | FpuOpcode::FIDIV | FpuOpcode::FIDIVR => {
+                let is_integer = instruction.fpu_opcode as u8 >= FpuOpcode::FIADD as u8 && instruction.fpu_opcode as u8 <= FpuOpcode::FIDIVR as u8;
+                let (destination, a, b) = if is_memory {
+                    let b = self.from_memory(bytes, instruction.data_width, is_integer);
+                    (0, self.get(0), b)
+                } else {
+                    let destination = register_index(instruction.first_operand);
+                    (destination, self.get(destination), self.get(register_index(instruction.second_operand)))
+                };
+                let result = self.binary_operation(instruction.fpu_opcode, a, b);
+                self.set(destination, result);
+                match instruction.fpu_opcode {
+                    FpuOpcode::FADDP | FpuOpcode::FMULP | FpuOpcode::FSUBP | FpuOpcode::FSUBRP | FpuOpcode::FDIVP | FpuOpcode::FDIVRP => { self.pop(); },
+                    _ => {}
+                }
+            },
+            FpuOpcode::FCOM | FpuOpcode::FCOMP | FpuOpcode::FCOMPP | FpuOpcode::FICOM | FpuOpcode::FICOMP |
+            FpuOpcode::FUCOM | FpuOpcode::FUCOMP | FpuOpcode::FUCOMPP | FpuOpcode::FTST => {
+                let a = self.get(0);
+                let b = match instruction.fpu_opcode {
+                    FpuOpcode::FTST => ZERO,
+                    FpuOpcode::FCOMPP | FpuOpcode::FUCOMPP => self.get(1),
+                    _ if is_memory => self.from_memory(bytes, instruction.data_width, instruction.fpu_opcode == FpuOpcode::FICOM || instruction.fpu_opcode == FpuOpcode::FICOMP),
+                    _ => self.get(register_index(instruction.first_operand))
+                };
+                let unordered = match instruction.fpu_opcode {
+                    FpuOpcode::FUCOM | FpuOpcode::FUCOMP | FpuOpcode::FUCOMPP => true,
+                    _ => false
+                };
+                self.compare(a, b, unordered);
+                match instruction.fpu_opcode {
+                    FpuOpcode::FCOMP | FpuOpcode::FICOMP | FpuOpcode::FUCOMP => { self.pop(); },
+                    FpuOpcode::FCOMPP | FpuOpcode::FUCOMPP => {
+                        self.pop();
+                        self.pop();
+                    },
+                    _ => {}
+                }
+            },
+            FpuOpcode::FXAM => {
+                self.examine();
+            },
+            FpuOpcode::FLD | FpuOpcode::FILD | FpuOpcode::FBLD => {
+                let value = if !is_memory {
+                    self.get(register_index(instruction.first_operand))
+                } else if instruction.fpu_opcode == FpuOpcode::FBLD {
+                    self.from_bcd(bytes)
+                } else {
+                    self.from_memory(bytes, instruction.data_width, instruction.fpu_opcode == FpuOpcode::FILD)
+                };
+                self.push(value);
+            },
+            FpuOpcode::FLD1 | FpuOpcode::FLDL2T | FpuOpcode::FLDL2E | FpuOpcode::FLDPI | FpuOpcode::FLDLG2 | FpuOpcode::FLDLN2 | FpuOpcode::FLDZ => {
+                self.push(CONSTANTS[instruction.fpu_opcode as usize-FpuOpcode::FLD1 as usize]);
+            },
+            FpuOpcode::FST | FpuOpcode::FSTP | FpuOpcode::FIST | FpuOpcode::FISTP | FpuOpcode::FBSTP => {
+                let value = self.get(0);
+                if !is_memory {
+                    self.set(register_index(instruction.first_operand), value);
+                } else {
+                    match instruction.fpu_opcode {
+                        FpuOpcode::FBSTP => {
+                            self.to_bcd(value, bytes);
+                        },
+                        FpuOpcode::FIST | FpuOpcode::FISTP => {
+                            let integer = self.to_integer(value, instruction.data_width);
+                            for index in 0..instruction.data_width as usize/8 {
+                                bytes[index] = (integer>>(index*8)) as u8;
+                            }
+                        },
+                        _ => {
+                            self.to_memory(value, bytes, instruction.data_width);
+                        }
+                    }
+                }
+                if instruction.fpu_opcode != FpuOpcode::FST && instruction.fpu_opcode != FpuOpcode::FIST {
+                    self.pop();
+                }
+                return is_memory;
+            },
+            FpuOpcode::FXCH => {
+                let index = register_index(instruction.first_operand);
+                let (a, b) = (self.get(0), self.get(index));
+                self.set(0, b);
+                self.set(index, a);
+            },
+            FpuOpcode::FFREE => {
+                self.set_empty(register_index(instruction.first_operand), true);
+            },
+            FpuOpcode::FINCSTP => {
+                self.set_top(self.top()+1);
+            },
+            FpuOpcode::FDECSTP => {
+                self.set_top(self.top().wrapping_sub(1));
+            },
+            FpuOpcode::FCHS | FpuOpcode::FABS => {
+                let value = self.get(0);
+                let sign = if instruction.fpu_opcode == FpuOpcode::FCHS { !value.sign() } else { false };
+                self.set(0, value.with_sign(sign));
+            },
+            FpuOpcode::FSQRT => {
+                let value = self.get(0);
+                let result = self.square_root(value);
+                self.set(0, result);
+            },
+            FpuOpcode::FRNDINT => {
+                let value = self.get(0);
+                let result = match value.class() {
+                    Class::Finite(exponent, _) if exponent < 63 => {
+                        let integer = self.round_to_integer(value).unwrap();
+                        self.make_extended_with_precision(value.sign(), 127, integer, 64)
+                    },
+                    Class::NaN => self.propagate_nan(value, value),
+                    _ => value
+                };
+                self.set(0, result);
+            },
+            FpuOpcode::FSCALE => {
+                let (a, b) = (self.get(0), self.get(1));
+                let result = self.scale(a, b);
+                self.set(0, result);
+            },
+            FpuOpcode::FPREM | FpuOpcode::FPREM1 => {
+                let (a, b) = (self.get(0), self.get(1));
+                let result = self.partial_remainder(a, b, instruction.fpu_opcode == FpuOpcode::FPREM1);
+                self.set(0, result);
+            },
+            FpuOpcode::FXTRACT => {
+                let value = self.get(0);
+                match value.class() {
+                    Class::Finite(exponent, mantissa) => {
+                        let exponent = self.from_integer(exponent as i64);
+                        self.set(0, exponent);
+                        self.push(Extended::pack(value.sign(), 0, mantissa));
+                    },
+                    Class::Zero => {
+                        self.raise(Exception::ZeroDivide);
+                        self.set(0, Extended::infinity(true));
+                        self.push(value);
+                    },
+                    Class::Infinity => {
+                        self.set(0, Extended::infinity(false));
+                        self.push(value);
+                    },
+                    Class::NaN => {
+                        let value = self.propagate_nan(value, value);
+                        self.set(0, value);
+                        self.push(value);
+                    }
+                }
+            },
+            FpuOpcode::F2XM1 => {
+                let value = self.get(0);
+                let result = self.exponential_minus_one(value);
+                self.set(0, result);
+            },
+            FpuOpcode::FYL2X | FpuOpcode::FYL2XP1 | FpuOpcode::FPATAN => {
+                let (x, y) = (self.get(0), self.get(1));
+                let result = if instruction.fpu_opcode == FpuOpcode::FPATAN {
+                    self.arctangent(x, y)
+                } else {
+                    self.logarithm(x, y, instruction.fpu_opcode == FpuOpcode::FYL2XP1)
+                };
+                self.pop();
+                self.set(0, result);
+            },
+            FpuOpcode::FPTAN | FpuOpcode::FSIN | FpuOpcode::FCOS | FpuOpcode::FSINCOS => {
+                let value = self.get(0);
+                let out_of_range = match value.class() {
+                    Class::Finite(exponent, _) => exponent >= 63,
+                    _ => false
+                };
+                self.set_condition_code(ConditionCode::C2, out_of_range);
+                if out_of_range {
+                    return false;
+                }
+                let (result, pushed) = self.trigonometric(instruction.fpu_opcode, value);
+                self.set(0, result);
+                if instruction.fpu_opcode == FpuOpcode::FPTAN || instruction.fpu_opcode == FpuOpcode::FSINCOS {
+                    self.push(pushed);
+                }
+            },
+            FpuOpcode::FLDCW => {
+                self.control_word = (bytes[0] as u16)|((bytes[1] as u16)<<8);
+            },
+            FpuOpcode::FNSTCW => {
+                bytes[0] = self.control_word as u8;
+                bytes[1] = (self.control_word>>8) as u8;
+                return true;
+            },
+            FpuOpcode::FNSTSW => {
+                if is_memory {
+                    bytes[0] = self.status_word as u8;
+                    bytes[1] = (self.status_word>>8) as u8;
+                    return true;
+                }
+                cpu.set_register(Operand::AX, self.status_word);
+            },
+            FpuOpcode::FNCLEX => {
+                self.status_word &= 0x7F00;
+            },
+            FpuOpcode::FNINIT => {
+                self.initialize();
+            },
+            FpuOpcode::FLDENV | FpuOpcode::FRSTOR => {
+                self.load_environment(bytes, instruction.operand_size);
+                if instruction.fpu_opcode == FpuOpcode::FRSTOR {
+                    let offset = instruction.operand_size as usize/8*7;
+                    for index in 0..8 {
+                        let physical_index = self.physical_index(index);
+                        self.registers[physical_index] = Extended::from_bytes(&bytes[offset+index*10..]);
+                    }
+                }
+            },
+            FpuOpcode::FNSTENV | FpuOpcode::FNSAVE => {
+                self.store_environment(bytes, instruction.operand_size);
+                if instruction.fpu_opcode == FpuOpcode::FNSAVE {
+                    let offset = instruction.operand_size as usize/8*7;
+                    for index in 0..8 {
+                        self.registers[self.physical_index(index)].to_bytes(&mut bytes[offset+index*10..]);
+                    }
+                    self.initialize();
+                } else {
+                    self.control_word |= 0x003F;
+                }
+                return true;
+            },
+            FpuOpcode::FENI | FpuOpcode::FDISI | FpuOpcode::FSETPM | FpuOpcode::FNOP => {}
+        }
+        false
+    }
+}
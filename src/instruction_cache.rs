@@ -0,0 +1,66 @@
+use crate::machinecode::Instruction;
+
+const PAGE_SIZE_LOG2: usize = 8;
+const PAGE_SIZE: usize = 1<<PAGE_SIZE_LOG2;
+
+#[derive(Copy, Clone)]
+struct CacheEntry {
+    generation: u32,
+    default_size: u8,
+    instruction: Instruction
+}
+
+struct CachePage {
+    generation: u32,
+    entries: Vec<CacheEntry>
+}
+
+pub struct InstructionCache {
+    pages: Vec<Option<Box<CachePage>>>
+}
+
+impl InstructionCache {
+    pub fn new() -> Self {
+        Self {
+            pages: Vec::new()
+        }
+    }
+
+    pub fn fetch(&self, address: usize, default_size: u8, instruction: &mut Instruction) -> bool {
+        if let Some(Some(page)) = self.pages.get(address>>PAGE_SIZE_LOG2) {
+            let entry = &page.entries[address&(PAGE_SIZE-1)];
+            if entry.generation == page.generation && entry.default_size == default_size && entry.instruction.position == instruction.position {
+                *instruction = entry.instruction;
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn insert(&mut self, address: usize, default_size: u8, instruction: &Instruction) {
+        let page_index = address>>PAGE_SIZE_LOG2;
+        if page_index >= self.pages.len() {
+            self.pages.resize_with(page_index+1, || None);
+        }
+        let page = self.pages[page_index].get_or_insert_with(|| Box::new(CachePage {
+            generation: 1,
+            entries: vec![CacheEntry { generation: 0, default_size: 0, instruction: unsafe { std::mem::zeroed() } }; PAGE_SIZE]
+        }));
+        page.entries[address&(PAGE_SIZE-1)] = CacheEntry {
+            generation: page.generation,
+            default_size: default_size,
+            instruction: *instruction
+        };
+    }
+
+    pub fn invalidate(&mut self, address: usize, length: usize) {
+        // Instructions can reach into the following page, so the preceding one is invalidated too
+        let first_page = (address>>PAGE_SIZE_LOG2).saturating_sub(1);
+        let last_page = (address+length.max(1)-1)>>PAGE_SIZE_LOG2;
+        for page_index in first_page..=last_page {
+            if let Some(Some(page)) = self.pages.get_mut(page_index) {
+                page.generation = page.generation.wrapping_add(1).max(1);
+            }
+        }
+    }
+}
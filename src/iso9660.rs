@@ -0,0 +1,195 @@
+use std::io;
+use std::os::unix::fs::FileExt;
+
+pub const SECTOR_SIZE: usize = 2048;
+const FIRST_VOLUME_DESCRIPTOR: u32 = 16;
+
+/// Directory record of an ISO 9660 directory, with the name mapped to 8.3
+#[derive(Clone)]
+struct Entry {
+    short_name: String,
+    extent: u32,
+    size: u32,
+    attribute: u8,
+    time: u16,
+    date: u16
+}
+
+/// Open file on an ISO 9660 volume
+pub struct IsoFile {
+    extent: u32,
+    size: u32,
+    position: u32,
+    pub time: u16,
+    pub date: u16
+}
+
+/// Read only ISO 9660 volume of a CD-ROM image, which is read sector by sector
+pub struct Iso9660FileSystem {
+    image: std::fs::File,
+    pub primary_volume_descriptor: Vec<u8>,
+    pub volume_space_size: u32, // Sectors
+    root: Entry,
+    directories: std::collections::HashMap<u32, Vec<Entry>> // Listings by extent
+}
+
+fn read_only_error() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "Read only CD-ROM")
+}
+
+/// Entry of a directory record, "." and ".." are named by the bytes 0 and 1
+fn parse_record(record: &[u8]) -> Option<(Vec<u8>, Entry)> {
+    let name_length = *record.get(32)? as usize;
+    let name = record.get(33..33+name_length)?.to_vec();
+    let date = &record[18..25];
+    let flags = record[25];
+    Some((name, Entry {
+        short_name: String::new(),
+        extent: crate::bit_utils::read_from_buffer_u32(&record[2..]),
+        size: crate::bit_utils::read_from_buffer_u32(&record[10..]),
+        attribute: if flags&0x02 != 0 { 0x10 } else { 0x01 }|if flags&0x01 != 0 { 0x02 } else { 0x00 },
+        time: (date[3] as u16)<<11|(date[4] as u16)<<5|(date[5] as u16/2),
+        date: ((date[0] as u16+1900).max(1980)-1980)<<9|(date[1] as u16)<<5|(date[2] as u16)
+    }))
+}
+
+impl Iso9660FileSystem {
+    /// Checks for the "CD001" signature of the first volume descriptor
+    pub fn is_iso9660(path: &std::path::Path) -> bool {
+        let mut signature = [0; 5];
+        match std::fs::File::open(path) {
+            Ok(file) => file.read_exact_at(&mut signature, FIRST_VOLUME_DESCRIPTOR as u64*SECTOR_SIZE as u64+1).is_ok() && &signature == b"CD001",
+            Err(_) => false
+        }
+    }
+
+    pub fn open(image_path: &std::path::Path) -> io::Result<Self> {
+        let image = std::fs::File::open(image_path)?;
+        let mut primary_volume_descriptor = vec![0; SECTOR_SIZE];
+        for sector in FIRST_VOLUME_DESCRIPTOR.. {
+            image.read_exact_at(&mut primary_volume_descriptor, sector as u64*SECTOR_SIZE as u64)?;
+            match primary_volume_descriptor[0] {
+                0x01 => { break; },
+                0xFF => { return Err(io::Error::new(io::ErrorKind::InvalidData, "No primary volume descriptor")); },
+                _ => {}
+            }
+        }
+        let (_, mut root) = parse_record(&primary_volume_descriptor[156..190]).unwrap();
+        root.attribute = 0x10;
+        Ok(Self {
+            image: image,
+            volume_space_size: crate::bit_utils::read_from_buffer_u32(&primary_volume_descriptor[80..]),
+            primary_volume_descriptor: primary_volume_descriptor,
+            root: root,
+            directories: std::collections::HashMap::new()
+        })
+    }
+
+    pub fn read_sectors(&self, sector: u32, buffer: &mut [u8]) -> io::Result<()> {
+        self.image.read_exact_at(buffer, sector as u64*SECTOR_SIZE as u64)
+    }
+
+    /// Entries of a directory, records never cross sector boundaries
+    fn directory(&mut self, directory: &Entry) -> io::Result<&Vec<Entry>> {
+        if !self.directories.contains_key(&directory.extent) {
+            let mut data = vec![0; (directory.size as usize+SECTOR_SIZE-1)/SECTOR_SIZE*SECTOR_SIZE];
+            self.read_sectors(directory.extent, &mut data)?;
+            let mut entries: Vec<Entry> = Vec::new();
+            let mut offset = 0;
+            while offset < data.len() {
+                let length = data[offset] as usize;
+                if length == 0 {
+                    offset = (offset/SECTOR_SIZE+1)*SECTOR_SIZE;
+                    continue;
+                }
+                if let Some((name, mut entry)) = parse_record(&data[offset..(offset+length).min(data.len())]) {
+                    let name = String::from_utf8_lossy(&name).to_string();
+                    let name = match name.as_str() {
+                        "\u{0}" => ".".to_string(),
+                        "\u{1}" => "..".to_string(),
+                        _ => name.split(';').next().unwrap().trim_end_matches('.').to_string()
+                    };
+                    entry.short_name = if name == "." || name == ".." {
+                        name
+                    } else {
+                        crate::name_mapping::short_name(&name, &|short_name| entries.iter().any(|entry| entry.short_name == short_name))
+                    };
+                    if !entries.iter().any(|other| other.short_name == entry.short_name) { // Multi extent files repeat their record
+                        entries.push(entry);
+                    }
+                }
+                offset += length;
+            }
+            self.directories.insert(directory.extent, entries);
+        }
+        Ok(&self.directories[&directory.extent])
+    }
+
+    fn find(&mut self, components: &[String]) -> io::Result<Entry> {
+        let mut entry = self.root.clone();
+        for component in components {
+            if entry.attribute&0x10 == 0 {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "Not a directory"));
+            }
+            let short_name = crate::name_mapping::normalize_short_name(component);
+            entry = self.directory(&entry)?.iter().find(|entry| entry.short_name == short_name).cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such file or directory"))?;
+        }
+        Ok(entry)
+    }
+
+    pub fn is_directory(&mut self, components: &[String]) -> bool {
+        self.find(components).map_or(false, |entry| entry.attribute&0x10 != 0)
+    }
+
+    pub fn list_directory(&mut self, components: &[String]) -> io::Result<Vec<crate::file_system::DirectoryEntry>> {
+        let directory = self.find(components)?;
+        if directory.attribute&0x10 == 0 {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Not a directory"));
+        }
+        Ok(self.directory(&directory)?.iter().map(|entry| crate::file_system::DirectoryEntry {
+            short_name: entry.short_name.clone(),
+            attribute: entry.attribute,
+            time: entry.time,
+            date: entry.date,
+            size: if entry.attribute&0x10 != 0 { 0 } else { entry.size }
+        }).collect())
+    }
+
+    pub fn volume_label(&self) -> Option<String> {
+        Some(String::from_utf8_lossy(&self.primary_volume_descriptor[40..72]).trim_end().to_string()).filter(|label| !label.is_empty())
+    }
+
+    /// Sectors per cluster, bytes per sector, free and total clusters
+    pub fn free_space(&self) -> (u16, u16, u16, u16) {
+        (1, SECTOR_SIZE as u16, 0, self.volume_space_size.min(0xFFFF) as u16)
+    }
+
+    pub fn open_file(&mut self, components: &[String]) -> io::Result<IsoFile> {
+        let entry = self.find(components)?;
+        if entry.attribute&0x10 != 0 {
+            return Err(read_only_error());
+        }
+        Ok(IsoFile { extent: entry.extent, size: entry.size, position: 0, time: entry.time, date: entry.date })
+    }
+
+    pub fn read(&mut self, file: &mut IsoFile, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = (file.size.saturating_sub(file.position) as usize).min(buffer.len());
+        self.image.read_exact_at(&mut buffer[0..length], file.extent as u64*SECTOR_SIZE as u64+file.position as u64)?;
+        file.position += length as u32;
+        Ok(length)
+    }
+
+    pub fn seek(&self, file: &mut IsoFile, seek: io::SeekFrom) -> io::Result<u64> {
+        let position = match seek {
+            io::SeekFrom::Start(offset) => offset as i64,
+            io::SeekFrom::Current(offset) => file.position as i64+offset,
+            io::SeekFrom::End(offset) => file.size as i64+offset
+        };
+        if position < 0 || position > std::u32::MAX as i64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek position"));
+        }
+        file.position = position as u32;
+        Ok(position as u64)
+    }
+}
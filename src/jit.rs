@@ -0,0 +1,262 @@
+use crate::machinecode::CpuModel;
+use crate::machinecode::Opcode;
+use crate::machinecode::Operand;
+use crate::machinecode::Instruction;
+use serde::Deserialize;
+use serde::Serialize;
+
+pub const HOT_BLOCK_THRESHOLD: usize = 64; // Executions of the block head counted in the instruction profile
+const CODE_BUFFER_SIZE: usize = 0x100000;
+const MAX_GUEST_BLOCK_SIZE: usize = 256;
+const ARITHMETIC_FLAGS_MASK: u32 = 0x08D5; // OF SF ZF AF PF CF
+
+#[derive(Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum JitMode {
+    #[default]
+    #[serde(rename = "disabled")]
+    Disabled,
+    #[serde(rename = "enabled")]
+    Enabled,
+    #[serde(rename = "lockstep")]
+    Lockstep
+}
+
+pub type TranslatedFunction = extern "sysv64" fn(registers: *mut u32, flags: *mut u16);
+
+pub struct TranslatedBlock {
+    pub position: u32,
+    pub default_size: u8,
+    pub end_position: u32,
+    pub instruction_count: usize,
+    pub cycles: u64,
+    pub function: TranslatedFunction,
+    guest_code: Vec<u8>
+}
+
+pub enum Lookup<'a> {
+    Hit(&'a TranslatedBlock),
+    Miss,
+    Untranslatable
+}
+
+enum BlockEntry {
+    Translated(TranslatedBlock),
+    Untranslatable(u32) // position
+}
+
+#[derive(Default)]
+struct AddressHasher(u64);
+
+impl std::hash::Hasher for AddressHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0<<8)|(*byte as u64);
+        }
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.0 = value as u64;
+    }
+}
+
+fn register_offset(operand: Operand) -> Option<u8> {
+    match operand as u8 {
+        0..=7 => Some(operand as u8*4), // AX CX DX BX SP BP SI DI
+        14..=17 => Some((operand as u8-Operand::AL as u8)*4), // AL CL DL BL
+        18..=21 => Some((operand as u8-Operand::AH as u8)*4+1), // AH CH DH BH
+        _ => None
+    }
+}
+
+fn is_translatable(instruction: &Instruction) -> bool {
+    match instruction.opcode {
+        Opcode::MOV | Opcode::ADD | Opcode::OR | Opcode::AND | Opcode::SUB | Opcode::XOR | Opcode::CMP | Opcode::TEST => {},
+        _ => { return false; }
+    }
+    (instruction.data_width == 8 || instruction.data_width == 16) &&
+    instruction.prefix == Opcode::BAD &&
+    register_offset(instruction.first_operand).is_some() &&
+    (instruction.second_operand == Operand::None || register_offset(instruction.second_operand).is_some())
+}
+
+struct Emitter {
+    code: Vec<u8>
+}
+
+impl Emitter {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_instruction(&mut self, instruction: &Instruction, materialize_flags: bool) {
+        let wide = instruction.data_width == 16;
+        let destination = register_offset(instruction.first_operand).unwrap();
+        // movzx eax, word/byte [rdi+destination]
+        self.emit(&[0x0F, if wide { 0xB7 } else { 0xB6 }, 0x47, destination]);
+        let (register_opcode, accumulator_opcode) = match instruction.opcode {
+            Opcode::ADD => (0x02, 0x04),
+            Opcode::OR => (0x0A, 0x0C),
+            Opcode::AND => (0x22, 0x24),
+            Opcode::SUB => (0x2A, 0x2C),
+            Opcode::XOR => (0x32, 0x34),
+            Opcode::CMP => (0x3A, 0x3C),
+            Opcode::TEST => (0x84, 0xA8),
+            Opcode::MOV => (0x8A, 0xB0),
+            _ => unreachable!()
+        };
+        if wide {
+            self.emit(&[0x66]);
+        }
+        match register_offset(instruction.second_operand) {
+            Some(source) => { // op al/ax, [rdi+source]
+                self.emit(&[register_opcode+wide as u8, 0x47, source]);
+            },
+            None => { // op al/ax, immediate
+                let opcode = if instruction.opcode == Opcode::MOV { if wide { 0xB8 } else { 0xB0 } } else { accumulator_opcode+wide as u8 };
+                self.emit(&[opcode]);
+                self.emit(&(instruction.immediate as u16).to_le_bytes()[0..if wide { 2 } else { 1 }]);
+            }
+        }
+        if instruction.opcode != Opcode::MOV && materialize_flags {
+            let defined_flags = match instruction.opcode {
+                Opcode::OR | Opcode::AND | Opcode::XOR | Opcode::TEST => ARITHMETIC_FLAGS_MASK&!0x0010, // AF is cleared
+                _ => ARITHMETIC_FLAGS_MASK
+            };
+            self.emit(&[0x9C, 0x59]); // pushfq; pop rcx
+            self.emit(&[0x0F, 0xB7, 0x16]); // movzx edx, word [rsi]
+            self.emit(&[0x81, 0xE2]); // and edx, !ARITHMETIC_FLAGS_MASK
+            self.emit(&(!ARITHMETIC_FLAGS_MASK).to_le_bytes());
+            self.emit(&[0x81, 0xE1]); // and ecx, defined_flags
+            self.emit(&defined_flags.to_le_bytes());
+            self.emit(&[0x09, 0xCA]); // or edx, ecx
+            self.emit(&[0x66, 0x89, 0x16]); // mov word [rsi], dx
+        }
+        if instruction.opcode != Opcode::CMP && instruction.opcode != Opcode::TEST {
+            // mov word/byte [rdi+destination], ax/al
+            if wide {
+                self.emit(&[0x66, 0x89, 0x47, destination]);
+            } else {
+                self.emit(&[0x88, 0x47, destination]);
+            }
+        }
+    }
+}
+
+pub struct JustInTimeCompiler {
+    pub mode: JitMode,
+    blocks: std::collections::HashMap<usize, BlockEntry, std::hash::BuildHasherDefault<AddressHasher>>,
+    code_buffer: *mut u8,
+    code_buffer_used: usize
+}
+
+impl JustInTimeCompiler {
+    pub fn new(mode: JitMode) -> Option<Self> {
+        if mode == JitMode::Disabled || !cfg!(all(target_arch = "x86_64", unix)) {
+            return None;
+        }
+        let code_buffer = unsafe { libc::mmap(std::ptr::null_mut(), CODE_BUFFER_SIZE, libc::PROT_READ|libc::PROT_WRITE|libc::PROT_EXEC, libc::MAP_PRIVATE|libc::MAP_ANONYMOUS, -1, 0) };
+        if code_buffer == libc::MAP_FAILED {
+            println!("JIT: Could not allocate executable memory");
+            return None;
+        }
+        Some(Self {
+            mode: mode,
+            blocks: std::collections::HashMap::default(),
+            code_buffer: code_buffer as *mut u8,
+            code_buffer_used: 0
+        })
+    }
+
+    pub fn lookup(&mut self, address: usize, position: u32, default_size: u8, memory: &[u8]) -> Lookup<'_> {
+        let is_valid = match self.blocks.get(&address) {
+            Some(BlockEntry::Translated(block)) => block.position == position && block.default_size == default_size && memory.starts_with(&block.guest_code),
+            Some(BlockEntry::Untranslatable(untranslatable_position)) => *untranslatable_position == position,
+            None => { return Lookup::Miss; }
+        };
+        if !is_valid {
+            self.blocks.remove(&address);
+            return Lookup::Miss;
+        }
+        match self.blocks.get(&address) {
+            Some(BlockEntry::Translated(block)) => Lookup::Hit(block),
+            _ => Lookup::Untranslatable
+        }
+    }
+
+    /// Translates a block which became hot, remembering if it can not be translated
+    pub fn translate(&mut self, address: usize, position: u32, default_size: u8, memory: &[u8], model: CpuModel, timing_model: &crate::timing::TimingModel) -> Option<&TranslatedBlock> {
+        let entry = match self.translate_block(position, default_size, memory, model, timing_model) {
+            Some(block) => BlockEntry::Translated(block),
+            None => BlockEntry::Untranslatable(position)
+        };
+        self.blocks.insert(address, entry);
+        match self.blocks.get(&address) {
+            Some(BlockEntry::Translated(block)) => Some(block),
+            _ => None
+        }
+    }
+
+    fn translate_block(&mut self, position: u32, default_size: u8, memory: &[u8], model: CpuModel, timing_model: &crate::timing::TimingModel) -> Option<TranslatedBlock> {
+        let mut instructions = Vec::new();
+        let mut instruction: Instruction = unsafe { std::mem::zeroed() };
+        instruction.position = position;
+        let mut offset = 0;
+        while offset+16 <= memory.len().min(MAX_GUEST_BLOCK_SIZE) {
+            let mut read_buffer = &memory[offset..offset+16];
+            if !crate::machinecode::decode_instruction(&mut read_buffer, &mut instruction, model, default_size).is_ok() || !is_translatable(&instruction) {
+                break;
+            }
+            if (instruction.position as u64)+(instruction.length as u64) > crate::bit_utils::lsb_mask(default_size as u32) as u64 {
+                break;
+            }
+            instructions.push(instruction);
+            offset += instruction.length as usize;
+            instruction.position += instruction.length as u32;
+        }
+        if instructions.is_empty() {
+            return None;
+        }
+        // Lazy flags: Every translatable instruction overwrites all arithmetic flags without reading them,
+        // so only the last one which sets flags needs to materialize them.
+        let last_flag_producer = instructions.iter().rposition(|instruction| instruction.opcode != Opcode::MOV);
+        let mut emitter = Emitter { code: Vec::new() };
+        let mut cycles = 0;
+        for (index, instruction) in instructions.iter().enumerate() {
+            emitter.emit_instruction(instruction, Some(index) == last_flag_producer);
+            cycles += timing_model.instruction_cycles(instruction, 0);
+        }
+        emitter.emit(&[0xC3]); // ret
+        if self.code_buffer_used+emitter.code.len() > CODE_BUFFER_SIZE {
+            self.blocks.clear();
+            self.code_buffer_used = 0;
+        }
+        let function = unsafe {
+            let destination = self.code_buffer.add(self.code_buffer_used);
+            std::ptr::copy_nonoverlapping(emitter.code.as_ptr(), destination, emitter.code.len());
+            std::mem::transmute::<*mut u8, TranslatedFunction>(destination)
+        };
+        self.code_buffer_used += emitter.code.len();
+        Some(TranslatedBlock {
+            position: position,
+            default_size: default_size,
+            end_position: instruction.position,
+            instruction_count: instructions.len(),
+            cycles: cycles,
+            function: function,
+            guest_code: memory[0..offset].to_vec()
+        })
+    }
+}
+
+// The code buffer is owned exclusively and only ever executed by the thread owning the CPU
+unsafe impl Send for JustInTimeCompiler {}
+
+impl Drop for JustInTimeCompiler {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.code_buffer as *mut libc::c_void, CODE_BUFFER_SIZE); }
+    }
+}
@@ -2,30 +2,102 @@ use std::io;
 use std::io::prelude::*;
 use std::mem;
 use crate::bit_utils::read_bytes;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Default, Deserialize, Serialize)]
+pub enum CpuModel {
+    #[default]
+    #[serde(rename = "8086")]
+    I8086,
+    #[serde(rename = "80186")]
+    I80186,
+    #[serde(rename = "80286")]
+    I80286,
+    #[serde(rename = "80386")]
+    I80386
+}
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Display)]
 pub enum Opcode {
     ADD = 0x00,
+    SETO = 0x01,
+    SETNO = 0x02,
+    SETB = 0x03,
+    SETNB = 0x04,
+    SETE = 0x05,
+    SETNE = 0x06,
+    SETBE = 0x07,
     OR = 0x08,
+    SETNBE = 0x09,
+    SETS = 0x0A,
+    SETNS = 0x0B,
+    SETP = 0x0C,
+    SETNP = 0x0D,
+    SETL = 0x0E,
+    SETNL = 0x0F,
     ADC = 0x10,
+    SETLE = 0x11,
+    SETNLE = 0x12,
+    MOVZX = 0x13,
+    MOVSX = 0x14,
+    BT = 0x15,
+    BTS = 0x16,
+    BTR = 0x17,
     SBB = 0x18,
+    BTC = 0x19,
+    BSF = 0x1A,
+    BSR = 0x1B,
+    SHLD = 0x1C,
+    SHRD = 0x1D,
+    LSS = 0x1E,
+    LFS = 0x1F,
     AND = 0x20,
+    LGS = 0x21,
+    #[strum(to_string = "MOVZX")]
+    MOVZXW = 0x22, // MOVZX r, r/m16
+    #[strum(to_string = "MOVSX")]
+    MOVSXW = 0x23, // MOVSX r, r/m16
+    SLDT = 0x24,
+    STR = 0x25,
     ES = 0x26,
     DAA = 0x27,
     SUB = 0x28,
+    LLDT = 0x29,
+    LTR = 0x2A,
+    VERR = 0x2B,
+    VERW = 0x2C,
+    SGDT = 0x2D,
     CS = 0x2E,
     DAS = 0x2F,
     XOR = 0x30,
+    SIDT = 0x31,
+    LGDT = 0x32,
+    LIDT = 0x33,
+    SMSW = 0x34,
+    LMSW = 0x35,
     SS = 0x36,
     AAA = 0x37,
     CMP = 0x38,
+    LAR = 0x39,
+    LSL = 0x3A,
+    CLTS = 0x3B,
+    ARPL = 0x3C,
+    BAD = 0x3D,
     DS = 0x3E,
     AAS = 0x3F,
     INC = 0x40,
     DEC = 0x48,
     PUSH = 0x50,
     POP = 0x58,
+    PUSHA = 0x60,
+    POPA = 0x61,
+    BOUND = 0x62,
+    INSB = 0x6C,
+    INSW = 0x6D,
+    OUTSB = 0x6E,
+    OUTSW = 0x6F,
     JO = 0x70,
     JNO = 0x71,
     JB = 0x72, // JB JC JNAE
@@ -75,14 +147,17 @@ pub enum Opcode {
     RET = 0xC3,
     LES = 0xC4,
     LDS = 0xC5,
+    ENTER = 0xC8,
+    LEAVE = 0xC9,
     RETF = 0xCB,
     INT = 0xCD,
     INTO = 0xCE,
     IRET = 0xCF,
     AAM = 0xD4,
     AAD = 0xD5,
-    BAD = 0xD6,
+    SALC = 0xD6,
     XLAT = 0xD7,
+    ESC = 0xD8, // x87 instruction, see fpu_opcode
     LOOPNZ = 0xE0, // LOOPNZ LOOPNE
     LOOPZ = 0xE1, // LOOPZ LOOPE
     LOOP = 0xE2,
@@ -112,7 +187,7 @@ pub enum Opcode {
     LCALL = 0xFE,
     LJMP = 0xFF
 }
-// FREE: 0x01..=0x07 | 0x09..=0x0F | 0x11..=0x17 | 0x19..=0x1F | 0x21..=0x25 | 0x29..=0x2D | 0x31..=0x35 | 0x39..=0x3D | 0x41..=0x47 | 0x49..=0x4F | 0x51..=0x57 | 0x59..=0x6F | 0x80..=0x83 | 0x85 | 0x87..=0x8C | 0x8F | 0x91..=0x97 | 0x9A | 0xA0..=0xA3 | 0xA8..=0xA9 | 0xB0..=0xB7 | 0xC0..=0xC2 | 0xC6..=0xCA | 0xCC | 0xD0..=0xD3 | 0xD8..=0xDF | 0xE5 | 0xE7 | 0xF6..=0xF7
+// FREE: 0x41..=0x47 | 0x49..=0x4F | 0x51..=0x57 | 0x59..=0x5F | 0x63..=0x6B | 0x80..=0x83 | 0x85 | 0x87..=0x8C | 0x8F | 0x91..=0x97 | 0x9A | 0xA0..=0xA3 | 0xA8..=0xA9 | 0xB0..=0xB7 | 0xC0..=0xC2 | 0xC6..=0xC7 | 0xCA | 0xCC | 0xD0..=0xD3 | 0xD9..=0xDF | 0xE5 | 0xE7 | 0xF6..=0xF7
 
 impl From<u8> for Opcode {
     fn from(value: u8) -> Self {
@@ -120,6 +195,32 @@ impl From<u8> for Opcode {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Display)]
+pub enum FpuOpcode {
+    FADD, FMUL, FCOM, FCOMP, FSUB, FSUBR, FDIV, FDIVR,
+    FADDP, FMULP, FCOMPP, FSUBP, FSUBRP, FDIVP, FDIVRP,
+    FIADD, FIMUL, FICOM, FICOMP, FISUB, FISUBR, FIDIV, FIDIVR,
+    FUCOM, FUCOMP, FUCOMPP, FTST, FXAM,
+    FLD, FST, FSTP, FILD, FIST, FISTP, FBLD, FBSTP, FXCH, FFREE,
+    FLD1, FLDL2T, FLDL2E, FLDPI, FLDLG2, FLDLN2, FLDZ,
+    FCHS, FABS, FSQRT, FSCALE, FPREM, FPREM1, FRNDINT, FXTRACT,
+    F2XM1, FYL2X, FYL2XP1, FPTAN, FPATAN, FSIN, FCOS, FSINCOS,
+    FLDCW, FNSTCW, FNSTSW, FLDENV, FNSTENV, FRSTOR, FNSAVE,
+    FNINIT, FNCLEX, FENI, FDISI, FSETPM, FINCSTP, FDECSTP, FNOP
+}
+
+static ARITHMETIC_FPU_OPCODES: &'static [FpuOpcode] = &[
+    FpuOpcode::FADD, FpuOpcode::FMUL, FpuOpcode::FCOM, FpuOpcode::FCOMP,
+    FpuOpcode::FSUB, FpuOpcode::FSUBR, FpuOpcode::FDIV, FpuOpcode::FDIVR
+];
+
+pub static SETCC_OPCODES: &'static [Opcode] = &[
+    Opcode::SETO, Opcode::SETNO, Opcode::SETB, Opcode::SETNB,
+    Opcode::SETE, Opcode::SETNE, Opcode::SETBE, Opcode::SETNBE,
+    Opcode::SETS, Opcode::SETNS, Opcode::SETP, Opcode::SETNP,
+    Opcode::SETL, Opcode::SETNL, Opcode::SETLE, Opcode::SETNLE
+];
+
 #[allow(dead_code)]
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Display)]
 pub enum Operand {
@@ -135,24 +236,47 @@ pub enum Operand {
     CS = 9,
     SS = 10,
     DS = 11,
-    AL = 12,
-    CL = 13,
-    DL = 14,
-    BL = 15,
-    AH = 16,
-    CH = 17,
-    DH = 18,
-    BH = 19,
-    DisplacementBXSI = 20,
-    DisplacementBXDI = 21,
-    DisplacementBPSI = 22,
-    DisplacementBPDI = 23,
-    DisplacementSI = 24,
-    DisplacementDI = 25,
-    DisplacementBP = 26,
-    DisplacementBX = 27,
-    Displacement = 28,
-    None = 29
+    FS = 12,
+    GS = 13,
+    AL = 14,
+    CL = 15,
+    DL = 16,
+    BL = 17,
+    AH = 18,
+    CH = 19,
+    DH = 20,
+    BH = 21,
+    EAX = 22,
+    ECX = 23,
+    EDX = 24,
+    EBX = 25,
+    ESP = 26,
+    EBP = 27,
+    ESI = 28,
+    EDI = 29,
+    ST0 = 30,
+    ST1 = 31,
+    ST2 = 32,
+    ST3 = 33,
+    ST4 = 34,
+    ST5 = 35,
+    ST6 = 36,
+    ST7 = 37,
+    CR0 = 38,
+    CR1 = 39,
+    CR2 = 40,
+    CR3 = 41,
+    DisplacementBXSI = 42,
+    DisplacementBXDI = 43,
+    DisplacementBPSI = 44,
+    DisplacementBPDI = 45,
+    DisplacementSI = 46,
+    DisplacementDI = 47,
+    DisplacementBP = 48,
+    DisplacementBX = 49,
+    Displacement = 50,
+    DisplacementExtended = 51, // 32 bit addressing: [address_base+address_index*address_scale+displacement]
+    None = 52
 }
 
 impl From<u8> for Operand {
@@ -163,17 +287,76 @@ impl From<u8> for Operand {
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Instruction {
-    pub position: u16,
-    pub buffer: [u8; 8],
+    pub position: u32,
+    pub buffer: [u8; 16],
     pub length: u8,
     pub prefix: Opcode,
     pub opcode: Opcode,
     pub data_width: u8,
+    pub operand_size: u8,
+    pub address_size: u8,
     pub first_operand: Operand,
     pub second_operand: Operand,
+    pub third_operand: Operand,
     pub segment_override: Operand,
+    pub address_base: Operand,
+    pub address_index: Operand,
+    pub address_scale: u8,
     pub immediate: u32,
-    pub displacement: i32
+    pub displacement: i32,
+    pub fpu_opcode: FpuOpcode
+}
+
+fn register_of_width(index: u8, data_width: u8) -> u8 {
+    match data_width {
+        8 => index+Operand::AL as u8,
+        32 => index+Operand::EAX as u8,
+        _ => index
+    }
+}
+
+fn accumulator(data_width: u8) -> Operand {
+    Operand::from(register_of_width(0, data_width))
+}
+
+fn decode_sib<R>(stream: &mut R, instruction: &mut Instruction, mode: u8) -> io::Result<()> where R: Read {
+    let mut field: u32 = 0;
+    read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut field)?;
+    let index = ((field as u8)>>3)&0x7;
+    let base = (field as u8)&0x7;
+    instruction.address_scale = 1<<((field as u8)>>6);
+    instruction.address_index = if index == 4 { Operand::None } else { Operand::from(index+Operand::EAX as u8) };
+    instruction.address_base = if mode == 0 && base == 5 { Operand::None } else { Operand::from(base+Operand::EAX as u8) };
+    Ok(())
+}
+
+fn decode_extended_mod_rm<R>(stream: &mut R, instruction: &mut Instruction, mut mode: u8, rm: u8) -> io::Result<u8> where R: Read {
+    instruction.address_scale = 1;
+    instruction.address_index = Operand::None;
+    if rm == 4 {
+        decode_sib(stream, instruction, mode)?;
+        if instruction.address_base == Operand::None {
+            mode = 2;
+        }
+    } else if mode == 0 && rm == 5 {
+        mode = 2;
+        instruction.address_base = Operand::None;
+    } else {
+        instruction.address_base = Operand::from(rm+Operand::EAX as u8);
+    }
+    let mut displacement: u32 = 0;
+    match mode {
+        1 => {
+            read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut displacement)?;
+            instruction.displacement = displacement as i8 as i32;
+        },
+        2 => {
+            read_bytes(stream, 4, &mut instruction.buffer, &mut instruction.length, &mut displacement)?;
+            instruction.displacement = displacement as i32;
+        }
+        _ => {}
+    };
+    Ok(Operand::DisplacementExtended as u8)
 }
 
 fn decode_mod_rm<R>(stream: &mut R, instruction: &mut Instruction, is_grp: bool, swap_operands: bool) -> io::Result<()> where R: Read {
@@ -181,14 +364,15 @@ fn decode_mod_rm<R>(stream: &mut R, instruction: &mut Instruction, is_grp: bool,
     read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut field)?;
     let mut mode: u8 = ((field as u8)>>6)&0x3;
     let mut first_operand = ((field as u8)>>3)&0x7;
-    if !is_grp && instruction.data_width == 8 {
-        first_operand += Operand::AL as u8;
+    if !is_grp {
+        first_operand = register_of_width(first_operand, instruction.data_width);
     }
     let mut second_operand = (field as u8)&0x7;
     if mode == 3 {
-        if instruction.data_width == 8 {
-            second_operand += Operand::AL as u8;
-        }
+        second_operand = register_of_width(second_operand, instruction.data_width);
+    } else if instruction.address_size == 32 {
+        second_operand = decode_extended_mod_rm(stream, instruction, mode, second_operand)?;
+        mode = 0;
     } else if mode == 0 && second_operand == 6 {
         mode = 2;
         second_operand = Operand::Displacement as u8;
@@ -215,19 +399,329 @@ fn decode_mod_rm<R>(stream: &mut R, instruction: &mut Instruction, is_grp: bool,
     Ok(())
 }
 
-pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction) -> io::Result<()> where R: Read {
+fn decode_relative_target<R>(stream: &mut R, instruction: &mut Instruction) -> io::Result<()> where R: Read {
+    read_bytes(stream, instruction.operand_size/8, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+    let offset = if instruction.operand_size == 32 { instruction.immediate as i32 } else { instruction.immediate as i16 as i32 };
+    instruction.immediate = (offset+(instruction.length as i32)+(instruction.position as i32)) as u32;
+    Ok(())
+}
+
+fn decode_two_byte_instruction<R>(stream: &mut R, instruction: &mut Instruction, model: CpuModel) -> io::Result<()> where R: Read {
     let opcode = {
         let mut opcode: u32 = 0;
         read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut opcode)?;
         opcode as u8
     };
+    instruction.data_width = instruction.operand_size;
+    match opcode {
+        0x04 | 0x05 | 0x07..=0xFF if model < CpuModel::I80386 => { // BAD
+            instruction.opcode = Opcode::BAD;
+        },
+        0x00 => { // GRP6 : SLDT/0 STR/1 LLDT/2 LTR/3 VERR/4 VERW/5
+            instruction.data_width = 16;
+            decode_mod_rm(stream, instruction, true, true)?;
+            instruction.opcode = match instruction.second_operand as u8 {
+                0 => Opcode::SLDT,
+                1 => Opcode::STR,
+                2 => Opcode::LLDT,
+                3 => Opcode::LTR,
+                4 => Opcode::VERR,
+                5 => Opcode::VERW,
+                _ => Opcode::BAD
+            };
+            instruction.second_operand = Operand::None;
+        },
+        0x01 => { // GRP7 : SGDT/0 SIDT/1 LGDT/2 LIDT/3 SMSW/4 LMSW/6
+            instruction.data_width = 16;
+            decode_mod_rm(stream, instruction, true, true)?;
+            let sub_type = instruction.second_operand as u8;
+            instruction.opcode = match sub_type {
+                0 => Opcode::SGDT,
+                1 => Opcode::SIDT,
+                2 => Opcode::LGDT,
+                3 => Opcode::LIDT,
+                4 => Opcode::SMSW,
+                6 => Opcode::LMSW,
+                _ => Opcode::BAD
+            };
+            if sub_type < 4 {
+                if (instruction.first_operand as u8) < Operand::DisplacementBXSI as u8 {
+                    instruction.opcode = Opcode::BAD;
+                }
+                instruction.data_width = 0;
+            }
+            instruction.second_operand = Operand::None;
+        },
+        0x02 | 0x03 => { // LAR LSL
+            instruction.opcode = if opcode == 0x02 { Opcode::LAR } else { Opcode::LSL };
+            decode_mod_rm(stream, instruction, false, false)?;
+            if (instruction.second_operand as u8) < Operand::DisplacementBXSI as u8 {
+                instruction.second_operand = Operand::from((instruction.second_operand as u8-register_of_width(0, instruction.data_width))&7);
+            }
+        },
+        0x06 => { // CLTS
+            instruction.opcode = Opcode::CLTS;
+        },
+        0x20 | 0x22 => { // MOV
+            let mut field: u32 = 0;
+            read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut field)?;
+            let control_register = ((field as u8)>>3)&0x7;
+            let register = Operand::from(((field as u8)&0x7)+Operand::EAX as u8);
+            instruction.opcode = if control_register == 1 || control_register > 3 { Opcode::BAD } else { Opcode::MOV };
+            instruction.data_width = 32;
+            if opcode == 0x20 {
+                instruction.first_operand = register;
+                instruction.second_operand = Operand::from(control_register+Operand::CR0 as u8);
+            } else {
+                instruction.first_operand = Operand::from(control_register+Operand::CR0 as u8);
+                instruction.second_operand = register;
+            }
+        },
+        0x80..=0x8F => { // Jcc
+            instruction.opcode = Opcode::from(opcode-0x10);
+            instruction.data_width = 16;
+            decode_relative_target(stream, instruction)?;
+        },
+        0x90..=0x9F => { // SETcc
+            instruction.opcode = SETCC_OPCODES[(opcode&0xF) as usize];
+            instruction.data_width = 8;
+            decode_mod_rm(stream, instruction, true, true)?;
+            instruction.second_operand = Operand::None;
+        },
+        0xA0 | 0xA1 | 0xA8 | 0xA9 => { // PUSH POP
+            instruction.opcode = if opcode&1 == 0 { Opcode::PUSH } else { Opcode::POP };
+            instruction.data_width = 16;
+            instruction.first_operand = if opcode < 0xA8 { Operand::FS } else { Operand::GS };
+        },
+        0xA3 | 0xAB | 0xB3 | 0xBB => { // BT BTS BTR BTC
+            instruction.opcode = match opcode {
+                0xA3 => Opcode::BT,
+                0xAB => Opcode::BTS,
+                0xB3 => Opcode::BTR,
+                0xBB => Opcode::BTC,
+                _ => unreachable!()
+            };
+            decode_mod_rm(stream, instruction, false, true)?;
+        },
+        0xBA => { // GRP8 : BT/4 BTS/5 BTR/6 BTC/7
+            decode_mod_rm(stream, instruction, true, true)?;
+            instruction.opcode = match instruction.second_operand as u8 {
+                4 => Opcode::BT,
+                5 => Opcode::BTS,
+                6 => Opcode::BTR,
+                7 => Opcode::BTC,
+                _ => Opcode::BAD
+            };
+            instruction.second_operand = Operand::None;
+            read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+        },
+        0xA4 | 0xA5 | 0xAC | 0xAD => { // SHLD SHRD
+            instruction.opcode = if opcode < 0xAC { Opcode::SHLD } else { Opcode::SHRD };
+            decode_mod_rm(stream, instruction, false, true)?;
+            if opcode&1 == 0 {
+                read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+            } else {
+                instruction.third_operand = Operand::CL;
+            }
+        },
+        0xAF => { // IMUL
+            instruction.opcode = Opcode::IMUL;
+            decode_mod_rm(stream, instruction, false, false)?;
+            instruction.third_operand = instruction.second_operand;
+            instruction.second_operand = instruction.first_operand;
+        },
+        0xB2 | 0xB4 | 0xB5 => { // LSS LFS LGS
+            instruction.opcode = match opcode {
+                0xB2 => Opcode::LSS,
+                0xB4 => Opcode::LFS,
+                0xB5 => Opcode::LGS,
+                _ => unreachable!()
+            };
+            decode_mod_rm(stream, instruction, false, false)?;
+        },
+        0xB6 | 0xB7 | 0xBE | 0xBF => { // MOVZX MOVSX
+            instruction.opcode = match opcode {
+                0xB6 => Opcode::MOVZX,
+                0xB7 => Opcode::MOVZXW,
+                0xBE => Opcode::MOVSX,
+                0xBF => Opcode::MOVSXW,
+                _ => unreachable!()
+            };
+            decode_mod_rm(stream, instruction, false, false)?;
+            let source_width = if opcode&1 == 0 { 8 } else { 16 };
+            if (instruction.second_operand as u8) < Operand::DisplacementBXSI as u8 {
+                instruction.second_operand = Operand::from(register_of_width((instruction.second_operand as u8-register_of_width(0, instruction.data_width))&7, source_width));
+            }
+        },
+        0xBC | 0xBD => { // BSF BSR
+            instruction.opcode = if opcode == 0xBC { Opcode::BSF } else { Opcode::BSR };
+            decode_mod_rm(stream, instruction, false, false)?;
+        },
+        _ => { // BAD
+            instruction.opcode = Opcode::BAD;
+        }
+    }
+    Ok(())
+}
+
+fn decode_fpu_instruction<R>(stream: &mut R, instruction: &mut Instruction, opcode: u8) -> io::Result<()> where R: Read {
+    instruction.data_width = 16;
+    decode_mod_rm(stream, instruction, true, true)?;
+    let sub_type = instruction.second_operand as u8;
+    instruction.second_operand = Operand::None;
+    let fpu_opcode = if (instruction.first_operand as u8) < Operand::DisplacementBXSI as u8 {
+        let index = instruction.first_operand as u8;
+        let register = Operand::from(Operand::ST0 as u8+index);
+        instruction.first_operand = Operand::None;
+        match (opcode, sub_type) {
+            (0xD8, 0) | (0xD8, 1) | (0xD8, 4..=7) => { // FADD FMUL FSUB FSUBR FDIV FDIVR ST(0), ST(i)
+                instruction.first_operand = Operand::ST0;
+                instruction.second_operand = register;
+                Some(ARITHMETIC_FPU_OPCODES[sub_type as usize])
+            },
+            (0xDC, 0) | (0xDC, 1) | (0xDC, 4..=7) | (0xDE, 0) | (0xDE, 1) | (0xDE, 4..=7) => { // FADD(P) FMUL(P) FSUBR(P) FSUB(P) FDIVR(P) FDIV(P) ST(i), ST(0)
+                instruction.first_operand = register;
+                instruction.second_operand = Operand::ST0;
+                Some(match (opcode, sub_type) {
+                    (0xDC, 0) => FpuOpcode::FADD,
+                    (0xDC, 1) => FpuOpcode::FMUL,
+                    (0xDC, 4) => FpuOpcode::FSUBR,
+                    (0xDC, 5) => FpuOpcode::FSUB,
+                    (0xDC, 6) => FpuOpcode::FDIVR,
+                    (0xDC, 7) => FpuOpcode::FDIV,
+                    (_, 0) => FpuOpcode::FADDP,
+                    (_, 1) => FpuOpcode::FMULP,
+                    (_, 4) => FpuOpcode::FSUBRP,
+                    (_, 5) => FpuOpcode::FSUBP,
+                    (_, 6) => FpuOpcode::FDIVRP,
+                    _ => FpuOpcode::FDIVP
+                })
+            },
+            (0xD8, 2) | (0xD8, 3) | (0xD9, 0) | (0xD9, 1) | (0xDD, 0) | (0xDD, 2..=5) => { // FCOM FCOMP FLD FXCH FFREE FST FSTP FUCOM FUCOMP ST(i)
+                instruction.first_operand = register;
+                Some(match (opcode, sub_type) {
+                    (0xD8, 2) => FpuOpcode::FCOM,
+                    (0xD8, _) => FpuOpcode::FCOMP,
+                    (0xD9, 0) => FpuOpcode::FLD,
+                    (0xD9, _) => FpuOpcode::FXCH,
+                    (_, 0) => FpuOpcode::FFREE,
+                    (_, 2) => FpuOpcode::FST,
+                    (_, 3) => FpuOpcode::FSTP,
+                    (_, 4) => FpuOpcode::FUCOM,
+                    _ => FpuOpcode::FUCOMP
+                })
+            },
+            (0xD9, 2) if index == 0 => Some(FpuOpcode::FNOP),
+            (0xD9, 4) => match index {
+                0 => Some(FpuOpcode::FCHS),
+                1 => Some(FpuOpcode::FABS),
+                4 => Some(FpuOpcode::FTST),
+                5 => Some(FpuOpcode::FXAM),
+                _ => None
+            },
+            (0xD9, 5) if index < 7 => Some([FpuOpcode::FLD1, FpuOpcode::FLDL2T, FpuOpcode::FLDL2E, FpuOpcode::FLDPI, FpuOpcode::FLDLG2, FpuOpcode::FLDLN2, FpuOpcode::FLDZ][index as usize]),
+            (0xD9, 6) => Some([FpuOpcode::F2XM1, FpuOpcode::FYL2X, FpuOpcode::FPTAN, FpuOpcode::FPATAN, FpuOpcode::FXTRACT, FpuOpcode::FPREM1, FpuOpcode::FDECSTP, FpuOpcode::FINCSTP][index as usize]),
+            (0xD9, 7) => Some([FpuOpcode::FPREM, FpuOpcode::FYL2XP1, FpuOpcode::FSQRT, FpuOpcode::FSINCOS, FpuOpcode::FRNDINT, FpuOpcode::FSCALE, FpuOpcode::FSIN, FpuOpcode::FCOS][index as usize]),
+            (0xDA, 5) if index == 1 => Some(FpuOpcode::FUCOMPP),
+            (0xDB, 4) if index < 5 => Some([FpuOpcode::FENI, FpuOpcode::FDISI, FpuOpcode::FNCLEX, FpuOpcode::FNINIT, FpuOpcode::FSETPM][index as usize]),
+            (0xDE, 3) if index == 1 => Some(FpuOpcode::FCOMPP),
+            (0xDF, 4) if index == 0 => {
+                instruction.first_operand = Operand::AX;
+                Some(FpuOpcode::FNSTSW)
+            },
+            _ => None
+        }
+    } else {
+        match (opcode, sub_type) {
+            (0xD8, _) | (0xDC, _) => { // FADD FMUL FCOM FCOMP FSUB FSUBR FDIV FDIVR m32/m64
+                instruction.data_width = if opcode == 0xD8 { 32 } else { 64 };
+                Some(ARITHMETIC_FPU_OPCODES[sub_type as usize])
+            },
+            (0xDA, _) | (0xDE, _) => { // FIADD FIMUL FICOM FICOMP FISUB FISUBR FIDIV FIDIVR m32/m16
+                instruction.data_width = if opcode == 0xDA { 32 } else { 16 };
+                Some([FpuOpcode::FIADD, FpuOpcode::FIMUL, FpuOpcode::FICOM, FpuOpcode::FICOMP, FpuOpcode::FISUB, FpuOpcode::FISUBR, FpuOpcode::FIDIV, FpuOpcode::FIDIVR][sub_type as usize])
+            },
+            (0xD9, 0) | (0xD9, 2) | (0xD9, 3) | (0xDD, 0) | (0xDD, 2) | (0xDD, 3) => { // FLD FST FSTP m32/m64
+                instruction.data_width = if opcode == 0xD9 { 32 } else { 64 };
+                Some([FpuOpcode::FLD, FpuOpcode::FLD, FpuOpcode::FST, FpuOpcode::FSTP][sub_type as usize])
+            },
+            (0xDB, 5) | (0xDB, 7) => { // FLD FSTP m80
+                instruction.data_width = 80;
+                Some(if sub_type == 5 { FpuOpcode::FLD } else { FpuOpcode::FSTP })
+            },
+            (0xDB, 0) | (0xDB, 2) | (0xDB, 3) | (0xDF, 0) | (0xDF, 2) | (0xDF, 3) => { // FILD FIST FISTP m32/m16
+                instruction.data_width = if opcode == 0xDB { 32 } else { 16 };
+                Some([FpuOpcode::FILD, FpuOpcode::FILD, FpuOpcode::FIST, FpuOpcode::FISTP][sub_type as usize])
+            },
+            (0xDF, 5) | (0xDF, 7) => { // FILD FISTP m64
+                instruction.data_width = 64;
+                Some(if sub_type == 5 { FpuOpcode::FILD } else { FpuOpcode::FISTP })
+            },
+            (0xDF, 4) | (0xDF, 6) => { // FBLD FBSTP m80
+                instruction.data_width = 80;
+                Some(if sub_type == 4 { FpuOpcode::FBLD } else { FpuOpcode::FBSTP })
+            },
+            (0xD9, 5) | (0xD9, 7) | (0xDD, 7) => { // FLDCW FNSTCW FNSTSW m16
+                Some(match (opcode, sub_type) {
+                    (0xD9, 5) => FpuOpcode::FLDCW,
+                    (0xD9, _) => FpuOpcode::FNSTCW,
+                    _ => FpuOpcode::FNSTSW
+                })
+            },
+            (0xD9, 4) | (0xD9, 6) | (0xDD, 4) | (0xDD, 6) => { // FLDENV FNSTENV FRSTOR FNSAVE
+                instruction.data_width = 0;
+                Some(match (opcode, sub_type) {
+                    (0xD9, 4) => FpuOpcode::FLDENV,
+                    (0xD9, _) => FpuOpcode::FNSTENV,
+                    (_, 4) => FpuOpcode::FRSTOR,
+                    _ => FpuOpcode::FNSAVE
+                })
+            },
+            _ => None
+        }
+    };
+    match fpu_opcode {
+        Some(fpu_opcode) => {
+            instruction.opcode = Opcode::ESC;
+            instruction.fpu_opcode = fpu_opcode;
+        },
+        None => {
+            instruction.opcode = Opcode::BAD;
+        }
+    }
+    Ok(())
+}
+
+pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction, model: CpuModel, default_size: u8) -> io::Result<()> where R: Read {
+    let opcode = {
+        let mut opcode: u32 = 0;
+        read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut opcode)?;
+        let opcode = opcode as u8;
+        if model < CpuModel::I80186 {
+            match opcode { // 8086 aliases of opcodes which were assigned later
+                0x60..=0x6F => opcode|0x10, // Jcc
+                0xC0 | 0xC1 | 0xC8 | 0xC9 => opcode|0x02, // RET RETF
+                0xF1 => 0xF0, // LOCK
+                _ => opcode
+            }
+        } else {
+            opcode
+        }
+    };
     let swap_operands: bool = opcode&2 == 0;
-    instruction.data_width = if opcode&1 == 0 { 8 } else { 16 };
+    instruction.data_width = if opcode&1 == 0 { 8 } else { instruction.operand_size };
     instruction.first_operand = Operand::None;
     instruction.second_operand = Operand::None;
+    instruction.third_operand = Operand::None;
     instruction.immediate = 0xFFFFFFFF;
     instruction.displacement = 0;
     match opcode {
+        0x64..=0x67 if model < CpuModel::I80386 => { // BAD
+            instruction.opcode = Opcode::BAD;
+        },
+        0x0F if model == CpuModel::I80186 => { // BAD
+            instruction.opcode = Opcode::BAD;
+        },
         0x88..=0x8C | 0x8E => { // MOV
             instruction.opcode = Opcode::MOV;
             if opcode > 0x8B {
@@ -247,13 +741,17 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
         0x8D => { // LEA
             instruction.opcode = Opcode::LEA;
             decode_mod_rm(stream, instruction, false, false)?;
-            instruction.displacement = instruction.displacement as i16 as i32;
+            if instruction.address_size == 16 {
+                instruction.displacement = instruction.displacement as i16 as i32;
+            }
         },
         0xC4 | 0xC5 => { // LES LDS
             instruction.opcode = Opcode::from(opcode);
-            instruction.data_width = 16;
+            instruction.data_width = instruction.operand_size;
             decode_mod_rm(stream, instruction, false, false)?;
-            instruction.displacement = instruction.displacement as i16 as i32;
+            if instruction.address_size == 16 {
+                instruction.displacement = instruction.displacement as i16 as i32;
+            }
         },
         0x8F => { // POP
             instruction.opcode = Opcode::POP;
@@ -265,10 +763,14 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
             instruction.opcode = Opcode::from(opcode&0xFE);
             decode_mod_rm(stream, instruction, false, swap_operands)?;
         },
-        0xD0..=0xD3 => { // GRP2 : ROL/0 ROR/1 RCL/2 RCR/3 SHL/4 SHR/5 SAL/6 SAR/7
+        0xD0..=0xD3 | 0xC0 | 0xC1 => { // GRP2 : ROL/0 ROR/1 RCL/2 RCR/3 SHL/4 SHR/5 SAL/6 SAR/7
             decode_mod_rm(stream, instruction, true, true)?;
             instruction.opcode = Opcode::from(0xB8+instruction.second_operand as u8);
             match opcode {
+                0xC0 | 0xC1 => {
+                    instruction.second_operand = Operand::None;
+                    read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+                },
                 0xD0 | 0xD1 => {
                     instruction.second_operand = Operand::None;
                     instruction.immediate = 1;
@@ -288,7 +790,7 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
             };
             instruction.second_operand = Operand::None;
         },
-        0xFF => { // GRP5 : INC/0 DEC/1 CALL/2 LCALL/3 JMP/4 LJMP/5 PUSH/6
+        0xFF => { // GRP5 : INC/0 DEC/1 CALL/2 LCALL/3 JMP/4 LJMP/5 PUSH/6 PUSH/7
             decode_mod_rm(stream, instruction, true, true)?;
             let sub_type = instruction.second_operand as u8;
             instruction.opcode = match sub_type {
@@ -299,9 +801,10 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
                 4 => Opcode::JMP,
                 5 => Opcode::LJMP,
                 6 => Opcode::PUSH,
+                7 if model < CpuModel::I80186 => Opcode::PUSH,
                 _ => Opcode::BAD
             };
-            if sub_type >= 2 && sub_type <= 5 {
+            if sub_type >= 2 && sub_type <= 5 && instruction.address_size == 16 {
                 instruction.displacement = instruction.displacement as i16 as i32;
             }
             instruction.second_operand = Operand::None;
@@ -310,20 +813,19 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
             decode_mod_rm(stream, instruction, true, true)?;
             instruction.opcode = Opcode::from(instruction.second_operand as u8*8);
             instruction.second_operand = Operand::None;
-            read_bytes(stream, if opcode != 0x81 { 1 } else { 2 }, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+            read_bytes(stream, if opcode != 0x81 { 1 } else { instruction.data_width/8 }, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
             if opcode == 0x83 {
                 instruction.immediate = instruction.immediate as i8 as u32;
             }
         },
-        0xF6 | 0xF7 => { // GRP3 : TEST/0 NOT/2 NEG/3 MUL/4 IMUL/5 DIV/6 IDIV/7
+        0xF6 | 0xF7 => { // GRP3 : TEST/0 TEST/1 NOT/2 NEG/3 MUL/4 IMUL/5 DIV/6 IDIV/7
             decode_mod_rm(stream, instruction, true, true)?;
             let sub_type = instruction.second_operand as u8;
             instruction.opcode = match sub_type {
-                0 => {
-                    read_bytes(stream, if opcode == 0xF6 { 1 } else { 2 }, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+                0 | 1 => {
+                    read_bytes(stream, instruction.data_width/8, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
                     Opcode::TEST
                 },
-                1 => Opcode::BAD,
                 _ => Opcode::from(0xE8+sub_type) // NOT/2 NEG/3 MUL/4 IMUL/5 DIV/6 IDIV/7
             };
             instruction.second_operand = Operand::None;
@@ -331,13 +833,9 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
         0x00..=0x05 | 0x08..=0x0D | 0x10..=0x15 | 0x18..=0x1D | 0x20..=0x25 | 0x28..=0x2D | 0x30..=0x35 | 0x38..=0x3D => { // ADD OR ADC SBB AND SUB XOR CMP
             instruction.opcode = Opcode::from(opcode&0xF8);
             match (opcode)&7 {
-                0x04 => {
-                    instruction.first_operand = Operand::AL;
-                    read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
-                },
-                0x05 => {
-                    instruction.first_operand = Operand::AX;
-                    read_bytes(stream, 2, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+                0x04 | 0x05 => {
+                    instruction.first_operand = accumulator(instruction.data_width);
+                    read_bytes(stream, instruction.data_width/8, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
                 },
                 _ => {
                     decode_mod_rm(stream, instruction, false, swap_operands)?;
@@ -349,7 +847,7 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
             decode_mod_rm(stream, instruction, false, swap_operands)?;
             instruction.first_operand = instruction.second_operand;
             instruction.second_operand = Operand::None;
-            read_bytes(stream, if opcode == 0xC6 { 1 } else { 2 }, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+            read_bytes(stream, instruction.data_width/8, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
         },
         0x70..=0x7F | 0xE0..=0xE3 | 0xEB => { // Jcc LOOPcc JCXZ JMP
             instruction.opcode = if opcode == 0xEB { Opcode::JMP } else { Opcode::from(opcode) };
@@ -360,25 +858,27 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
         0xE8 | 0xE9 => { // CALL JMP
             instruction.opcode = Opcode::from(opcode);
             instruction.data_width = 16;
-            read_bytes(stream, 2, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
-            instruction.immediate = ((instruction.immediate as i16 as i32)+(instruction.length as i32)+(instruction.position as i32)) as u32;
+            decode_relative_target(stream, instruction)?;
         },
         0xA0..=0xA3 => { // MOV
             instruction.opcode = Opcode::MOV;
+            let displacement_operand = if instruction.address_size == 32 { Operand::DisplacementExtended } else { Operand::Displacement };
             if opcode&2 == 0 {
-                instruction.first_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
-                instruction.second_operand = Operand::Displacement;
+                instruction.first_operand = accumulator(instruction.data_width);
+                instruction.second_operand = displacement_operand;
             } else {
-                instruction.first_operand = Operand::Displacement;
-                instruction.second_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
+                instruction.first_operand = displacement_operand;
+                instruction.second_operand = accumulator(instruction.data_width);
             }
+            instruction.address_base = Operand::None;
+            instruction.address_index = Operand::None;
             let mut displacement: u32 = 0;
-            read_bytes(stream, 2, &mut instruction.buffer, &mut instruction.length, &mut displacement)?;
-            instruction.displacement = displacement as i16 as i32;
+            read_bytes(stream, instruction.address_size/8, &mut instruction.buffer, &mut instruction.length, &mut displacement)?;
+            instruction.displacement = if instruction.address_size == 32 { displacement as i32 } else { displacement as i16 as i32 };
         },
         0xA8 | 0xA9 => { // TEST
             instruction.opcode = Opcode::TEST;
-            instruction.first_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
+            instruction.first_operand = accumulator(instruction.data_width);
             read_bytes(stream, instruction.data_width/8, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
         },
         0xB0..=0xBF => { // MOV
@@ -387,8 +887,8 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
                 instruction.data_width = 8;
                 instruction.first_operand = Operand::from((opcode&7)+(Operand::AL as u8));
             } else {
-                instruction.data_width = 16;
-                instruction.first_operand = Operand::from(opcode&7);
+                instruction.data_width = instruction.operand_size;
+                instruction.first_operand = Operand::from(register_of_width(opcode&7, instruction.data_width));
             }
             read_bytes(stream, instruction.data_width/8, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
         },
@@ -400,6 +900,12 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
             };
             instruction.data_width = 32;
             read_bytes(stream, 4, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+            if instruction.operand_size == 32 { // ptr16:32, selector in displacement
+                let mut selector: u32 = 0;
+                read_bytes(stream, 2, &mut instruction.buffer, &mut instruction.length, &mut selector)?;
+                instruction.data_width = 48;
+                instruction.displacement = selector as i32;
+            }
         },
         0xC2 | 0xCA => { // RET RETF
             instruction.opcode = match opcode {
@@ -417,25 +923,28 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
         },
         0xE4 | 0xE5 => { // IN
             instruction.opcode = Opcode::from(opcode&0xF6);
-            instruction.first_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
+            instruction.first_operand = accumulator(instruction.data_width);
             instruction.data_width = 8;
             read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
         },
         0xE6 | 0xE7 => { // OUT
             instruction.opcode = Opcode::from(opcode&0xF6);
-            instruction.second_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
+            instruction.second_operand = accumulator(instruction.data_width);
             instruction.data_width = 8;
             read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
         },
         0xEC | 0xED => { // IN
             instruction.opcode = Opcode::from(opcode&0xF6);
-            instruction.first_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
+            instruction.first_operand = accumulator(instruction.data_width);
             instruction.second_operand = Operand::DX;
         },
         0xEE | 0xEF => { // OUT
             instruction.opcode = Opcode::from(opcode&0xF6);
             instruction.first_operand = Operand::DX;
-            instruction.second_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
+            instruction.second_operand = accumulator(instruction.data_width);
+        },
+        0x0F if model >= CpuModel::I80286 => { // Two Byte Opcodes
+            decode_two_byte_instruction(stream, instruction, model)?;
         },
         0x06 | 0x0E | 0x16 | 0x1E | 0x07 | 0x0F | 0x17 | 0x1F => { // PUSH POP
             instruction.opcode = if instruction.data_width == 8 { Opcode::PUSH } else { Opcode::POP };
@@ -450,14 +959,14 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
         },
         0x40..=0x5F => { // INC DEC PUSH POP
             instruction.opcode = Opcode::from(opcode&0xF8);
-            instruction.data_width = 16;
-            instruction.first_operand = Operand::from(opcode&7);
+            instruction.data_width = instruction.operand_size;
+            instruction.first_operand = Operand::from(register_of_width(opcode&7, instruction.data_width));
         },
         0x91..=0x97 => { // XCHG
             instruction.opcode = Opcode::XCHG;
-            instruction.data_width = 16;
-            instruction.first_operand = Operand::from(opcode&7);
-            instruction.second_operand = Operand::AX;
+            instruction.data_width = instruction.operand_size;
+            instruction.first_operand = Operand::from(register_of_width(opcode&7, instruction.data_width));
+            instruction.second_operand = accumulator(instruction.data_width);
         },
         0xCC => { // INT
             instruction.opcode = Opcode::INT;
@@ -471,40 +980,112 @@ pub fn decode_instruction_part<R>(stream: &mut R, instruction: &mut Instruction)
         0xAA | 0xAB | 0xAE | 0xAF => { // STOSB STOSW SCASB SCASW
             instruction.opcode = Opcode::from(opcode);
             instruction.first_operand = Operand::DisplacementDI;
-            instruction.second_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
+            instruction.second_operand = accumulator(instruction.data_width);
         },
         0xAC | 0xAD => { // LODSB LODSW
             instruction.opcode = Opcode::from(opcode);
-            instruction.first_operand = if instruction.data_width == 8 { Operand::AL } else { Operand::AX };
+            instruction.first_operand = accumulator(instruction.data_width);
             instruction.second_operand = Operand::DisplacementSI;
         },
+        0x60 | 0x61 | 0xC9 => { // PUSHA POPA LEAVE
+            instruction.opcode = Opcode::from(opcode);
+            instruction.data_width = instruction.operand_size;
+        },
+        0x62 => { // BOUND
+            instruction.opcode = Opcode::BOUND;
+            instruction.data_width = instruction.operand_size;
+            decode_mod_rm(stream, instruction, false, false)?;
+        },
+        0x68 | 0x6A => { // PUSH
+            instruction.opcode = Opcode::PUSH;
+            instruction.data_width = instruction.operand_size;
+            read_bytes(stream, if opcode == 0x68 { instruction.data_width/8 } else { 1 }, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+            if opcode == 0x6A {
+                instruction.immediate = instruction.immediate as i8 as u32;
+            }
+        },
+        0x69 | 0x6B => { // IMUL
+            instruction.opcode = Opcode::IMUL;
+            decode_mod_rm(stream, instruction, false, false)?;
+            read_bytes(stream, if opcode == 0x69 { instruction.data_width/8 } else { 1 }, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+            if opcode == 0x6B {
+                instruction.immediate = instruction.immediate as i8 as u32;
+            }
+        },
+        0x6C | 0x6D => { // INSB INSW
+            instruction.opcode = Opcode::from(opcode);
+            instruction.first_operand = Operand::DisplacementDI;
+            instruction.second_operand = Operand::DX;
+        },
+        0x6E | 0x6F => { // OUTSB OUTSW
+            instruction.opcode = Opcode::from(opcode);
+            instruction.first_operand = Operand::DX;
+            instruction.second_operand = Operand::DisplacementSI;
+        },
+        0xC8 => { // ENTER
+            instruction.opcode = Opcode::ENTER;
+            instruction.data_width = instruction.operand_size;
+            read_bytes(stream, 2, &mut instruction.buffer, &mut instruction.length, &mut instruction.immediate)?;
+            let mut nesting_level: u32 = 0;
+            read_bytes(stream, 1, &mut instruction.buffer, &mut instruction.length, &mut nesting_level)?;
+            instruction.displacement = nesting_level as i32;
+        },
         0x26 | 0x2E | 0x36 | 0x3E => { // ES: CS: SS: DS:
             instruction.opcode = Opcode::from(opcode);
             instruction.segment_override = Operand::from((Operand::ES as u8)+(opcode-0x26)/8);
-            decode_instruction_part(stream, instruction)?;
+            decode_instruction_part(stream, instruction, model, default_size)?;
+        },
+        0x64 | 0x65 => { // FS: GS:
+            instruction.segment_override = Operand::from((Operand::FS as u8)+(opcode-0x64));
+            decode_instruction_part(stream, instruction, model, default_size)?;
+        },
+        0x66 => { // Operand Size
+            instruction.operand_size = 48-default_size;
+            decode_instruction_part(stream, instruction, model, default_size)?;
+        },
+        0x67 => { // Address Size
+            instruction.address_size = 48-default_size;
+            decode_instruction_part(stream, instruction, model, default_size)?;
         },
         0xF2 | 0xF3 => { // REPZ/REPNE REPZ/REPE
             instruction.prefix = Opcode::from(opcode);
-            decode_instruction_part(stream, instruction)?;
+            decode_instruction_part(stream, instruction, model, default_size)?;
             match instruction.opcode {
-                Opcode::MOVSB | Opcode::MOVSW | Opcode::STOSB | Opcode::STOSW | Opcode::LODSB | Opcode::LODSW => { instruction.prefix = Opcode::REP },
+                Opcode::MOVSB | Opcode::MOVSW | Opcode::STOSB | Opcode::STOSW | Opcode::LODSB | Opcode::LODSW | Opcode::INSB | Opcode::INSW | Opcode::OUTSB | Opcode::OUTSW => { instruction.prefix = Opcode::REP },
                 _ => {}
             }
         },
-        0x27 | 0x2F | 0x37 | 0x3F | 0x90 | 0x98 | 0x99 | 0x9B | 0x9C | 0x9D | 0x9E | 0x9F | 0xC3 | 0xCB | 0xCE | 0xCF | 0xD7 | 0xF0 | 0xF4 | 0xF5 | 0xF8 | 0xF9 | 0xFA | 0xFB | 0xFC | 0xFD => {
-            // DAA DAS AAA AAS NOP CBW CWD WAIT PUSHF POPF SAHF LAHF RET LEAVE RETF INTO IRET XLAT LOCK HLT CMC CLC STC CLI STI CLD STD
+        0x9C | 0x9D => { // PUSHF POPF
+            instruction.opcode = Opcode::from(opcode);
+            instruction.data_width = instruction.operand_size;
+        },
+        0x27 | 0x2F | 0x37 | 0x3F | 0x90 | 0x98 | 0x99 | 0x9B | 0x9E | 0x9F | 0xC3 | 0xCB | 0xCE | 0xCF | 0xD7 | 0xF0 | 0xF4 | 0xF5 | 0xF8 | 0xF9 | 0xFA | 0xFB | 0xFC | 0xFD => {
+            // DAA DAS AAA AAS NOP CBW CWD WAIT SAHF LAHF RET LEAVE RETF INTO IRET XLAT LOCK HLT CMC CLC STC CLI STI CLD STD
             instruction.opcode = Opcode::from(opcode);
         },
-        0x60..=0x6F | 0xC0 | 0xC1 | 0xC8 | 0xC9 | 0xD8..=0xDF | 0xF1 | 0xD6 => { // BAD
+        0xD8..=0xDF => { // ESC
+            decode_fpu_instruction(stream, instruction, opcode)?;
+        },
+        0x63 if model >= CpuModel::I80286 => { // ARPL
+            instruction.opcode = Opcode::ARPL;
+            instruction.data_width = 16;
+            decode_mod_rm(stream, instruction, false, swap_operands)?;
+        },
+        0xD6 => { // SALC
+            instruction.opcode = Opcode::SALC;
+        },
+        0x63 | 0xF1 => { // BAD
             instruction.opcode = Opcode::BAD;
         }
     }
     Ok(())
 }
 
-pub fn decode_instruction<R>(stream: &mut R, instruction: &mut Instruction) -> io::Result<()> where R: Read {
+pub fn decode_instruction<R>(stream: &mut R, instruction: &mut Instruction, model: CpuModel, default_size: u8) -> io::Result<()> where R: Read {
     instruction.length = 0;
     instruction.prefix = Opcode::BAD;
     instruction.segment_override = Operand::None;
-    decode_instruction_part(stream, instruction)
+    instruction.operand_size = default_size;
+    instruction.address_size = default_size;
+    decode_instruction_part(stream, instruction, model, default_size)
 }
@@ -16,17 +16,31 @@ mod disassembler;
 mod config;
 mod bus;
 mod cpu;
+mod fpu;
 mod dos;
+mod name_mapping;
+mod file_system;
+mod disk_image;
+mod fat;
+mod iso9660;
+mod mscdex;
 mod bios;
 mod pic;
 mod pit;
 mod ps2_controller;
+mod ems;
+mod xms;
 mod sound_blaster;
 mod vga;
 mod debugger;
 mod gui;
 mod keyboard_mapping;
 mod audio;
+mod timing;
+mod instruction_cache;
+mod jit;
+#[cfg(test)]
+mod single_step_tests;
 
 use termion::input::TermRead;
 use std::os::unix::io::AsRawFd;
@@ -56,9 +70,19 @@ fn main() {
             .short("C")
             .help("Where to mount C: at")
             .takes_value(true))
+        .arg(clap::Arg::with_name("mount")
+            .long("mount")
+            .help("additional drive as D=path[,floppy|hard_disk|cdrom][,ro][,cow][,label=NAME], path is a directory, a FAT disk image or an ISO 9660 image")
+            .multiple(true)
+            .number_of_values(1))
+        .arg(clap::Arg::with_name("boot")
+            .long("boot")
+            .help("BIOS drive number to boot from instead of running an executable, like 00 for the first floppy or 80 for the first hard disk")
+            .takes_value(true)
+            .conflicts_with("executable"))
         .arg(clap::Arg::with_name("executable")
-            .help("Executable file to run, must be inside the mounting of C:")
-            .required(true)
+            .help("Executable file to run, must be inside one of the mounted drives")
+            .required_unless("boot")
             .index(1))
         .arg(clap::Arg::with_name("disassemble")
             .long("disasm")
@@ -69,21 +93,76 @@ fn main() {
     cpu.interrupt_breakpoints[1] = true;
     cpu.interrupt_breakpoints[3] = true;
     let config_path = matches.value_of("config").map_or(std::path::Path::new("config.toml").to_path_buf(), |v| std::path::Path::new(v).to_path_buf());
-    let executable_path = std::path::Path::new(matches.value_of("executable").unwrap());
+    let executable_path = matches.value_of("executable").map(std::path::Path::new);
+    let boot_drive = matches.value_of("boot").map(|number| match u8::from_str_radix(number.trim_start_matches("0x"), 16) {
+        Ok(number) => number,
+        Err(_) => {
+            eprintln!("Invalid BIOS drive number {}", number);
+            std::process::exit(1);
+        }
+    });
     bus.config = toml::from_str(std::fs::read_to_string(&config_path).unwrap().as_str()).unwrap();
-    bus.dos.mount_point_c = matches.value_of("path C").map_or(std::env::current_dir().unwrap(), |v| std::path::Path::new(v).to_path_buf());
-    bus.dos.load_executable(&mut cpu, &mut bus.ram, executable_path).unwrap();
+    cpu.model = bus.config.cpu.model;
+    cpu.jit = crate::jit::JustInTimeCompiler::new(bus.config.cpu.jit);
+    cpu.timing_model.profile = bus.config.timing.profile;
+    cpu.timing_model.memory_wait_states = bus.config.timing.memory_wait_states;
+    bus.pit.clock_cycles_per_tick = bus.config.timing.clock_frequency/crate::pit::CLOCK_FREQUENCY;
+    bus.configure_memory();
+    let mut drives = vec![crate::config::Drive {
+        letter: 'C',
+        path: matches.value_of("path C").map_or(std::env::current_dir().unwrap(), |v| std::path::Path::new(v).to_path_buf()),
+        kind: crate::dos::DriveKind::HardDisk,
+        read_only: false,
+        copy_on_write: false,
+        label: String::new()
+    }];
+    drives.extend(bus.config.drives.iter().cloned());
+    for argument in matches.values_of("mount").into_iter().flatten() {
+        match crate::config::Drive::from_argument(argument) {
+            Ok(drive) => drives.push(drive),
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(1);
+            }
+        }
+    }
+    for disk in bus.config.disks.clone() {
+        if let Err(error) = bus.attach_disk(disk.number, &disk.path, disk.read_only, disk.copy_on_write) {
+            eprintln!("Could not attach {:?}: {}", disk.path, error);
+            std::process::exit(1);
+        }
+    }
+    for drive in drives {
+        let path = drive.path.clone();
+        if let Err(error) = bus.mount_drive(drive) {
+            eprintln!("Could not mount {:?}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+    match (boot_drive, executable_path) {
+        (Some(number), _) => {
+            if let Err(error) = bus.boot(&mut cpu, number) {
+                eprintln!("Could not boot: {}", error);
+                std::process::exit(1);
+            }
+        },
+        (None, Some(executable_path)) => bus.dos.load_executable(&mut cpu, &mut bus.ram, executable_path).unwrap(),
+        (None, None) => unreachable!()
+    }
     if matches.is_present("disassemble") {
-        crate::disassembler::disassemble(&mut bus.ram, crate::bus::BUS::physical_address(cpu.get_register(crate::machinecode::Operand::CS), cpu.instruction.position) as u32);
+        crate::disassembler::disassemble(&mut bus.ram, cpu.model, crate::bus::BUS::physical_address(cpu.get_register(crate::machinecode::Operand::CS), cpu.instruction.position as u16) as u32);
         std::process::exit(0);
     }
-    bus.dos.set_environment_and_arguments(&mut bus.ram, executable_path,
-        match matches.values_of("environment") {
-            Some(environments) => environments.collect(),
-            None => vec![""]
-        },
-        matches.value_of("arguments").unwrap_or("")
-    );
+    if let Some(executable_path) = executable_path {
+        bus.dos.set_environment_and_arguments(&mut bus.ram, executable_path,
+            match matches.values_of("environment") {
+                Some(environments) => environments.collect(),
+                None => vec![""]
+            },
+            matches.value_of("arguments").unwrap_or("")
+        );
+        crate::bios::BIOS::from_ram(&mut bus.ram).set_video_mode(cpu.cycle_counter, &mut bus.vga, 0x03);
+    }
     let clock_frequency = bus.config.timing.clock_frequency;
     let cpu_cycles_per_compensation_interval = (clock_frequency/bus.config.timing.compensation_frequency) as u64;
     let cpu_ptr = { &mut *cpu as *mut crate::cpu::CPU as usize };
@@ -147,7 +226,8 @@ fn main() {
                 }
             }
             if cpu.execution_state == crate::cpu::ExecutionState::Running {
-                for _ in 0..cpu_cycles_per_compensation_interval {
+                let end_of_compensation_interval = cpu.cycle_counter+cpu_cycles_per_compensation_interval;
+                while cpu.cycle_counter < end_of_compensation_interval {
                     cpu.execute_instruction(&mut bus);
                     if cpu.execution_state != crate::cpu::ExecutionState::Running {
                         debugger.pause(&mut cpu, &mut bus);
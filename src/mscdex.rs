@@ -0,0 +1,216 @@
+use crate::machinecode::Operand;
+use crate::cpu::Flag;
+
+pub const DEVICE_HEADER_SEGMENT: u16 = 0x00F2;
+const VERSION: u16 = 0x0215; // 2.21
+
+/// Red Book address of a sector as frame, second and minute bytes
+fn to_red_book(sector: u32) -> u32 {
+    let frames = sector+150;
+    ((frames/(75*60))<<16)|((frames/75%60)<<8)|(frames%75)
+}
+
+fn from_red_book(address: u32) -> u32 {
+    (((address>>16)&0xFF)*60*75+((address>>8)&0xFF)*75+(address&0xFF)).saturating_sub(150)
+}
+
+/// Writes the header of the CD-ROM device driver, which covers all CD-ROM drives as its subunits
+fn setup_device_header(ram: &mut [u8], drives: &[u8]) {
+    let address = crate::bus::BUS::physical_address(DEVICE_HEADER_SEGMENT, 0);
+    ram[address..address+0x0A].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xC8, 0x16, 0x00, 0x16, 0x00]);
+    ram[address+0x0A..address+0x12].copy_from_slice(b"MSCD001 ");
+    ram[address+0x12..address+0x14].copy_from_slice(&[0x00, 0x00]);
+    ram[address+0x14] = drives.first().map_or(0, |drive| drive+1);
+    ram[address+0x15] = drives.len() as u8;
+    ram[address+0x16] = 0xCB; // RETF of the strategy and interrupt routines
+}
+
+/// Reads sectors into guest memory through the bus, false if they run past the segment or the first MiB or can not be read
+fn read_sectors_to_memory(cpu: &mut crate::cpu::CPU, bus: &mut crate::bus::BUS, drive: u8, sector: u32, count: usize, segment: u16, offset: u16) -> bool {
+    let address = crate::bus::BUS::physical_address(segment, offset);
+    let length = count*crate::iso9660::SECTOR_SIZE;
+    if offset as usize+length > 0x10000 || address+length > 0x100000 {
+        return false;
+    }
+    let mut buffer = vec![0; length];
+    match bus.dos.cd_rom_image(drive) {
+        Some(image) if image.read_sectors(sector, &mut buffer).is_ok() => {},
+        _ => { return false; }
+    }
+    for (index, byte) in buffer.iter().enumerate() {
+        let dst = bus.get_memory(cpu, address+index);
+        bus.write_to_memory(cpu, dst, 8, *byte as u32);
+    }
+    true
+}
+
+/// Executes a device driver request and returns its status word
+fn device_request(cpu: &mut crate::cpu::CPU, bus: &mut crate::bus::BUS, drive: u8, request: usize) -> u16 {
+    const DONE: u16 = 0x0100;
+    const ERROR: u16 = 0x8000;
+    const UNKNOWN_COMMAND: u16 = ERROR|DONE|0x03;
+    const NOT_READY: u16 = ERROR|DONE|0x02;
+    let (transfer_segment, transfer_offset) = (crate::bit_utils::read_from_buffer_u16(&bus.ram[request+16..]), crate::bit_utils::read_from_buffer_u16(&bus.ram[request+14..]));
+    let transfer_address = crate::bus::BUS::physical_address(transfer_segment, transfer_offset);
+    let command = bus.ram[request+2];
+    match command {
+        0x03 => { // IOCTL Input
+            let volume_space_size = match bus.dos.cd_rom_image(drive) {
+                Some(image) => image.volume_space_size,
+                None => { return NOT_READY; }
+            };
+            let control_block = &mut bus.ram[transfer_address..];
+            match control_block[0] {
+                0x00 => { // Device Header Address
+                    crate::bit_utils::write_to_buffer_u32(&mut control_block[1..], (DEVICE_HEADER_SEGMENT as u32)<<16);
+                },
+                0x01 => { // Location of Head
+                    crate::bit_utils::write_to_buffer_u32(&mut control_block[2..], 0);
+                },
+                0x06 => { // Device Status: Door unlocked, cooked and raw reading, data and audio
+                    crate::bit_utils::write_to_buffer_u32(&mut control_block[1..], 0x0000_0016);
+                },
+                0x07 => { // Sector Size
+                    crate::bit_utils::write_to_buffer_u16(&mut control_block[2..], crate::iso9660::SECTOR_SIZE as u16);
+                },
+                0x08 => { // Volume Size
+                    crate::bit_utils::write_to_buffer_u32(&mut control_block[1..], volume_space_size);
+                },
+                0x09 => { // Media Changed
+                    control_block[1] = 1; // Not changed
+                },
+                0x0A => { // Audio Disk Info, a single data track
+                    control_block[1] = 1;
+                    control_block[2] = 1;
+                    crate::bit_utils::write_to_buffer_u32(&mut control_block[3..], to_red_book(volume_space_size));
+                },
+                0x0B => { // Audio Track Info
+                    crate::bit_utils::write_to_buffer_u32(&mut control_block[2..], to_red_book(0));
+                    control_block[6] = 0x40; // Data track
+                },
+                0x0C | 0x0F => { // Audio Q-Channel Info / Audio Status Info
+                    control_block[1..11].iter_mut().for_each(|byte| *byte = 0);
+                },
+                code => {
+                    println!("MSCDEX ({}): Unsupported IOCTL input code={:02X}", cpu.cycle_counter, code);
+                    return UNKNOWN_COMMAND;
+                }
+            }
+            DONE
+        },
+        0x0C | 0x0D | 0x0E | 0x82 | 0x83 | 0x84 | 0x85 | 0x88 => DONE, // IOCTL Output, Open, Close, Prefetch, Seek, Play / Stop / Resume Audio
+        0x80 => { // Read Long
+            let count = crate::bit_utils::read_from_buffer_u16(&bus.ram[request+18..]) as usize;
+            let start = crate::bit_utils::read_from_buffer_u32(&bus.ram[request+20..]);
+            let sector = if bus.ram[request+13] == 1 { from_red_book(start) } else { start };
+            if bus.dos.cd_rom_image(drive).is_none() {
+                return NOT_READY;
+            }
+            println!("MSCDEX ({}): Read Long drive={} sector={} count={}", cpu.cycle_counter, (b'A'+drive) as char, sector, count);
+            if bus.ram[request+24] != 0 || !read_sectors_to_memory(cpu, bus, drive, sector, count, transfer_segment, transfer_offset) {
+                return ERROR|DONE|0x08; // Sector not found
+            }
+            DONE
+        },
+        _ => {
+            println!("MSCDEX ({}): Unsupported device request command={:02X}", cpu.cycle_counter, command);
+            UNKNOWN_COMMAND
+        }
+    }
+}
+
+/// CD-ROM extensions on INT 2Fh AH=15h
+pub fn handle_interrupt(cpu: &mut crate::cpu::CPU, bus: &mut crate::bus::BUS) {
+    let drives = bus.dos.cd_rom_drives();
+    let drive = cpu.get_register(Operand::CX) as u8;
+    let buffer_address = crate::bus::BUS::physical_address(cpu.get_register(Operand::ES), cpu.get_register(Operand::BX));
+    let function = cpu.get_register(Operand::AL);
+    match function {
+        0x00 => { // Installation Check
+            cpu.set_register(Operand::BX, drives.len() as u16);
+            if let Some(first_drive) = drives.first() {
+                cpu.set_register(Operand::CX, *first_drive as u16);
+            }
+            return;
+        },
+        0x01 => { // Get Drive Device List
+            setup_device_header(&mut bus.ram, &drives);
+            for subunit in 0..drives.len() {
+                bus.ram[buffer_address+subunit*5] = subunit as u8;
+                crate::bit_utils::write_to_buffer_u32(&mut bus.ram[buffer_address+subunit*5+1..], (DEVICE_HEADER_SEGMENT as u32)<<16);
+            }
+            return;
+        },
+        0x0B => { // CD-ROM Drive Check
+            cpu.set_register(Operand::AX, if drives.contains(&drive) { 0x5AD8 } else { 0x0000 });
+            cpu.set_register(Operand::BX, 0xADAD);
+            return;
+        },
+        0x0C => { // Get Version
+            cpu.set_register(Operand::BX, VERSION);
+            return;
+        },
+        0x0D => { // Get Drive Letters
+            bus.ram[buffer_address..buffer_address+drives.len()].copy_from_slice(&drives);
+            return;
+        },
+        _ => {}
+    }
+    cpu.set_flag(Flag::Carry, 1);
+    if !drives.contains(&drive) {
+        println!("MSCDEX ({}): function={:02X} drive={} err=(Invalid drive)", cpu.cycle_counter, function, drive);
+        cpu.set_register(Operand::AX, 0x0F); // Invalid drive
+        return;
+    }
+    match function {
+        0x02 | 0x03 | 0x04 => { // Get Copyright / Abstract / Bibliographic File Name
+            let image = match bus.dos.cd_rom_image(drive) {
+                Some(image) => image,
+                None => {
+                    cpu.set_register(Operand::AX, 0x15); // Drive not ready
+                    return;
+                }
+            };
+            let offset = 702+(function as usize-2)*37;
+            bus.ram[buffer_address..buffer_address+37].copy_from_slice(&image.primary_volume_descriptor[offset..offset+37]);
+            bus.ram[buffer_address+37] = 0;
+        },
+        0x05 => { // Read Volume Table of Contents
+            let sector = 16+cpu.get_register(Operand::DX) as u32;
+            if !read_sectors_to_memory(cpu, bus, drive, sector, 1, cpu.get_register(Operand::ES), cpu.get_register(Operand::BX)) {
+                cpu.set_register(Operand::AX, 0x15); // Drive not ready
+                return;
+            }
+            let src = bus.get_memory(cpu, buffer_address);
+            let descriptor_type = bus.read_from_memory(cpu, src, 8);
+            cpu.set_register(Operand::AX, match descriptor_type { 0x01 => 1, 0xFF => 0xFF, _ => 0 });
+        },
+        0x08 => { // Absolute Disk Read
+            let sector = (cpu.get_register(Operand::SI) as u32)<<16|cpu.get_register(Operand::DI) as u32;
+            let count = cpu.get_register(Operand::DX) as usize;
+            if bus.dos.cd_rom_image(drive).is_none() {
+                cpu.set_register(Operand::AX, 0x15); // Drive not ready
+                return;
+            }
+            println!("MSCDEX ({}): Absolute Disk Read drive={} sector={} count={}", cpu.cycle_counter, (b'A'+drive) as char, sector, count);
+            if !read_sectors_to_memory(cpu, bus, drive, sector, count, cpu.get_register(Operand::ES), cpu.get_register(Operand::BX)) {
+                cpu.set_register(Operand::AX, 0x1E); // Read fault
+                return;
+            }
+        },
+        0x09 => { // Absolute Disk Write
+            cpu.set_register(Operand::AX, 0x13); // Write protected
+            return;
+        },
+        0x10 => { // Send Device Driver Request
+            let status = device_request(cpu, bus, drive, buffer_address);
+            crate::bit_utils::write_to_buffer_u16(&mut bus.ram[buffer_address+3..], status);
+        },
+        _ => {
+            println!("MSCDEX ({}): Unsupported function={:02X}", cpu.cycle_counter, function);
+            cpu.set_register(Operand::AX, 0x01); // Invalid function
+            return;
+        }
+    }
+    cpu.reset_flag(Flag::Carry);
+}
@@ -0,0 +1,161 @@
+/// Host file name and the 8.3 name DOS sees for it
+pub struct MappedEntry {
+    pub short_name: String,
+    pub host_name: std::ffi::OsString
+}
+
+/// Maps host file names to unique upper case 8.3 names per directory.
+/// The names stay stable as long as the host files exist, so FindNext and reopening agree with FindFirst.
+pub struct NameMapping {
+    directories: std::collections::HashMap<std::path::PathBuf, Vec<MappedEntry>>
+}
+
+fn is_legal_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(character)
+}
+
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(index) if index > 0 => (&name[0..index], &name[index+1..]),
+        _ => (name, "")
+    }
+}
+
+/// Upper case 8.3 form of a name as typed by a DOS program, which truncates the base name and extension
+pub fn normalize_short_name(name: &str) -> String {
+    let (base, extension) = split_extension(name);
+    let base: String = base.chars().take(8).collect();
+    let extension: String = extension.chars().take(3).collect();
+    if extension.is_empty() { base.to_uppercase() } else { format!("{}.{}", base, extension).to_uppercase() }
+}
+
+/// Whether a component of a DOS path names an entry, empty or all dot components like "..." would map outside the directory
+pub fn is_valid_component(component: &str) -> bool {
+    !component.is_empty() && !component.chars().all(|character| character == '.')
+}
+
+/// The host name in upper case if it already is a legal 8.3 name
+fn legal_short_name(host_name: &str) -> Option<String> {
+    let (base, extension) = split_extension(host_name);
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 || host_name.ends_with('.') ||
+       !base.chars().chain(extension.chars()).all(is_legal_character) {
+        return None;
+    }
+    Some(host_name.to_uppercase())
+}
+
+/// Generates a "BASENA~1.EXT" style name which does not collide with the given names
+fn mangle(host_name: &str, taken: &dyn Fn(&str) -> bool) -> String {
+    let filter = |part: &str, length: usize| -> String {
+        part.chars().filter(|character| *character != ' ' && *character != '.')
+            .map(|character| if is_legal_character(character) { character.to_ascii_uppercase() } else { '_' })
+            .take(length).collect()
+    };
+    let (base, extension) = split_extension(host_name.trim_start_matches('.'));
+    let base = filter(base, 8);
+    let extension = filter(extension, 3);
+    let base = if base.is_empty() { "_".to_string() } else { base };
+    for number in 1.. {
+        let suffix = format!("~{}", number);
+        let short_name = format!("{}{}", &base[0..base.len().min(8-suffix.len())], suffix);
+        let short_name = if extension.is_empty() { short_name } else { format!("{}.{}", short_name, extension) };
+        if !taken(&short_name) {
+            return short_name;
+        }
+    }
+    unreachable!()
+}
+
+/// The 8.3 name of a host name, mangled if it is not a legal 8.3 name or collides with a taken one
+pub fn short_name(host_name: &str, taken: &dyn Fn(&str) -> bool) -> String {
+    match legal_short_name(host_name) {
+        Some(short_name) if !taken(&short_name) => short_name,
+        _ => mangle(host_name, taken)
+    }
+}
+
+/// Space padded 11 character form of a name as used in FCBs, where "*" fills the rest of its part with "?"
+pub fn to_fcb_form(name: &str) -> [u8; 11] {
+    let (base, extension) = if name == "." || name == ".." { (name, "") } else { split_extension(name) };
+    let mut fcb_form = [b' '; 11];
+    for (part, begin, length) in [(base, 0, 8), (extension, 8, 3)].iter() {
+        for (index, character) in part.bytes().take(*length).enumerate() {
+            if character == b'*' {
+                fcb_form[begin+index..begin+length].iter_mut().for_each(|character| *character = b'?');
+                break;
+            }
+            fcb_form[begin+index] = character.to_ascii_uppercase();
+        }
+    }
+    fcb_form
+}
+
+/// Name of the space padded 11 character form of an FCB
+pub fn from_fcb_form(fcb_form: &[u8]) -> String {
+    let base: String = fcb_form[0..8].iter().map(|byte| byte.to_ascii_uppercase() as char).collect();
+    let extension: String = fcb_form[8..11].iter().map(|byte| byte.to_ascii_uppercase() as char).collect();
+    let (base, extension) = (base.trim_end(), extension.trim_end());
+    if extension.is_empty() { base.to_string() } else { format!("{}.{}", base, extension) }
+}
+
+/// DOS wildcard matching, "?" in the pattern matches any character
+pub fn matches_pattern(short_name: &str, pattern: &[u8; 11]) -> bool {
+    to_fcb_form(short_name).iter().zip(pattern.iter()).all(|(character, pattern_character)| *pattern_character == b'?' || character == pattern_character)
+}
+
+impl NameMapping {
+    pub fn new() -> Self {
+        Self {
+            directories: std::collections::HashMap::new()
+        }
+    }
+
+    /// Rereads the host directory, keeping the names and order of the entries which still exist
+    pub fn scan(&mut self, directory: &std::path::Path) -> &[MappedEntry] {
+        let mut host_names: Vec<std::ffi::OsString> = match std::fs::read_dir(directory) {
+            Ok(read_directory) => read_directory.filter_map(|entry| entry.ok()).map(|entry| entry.file_name()).collect(),
+            Err(_) => Vec::new()
+        };
+        host_names.sort();
+        let mut entries = self.directories.remove(directory).unwrap_or_else(Vec::new);
+        entries.retain(|entry| host_names.contains(&entry.host_name));
+        for host_name in host_names {
+            if entries.iter().any(|entry| entry.host_name == host_name) {
+                continue;
+            }
+            let host_name_str = host_name.to_string_lossy().to_string();
+            let taken = |short_name: &str| entries.iter().any(|entry| entry.short_name == short_name);
+            let short_name = short_name(&host_name_str, &taken);
+            entries.push(MappedEntry { short_name: short_name, host_name: host_name });
+        }
+        self.directories.insert(directory.to_path_buf(), entries);
+        &self.directories[directory]
+    }
+
+    pub fn entries(&mut self, directory: &std::path::Path) -> &[MappedEntry] {
+        if !self.directories.contains_key(directory) {
+            self.scan(directory);
+        }
+        &self.directories[directory]
+    }
+
+    /// Case insensitive lookup of a DOS name, rescanning once in case the directory changed
+    pub fn lookup(&mut self, directory: &std::path::Path, name: &str) -> Option<std::ffi::OsString> {
+        let short_name = normalize_short_name(name);
+        let find = |entries: &[MappedEntry]| entries.iter().find(|entry| entry.short_name == short_name).map(|entry| entry.host_name.clone());
+        find(self.entries(directory)).or_else(|| find(self.scan(directory)))
+    }
+
+    /// Translates DOS path components below the root, components which do not exist (yet) keep their normalized DOS name
+    pub fn host_path(&mut self, root: &std::path::Path, components: &[String]) -> std::path::PathBuf {
+        let mut path = root.to_path_buf();
+        for component in components {
+            let host_name = match self.lookup(&path, component) {
+                Some(host_name) => host_name,
+                None => std::ffi::OsString::from(normalize_short_name(component))
+            };
+            path.push(host_name);
+        }
+        path
+    }
+}
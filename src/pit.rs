@@ -1,3 +1,5 @@
+pub const CLOCK_FREQUENCY: f64 = 1193181.666;
+
 #[derive(Copy, Clone, PartialEq)]
 enum AccessMode {
     HighThenLow = 0,
@@ -18,7 +20,8 @@ pub struct Timer {
 }
 
 pub struct ProgrammableIntervalTimer {
-    timers: [Timer; 3]
+    timers: [Timer; 3],
+    pub clock_cycles_per_tick: f64
 }
 
 impl ProgrammableIntervalTimer {
@@ -32,17 +35,17 @@ impl ProgrammableIntervalTimer {
                 trigger_at_cycle: u64::max_value(),
                 is_latched: false,
                 input_mask: true
-            }; 3]
+            }; 3],
+            clock_cycles_per_tick: 4.0
         }
     }
 
+    fn reload_ticks_of_timer(&self, timer: usize) -> u64 {
+        if self.timers[timer].reload == 0 { 0x10000 } else { self.timers[timer].reload as u64 }
+    }
+
     fn calculate_reload_of_timer(&mut self, timer: usize) -> u64 {
-        let mut reload = self.timers[timer].reload as u64;
-        if reload == 0 { reload = 0x10000; }
-        (match self.timers[timer].operation_mode {
-            3 | 7 => 4,
-            _ => 2
-        })*reload
+        (self.reload_ticks_of_timer(timer) as f64*self.clock_cycles_per_tick) as u64
     }
 
     fn calculate_counter_of_timer(&mut self, timer: usize, cycle_counter: u64) -> u16 {
@@ -50,11 +53,14 @@ impl ProgrammableIntervalTimer {
             return 0;
         }
         let reload_value = self.calculate_reload_of_timer(timer);
-        let last_start = cycle_counter-self.timers[timer].trigger_at_cycle+reload_value;
-        match self.timers[timer].operation_mode {
-            2 | 6 | 3 | 7 => (last_start%reload_value) as u16,
-            _ => last_start as u16
-        }
+        let last_start = cycle_counter.wrapping_sub(self.timers[timer].trigger_at_cycle).wrapping_add(reload_value);
+        let elapsed_ticks = (last_start as f64/self.clock_cycles_per_tick) as u64;
+        let reload_ticks = self.reload_ticks_of_timer(timer);
+        (match self.timers[timer].operation_mode {
+            2 | 6 => reload_ticks-elapsed_ticks%reload_ticks,
+            3 | 7 => reload_ticks-(elapsed_ticks*2)%reload_ticks,
+            _ => reload_ticks.wrapping_sub(elapsed_ticks)
+        }) as u16
     }
 
     fn calculate_output_of_timer(&mut self, cycle_counter: u64, timer: usize) -> bool {
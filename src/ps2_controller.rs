@@ -2,7 +2,9 @@ pub struct PS2Controller {
     data_buffer: [u8; 16],
     write_pos: usize,
     read_pos: usize,
-    // status: u8
+    output_port: u8,
+    pending_command: u8,
+    command_response: Option<u8>
 }
 
 impl PS2Controller {
@@ -11,10 +13,23 @@ impl PS2Controller {
             data_buffer: unsafe { std::mem::zeroed() },
             write_pos: 0,
             read_pos: 0,
-            // status: 0
+            output_port: 0xCD, // A20 disabled
+            pending_command: 0,
+            command_response: None
         }
     }
 
+    pub fn is_a20_enabled(&self) -> bool {
+        self.output_port&0x02 != 0
+    }
+
+    pub fn set_a20_enabled(&mut self, cycle_counter: u64, enabled: bool) {
+        if self.is_a20_enabled() != enabled {
+            println!("PS/2 ({}): A20 gate enabled={}", cycle_counter, enabled);
+        }
+        self.output_port = (self.output_port&!0x02)|((enabled as u8)<<1);
+    }
+
     pub fn is_data_available(&mut self) -> bool {
         self.read_pos != self.write_pos
     }
@@ -55,14 +70,20 @@ impl PS2Controller {
     pub fn read_from_port(&mut self, cycle_counter: u64, address: u16) -> u8 {
         match address {
             0x60 => {
-                if self.read_pos != self.write_pos {
+                if let Some(value) = self.command_response.take() {
+                    value
+                } else if self.read_pos != self.write_pos {
                     self.data_buffer[self.read_pos]
                 } else {
                     println!("PS/2 ({}): Data buffer underflow", cycle_counter);
                     0
                 }
             },
-            // 0x64 => self.status,
+            0x64 => {
+                let output_buffer_full = self.command_response.is_some() || self.read_pos != self.write_pos;
+                (output_buffer_full as u8)|0x04 // System flag
+            },
+            0x92 => (self.is_a20_enabled() as u8)<<1, // System control port A
             _ => {
                 println!("PS/2 ({}): Unsupported port read address={:04X}", cycle_counter, address);
                 0
@@ -72,8 +93,35 @@ impl PS2Controller {
 
     pub fn write_to_port(&mut self, cycle_counter: u64, address: u16, value: u8) {
         match address {
-            // 0x60 => { self.data = value; },
-            // 0x64 => { },
+            0x60 => {
+                match std::mem::replace(&mut self.pending_command, 0) {
+                    0xD1 => { // Write output port
+                        self.set_a20_enabled(cycle_counter, value&0x02 != 0);
+                        self.output_port = value|0x01; // Reset line stays high
+                    },
+                    _ => {
+                        println!("PS/2 ({}): Unsupported keyboard command={:02X}", cycle_counter, value);
+                    }
+                }
+            },
+            0x64 => {
+                match value {
+                    0xD0 => { self.command_response = Some(self.output_port); }, // Read output port
+                    0xD1 => { self.pending_command = value; }, // Write output port
+                    0xDD => { self.set_a20_enabled(cycle_counter, false); },
+                    0xDF => { self.set_a20_enabled(cycle_counter, true); },
+                    0xFF => {}, // Pulse output port (no-op)
+                    _ => {
+                        println!("PS/2 ({}): Unsupported controller command={:02X}", cycle_counter, value);
+                    }
+                }
+            },
+            0x92 => { // System control port A
+                self.set_a20_enabled(cycle_counter, value&0x02 != 0);
+                if value&0x01 != 0 {
+                    println!("PS/2 ({}): Unsupported fast reset", cycle_counter);
+                }
+            },
             _ => {
                 println!("PS/2 ({}): Unsupported port write address={:04X} value={:02X}", cycle_counter, address, value);
             }
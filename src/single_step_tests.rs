@@ -0,0 +1,257 @@
+use crate::machinecode::CpuModel;
+use crate::machinecode::Operand;
+use serde::Deserialize;
+
+// Runs the single step test vectors (one JSON file per opcode like "00.json" or "F6.6.json", as published for the 8088 and 80286)
+// through CPU::execute_instruction and reports the mismatching registers, flags and memory per opcode.
+//
+// single_step_fixtures runs the small set of vectors in FIXTURES_DIRECTORY, which has a directory per CPU model.
+// They are generated by its generate.py and prefixed opcodes are named by all their bytes, like "0FA4.json" or "66F7.7.json".
+// single_step_tests runs the full published suite, it is ignored by default: cargo test single_step_tests -- --ignored
+//
+// CPU_TESTS_DIRECTORY: Directory of the uncompressed vectors, defaults to "tests/single_step".
+// CPU_TESTS_MODEL: CPU model to emulate, "8086" (default) or "80286".
+// CPU_TESTS_OPCODES: Comma separated list of opcodes to run, e.g. "00,D0.4". Defaults to all.
+// CPU_TESTS_FLAGS_MASKS: Comma separated overrides of the flags which are compared, e.g. "D4=0x08D4,F6.6=0x0000".
+//                        Defaults to the "flags-mask" entries of the metadata file (e.g. "8088.json") in the directory.
+
+const FIXTURES_DIRECTORY: &str = "tests/single_step_fixtures";
+
+const REGISTERS: [(&str, Operand); 12] = [
+    ("ax", Operand::AX), ("bx", Operand::BX), ("cx", Operand::CX), ("dx", Operand::DX),
+    ("sp", Operand::SP), ("bp", Operand::BP), ("si", Operand::SI), ("di", Operand::DI),
+    ("cs", Operand::CS), ("ds", Operand::DS), ("es", Operand::ES), ("ss", Operand::SS)
+];
+
+// Only present in the vectors of the 80386
+const REGISTERS_32: [(&str, Operand); 8] = [
+    ("eax", Operand::EAX), ("ebx", Operand::EBX), ("ecx", Operand::ECX), ("edx", Operand::EDX),
+    ("esp", Operand::ESP), ("ebp", Operand::EBP), ("esi", Operand::ESI), ("edi", Operand::EDI)
+];
+
+const FLAGS: [(&str, u16); 9] = [
+    ("CF", 0), ("PF", 2), ("AF", 4), ("ZF", 6), ("SF", 7), ("TF", 8), ("IF", 9), ("DF", 10), ("OF", 11)
+];
+
+const REPORTED_FAILURES_PER_OPCODE: usize = 3;
+
+#[derive(Deserialize)]
+struct TestState {
+    regs: std::collections::HashMap<String, u32>,
+    ram: Vec<(u32, u8)>
+}
+
+#[derive(Deserialize)]
+struct TestCase {
+    name: String,
+    initial: TestState,
+    #[serde(rename = "final")]
+    final_state: TestState
+}
+
+#[derive(Default)]
+struct Report {
+    tests: usize,
+    failed: usize,
+    mismatches: std::collections::BTreeMap<String, usize>
+}
+
+impl Report {
+    fn merge(&mut self, other: &Report) {
+        self.tests += other.tests;
+        self.failed += other.failed;
+        for (location, count) in &other.mismatches {
+            *self.mismatches.entry(location.clone()).or_insert(0) += count;
+        }
+    }
+
+    fn summary(&self) -> String {
+        let mismatches: Vec<String> = self.mismatches.iter().map(|(location, count)| format!("{}={}", location, count)).collect();
+        format!("{}/{} passed {}", self.tests-self.failed, self.tests, mismatches.join(" ")).trim_end().to_string()
+    }
+}
+
+fn is_opcode_name(name: &str) -> bool {
+    let mut parts = name.split('.');
+    parts.next().map_or(false, |opcode| opcode.len()%2 == 0 && (2..=6).contains(&opcode.len()) && u32::from_str_radix(opcode, 16).is_ok()) &&
+    parts.all(|part| part.parse::<u8>().is_ok())
+}
+
+fn parse_flags_mask(value: &serde_json::Value) -> Option<u16> {
+    match value {
+        serde_json::Value::Number(number) => number.as_u64().map(|mask| mask as u16),
+        serde_json::Value::String(string) => u16::from_str_radix(string.trim_start_matches("0x"), 16).ok(),
+        _ => None
+    }
+}
+
+fn load_flags_masks(directory: &std::path::Path) -> std::collections::HashMap<String, u16> {
+    let mut flags_masks = std::collections::HashMap::new();
+    for entry in std::fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+        if path.extension().map_or(true, |extension| extension != "json") || is_opcode_name(&stem) {
+            continue;
+        }
+        let metadata: serde_json::Value = match serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(&path).unwrap())) {
+            Ok(metadata) => metadata,
+            Err(_) => { continue; }
+        };
+        if let Some(opcodes) = metadata["opcodes"].as_object() {
+            for (opcode, entry) in opcodes {
+                if let Some(mask) = parse_flags_mask(&entry["flags-mask"]) {
+                    flags_masks.insert(opcode.to_uppercase(), mask);
+                }
+                if let Some(extensions) = entry["reg"].as_object() {
+                    for (extension, entry) in extensions {
+                        if let Some(mask) = parse_flags_mask(&entry["flags-mask"]) {
+                            flags_masks.insert(format!("{}.{}", opcode.to_uppercase(), extension), mask);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if let Ok(overrides) = std::env::var("CPU_TESTS_FLAGS_MASKS") {
+        for entry in overrides.split(',').filter(|entry| !entry.is_empty()) {
+            let mut parts = entry.split('=');
+            let opcode = parts.next().unwrap().trim().to_uppercase();
+            let mask = parts.next().and_then(|mask| u16::from_str_radix(mask.trim().trim_start_matches("0x"), 16).ok())
+                .unwrap_or_else(|| panic!("Invalid flags mask override {}", entry));
+            flags_masks.insert(opcode, mask);
+        }
+    }
+    flags_masks
+}
+
+fn run_test(bus: &mut crate::bus::BUS, model: CpuModel, flags_mask: u16, test: &TestCase) -> Vec<(String, String)> {
+    for (address, value) in &test.initial.ram {
+        bus.ram[*address as usize] = *value;
+    }
+    let mut cpu = crate::cpu::CPU::new();
+    cpu.model = model;
+    for (name, register) in REGISTERS.iter() {
+        cpu.set_register(*register, test.initial.regs[*name] as u16);
+    }
+    for (name, register) in REGISTERS_32.iter() {
+        if let Some(value) = test.initial.regs.get(*name) {
+            cpu.set_register_32(*register, *value);
+        }
+    }
+    cpu.instruction.position = test.initial.regs["ip"];
+    cpu.set_flags(test.initial.regs["flags"] as u16);
+    cpu.execute_instruction(bus);
+    let expected = |name: &str| *test.final_state.regs.get(name).unwrap_or(&test.initial.regs[name]);
+    let mut mismatches = Vec::new();
+    if let Some(diagnostic) = &cpu.diagnostic {
+        mismatches.push(("diagnostic".to_string(), diagnostic.clone()));
+    }
+    for (name, register) in REGISTERS.iter() {
+        let value = cpu.get_register(*register) as u32;
+        if value != expected(name) {
+            mismatches.push((name.to_string(), format!("{}={:04X} expected={:04X}", name, value, expected(name))));
+        }
+    }
+    for (name, register) in REGISTERS_32.iter().filter(|(name, _register)| test.initial.regs.contains_key(*name)) {
+        let value = cpu.get_register_32(*register);
+        if value != expected(name) {
+            mismatches.push((name.to_string(), format!("{}={:08X} expected={:08X}", name, value, expected(name))));
+        }
+    }
+    if cpu.instruction.position&0xFFFF != expected("ip") {
+        mismatches.push(("ip".to_string(), format!("ip={:04X} expected={:04X}", cpu.instruction.position, expected("ip"))));
+    }
+    let flags = cpu.get_flags();
+    for (name, bit) in FLAGS.iter() {
+        let (value, expected_value) = ((flags>>bit)&1, (expected("flags") as u16>>bit)&1);
+        if (flags_mask>>bit)&1 == 1 && value != expected_value {
+            mismatches.push((format!("flags.{}", name), format!("{}={} expected={}", name, value, expected_value)));
+        }
+    }
+    for (address, expected_value) in &test.final_state.ram {
+        let value = bus.ram[*address as usize];
+        if value != *expected_value {
+            mismatches.push(("ram".to_string(), format!("[{:05X}]={:02X} expected={:02X}", address, value, expected_value)));
+        }
+    }
+    for (address, _value) in test.initial.ram.iter().chain(test.final_state.ram.iter()) {
+        bus.ram[*address as usize] = 0;
+    }
+    mismatches
+}
+
+/// Runs the vectors of all opcodes in the directory which pass the filter
+fn run_directory(directory: &std::path::Path, model: CpuModel, opcodes_filter: Option<&Vec<String>>) -> Report {
+    let flags_masks = load_flags_masks(directory);
+    let mut paths: Vec<std::path::PathBuf> = std::fs::read_dir(directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |extension| extension == "json") && is_opcode_name(&path.file_stem().unwrap().to_string_lossy()))
+        .collect();
+    paths.sort();
+    let mut bus = crate::bus::BUS::new();
+    bus.flat_memory = true;
+    let mut total = Report::default();
+    for path in paths {
+        let opcode = path.file_stem().unwrap().to_string_lossy().to_uppercase();
+        if opcodes_filter.map_or(false, |opcodes| !opcodes.contains(&opcode)) {
+            continue;
+        }
+        let tests: Vec<TestCase> = serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(&path).unwrap()))
+            .unwrap_or_else(|error| panic!("Could not parse {}: {}", path.display(), error));
+        let flags_mask = *flags_masks.get(&opcode).or_else(|| flags_masks.get(opcode.split('.').next().unwrap())).unwrap_or(&0xFFFF);
+        let mut report = Report::default();
+        for test in &tests {
+            let mismatches = run_test(&mut bus, model, flags_mask, test);
+            report.tests += 1;
+            if mismatches.is_empty() {
+                continue;
+            }
+            if report.failed < REPORTED_FAILURES_PER_OPCODE {
+                let details: Vec<&str> = mismatches.iter().map(|(_location, detail)| detail.as_str()).collect();
+                println!("{} {}: {}", opcode, test.name, details.join(" "));
+            }
+            report.failed += 1;
+            let mut locations: Vec<&String> = mismatches.iter().map(|(location, _detail)| location).collect();
+            locations.dedup();
+            for location in locations {
+                *report.mismatches.entry(location.clone()).or_insert(0) += 1;
+            }
+        }
+        println!("{}: {}", opcode, report.summary());
+        total.merge(&report);
+    }
+    total
+}
+
+#[test]
+fn single_step_fixtures() {
+    let mut total = Report::default();
+    for model_name in ["8086", "80286", "80386"].iter() {
+        let directory = std::path::Path::new(FIXTURES_DIRECTORY).join(model_name);
+        if !directory.is_dir() {
+            continue;
+        }
+        let model: CpuModel = serde_json::from_value(serde_json::Value::String(model_name.to_string())).unwrap();
+        let report = run_directory(&directory, model, None);
+        println!("Single step fixtures {}: {}", model_name, report.summary());
+        total.merge(&report);
+    }
+    assert!(total.tests > 0, "No single step fixtures found in {}", FIXTURES_DIRECTORY);
+    assert_eq!(total.failed, 0, "CPU does not match the single step fixtures");
+}
+
+#[test]
+#[ignore]
+fn single_step_tests() {
+    let directory = std::env::var("CPU_TESTS_DIRECTORY").unwrap_or_else(|_| "tests/single_step".to_string());
+    let directory = std::path::Path::new(&directory);
+    assert!(directory.is_dir(), "{} does not exist, set CPU_TESTS_DIRECTORY to the uncompressed vectors", directory.display());
+    let model: CpuModel = serde_json::from_value(serde_json::Value::String(std::env::var("CPU_TESTS_MODEL").unwrap_or_else(|_| "8086".to_string())))
+        .expect("CPU_TESTS_MODEL must be 8086 or 80286");
+    let opcodes_filter: Option<Vec<String>> = std::env::var("CPU_TESTS_OPCODES").ok()
+        .map(|opcodes| opcodes.split(',').map(|opcode| opcode.trim().to_uppercase()).collect());
+    let total = run_directory(directory, model, opcodes_filter.as_ref());
+    println!("Single step tests: {}", total.summary());
+    assert!(total.tests > 0, "No single step tests found in {}", directory.display());
+    assert_eq!(total.failed, 0, "CPU does not match the single step test vectors");
+}
@@ -86,10 +86,11 @@ impl SoundBlaster {
         }
     }
 
-    fn set_timer(&mut self, cycle_counter: u64, handler_schedule: &mut crate::bus::HandlerSchedule, timer: usize, enabled: bool) {
+    fn set_timer(&mut self, cycle_counter: u64, handler_schedule: &mut crate::bus::HandlerSchedule, config: &mut crate::config::Config, timer: usize, enabled: bool) {
         self.timers[timer].enabled = enabled;
         if self.timers[timer].enabled {
-            self.timers[timer].trigger_at_cycle = cycle_counter+if timer == 0 { 382 } else { 1527 }*(0x100-self.timers[timer].latch as u64);
+            let cycles_per_step = config.timing.clock_frequency*if timer == 0 { 0.00008 } else { 0.00032 };
+            self.timers[timer].trigger_at_cycle = cycle_counter+(cycles_per_step*(0x100-self.timers[timer].latch as u64) as f64) as u64;
             handler_schedule.schedule_handler(crate::bus::HandlerScheduleEntry {
                 trigger_at_cycle: self.timers[timer].trigger_at_cycle,
                 kind: crate::bus::HandlerScheduleEntryKind::from(crate::bus::HandlerScheduleEntryKind::SoundBlasterTimerChannel0 as usize+timer)
@@ -185,10 +186,10 @@ impl SoundBlaster {
                              self.timers[1].expired = false;
                         } else {
                             if (value>>6) != 0 {
-                                self.set_timer(cycle_counter, handler_schedule, 0, (value>>0) != 0);
+                                self.set_timer(cycle_counter, handler_schedule, config, 0, (value>>0) != 0);
                             }
                             if (value>>5) != 0 {
-                                self.set_timer(cycle_counter, handler_schedule, 0, (value>>1) != 0);
+                                self.set_timer(cycle_counter, handler_schedule, config, 0, (value>>1) != 0);
                             }
                         }
                     },
@@ -0,0 +1,286 @@
+use crate::machinecode::Opcode;
+use crate::machinecode::FpuOpcode;
+use crate::machinecode::Operand;
+use crate::machinecode::Instruction;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+pub enum TimingProfile {
+    #[serde(rename = "8088")]
+    I8088,
+    #[default]
+    #[serde(rename = "8086")]
+    I8086,
+    #[serde(rename = "80286")]
+    I80286
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Form {
+    Register, // r or r,r
+    Memory, // m or m,r
+    RegisterMemory, // r,m
+    RegisterImmediate, // r,i
+    MemoryImmediate // m,i
+}
+
+fn is_memory(operand: Operand) -> bool {
+    operand as u8 >= Operand::DisplacementBXSI as u8 && operand as u8 <= Operand::DisplacementExtended as u8
+}
+
+fn form(instruction: &Instruction) -> Form {
+    match (is_memory(instruction.first_operand), instruction.second_operand) {
+        (false, Operand::None) => Form::RegisterImmediate,
+        (true, Operand::None) => Form::MemoryImmediate,
+        (false, second_operand) if is_memory(second_operand) => Form::RegisterMemory,
+        (true, _) => Form::Memory,
+        _ => Form::Register
+    }
+}
+
+fn fpu_cycles(fpu_opcode: FpuOpcode) -> u64 {
+    match fpu_opcode {
+        FpuOpcode::FADD | FpuOpcode::FADDP | FpuOpcode::FSUB | FpuOpcode::FSUBR | FpuOpcode::FSUBP | FpuOpcode::FSUBRP |
+        FpuOpcode::FIADD | FpuOpcode::FISUB | FpuOpcode::FISUBR => 85,
+        FpuOpcode::FMUL | FpuOpcode::FMULP | FpuOpcode::FIMUL => 140,
+        FpuOpcode::FDIV | FpuOpcode::FDIVR | FpuOpcode::FDIVP | FpuOpcode::FDIVRP | FpuOpcode::FIDIV | FpuOpcode::FIDIVR => 200,
+        FpuOpcode::FCOM | FpuOpcode::FCOMP | FpuOpcode::FCOMPP | FpuOpcode::FICOM | FpuOpcode::FICOMP |
+        FpuOpcode::FUCOM | FpuOpcode::FUCOMP | FpuOpcode::FUCOMPP | FpuOpcode::FTST | FpuOpcode::FXAM => 45,
+        FpuOpcode::FILD | FpuOpcode::FIST | FpuOpcode::FISTP => 60,
+        FpuOpcode::FBLD | FpuOpcode::FBSTP => 400,
+        FpuOpcode::FSQRT => 183,
+        FpuOpcode::FSCALE | FpuOpcode::FRNDINT | FpuOpcode::FXTRACT => 40,
+        FpuOpcode::FPREM | FpuOpcode::FPREM1 => 125,
+        FpuOpcode::F2XM1 | FpuOpcode::FYL2X | FpuOpcode::FYL2XP1 | FpuOpcode::FPTAN | FpuOpcode::FPATAN |
+        FpuOpcode::FSIN | FpuOpcode::FCOS | FpuOpcode::FSINCOS => 600,
+        FpuOpcode::FLDENV | FpuOpcode::FNSTENV => 40,
+        FpuOpcode::FRSTOR | FpuOpcode::FNSAVE => 200,
+        FpuOpcode::FLD | FpuOpcode::FST | FpuOpcode::FSTP | FpuOpcode::FXCH | FpuOpcode::FFREE | FpuOpcode::FCHS | FpuOpcode::FABS |
+        FpuOpcode::FLD1 | FpuOpcode::FLDL2T | FpuOpcode::FLDL2E | FpuOpcode::FLDPI | FpuOpcode::FLDLG2 | FpuOpcode::FLDLN2 | FpuOpcode::FLDZ => 20,
+        _ => 10
+    }
+}
+
+pub struct TimingModel {
+    pub profile: TimingProfile,
+    pub memory_wait_states: u64
+}
+
+impl TimingModel {
+    pub fn new() -> Self {
+        Self {
+            profile: TimingProfile::I8086,
+            memory_wait_states: 0
+        }
+    }
+
+    fn select(&self, cycles_8086: u64, cycles_80286: u64) -> u64 {
+        if self.profile == TimingProfile::I80286 { cycles_80286 } else { cycles_8086 }
+    }
+
+    fn effective_address_cycles(&self, instruction: &Instruction) -> u64 {
+        let operand = if is_memory(instruction.first_operand) { instruction.first_operand } else { instruction.second_operand };
+        let has_displacement = instruction.displacement != 0 || operand == Operand::DisplacementBP;
+        if self.profile == TimingProfile::I80286 {
+            return match operand {
+                Operand::DisplacementBXSI | Operand::DisplacementBXDI | Operand::DisplacementBPSI | Operand::DisplacementBPDI if has_displacement => 1,
+                Operand::DisplacementExtended if instruction.address_base != Operand::None && instruction.address_index != Operand::None && has_displacement => 1,
+                _ => 0
+            };
+        }
+        let displacement_cycles = if has_displacement && operand != Operand::Displacement { 4 } else { 0 };
+        displacement_cycles+match operand {
+            Operand::DisplacementBXSI | Operand::DisplacementBPDI => 7,
+            Operand::DisplacementBXDI | Operand::DisplacementBPSI => 8,
+            Operand::DisplacementSI | Operand::DisplacementDI | Operand::DisplacementBP | Operand::DisplacementBX => 5,
+            Operand::DisplacementExtended if instruction.address_base != Operand::None && instruction.address_index != Operand::None => 8,
+            Operand::DisplacementExtended if instruction.address_base != Operand::None || instruction.address_index != Operand::None => 5,
+            _ => 6
+        }
+    }
+
+    fn transfer_cycles(&self, wide: bool, transfers: u64) -> u64 {
+        // The 8088 splits every word transfer into two bus cycles on its 8 bit bus
+        let bus_cycles = if self.profile == TimingProfile::I8088 && wide { 2 } else { 1 };
+        transfers*((bus_cycles-1)*4+bus_cycles*self.memory_wait_states)
+    }
+
+    fn string_cycles(&self, opcode: Opcode) -> (u64, u64, u64) {
+        // Single execution, repeated iteration, transfers per iteration
+        match opcode {
+            Opcode::MOVSB | Opcode::MOVSW => (self.select(18, 5), self.select(17, 4), 2),
+            Opcode::CMPSB | Opcode::CMPSW => (self.select(22, 8), self.select(22, 9), 2),
+            Opcode::SCASB | Opcode::SCASW => (self.select(15, 7), self.select(15, 8), 1),
+            Opcode::LODSB | Opcode::LODSW => (self.select(12, 5), self.select(13, 4), 1),
+            Opcode::STOSB | Opcode::STOSW => (self.select(11, 3), self.select(10, 3), 1),
+            Opcode::INSB | Opcode::INSW | Opcode::OUTSB | Opcode::OUTSW => (self.select(14, 5), self.select(8, 4), 2),
+            _ => unreachable!()
+        }
+    }
+
+    pub fn string_iteration_cycles(&self, instruction: &Instruction) -> u64 {
+        let (_, iteration, transfers) = self.string_cycles(instruction.opcode);
+        iteration+self.transfer_cycles(instruction.data_width > 8, transfers)
+    }
+
+    pub fn interrupt_acknowledge_cycles(&self) -> u64 {
+        self.select(61, 23)+self.transfer_cycles(true, 5)
+    }
+
+    pub fn branch_taken_cycles(&self, opcode: Opcode) -> u64 {
+        match opcode {
+            Opcode::JO | Opcode::JNO | Opcode::JB | Opcode::JNB | Opcode::JE | Opcode::JNE | Opcode::JBE | Opcode::JNBE |
+            Opcode::JS | Opcode::JNS | Opcode::JP | Opcode::JNP | Opcode::JL | Opcode::JNL | Opcode::JLE | Opcode::JNLE => self.select(12, 5),
+            Opcode::JCXZ | Opcode::LOOP | Opcode::LOOPZ => self.select(12, 4),
+            Opcode::LOOPNZ => self.select(14, 4),
+            Opcode::INTO => self.select(49, 21)+self.transfer_cycles(true, 5),
+            _ => 0
+        }
+    }
+
+    pub fn instruction_cycles(&self, instruction: &Instruction, count: u64) -> u64 {
+        let form = form(instruction);
+        let memory = form == Form::Memory || form == Form::RegisterMemory || form == Form::MemoryImmediate;
+        let wide = instruction.data_width > 8;
+        let (cycles, transfers) = match instruction.opcode {
+            Opcode::ADD | Opcode::OR | Opcode::ADC | Opcode::SBB | Opcode::AND | Opcode::SUB | Opcode::XOR => match form {
+                Form::Register => (self.select(3, 2), 0),
+                Form::RegisterMemory => (self.select(9, 7), 1),
+                Form::Memory => (self.select(16, 7), 2),
+                Form::RegisterImmediate => (self.select(4, 3), 0),
+                Form::MemoryImmediate => (self.select(17, 7), 2)
+            },
+            Opcode::CMP => match form {
+                Form::Register => (self.select(3, 2), 0),
+                Form::RegisterMemory => (self.select(9, 6), 1),
+                Form::Memory => (self.select(9, 7), 1),
+                Form::RegisterImmediate => (self.select(4, 3), 0),
+                Form::MemoryImmediate => (self.select(10, 6), 1)
+            },
+            Opcode::TEST => match form {
+                Form::Register => (self.select(3, 2), 0),
+                Form::RegisterMemory | Form::Memory => (self.select(9, 6), 1),
+                Form::RegisterImmediate => (self.select(5, 3), 0),
+                Form::MemoryImmediate => (self.select(11, 6), 1)
+            },
+            Opcode::MOV => match form {
+                _ if instruction.first_operand as u8 >= Operand::CR0 as u8 && instruction.first_operand as u8 <= Operand::CR3 as u8 => (10, 0),
+                _ if instruction.second_operand as u8 >= Operand::CR0 as u8 && instruction.second_operand as u8 <= Operand::CR3 as u8 => (6, 0),
+                Form::Register => (2, 0),
+                Form::RegisterMemory => (self.select(8, 5), 1),
+                Form::Memory => (self.select(9, 3), 1),
+                Form::RegisterImmediate => (self.select(4, 2), 0),
+                Form::MemoryImmediate => (self.select(10, 3), 1)
+            },
+            Opcode::XCHG => match form {
+                Form::Register if instruction.second_operand == Operand::AX || instruction.second_operand == Operand::EAX => (3, 0),
+                Form::Register => (self.select(4, 3), 0),
+                _ => (self.select(17, 5), 2)
+            },
+            Opcode::INC | Opcode::DEC => if memory { (self.select(15, 7), 2) } else if wide { (2, 0) } else { (self.select(3, 2), 0) },
+            Opcode::NOT | Opcode::NEG => if memory { (self.select(16, 7), 2) } else { (self.select(3, 2), 0) },
+            Opcode::IMUL if instruction.second_operand != Operand::None => (self.select(22, 21)+memory as u64*3, memory as u64), // IMUL r, r/m, imm
+            Opcode::MUL | Opcode::IMUL | Opcode::DIV | Opcode::IDIV => (match (instruction.opcode, wide) {
+                (Opcode::MUL, false) => self.select(74, 13),
+                (Opcode::MUL, true) => self.select(124, 21),
+                (Opcode::IMUL, false) => self.select(86, 13),
+                (Opcode::IMUL, true) => self.select(138, 21),
+                (Opcode::DIV, false) => self.select(85, 14),
+                (Opcode::DIV, true) => self.select(153, 22),
+                (Opcode::IDIV, false) => self.select(107, 17),
+                _ => self.select(175, 25)
+            }+memory as u64*self.select(6, 3), memory as u64),
+            Opcode::ROL | Opcode::ROR | Opcode::RCL | Opcode::RCR | Opcode::SHL | Opcode::SHR | Opcode::SAL | Opcode::SAR => {
+                let count = if self.profile == TimingProfile::I80286 { count&0x1F } else { count };
+                match (instruction.second_operand, instruction.immediate) {
+                    (Operand::None, 1) => if memory { (self.select(15, 7), 2) } else { (2, 0) },
+                    (Operand::CL, _) => if memory { (self.select(20+4*count, 8+count), 2) } else { (self.select(8+4*count, 5+count), 0) },
+                    _ => if memory { (self.select(17+count, 8+count), 2) } else { (5+count, 0) }
+                }
+            },
+            Opcode::LEA => (self.select(2, 3), 0),
+            Opcode::LES | Opcode::LDS | Opcode::LSS | Opcode::LFS | Opcode::LGS => (self.select(16, 7), 2),
+            Opcode::PUSH => match instruction.first_operand {
+                Operand::None => (self.select(10, 3), 1),
+                Operand::ES | Operand::CS | Operand::SS | Operand::DS | Operand::FS | Operand::GS => (self.select(10, 3), 1),
+                operand if is_memory(operand) => (self.select(16, 5), 2),
+                _ => (self.select(11, 3), 1)
+            },
+            Opcode::POP => if memory { (self.select(17, 5), 2) } else { (self.select(8, 5), 1) },
+            Opcode::PUSHA => (self.select(36, 17), 8),
+            Opcode::POPA => (self.select(51, 19), 8),
+            Opcode::PUSHF => (self.select(10, 3), 1),
+            Opcode::POPF => (self.select(8, 5), 1),
+            Opcode::ENTER => match count {
+                0 => (self.select(15, 11), 1),
+                1 => (self.select(25, 15), 2),
+                _ => (self.select(22+16*(count-1), 12+4*(count-1)), count+1)
+            },
+            Opcode::LEAVE => (self.select(8, 5), 1),
+            Opcode::BOUND => (self.select(35, 13), 2),
+            Opcode::CBW | Opcode::CLC | Opcode::STC | Opcode::CMC | Opcode::CLI | Opcode::STI | Opcode::CLD | Opcode::STD | Opcode::HLT => (2, 0),
+            Opcode::CWD => (self.select(5, 2), 0),
+            Opcode::LAHF | Opcode::SAHF => (self.select(4, 2), 0),
+            Opcode::NOP | Opcode::WAIT | Opcode::SALC => (3, 0),
+            Opcode::LOCK => (self.select(2, 0), 0),
+            Opcode::XLAT => (self.select(11, 5), 1),
+            Opcode::DAA | Opcode::DAS => (self.select(4, 3), 0),
+            Opcode::AAA | Opcode::AAS => (self.select(8, 3), 0),
+            Opcode::AAM => (self.select(83, 16), 0),
+            Opcode::AAD => (self.select(60, 14), 0),
+            Opcode::IN => (if instruction.second_operand == Operand::DX { self.select(8, 5) } else { self.select(10, 5) }, 1),
+            Opcode::OUT => (if instruction.first_operand == Operand::DX { self.select(8, 3) } else { self.select(10, 3) }, 1),
+            Opcode::JO | Opcode::JNO | Opcode::JB | Opcode::JNB | Opcode::JE | Opcode::JNE | Opcode::JBE | Opcode::JNBE |
+            Opcode::JS | Opcode::JNS | Opcode::JP | Opcode::JNP | Opcode::JL | Opcode::JNL | Opcode::JLE | Opcode::JNLE => (self.select(4, 3), 0),
+            Opcode::JCXZ | Opcode::LOOPZ => (self.select(6, 4), 0),
+            Opcode::LOOP | Opcode::LOOPNZ => (self.select(5, 4), 0),
+            Opcode::JMP => match instruction.first_operand {
+                Operand::None => (self.select(15, 7), 0),
+                operand if is_memory(operand) => (self.select(18, 11), 1),
+                _ => (self.select(11, 7), 0)
+            },
+            Opcode::CALL => match instruction.first_operand {
+                Operand::None => (self.select(19, 7), 1),
+                operand if is_memory(operand) => (self.select(21, 11), 2),
+                _ => (self.select(16, 7), 1)
+            },
+            Opcode::LJMP => if memory { (self.select(24, 15), 2) } else { (self.select(15, 11), 0) },
+            Opcode::LCALL => if memory { (self.select(37, 16), 4) } else { (self.select(28, 13), 2) },
+            Opcode::RET => (if instruction.immediate != 0xFFFFFFFF { self.select(20, 11) } else { self.select(16, 11) }, 1),
+            Opcode::RETF => (if instruction.immediate != 0xFFFFFFFF { self.select(25, 15) } else { self.select(26, 15) }, 2),
+            Opcode::IRET => (self.select(24, 17), 3),
+            Opcode::INT => (self.select(51, 23), 5),
+            Opcode::INTO => (self.select(4, 3), 0),
+            Opcode::MOVSB | Opcode::MOVSW | Opcode::CMPSB | Opcode::CMPSW | Opcode::SCASB | Opcode::SCASW |
+            Opcode::LODSB | Opcode::LODSW | Opcode::STOSB | Opcode::STOSW | Opcode::INSB | Opcode::INSW | Opcode::OUTSB | Opcode::OUTSW => {
+                let (single, iteration, transfers) = self.string_cycles(instruction.opcode);
+                let cycles = if instruction.prefix != Opcode::BAD { self.select(9, 5)+iteration } else { single };
+                return cycles+self.transfer_cycles(wide, transfers);
+            },
+            Opcode::ESC => (fpu_cycles(instruction.fpu_opcode), if memory { 1 } else { 0 }),
+            Opcode::SETO | Opcode::SETNO | Opcode::SETB | Opcode::SETNB | Opcode::SETE | Opcode::SETNE | Opcode::SETBE | Opcode::SETNBE |
+            Opcode::SETS | Opcode::SETNS | Opcode::SETP | Opcode::SETNP | Opcode::SETL | Opcode::SETNL | Opcode::SETLE | Opcode::SETNLE => if memory { (5, 1) } else { (4, 0) },
+            Opcode::MOVZX | Opcode::MOVSX | Opcode::MOVZXW | Opcode::MOVSXW => if memory { (6, 1) } else { (3, 0) },
+            Opcode::BT => if memory { (12, 1) } else { (3, 0) },
+            Opcode::BTS | Opcode::BTR | Opcode::BTC => if memory { (13, 2) } else { (6, 0) },
+            Opcode::BSF | Opcode::BSR => (20, if memory { 1 } else { 0 }),
+            Opcode::SHLD | Opcode::SHRD => if memory { (7, 2) } else { (3, 0) },
+            Opcode::SLDT | Opcode::STR | Opcode::SMSW | Opcode::CLTS => (2, 0),
+            Opcode::LLDT | Opcode::LTR => (17, 1),
+            Opcode::VERR | Opcode::VERW | Opcode::LAR | Opcode::LSL => (14, 1),
+            Opcode::SGDT | Opcode::SIDT | Opcode::LGDT | Opcode::LIDT => (11, 3),
+            Opcode::LMSW => (3, 0),
+            Opcode::ARPL => (10, if memory { 2 } else { 0 }),
+            _ => (2, 0)
+        };
+        let mut cycles = cycles+self.transfer_cycles(wide, transfers);
+        if memory {
+            cycles += self.effective_address_cycles(instruction);
+        }
+        if instruction.segment_override != Operand::None {
+            cycles += self.select(2, 0);
+        }
+        cycles
+    }
+}
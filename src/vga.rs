@@ -363,6 +363,17 @@ impl VideoGraphicsArray {
         value
     }
 
+    /// Reads a byte of text VRAM from the first plane, bypassing the graphics controller
+    pub fn read_text_byte(&self, address: usize) -> u8 {
+        self.vram[address*4]
+    }
+
+    /// Writes a byte of text VRAM to all planes, bypassing the graphics controller which may not be set up yet
+    pub fn write_text_byte(&mut self, address: usize, value: u8) {
+        crate::bit_utils::write_to_buffer_u32(&mut self.vram[address*4..], VideoGraphicsArray::replicate_8(value));
+        self.vram_dirty = true;
+    }
+
     pub fn write_to_memory(&mut self, _cycle_counter: u64, address: usize, value: u8) {
         let mut result: u32;
         match self.read_write_mode&0x03 {
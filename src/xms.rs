@@ -0,0 +1,297 @@
+use crate::machinecode::Operand;
+
+pub const ENTRY_POINT: u16 = 0xF8B0; // Far called at 0xF000:ENTRY_POINT, see CPU::long_jump
+const EXTENDED_MEMORY_BEGIN: usize = 0x110000; // Behind the HMA
+const MAX_HANDLE_COUNT: usize = 64;
+
+#[derive(Copy, Clone)]
+struct ExtendedMemoryBlock {
+    address: usize,
+    size: usize, // KiB
+    lock_count: u8
+}
+
+#[derive(Copy, Clone)]
+struct UpperMemoryBlock {
+    segment: u16,
+    paragraphs: u16,
+    allocated: bool
+}
+
+#[repr(C, packed)]
+struct MoveParameters {
+    length: u32,
+    source_handle: u16,
+    source_offset: u32,
+    destination_handle: u16,
+    destination_offset: u32
+}
+
+pub struct ExtendedMemoryManager {
+    memory_end: usize,
+    hma_allocated: bool,
+    a20_global_enabled: bool,
+    a20_local_count: u16,
+    handles: Vec<Option<ExtendedMemoryBlock>>,
+    upper_memory_blocks: Vec<UpperMemoryBlock>
+}
+
+impl ExtendedMemoryManager {
+    pub fn new() -> Self {
+        Self {
+            memory_end: 0,
+            hma_allocated: false,
+            a20_global_enabled: false,
+            a20_local_count: 0,
+            handles: Vec::new(),
+            upper_memory_blocks: Vec::new()
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.memory_end > 0
+    }
+
+    pub fn install(&mut self, memory_end: usize, upper_memory_blocks: &[(usize, usize)]) {
+        self.memory_end = memory_end;
+        self.handles = vec![None; MAX_HANDLE_COUNT];
+        self.upper_memory_blocks = upper_memory_blocks.iter().map(|(begin, end)| UpperMemoryBlock {
+            segment: (begin>>4) as u16,
+            paragraphs: ((end-begin)>>4) as u16,
+            allocated: false
+        }).collect();
+    }
+
+    fn hma_exists(&self) -> bool {
+        self.memory_end >= EXTENDED_MEMORY_BEGIN
+    }
+
+    /// Free ranges of extended memory (above the HMA) as (address, KiB)
+    fn free_ranges(&self, ignored_handle: Option<usize>) -> Vec<(usize, usize)> {
+        let mut used: Vec<(usize, usize)> = self.handles.iter().enumerate()
+            .filter(|(index, _block)| Some(*index) != ignored_handle)
+            .filter_map(|(_index, block)| block.map(|block| (block.address, block.address+(block.size<<10))))
+            .collect();
+        used.sort();
+        let mut free_ranges = Vec::new();
+        let mut address = EXTENDED_MEMORY_BEGIN;
+        for (begin, end) in used.iter().chain(std::iter::once(&(self.memory_end, self.memory_end))) {
+            if *begin > address {
+                free_ranges.push((address, (begin-address)>>10));
+            }
+            address = address.max(*end);
+        }
+        free_ranges
+    }
+
+    fn find_free_range(&self, size: usize, ignored_handle: Option<usize>) -> Option<usize> {
+        self.free_ranges(ignored_handle).iter().find(|(_address, free_size)| *free_size >= size).map(|(address, _free_size)| *address)
+    }
+
+    fn set_a20(&self, cpu: &mut crate::cpu::CPU, ps2_controller: &mut crate::ps2_controller::PS2Controller) {
+        ps2_controller.set_a20_enabled(cpu.cycle_counter, self.a20_global_enabled || self.a20_local_count > 0);
+    }
+
+    fn get_block(&self, handle: u16) -> Result<(usize, ExtendedMemoryBlock), u8> {
+        match self.handles.get((handle as usize).wrapping_sub(1)) {
+            Some(Some(block)) => Ok((handle as usize-1, *block)),
+            _ => Err(0xA2) // Invalid handle
+        }
+    }
+
+    fn linear_address(&self, handle: u16, offset: u32, length: usize, handle_error: u8, offset_error: u8) -> Result<usize, u8> {
+        if handle == 0 {
+            let address = crate::bus::BUS::physical_address((offset>>16) as u16, offset as u16);
+            return if address+length <= EXTENDED_MEMORY_BEGIN { Ok(address) } else { Err(offset_error) };
+        }
+        let (_index, block) = self.get_block(handle).map_err(|_error| handle_error)?;
+        if offset as usize+length > block.size<<10 {
+            return Err(offset_error);
+        }
+        Ok(block.address+offset as usize)
+    }
+
+    fn handle_function(&mut self, cpu: &mut crate::cpu::CPU, bus: &mut crate::bus::BUS) -> Result<(), u8> {
+        let function = cpu.get_register(Operand::AH);
+        let handle = cpu.get_register(Operand::DX);
+        let is_80386 = cpu.model >= crate::machinecode::CpuModel::I80386;
+        match function {
+            0x00 => { // Get Version
+                cpu.set_register(Operand::AX, 0x0300);
+                cpu.set_register(Operand::BX, 0x0001);
+                cpu.set_register(Operand::DX, self.hma_exists() as u16);
+                return Ok(());
+            },
+            0x01 => { // Request HMA
+                if !self.hma_exists() {
+                    return Err(0x90); // HMA does not exist
+                } else if self.hma_allocated {
+                    return Err(0x91); // HMA already in use
+                }
+                self.hma_allocated = true;
+            },
+            0x02 => { // Release HMA
+                if !self.hma_exists() {
+                    return Err(0x90); // HMA does not exist
+                } else if !self.hma_allocated {
+                    return Err(0x93); // HMA not allocated
+                }
+                self.hma_allocated = false;
+            },
+            0x03 | 0x04 => { // Global Enable / Disable A20
+                self.a20_global_enabled = function == 0x03;
+                self.set_a20(cpu, &mut bus.ps2_controller);
+            },
+            0x05 => { // Local Enable A20
+                self.a20_local_count = self.a20_local_count.saturating_add(1);
+                self.set_a20(cpu, &mut bus.ps2_controller);
+            },
+            0x06 => { // Local Disable A20
+                if self.a20_local_count == 0 {
+                    return Err(0x94); // A20 still enabled
+                }
+                self.a20_local_count -= 1;
+                self.set_a20(cpu, &mut bus.ps2_controller);
+            },
+            0x07 => { // Query A20
+                cpu.set_register(Operand::AX, bus.ps2_controller.is_a20_enabled() as u16);
+                cpu.set_register(Operand::BL, 0x00);
+                return Ok(());
+            },
+            0x08 | 0x88 if function == 0x08 || is_80386 => { // Query Free Extended Memory
+                let free_ranges = self.free_ranges(None);
+                let largest = free_ranges.iter().map(|(_address, size)| *size).max().unwrap_or(0);
+                let total = free_ranges.iter().map(|(_address, size)| *size).sum::<usize>();
+                if function == 0x88 {
+                    cpu.set_register_32(Operand::EAX, largest as u32);
+                    cpu.set_register_32(Operand::EDX, total as u32);
+                    cpu.set_register_32(Operand::ECX, self.memory_end as u32-1);
+                } else {
+                    cpu.set_register(Operand::AX, largest.min(0xFFFF) as u16);
+                    cpu.set_register(Operand::DX, total.min(0xFFFF) as u16);
+                }
+                cpu.set_register(Operand::BL, if total == 0 { 0xA0 } else { 0x00 }); // All extended memory allocated
+                return Ok(());
+            },
+            0x09 | 0x89 if function == 0x09 || is_80386 => { // Allocate Extended Memory Block
+                let size = if function == 0x89 { cpu.get_register_32(Operand::EDX) as usize } else { handle as usize };
+                let index = self.handles.iter().position(|block| block.is_none()).ok_or(0xA1u8)?; // All handles in use
+                let address = self.find_free_range(size, None).ok_or(0xA0u8)?; // All extended memory allocated
+                self.handles[index] = Some(ExtendedMemoryBlock { address: address, size: size, lock_count: 0 });
+                cpu.set_register(Operand::DX, index as u16+1);
+                println!("XMS ({}): Allocate Extended Memory Block handle={} address={:06X} size={}KiB", cpu.cycle_counter, index+1, address, size);
+            },
+            0x0A => { // Free Extended Memory Block
+                let (index, block) = self.get_block(handle)?;
+                if block.lock_count > 0 {
+                    return Err(0xAB); // Block is locked
+                }
+                self.handles[index] = None;
+                println!("XMS ({}): Free Extended Memory Block handle={}", cpu.cycle_counter, handle);
+            },
+            0x0B => { // Move Extended Memory Block
+                let address = crate::bus::BUS::physical_address(cpu.get_register(Operand::DS), cpu.get_register(Operand::SI));
+                let parameters = unsafe { std::ptr::read_unaligned(&bus.ram[address] as *const u8 as *const MoveParameters) };
+                let length = parameters.length as usize;
+                if length&1 != 0 {
+                    return Err(0xA7); // Invalid length
+                }
+                let source = self.linear_address(parameters.source_handle, parameters.source_offset, length, 0xA3, 0xA4)?;
+                let destination = self.linear_address(parameters.destination_handle, parameters.destination_offset, length, 0xA5, 0xA6)?;
+                if parameters.source_handle == 0 || parameters.destination_handle == 0 {
+                    // Conventional memory may be mapped to VRAM or the EMS page frame
+                    for index in 0..length {
+                        let src = bus.get_memory(cpu, source+index);
+                        let value = bus.read_from_memory(cpu, src, 8);
+                        let dst = bus.get_memory(cpu, destination+index);
+                        bus.write_to_memory(cpu, dst, 8, value);
+                    }
+                } else {
+                    bus.ram.copy_within(source..source+length, destination);
+                    cpu.instruction_cache.invalidate(destination, length);
+                }
+            },
+            0x0C => { // Lock Extended Memory Block
+                let (index, block) = self.get_block(handle)?;
+                if block.lock_count == 0xFF {
+                    return Err(0xAC); // Lock count overflow
+                }
+                self.handles[index].as_mut().unwrap().lock_count += 1;
+                cpu.set_register(Operand::DX, (block.address>>16) as u16);
+                cpu.set_register(Operand::BX, block.address as u16);
+            },
+            0x0D => { // Unlock Extended Memory Block
+                let (index, block) = self.get_block(handle)?;
+                if block.lock_count == 0 {
+                    return Err(0xAA); // Block is not locked
+                }
+                self.handles[index].as_mut().unwrap().lock_count -= 1;
+            },
+            0x0E | 0x8E if function == 0x0E || is_80386 => { // Get Handle Information
+                let (_index, block) = self.get_block(handle)?;
+                let free_handles = self.handles.iter().filter(|block| block.is_none()).count() as u16;
+                cpu.set_register(Operand::BH, block.lock_count as u16);
+                if function == 0x8E {
+                    cpu.set_register(Operand::CX, free_handles);
+                    cpu.set_register_32(Operand::EDX, block.size as u32);
+                } else {
+                    cpu.set_register(Operand::BL, free_handles.min(0xFF));
+                    cpu.set_register(Operand::DX, block.size.min(0xFFFF) as u16);
+                }
+            },
+            0x0F | 0x8F if function == 0x0F || is_80386 => { // Reallocate Extended Memory Block
+                let (index, block) = self.get_block(handle)?;
+                let size = if function == 0x8F { cpu.get_register_32(Operand::EBX) as usize } else { cpu.get_register(Operand::BX) as usize };
+                if block.lock_count > 0 {
+                    return Err(0xAB); // Block is locked
+                }
+                let address = if size <= block.size || self.free_ranges(None).iter().any(|(address, free_size)| *address == block.address+(block.size<<10) && block.size+free_size >= size) {
+                    block.address
+                } else {
+                    let address = self.find_free_range(size, Some(index)).ok_or(0xA0u8)?; // All extended memory allocated
+                    bus.ram.copy_within(block.address..block.address+(block.size<<10), address);
+                    address
+                };
+                self.handles[index] = Some(ExtendedMemoryBlock { address: address, size: size, lock_count: 0 });
+                println!("XMS ({}): Reallocate Extended Memory Block handle={} address={:06X} size={}KiB", cpu.cycle_counter, handle, address, size);
+            },
+            0x10 => { // Request Upper Memory Block
+                let largest = self.upper_memory_blocks.iter().filter(|block| !block.allocated).map(|block| block.paragraphs).max();
+                match self.upper_memory_blocks.iter_mut().find(|block| !block.allocated && block.paragraphs >= handle) {
+                    Some(block) => {
+                        block.allocated = true;
+                        cpu.set_register(Operand::BX, block.segment);
+                        cpu.set_register(Operand::DX, block.paragraphs);
+                        println!("XMS ({}): Request Upper Memory Block segment={:04X} paragraphs={:04X}", cpu.cycle_counter, block.segment, block.paragraphs);
+                    },
+                    None => {
+                        cpu.set_register(Operand::DX, largest.unwrap_or(0));
+                        return Err(if largest.is_some() { 0xB0 } else { 0xB1 }); // Smaller UMB available / No UMBs available
+                    }
+                }
+            },
+            0x11 => { // Release Upper Memory Block
+                match self.upper_memory_blocks.iter_mut().find(|block| block.allocated && block.segment == handle) {
+                    Some(block) => { block.allocated = false; },
+                    None => { return Err(0xB2); } // Invalid UMB segment
+                }
+            },
+            _ => {
+                println!("XMS ({}): Unsupported function={:02X}", cpu.cycle_counter, function);
+                return Err(0x80); // Function not implemented
+            }
+        }
+        cpu.set_register(Operand::AX, 0x0001);
+        Ok(())
+    }
+
+    /// Called with the manager taken out of the bus, so moves can go through its memory mapping
+    pub fn handle_call(&mut self, cpu: &mut crate::cpu::CPU, bus: &mut crate::bus::BUS) {
+        let function = cpu.get_register(Operand::AH);
+        if let Err(error) = self.handle_function(cpu, bus) {
+            println!("XMS ({}): function={:02X} error={:02X}", cpu.cycle_counter, function, error);
+            cpu.set_register(Operand::AX, 0x0000);
+            cpu.set_register(Operand::BL, error as u16);
+        }
+    }
+}
@@ -0,0 +1,600 @@
+#!/usr/bin/env python3
+"""Generates the single step regression fixtures from a Python model of the instructions, separate from the emulator.
+
+The model is written for this project and not verified against hardware, so the fixtures only catch regressions.
+
+The vectors use the format of the published single step tests, with a directory per CPU model.
+Run it from anywhere to rewrite the fixtures next to this file: python3 generate.py
+"""
+import json, os, random, sys
+
+OUT = sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(os.path.abspath(__file__))
+CF, PF, AF, ZF, SF, TF, IF, DF, OF = 0, 2, 4, 6, 7, 8, 9, 10, 11
+REGS16 = ['ax', 'cx', 'dx', 'bx', 'sp', 'bp', 'si', 'di']
+REGS8 = ['al', 'cl', 'dl', 'bl', 'ah', 'ch', 'dh', 'bh']
+REGS32 = ['eax', 'ecx', 'edx', 'ebx', 'esp', 'ebp', 'esi', 'edi']
+SEGS = ['es', 'cs', 'ss', 'ds']
+
+def parity(value):
+    return bin(value & 0xFF).count('1') % 2 == 0
+
+class State:
+    def __init__(self, model, rng):
+        self.model = model
+        self.r32 = {name: rng.randrange(0x10000) | (rng.randrange(0x10000) << 16 if model == '80386' else 0) for name in REGS32}
+        self.r32['esp'] = (self.r32['esp'] & 0xFFFF0000) | 0x0100 if model == '80386' else 0x0100
+        self.seg = {'cs': 0x1000, 'ds': 0x3000, 'es': 0x4000, 'ss': 0x2000}
+        self.ip = 0x0100
+        self.flags = 0xF002 if model == '8086' else 0x0002
+        self.ram = {}
+    def copy(self):
+        other = State.__new__(State)
+        other.model = self.model
+        other.r32 = dict(self.r32)
+        other.seg = dict(self.seg)
+        other.ip = self.ip
+        other.flags = self.flags
+        other.ram = dict(self.ram)
+        return other
+    def get16(self, index): return self.r32[REGS32[index]] & 0xFFFF
+    def set16(self, index, value): self.r32[REGS32[index]] = (self.r32[REGS32[index]] & 0xFFFF0000) | (value & 0xFFFF)
+    def get32(self, index): return self.r32[REGS32[index]]
+    def set32(self, index, value): self.r32[REGS32[index]] = value & 0xFFFFFFFF
+    def get8(self, index):
+        value = self.get16(index & 3)
+        return (value >> 8) & 0xFF if index >= 4 else value & 0xFF
+    def set8(self, index, value):
+        old = self.get16(index & 3)
+        self.set16(index & 3, (old & 0x00FF) | ((value & 0xFF) << 8) if index >= 4 else (old & 0xFF00) | (value & 0xFF))
+    def get(self, index, width): return {8: self.get8, 16: self.get16, 32: self.get32}[width](index)
+    def set(self, index, width, value): {8: self.set8, 16: self.set16, 32: self.set32}[width](index, value)
+    def flag(self, bit): return (self.flags >> bit) & 1
+    def setflag(self, bit, value): self.flags = (self.flags & ~(1 << bit)) | ((1 if value else 0) << bit)
+    def address(self, segment, offset): return ((self.seg[segment] << 4) + (offset & 0xFFFF)) & 0xFFFFF
+    def read(self, segment, offset, width):
+        return sum(self.ram.get(self.address(segment, offset + i), 0) << (8 * i) for i in range(width // 8))
+    def write(self, segment, offset, width, value):
+        for i in range(width // 8):
+            self.ram[self.address(segment, offset + i)] = (value >> (8 * i)) & 0xFF
+    def push(self, width, value):
+        self.set16(4, self.get16(4) - width // 8)
+        self.write('ss', self.get16(4), width, value)
+    def pop(self, width):
+        value = self.read('ss', self.get16(4), width)
+        self.set16(4, self.get16(4) + width // 8)
+        return value
+    def regs(self):
+        regs = {name: self.r32[name32] & 0xFFFF for name, name32 in zip(REGS16, REGS32)}
+        if self.model == '80386':
+            regs.update(self.r32)
+        regs.update(self.seg)
+        regs['ip'] = self.ip
+        regs['flags'] = self.flags
+        return regs
+
+def result_flags(state, width, result):
+    mask = (1 << width) - 1
+    state.setflag(ZF, result & mask == 0)
+    state.setflag(SF, (result >> (width - 1)) & 1)
+    state.setflag(PF, parity(result))
+
+def alu(state, operation, width, a, b):
+    mask = (1 << width) - 1
+    sign = 1 << (width - 1)
+    carry = state.flag(CF)
+    if operation in ('add', 'adc'):
+        c = carry if operation == 'adc' else 0
+        result = a + b + c
+        state.setflag(CF, result > mask)
+        state.setflag(AF, ((a & 0xF) + (b & 0xF) + c) > 0xF)
+        state.setflag(OF, (~(a ^ b) & (a ^ result)) & sign)
+    elif operation in ('sub', 'sbb', 'cmp'):
+        c = carry if operation == 'sbb' else 0
+        result = a - b - c
+        state.setflag(CF, result < 0)
+        state.setflag(AF, ((a & 0xF) - (b & 0xF) - c) < 0)
+        state.setflag(OF, ((a ^ b) & (a ^ result)) & sign)
+    else:
+        result = {'and': a & b, 'or': a | b, 'xor': a ^ b}[operation]
+        state.setflag(CF, 0)
+        state.setflag(OF, 0)
+        state.setflag(AF, 0)
+    result &= mask
+    result_flags(state, width, result)
+    return result
+
+class Case:
+    def __init__(self, model, rng):
+        self.initial = State(model, rng)
+        self.code = []
+    def emit(self, *data):
+        self.code.extend(data)
+
+def make(model, name, code, setup, execute, rng):
+    initial = State(model, rng)
+    setup(initial)
+    for i, byte in enumerate(code):
+        initial.ram[initial.address('cs', initial.ip + i)] = byte
+    final = initial.copy()
+    final.ip = (initial.ip + len(code)) & 0xFFFF
+    execute(initial, final)
+    for address in list(initial.ram):
+        final.ram.setdefault(address, initial.ram[address])
+    initial_regs, final_regs = initial.regs(), final.regs()
+    return {
+        'name': name,
+        'bytes': code,
+        'initial': {'regs': initial_regs, 'ram': sorted([address, value] for address, value in initial.ram.items())},
+        'final': {'regs': {key: value for key, value in final_regs.items() if initial_regs[key] != value},
+                  'ram': sorted([address, value] for address, value in final.ram.items())}
+    }
+
+def interrupt(final, vector, return_ip):
+    """Real mode interrupt or exception through the IVT"""
+    final.push(16, final.flags)
+    final.push(16, final.seg['cs'])
+    final.push(16, return_ip)
+    final.setflag(IF, 0)
+    final.setflag(TF, 0)
+    final.ip = final.ram.get(vector * 4, 0) | (final.ram.get(vector * 4 + 1, 0) << 8)
+    final.seg['cs'] = final.ram.get(vector * 4 + 2, 0) | (final.ram.get(vector * 4 + 3, 0) << 8)
+
+def setup_vector(state, vector):
+    for i, byte in enumerate([0x00, 0x05, 0x00, 0x05]): # 0500:0500
+        state.ram[vector * 4 + i] = byte
+
+tests = {}
+metadata = {}
+def add(model, opcode, case, mask=None):
+    tests.setdefault((model, opcode), []).append(case)
+    if mask is not None:
+        metadata.setdefault(model, {})[opcode] = mask
+
+ALU = [('add', 0x00), ('or', 0x08), ('adc', 0x10), ('sbb', 0x18), ('and', 0x20), ('sub', 0x28), ('xor', 0x30), ('cmp', 0x38)]
+ARITH_MASK = 0x08D5
+LOGIC_MASK = 0x08C5 # AF is undefined
+
+def alu_cases(model, rng):
+    for operation, base in ALU:
+        for width, opcode in ((8, base), (16, base + 1)):
+            for n in range(4):
+                memory = n >= 2
+                reg, rm = rng.randrange(8), rng.randrange(8)
+                if width == 16 and not memory:
+                    while rm == 4 or reg == 4:
+                        reg, rm = rng.randrange(8), rng.randrange(8)
+                a_value, b_value = rng.randrange(1 << width), rng.randrange(1 << width)
+                if n == 1:
+                    b_value = a_value # Zero results and equal operands
+                carry_in = rng.randrange(2)
+                displacement = rng.randrange(0x100, 0x8000)
+                if memory:
+                    code = [opcode, 0x06 | (reg << 3), displacement & 0xFF, displacement >> 8]
+                else:
+                    code = [opcode, 0xC0 | (reg << 3) | rm]
+                def setup(state):
+                    state.setflag(CF, carry_in)
+                    if memory:
+                        state.write('ds', displacement, width, a_value)
+                    else:
+                        state.set(rm, width, a_value)
+                    if memory or reg != rm:
+                        state.set(reg, width, b_value)
+                def execute(initial, final):
+                    a = final.read('ds', displacement, width) if memory else final.get(rm, width)
+                    b = final.get(reg, width)
+                    result = alu(final, operation, width, a, b)
+                    if operation != 'cmp':
+                        if memory:
+                            final.write('ds', displacement, width, result)
+                        else:
+                            final.set(rm, width, result)
+                add(model, '%02X' % opcode, make(model, '%s %s' % (operation, 'mem' if memory else 'reg'), code, setup, execute, rng),
+                    {'flags-mask': LOGIC_MASK if operation in ('and', 'or', 'xor') else ARITH_MASK})
+        # Accumulator with immediate
+        for n in range(3):
+            value, immediate = rng.randrange(0x10000), rng.randrange(0x10000)
+            carry_in = rng.randrange(2)
+            code = [base + 5, immediate & 0xFF, immediate >> 8]
+            def setup(state):
+                state.set16(0, value)
+                state.setflag(CF, carry_in)
+            def execute(initial, final):
+                result = alu(final, operation, 16, final.get16(0), immediate)
+                if operation != 'cmp':
+                    final.set16(0, result)
+            add(model, '%02X' % (base + 5), make(model, '%s ax,imm' % operation, code, setup, execute, rng),
+                {'flags-mask': LOGIC_MASK if operation in ('and', 'or', 'xor') else ARITH_MASK})
+
+def inc_dec_cases(model, rng):
+    for opcode in (0x40, 0x43, 0x48, 0x4E):
+        index = opcode & 7
+        for value in (0x7FFF, 0xFFFF, 0x000F, rng.randrange(0x10000)):
+            def setup(state):
+                state.set16(index, value)
+                state.setflag(CF, 1)
+            def execute(initial, final):
+                a = final.get16(index)
+                carry = final.flag(CF)
+                result = alu(final, 'add' if opcode < 0x48 else 'sub', 16, a, 1)
+                final.setflag(CF, carry)
+                final.set16(index, result)
+            add(model, '%02X' % opcode, make(model, 'inc/dec', [opcode], setup, execute, rng), {'flags-mask': ARITH_MASK})
+
+def stack_cases(model, rng):
+    for opcode in (0x50, 0x53, 0x56, 0x58, 0x5B, 0x5F):
+        index = opcode & 7
+        for n in range(3):
+            stack_value = rng.randrange(0x10000)
+            def setup(state):
+                state.write('ss', state.get16(4), 16, stack_value)
+            def execute(initial, final):
+                if opcode < 0x58:
+                    final.push(16, final.get16(index))
+                else:
+                    final.set16(index, final.pop(16))
+            add(model, '%02X' % opcode, make(model, 'push/pop', [opcode], setup, execute, rng))
+
+def jump_cases(model, rng):
+    conditions = {0x74: lambda s: s.flag(ZF), 0x75: lambda s: not s.flag(ZF), 0x7C: lambda s: s.flag(SF) != s.flag(OF), 0x72: lambda s: s.flag(CF)}
+    for opcode, condition in conditions.items():
+        for n in range(4):
+            flags = rng.randrange(0x1000) & 0x08D5
+            displacement = rng.randrange(0x100)
+            def setup(state):
+                state.flags = (state.flags & ~0x08D5) | flags
+            def execute(initial, final):
+                if condition(final):
+                    final.ip = (final.ip + (displacement - 0x100 if displacement >= 0x80 else displacement)) & 0xFFFF
+            add(model, '%02X' % opcode, make(model, 'jcc', [opcode, displacement], setup, execute, rng))
+    for n in range(3):
+        displacement = rng.randrange(0x10000)
+        def execute(initial, final):
+            final.push(16, final.ip)
+            final.ip = (final.ip + displacement) & 0xFFFF
+        add(model, 'E8', make(model, 'call', [0xE8, displacement & 0xFF, displacement >> 8], lambda state: None, execute, rng))
+        target = rng.randrange(0x10000)
+        def setup(state, target=target):
+            state.write('ss', state.get16(4), 16, target)
+        def execute(initial, final):
+            final.ip = final.pop(16)
+        add(model, 'C3', make(model, 'ret', [0xC3], setup, execute, rng))
+
+def move_cases(model, rng):
+    for n in range(4):
+        reg, displacement, value = rng.randrange(8), rng.randrange(0x100, 0x8000), rng.randrange(0x10000)
+        if reg == 4:
+            reg = 0
+        def setup(state):
+            state.set16(reg, value)
+        def execute(initial, final):
+            final.write('ds', displacement, 16, final.get16(reg))
+        add(model, '89', make(model, 'mov mem,reg', [0x89, 0x06 | (reg << 3), displacement & 0xFF, displacement >> 8], setup, execute, rng))
+        def setup(state):
+            state.write('ds', displacement, 16, value)
+        def execute(initial, final):
+            final.set16(reg, final.read('ds', displacement, 16))
+        add(model, '8B', make(model, 'mov reg,mem', [0x8B, 0x06 | (reg << 3), displacement & 0xFF, displacement >> 8], setup, execute, rng))
+        def execute(initial, final):
+            final.set16(3, (final.get16(3) + final.get16(6) + displacement) & 0xFFFF) # LEA BX,[BX+SI+disp16]
+        add(model, '8D', make(model, 'lea', [0x8D, 0x98, displacement & 0xFF, displacement >> 8], lambda state: None, execute, rng))
+        def execute(initial, final):
+            final.set16(0, value)
+        add(model, 'B8', make(model, 'mov ax,imm', [0xB8, value & 0xFF, value >> 8], lambda state: None, execute, rng))
+        def execute(initial, final):
+            ax, cx = final.get16(0), final.get16(1)
+            final.set16(0, cx)
+            final.set16(1, ax)
+        add(model, '91', make(model, 'xchg', [0x91], lambda state: None, execute, rng))
+    for value in (0x1280, 0x347F, 0x8000, 0x7FFF):
+        def setup(state):
+            state.set16(0, value)
+        def execute(initial, final):
+            final.set16(0, (final.get16(0) & 0xFF) | (0xFF00 if final.get16(0) & 0x80 else 0))
+        add(model, '98', make(model, 'cbw', [0x98], setup, execute, rng))
+        def execute(initial, final):
+            final.set16(2, 0xFFFF if final.get16(0) & 0x8000 else 0)
+        add(model, '99', make(model, 'cwd', [0x99], setup, execute, rng))
+
+def string_cases(model, rng):
+    for direction in (0, 1, 0, 1):
+        value = rng.randrange(0x10000)
+        def setup(state):
+            state.setflag(DF, direction)
+            state.write('ds', state.get16(6), 8, value & 0xFF)
+        def execute(initial, final):
+            final.write('es', final.get16(7), 8, final.read('ds', final.get16(6), 8))
+            step = -1 if direction else 1
+            final.set16(6, final.get16(6) + step)
+            final.set16(7, final.get16(7) + step)
+        add(model, 'A4', make(model, 'movsb', [0xA4], setup, execute, rng))
+        def setup(state):
+            state.setflag(DF, direction)
+        def execute(initial, final):
+            final.write('es', final.get16(7), 16, final.get16(0))
+            final.set16(7, final.get16(7) + (-2 if direction else 2))
+        add(model, 'AB', make(model, 'stosw', [0xAB], setup, execute, rng))
+
+def flag_cases(model, rng):
+    for opcode, change in ((0xF5, lambda s: s.setflag(CF, not s.flag(CF))), (0xF8, lambda s: s.setflag(CF, 0)), (0xF9, lambda s: s.setflag(CF, 1)),
+                           (0xFC, lambda s: s.setflag(DF, 0)), (0xFD, lambda s: s.setflag(DF, 1))):
+        for n in range(2):
+            flags = rng.randrange(0x1000) & 0x0CD5
+            def setup(state):
+                state.flags = (state.flags & ~0x0CD5) | flags
+            def execute(initial, final):
+                change(final)
+            add(model, '%02X' % opcode, make(model, 'flag', [opcode], setup, execute, rng))
+
+def shift(state, operation, width, value, count):
+    """Shift or rotate by a count of at least 1, with OF as defined for a count of 1"""
+    mask, msb = (1 << width) - 1, width - 1
+    result = value
+    for _ in range(count):
+        if operation == 'rol':
+            carry = (result >> msb) & 1
+            result = ((result << 1) | carry) & mask
+        elif operation == 'ror':
+            carry = result & 1
+            result = (result >> 1) | (carry << msb)
+        elif operation == 'rcl':
+            carry = (result >> msb) & 1
+            result = ((result << 1) | state.flag(CF)) & mask
+        elif operation == 'rcr':
+            carry = result & 1
+            result = (result >> 1) | (state.flag(CF) << msb)
+        elif operation == 'shl':
+            carry = (result >> msb) & 1
+            result = (result << 1) & mask
+        elif operation == 'shr':
+            carry = result & 1
+            result >>= 1
+        else: # sar
+            carry = result & 1
+            result = (result >> 1) | (result & (1 << msb))
+        state.setflag(CF, carry)
+    if operation in ('rol', 'rcl', 'shl'):
+        state.setflag(OF, ((result >> msb) & 1) ^ state.flag(CF))
+    elif operation in ('ror', 'rcr'):
+        state.setflag(OF, ((result >> msb) ^ (result >> (msb - 1))) & 1)
+    elif operation == 'shr':
+        state.setflag(OF, (value >> msb) & 1)
+    else:
+        state.setflag(OF, 0)
+    if operation in ('shl', 'shr', 'sar'):
+        result_flags(state, width, result)
+    return result
+
+SHIFTS = ['rol', 'ror', 'rcl', 'rcr', 'shl', 'shr', None, 'sar']
+
+def shift_cases(model, rng, opcode, with_count):
+    for extension, operation in enumerate(SHIFTS):
+        if operation is None:
+            continue
+        for n in range(3):
+            value = rng.randrange(0x10000)
+            count = rng.randrange(1, 16) if with_count else 1
+            code = [opcode, 0xC0 | (extension << 3)] + ([count] if with_count else [])
+            carry_in = rng.randrange(2)
+            def setup(state):
+                state.set16(0, value)
+                state.setflag(CF, carry_in)
+            def execute(initial, final):
+                final.set16(0, shift(final, operation, 16, final.get16(0), count))
+            # OF is only defined for a count of 1, AF is undefined
+            mask = (0x08C5 if operation in ('shl', 'shr', 'sar') else 0x0801) & (0xFFFF if not with_count else 0x00C5 if operation in ('shl', 'shr', 'sar') else 0x0001)
+            add(model, '%02X.%d' % (opcode, extension), make(model, operation, code, setup, execute, rng), {'flags-mask': mask})
+
+def multiply_cases(model, rng):
+    def signed(value, width):
+        return value - (1 << width) if value >> (width - 1) else value
+    for extension in (2, 3, 4, 5, 6, 7):
+        for n in range(5):
+            dx, ax, divisor = rng.randrange(0x10000), rng.randrange(0x10000), rng.randrange(1, 0x10000)
+            if extension == 6 and n < 4:
+                dx = rng.randrange(divisor) # No overflow
+            if extension == 7 and n < 4:
+                quotient = rng.randrange(-0x7F00, 0x7F00)
+                dividend = quotient * signed(divisor, 16) + rng.randrange(abs(signed(divisor, 16)))
+                dx, ax = (dividend >> 16) & 0xFFFF, dividend & 0xFFFF
+            code = [0xF7, 0xC0 | (extension << 3) | 3] # BX
+            def setup(state):
+                state.set16(0, ax)
+                state.set16(2, dx)
+                state.set16(3, divisor)
+                setup_vector(state, 0)
+            def execute(initial, final):
+                if extension == 2:
+                    final.set16(3, ~final.get16(3))
+                elif extension == 3:
+                    value = final.get16(3)
+                    result = alu(final, 'sub', 16, 0, value)
+                    final.setflag(CF, value != 0)
+                    final.set16(3, result)
+                elif extension in (4, 5):
+                    if extension == 4:
+                        product = ax * divisor
+                        overflow = product >> 16 != 0
+                    else:
+                        product = signed(ax, 16) * signed(divisor, 16)
+                        overflow = not -0x8000 <= product < 0x8000
+                    final.set16(0, product)
+                    final.set16(2, product >> 16)
+                    final.setflag(CF, overflow)
+                    final.setflag(OF, overflow)
+                else:
+                    if extension == 6:
+                        dividend = (dx << 16) | ax
+                        quotient, remainder = dividend // divisor, dividend % divisor
+                        valid = quotient <= 0xFFFF
+                    else:
+                        dividend, divisor_value = signed((dx << 16) | ax, 32), signed(divisor, 16)
+                        quotient = abs(dividend) // abs(divisor_value) * (1 if (dividend < 0) == (divisor_value < 0) else -1)
+                        remainder = dividend - quotient * divisor_value
+                        valid = -0x8000 <= quotient <= 0x7FFF if model != '8086' else -0x7FFF <= quotient <= 0x7FFF
+                    if valid:
+                        final.set16(0, quotient)
+                        final.set16(2, remainder)
+                    else:
+                        # The 8086 pushes the address of the next instruction, later models the one of DIV
+                        interrupt(final, 0, final.ip if model == '8086' else initial.ip)
+            mask = {2: 0x0000, 3: ARITH_MASK, 4: 0x0801, 5: 0x0801, 6: 0x0000, 7: 0x0000}[extension]
+            add(model, 'F7.%d' % extension, make(model, 'group3', code, setup, execute, rng), {'flags-mask': mask})
+
+def common(model, rng):
+    alu_cases(model, rng)
+    inc_dec_cases(model, rng)
+    stack_cases(model, rng)
+    jump_cases(model, rng)
+    move_cases(model, rng)
+    string_cases(model, rng)
+    flag_cases(model, rng)
+    shift_cases(model, rng, 0xD1, False)
+    multiply_cases(model, rng)
+
+def enter_cases(model, rng, width):
+    prefix = [0x66] if width == 32 else []
+    for nesting in (0, 1, 2, 3):
+        size = rng.randrange(0x40)
+        frames = [rng.randrange(1 << width) for _ in range(nesting)]
+        code = prefix + [0xC8, size, 0x00, nesting]
+        def setup(state):
+            state.set16(5, 0x0180)
+            for i, frame in enumerate(frames):
+                state.write('ss', 0x0180 - (i + 1) * width // 8, width, frame)
+        def execute(initial, final):
+            final.push(width, final.get(5, width))
+            frame_pointer = final.get16(4)
+            if nesting > 0:
+                for _ in range(1, nesting):
+                    final.set16(5, final.get16(5) - width // 8)
+                    final.push(width, final.read('ss', final.get16(5), width))
+                final.push(width, frame_pointer)
+            if width == 32:
+                final.set32(5, frame_pointer)
+            else:
+                final.set16(5, frame_pointer)
+            final.set16(4, final.get16(4) - size)
+        add(model, ''.join('%02X' % byte for byte in prefix) + 'C8', make(model, 'enter %d,%d' % (size, nesting), code, setup, execute, rng))
+
+def bound_cases(model, rng, width):
+    prefix = [0x66] if width == 32 else []
+    mask = (1 << width) - 1
+    for n in range(4):
+        lower = rng.randrange(-100, 100)
+        upper = lower + rng.randrange(0, 100)
+        index = [lower, upper, lower - 1 - rng.randrange(1 << (width - 2)), upper + 1 + rng.randrange(1 << (width - 2))][n]
+        displacement = rng.randrange(0x100, 0x8000)
+        code = prefix + [0x62, 0x06 | (2 << 3), displacement & 0xFF, displacement >> 8] # DX
+        def setup(state):
+            state.set(2, width, index & mask)
+            state.write('ds', displacement, width, lower & mask)
+            state.write('ds', displacement + width // 8, width, upper & mask)
+            setup_vector(state, 5)
+        def execute(initial, final):
+            if not lower <= index <= upper:
+                interrupt(final, 5, initial.ip)
+        add(model, ''.join('%02X' % byte for byte in prefix) + '62', make(model, 'bound', code, setup, execute, rng))
+
+def model_80286(model, rng):
+    for n in range(4):
+        immediate = rng.randrange(0x100)
+        def execute(initial, final):
+            final.push(16, immediate - 0x100 if immediate >= 0x80 else immediate)
+        add(model, '6A', make(model, 'push imm8', [0x6A, immediate], lambda state: None, execute, rng))
+        value, immediate = rng.randrange(0x10000), rng.randrange(0x10000)
+        def setup(state):
+            state.set16(3, value)
+        def execute(initial, final):
+            product = (value - 0x10000 if value >= 0x8000 else value) * (immediate - 0x10000 if immediate >= 0x8000 else immediate)
+            overflow = not -0x8000 <= product < 0x8000
+            final.set16(0, product)
+            final.setflag(CF, overflow)
+            final.setflag(OF, overflow)
+        add(model, '69', make(model, 'imul ax,bx,imm16', [0x69, 0xC3, immediate & 0xFF, immediate >> 8], setup, execute, rng), {'flags-mask': 0x0801})
+    shift_cases(model, rng, 0xC1, True)
+    enter_cases(model, rng, 16)
+    bound_cases(model, rng, 16)
+
+def double_shift_cases(model, rng, opcode, counts):
+    for count in counts:
+        dst, fill = rng.randrange(0x10000), rng.randrange(0x10000)
+        code = [0x0F, opcode, 0xD8, count] # AX,BX
+        def setup(state):
+            state.set16(0, dst)
+            state.set16(3, fill)
+        def execute(initial, final):
+            masked = count & 0x1F
+            if masked == 0:
+                return
+            # Shifts through destination:fill:destination, which continues with the destination for counts above 16 like the 386
+            concatenation = (dst << 32) | (fill << 16) | dst
+            if opcode == 0xA4:
+                result = ((concatenation << masked) >> 32) & 0xFFFF
+                carry = (concatenation >> (48 - masked)) & 1
+            else:
+                result = (concatenation >> masked) & 0xFFFF
+                carry = (concatenation >> (masked - 1)) & 1
+            final.set16(0, result)
+            final.setflag(CF, carry)
+            final.setflag(OF, ((dst ^ result) >> 15) & 1)
+            result_flags(final, 16, result)
+        name = '%s ax,bx,%d' % ('shld' if opcode == 0xA4 else 'shrd', count)
+        # OF is only defined for a count of 1, AF is undefined
+        add(model, '0F%02X' % opcode, make(model, name, code, setup, execute, rng), {'flags-mask': 0x00C5})
+
+def divide_overflow_cases(model, rng):
+    # The most negative dividend divided by -1 does not fit the quotient
+    code = [0xF7, 0xFB] # IDIV BX
+    def setup(state):
+        state.set16(2, 0x8000)
+        state.set16(0, 0x0000)
+        state.set16(3, 0xFFFF)
+        setup_vector(state, 0)
+    add(model, 'F7.7', make(model, 'idiv overflow', code, setup, lambda initial, final: interrupt(final, 0, initial.ip), rng), {'flags-mask': 0x0000})
+    code = [0x66, 0xF7, 0xFB] # IDIV EBX
+    def setup(state):
+        state.set32(2, 0x80000000)
+        state.set32(0, 0x00000000)
+        state.set32(3, 0xFFFFFFFF)
+        setup_vector(state, 0)
+    add(model, '66F7.7', make(model, 'idiv overflow 32', code, setup, lambda initial, final: interrupt(final, 0, initial.ip), rng), {'flags-mask': 0x0000})
+    for n in range(3):
+        divisor = rng.randrange(1, 0x80000000) * rng.choice([1, -1])
+        quotient = rng.randrange(-0x7FFFFFFF, 0x7FFFFFFF)
+        remainder = rng.randrange(abs(divisor)) * (1 if quotient >= 0 else -1)
+        dividend = quotient * divisor + remainder
+        if dividend < 0 and remainder > 0 or dividend > 0 and remainder < 0:
+            remainder = 0
+            dividend = quotient * divisor
+        def setup(state):
+            state.set32(2, (dividend >> 32) & 0xFFFFFFFF)
+            state.set32(0, dividend & 0xFFFFFFFF)
+            state.set32(3, divisor & 0xFFFFFFFF)
+        def execute(initial, final):
+            final.set32(0, quotient)
+            final.set32(2, remainder)
+        add(model, '66F7.7', make(model, 'idiv 32', code, setup, execute, rng))
+
+def model_80386(model, rng):
+    double_shift_cases(model, rng, 0xA4, [1, 4, 15, 16, 20, 31])
+    double_shift_cases(model, rng, 0xAC, [1, 4, 15, 16, 20, 31])
+    divide_overflow_cases(model, rng)
+    enter_cases(model, rng, 32)
+    bound_cases(model, rng, 32)
+
+rng = random.Random(1978)
+common('8086', rng)
+common('80286', rng)
+model_80286('80286', rng)
+model_80386('80386', rng)
+
+for (model, opcode), cases in tests.items():
+    directory = os.path.join(OUT, model)
+    os.makedirs(directory, exist_ok=True)
+    with open(os.path.join(directory, opcode + '.json'), 'w') as file:
+        json.dump(cases, file, separators=(',', ':'))
+for model, opcodes in metadata.items():
+    with open(os.path.join(OUT, model, 'metadata.json'), 'w') as file:
+        json.dump({'opcodes': {opcode: {'flags-mask': '0x%04X' % entry['flags-mask']} for opcode, entry in sorted(opcodes.items())}}, file, indent=1)
+print(sum(len(cases) for cases in tests.values()), 'tests')